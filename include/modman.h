@@ -0,0 +1,79 @@
+/*
+ * modman's C API, for embedding it in game launchers and the like.
+ * Build it with `cargo rustc --lib --release --features ffi --crate-type cdylib`,
+ * then link against target/release/libmodman.so (or modman.dll, or libmodman.dylib).
+ *
+ * Each call reads the game directory's profile, does its thing, and saves it,
+ * just like the command line would. Calls can come from any thread,
+ * but run one at a time. (They leave the working directory alone.)
+ *
+ * Functions returning int return 0 on success, or one of modman's exit codes
+ * (see `modman --help`) on failure. modman_last_error() says what went wrong.
+ */
+
+#ifndef MODMAN_H
+#define MODMAN_H
+
+#include <stdbool.h>
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* A game directory with a modman profile */
+typedef struct ModmanProfile ModmanProfile;
+
+/*
+ * Opens the profile in the given game directory (made with `modman init`),
+ * or returns NULL if there isn't a usable one.
+ */
+ModmanProfile* modman_open_profile(const char* dir);
+
+/* Frees a profile from modman_open_profile(). NULL is fine. */
+void modman_close_profile(ModmanProfile* profile);
+
+/*
+ * Returns the installed mods as a JSON array of
+ * {"path", "version", "alias", "tags", "files"} objects, or NULL on failure.
+ * Free it with modman_free_string().
+ */
+char* modman_list(const ModmanProfile* profile);
+
+/*
+ * Installs the given mods (paths relative to the game directory, or absolute),
+ * like `modman add`.
+ */
+int modman_add(const ModmanProfile* profile, const char* const* mods, size_t count, bool dry_run);
+
+/*
+ * Uninstalls the given mods (by path, alias, or name), restoring the game files
+ * they replaced, like `modman remove`.
+ */
+int modman_remove(const ModmanProfile* profile, const char* const* mods, size_t count, bool dry_run);
+
+/*
+ * Says what went wrong with the last failed call on this thread, or returns NULL.
+ * The string is modman's, and is valid until the next call fails on this thread.
+ */
+const char* modman_last_error(void);
+
+/* Frees a string returned by modman. NULL is fine. */
+void modman_free_string(char* s);
+
+/*
+ * Called with each progress event (including warnings) as a line of JSON,
+ * like `modman --progress json` prints, e.g.,
+ * {"event":"progress","done":1,"total":4,"percent":25}
+ * It can be called from any of modman's worker threads.
+ */
+typedef void (*ModmanProgressCallback)(const char* event_json, void* user_data);
+
+/* Calls callback with each progress event, and user_data. Pass NULL to stop. */
+void modman_set_progress_callback(ModmanProgressCallback callback, void* user_data);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MODMAN_H */
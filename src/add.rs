@@ -2,21 +2,40 @@ use std::collections::*;
 use std::fs;
 use std::io::{self, prelude::*};
 use std::path::{Path, PathBuf};
-use std::sync::{mpsc::channel, Mutex};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Mutex;
 
 use anyhow::*;
 use log::*;
 use rayon::prelude::*;
 use structopt::*;
 
+use crate::auto_check::auto_check;
+use crate::delta::*;
+use crate::deps::*;
+use crate::download::*;
+use crate::failure::*;
 use crate::file_utils::*;
+use crate::games;
+use crate::history;
+use crate::hooks::*;
 use crate::journal::*;
+use crate::merge::*;
+use crate::mod_config::MOD_CONFIG_NAME;
 use crate::modification::*;
+use crate::output::{print_changes, print_diff};
+use crate::plan::{self, Operation, PlanCommand};
 use crate::profile::*;
+use crate::profile_store::*;
+use crate::progress::{self, FileEvent};
+use crate::space::SpaceNeeded;
+use crate::tag::check_tag;
+use crate::text::*;
+use crate::zip_mod::mod_roots;
 
 /// Installs a mod.
 ///
-/// Each <MOD> is assumed to be a ZIP archive or a directory containing
+/// Each <MOD> is assumed to be a ZIP archive (or the URL of one) or a directory containing
 /// a VERSION.txt file, a README.txt file, and a single sub-directory,
 /// which will be treated as the root of the mod files.
 /// (Any JSGME or OVGME-compatible archive should match this format.)
@@ -29,46 +48,655 @@ pub struct Args {
     #[structopt(short = "n", long)]
     dry_run: bool,
 
-    #[structopt(name = "MOD", required(true))]
+    /// Don't change anything; write a plan of every file operation
+    /// to <PLAN_FILE> (or stdout, if it's -) for `modman apply`.
+    /// (Plans only name the mods, so options that change what's installed
+    /// can't be given with it.)
+    #[structopt(long, name = "PLAN_FILE")]
+    plan: Option<PathBuf>,
+
+    /// Don't change anything; write the files the mods would install
+    /// to <STAGE_DIR>/files/ (and copies of the game files they'd replace
+    /// to <STAGE_DIR>/originals/) for review, then `modman apply <STAGE_DIR>`.
+    /// (Like plans, options that change what's installed can't be given with it.)
+    #[structopt(
+        long = "stage",
+        name = "STAGE_DIR",
+        conflicts_with_all = &["PLAN_FILE", "resume"]
+    )]
+    stage: Option<PathBuf>,
+
+    /// Set when `modman apply` checks staged files instead of writing them
+    #[structopt(skip)]
+    checking_stage: bool,
+
+    /// Give installed files the modification times they have in the mod,
+    /// instead of when they were installed.
+    #[structopt(long, conflicts_with_all = &["PLAN_FILE", "STAGE_DIR"])]
+    keep_mtimes: bool,
+
+    /// Install the mods to the named profile root
+    /// (see `modman roots`) instead of the one their mod.toml picks.
+    #[structopt(long, name = "ROOT", conflicts_with_all = &["PLAN_FILE", "STAGE_DIR"])]
+    root_name: Option<String>,
+
+    /// Give the mod a short name to refer to it by in other commands
+    /// (like `modman remove <ALIAS>`). Only one mod can be added with one.
+    #[structopt(long, name = "ALIAS", conflicts_with_all = &["PLAN_FILE", "STAGE_DIR"])]
+    alias: Option<String>,
+
+    /// Tag the mods (see `modman tag`). Can be given more than once.
+    #[structopt(
+        long = "tag",
+        name = "TAG",
+        number_of_values = 1,
+        conflicts_with_all = &["PLAN_FILE", "STAGE_DIR"]
+    )]
+    tags: Vec<String>,
+
+    /// Install even if it doesn't look like there's enough free space
+    /// for the mods and backups of the files they replace.
+    #[structopt(long, conflicts_with_all = &["PLAN_FILE", "STAGE_DIR"])]
+    skip_space_check: bool,
+
+    /// If a backup of a file is already there (say, from an interrupted
+    /// `modman add`), keep it if it matches the game's file,
+    /// or move it aside to modman-backup/quarantine/ if it doesn't.
+    #[structopt(long, conflicts_with_all = &["PLAN_FILE", "STAGE_DIR"])]
+    adopt_existing_backup: bool,
+
+    /// Pick up where an interrupted `modman add` of <MOD> left off,
+    /// using the backups it made and skipping files it already installed.
+    /// Pass the same options the interrupted run had.
+    #[structopt(long, conflicts_with = "PLAN_FILE")]
+    resume: bool,
+
+    /// Read every file in the mods (checking archives' CRCs) before
+    /// installing anything, so a corrupt download fails before any
+    /// game file is changed.
+    #[structopt(long, conflicts_with_all = &["PLAN_FILE", "STAGE_DIR"])]
+    verify_archive: bool,
+
+    /// Leave game files alone if the mod's file is identical to them,
+    /// instead of backing them up and installing the same contents.
+    #[structopt(long, conflicts_with_all = &["PLAN_FILE", "STAGE_DIR"])]
+    skip_identical: bool,
+
+    /// Install files over the same files from mods already installed,
+    /// instead of failing. Removing the mod puts theirs back.
+    #[structopt(long, conflicts_with_all = &["PLAN_FILE", "STAGE_DIR"])]
+    overwrite: bool,
+
+    /// Go ahead even if the automatic check (see `modman auto-check`)
+    /// finds problems.
+    #[structopt(long)]
+    force: bool,
+
+    /// Also install the mods listed in <LIST_FILE>, one path or URL per line.
+    /// (Blank lines and lines starting with # are skipped.)
+    #[structopt(long = "manifest", name = "LIST_FILE")]
+    mod_list: Option<PathBuf>,
+
+    /// Also install the mods listed on stdin, like --manifest.
+    #[structopt(long, conflicts_with = "LIST_FILE")]
+    from_stdin: bool,
+
+    /// Install the mod in <INNER_DIR> of an archive that bundles several
+    /// (say, `--select "Some Mod"` for the one in Some Mod/VERSION.txt).
+    /// Can be given more than once. Without it, modman asks which to install.
+    #[structopt(long = "select", name = "INNER_DIR", number_of_values = 1)]
+    select: Vec<PathBuf>,
+
+    #[structopt(name = "MOD", required_unless_one(&["LIST_FILE", "from-stdin"]))]
     mod_names: Vec<PathBuf>,
+
+    /// Where relative paths in mod lists are from (see resolve_paths())
+    #[structopt(skip)]
+    base: PathBuf,
 }
 
-pub fn run(args: Args) -> Result<()> {
-    let mut p = load_and_check_profile()?;
+impl Args {
+    pub fn new(mod_names: Vec<PathBuf>, dry_run: bool) -> Self {
+        Self {
+            dry_run,
+            plan: None,
+            stage: None,
+            checking_stage: false,
+            keep_mtimes: false,
+            root_name: None,
+            alias: None,
+            tags: Vec::new(),
+            skip_space_check: false,
+            adopt_existing_backup: false,
+            resume: false,
+            verify_archive: false,
+            skip_identical: false,
+            overwrite: false,
+            force: false,
+            mod_list: None,
+            from_stdin: false,
+            select: Vec::new(),
+            mod_names,
+            base: PathBuf::new(),
+        }
+    }
 
-    for mod_name in args.mod_names {
-        info!("Activating {}...", mod_name.display());
+    /// Installs the mods the way the given one was:
+    /// to the same root, with the same alias and tags, keeping mtimes if it did.
+    pub fn like(mut self, manifest: &ModManifest) -> Self {
+        self.keep_mtimes = manifest.keep_mtimes;
+        self.root_name = manifest.root.clone();
+        self.alias = manifest.alias.clone();
+        self.tags = manifest.tags.iter().cloned().collect();
+        self
+    }
+
+    /// Checks that the files staged in the given directory (with `--stage`)
+    /// are the ones we'd install, instead of installing anything.
+    pub fn check_stage(mut self, stage_dir: &Path) -> Self {
+        self.dry_run = true;
+        self.stage = Some(stage_dir.to_owned());
+        self.checking_stage = true;
+        self
+    }
+
+    /// Makes this a dry run, for `--no-write`.
+    /// Returns false if it would still write something (a plan file or staged files).
+    pub fn make_dry_run(&mut self) -> bool {
+        self.dry_run = true;
+        self.plan.as_deref().is_none_or(plan::is_stdout) && self.stage.is_none()
+    }
 
-        let mod_path = Path::new(&mod_name);
+    pub fn resolve_paths(&mut self, base: &Path) {
+        for name in &mut self.mod_names {
+            if !name.to_str().map(is_url).unwrap_or(false) {
+                *name = base.join(&name);
+            }
+        }
+        if let Some(plan) = self.plan.as_mut().filter(|p| !plan::is_stdout(p)) {
+            *plan = base.join(&plan);
+        }
+        if let Some(stage) = &mut self.stage {
+            *stage = base.join(&stage);
+        }
+        if let Some(list) = &mut self.mod_list {
+            *list = base.join(&list);
+        }
+        self.base = base.to_owned();
+    }
 
-        // First sanity check: we haven't already added this mod.
-        if p.mods.contains_key(mod_path) {
-            bail!("{} has already been added!", mod_name.display());
+    /// Adds the mods from --manifest or --from-stdin to the ones we were given.
+    fn read_mod_lists(&mut self) -> Result<()> {
+        let listed = if let Some(list) = &self.mod_list {
+            let f = fs::File::open(list)
+                .with_context(|| format!("Couldn't open mod list {}", list.display()))?;
+            read_mod_list(io::BufReader::new(f), &self.base)
+                .with_context(|| format!("Couldn't read mod list {}", list.display()))?
+        } else if self.from_stdin {
+            read_mod_list(io::stdin().lock(), &self.base)
+                .context("Couldn't read mod list from stdin")?
+        } else {
+            return Ok(());
+        };
+        self.mod_names.extend(listed);
+        if self.mod_names.is_empty() {
+            fail!(Failure::Usage, "The mod list is empty");
         }
+        Ok(())
+    }
 
-        apply_mod(mod_path, &mut p, args.dry_run)?;
+    /// Replaces archives that bundle several mods with the ones
+    /// we were told (or asked the user) to install.
+    /// (URLs are sorted out once they're downloaded.)
+    fn pick_sub_mods(&mut self) -> Result<()> {
+        let mut picked = Vec::with_capacity(self.mod_names.len());
+        for name in &self.mod_names {
+            if name.to_str().is_some_and(is_url) {
+                picked.push(name.clone());
+            } else {
+                picked.extend(sub_mods(name, &self.select)?);
+            }
+        }
+        self.mod_names = picked;
+        Ok(())
     }
+}
 
-    if !args.dry_run {
-        remove_empty_tree(Path::new(TEMPDIR_PATH), RemoveRoot(false))
-            .context("Couldn't clean up temp directory")?;
+/// Returns the mods to install from the given path:
+/// just it, unless it's an archive bundling several mods,
+/// in which case it's the ones in the selected directories
+/// (or the ones the user picks, if none were selected).
+/// They're named archive.zip/<INNER_DIR>; see split_archive_path().
+fn sub_mods(path: &Path, select: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    if !path.is_file() {
+        return Ok(vec![path.to_owned()]);
+    }
+    // If it's not an archive we can read, let opening it say why.
+    let roots = match mod_roots(path) {
+        Ok(r) => r,
+        Err(_) => return Ok(vec![path.to_owned()]),
+    };
+    match roots.as_slice() {
+        [] => return Ok(vec![path.to_owned()]),
+        [only] if only.as_os_str().is_empty() => return Ok(vec![path.to_owned()]),
+        _ => {}
+    }
+
+    if !select.is_empty() {
+        // --select applies to every bundle we're given,
+        // so each only needs to have some of the selected mods.
+        let picked: Vec<PathBuf> = select
+            .iter()
+            // (So that Some Mod/ is Some Mod.)
+            .map(|inner| inner.components().collect::<PathBuf>())
+            .filter(|inner| roots.contains(inner))
+            .map(|inner| path.join(inner))
+            .collect();
+        if picked.is_empty() {
+            fail!(
+                Failure::Usage,
+                "{} has none of the selected mods. Its mods are in:{}",
+                path.display(),
+                list_roots(&roots)
+            );
+        }
+        return Ok(picked);
+    }
+
+    match roots.as_slice() {
+        [only] => Ok(vec![path.join(only)]),
+        _ => Ok(choose_sub_mods(path, &roots)?
+            .into_iter()
+            .map(|r| path.join(r))
+            .collect()),
+    }
+}
+
+fn list_roots(roots: &[PathBuf]) -> String {
+    roots
+        .iter()
+        .map(|r| format!("\n\t{}", r.display()))
+        .collect()
+}
+
+/// Asks the user which of the archive's mods to install.
+fn choose_sub_mods<'a>(path: &Path, roots: &'a [PathBuf]) -> Result<Vec<&'a PathBuf>> {
+    if !atty::is(atty::Stream::Stdin) || !atty::is(atty::Stream::Stderr) {
+        fail!(
+            Failure::Usage,
+            "{} holds several mods, in:{}\nPick which to install with --select <INNER_DIR>.",
+            path.display(),
+            list_roots(roots)
+        );
+    }
+    eprintln!("{} holds several mods:", path.display());
+    for (i, root) in roots.iter().enumerate() {
+        eprintln!("{:>4}: {}", i + 1, root.display());
+    }
+    eprint!("Which should be installed? (numbers separated by spaces, or all) ");
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+
+    if answer.trim() == "all" {
+        return Ok(roots.iter().collect());
+    }
+    let mut chosen = Vec::new();
+    for choice in answer.split(|c: char| c.is_whitespace() || c == ',') {
+        if choice.is_empty() {
+            continue;
+        }
+        match choice.parse::<usize>() {
+            Ok(n) if (1..=roots.len()).contains(&n) => chosen.push(&roots[n - 1]),
+            _ => fail!(Failure::Usage, "{} isn't one of the choices", choice),
+        }
+    }
+    if chosen.is_empty() {
+        fail!(Failure::Usage, "No mods chosen; nothing to install");
+    }
+    chosen.dedup();
+    Ok(chosen)
+}
+
+/// Reads a list of mods, one per line, skipping blank lines and #comments.
+/// Paths are relative to base, like the ones on the command line.
+fn read_mod_list<R: BufRead>(reader: R, base: &Path) -> Result<Vec<PathBuf>> {
+    let mut mods = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        mods.push(if is_url(line) {
+            PathBuf::from(line)
+        } else {
+            base.join(line)
+        });
+    }
+    Ok(mods)
+}
+
+pub fn run(mut args: Args) -> Result<()> {
+    args.read_mod_lists()?;
+    args.pick_sub_mods()?;
+    if let Some(plan_path) = &args.plan {
+        let mods = args.mod_names.clone();
+        return plan::write_plan(plan_path, PlanCommand::Add, mods.clone(), || {
+            add_mods(Args::new(mods, true))
+        });
+    }
+    if let Some(stage_dir) = args.stage.as_ref().filter(|_| !args.checking_stage) {
+        return stage_mods(stage_dir, args.mod_names.clone());
+    }
+    if args.alias.is_some() && args.mod_names.len() > 1 {
+        fail!(Failure::Usage, "Only one mod can be added with --alias");
+    }
+    if args.resume && args.mod_names.len() > 1 {
+        fail!(Failure::Usage, "Only one mod can be resumed at a time");
+    }
+    // Resuming is how you clean up after an interrupted add.
+    if !args.dry_run && !args.resume {
+        auto_check(args.force, true)?;
+    }
+    add_mods(args)
+}
+
+/// Where `--stage` puts the files mods would install
+pub static STAGED_FILES_DIR: &str = "files";
+
+/// Where `--stage` puts copies of the game files mods would replace
+pub static STAGED_ORIGINALS_DIR: &str = "originals";
+
+/// The plan `--stage` writes, for `modman apply`
+pub fn staged_plan_path(stage_dir: &Path) -> PathBuf {
+    stage_dir.join("plan.json")
+}
+
+/// Does a dry run that writes everything it would install to stage_dir,
+/// along with a plan to install it.
+fn stage_mods(stage_dir: &Path, mods: Vec<PathBuf>) -> Result<()> {
+    if stage_dir.exists() && fs::read_dir(stage_dir)?.next().is_some() {
+        bail!(
+            "{} already exists and isn't empty. Pick somewhere else.",
+            stage_dir.display()
+        );
+    }
+    fs::create_dir_all(stage_dir)
+        .with_context(|| format!("Couldn't create directory {}", stage_dir.display()))?;
+
+    let staging = Args {
+        stage: Some(stage_dir.to_owned()),
+        ..Args::new(mods.clone(), true)
+    };
+    plan::write_plan(&staged_plan_path(stage_dir), PlanCommand::Add, mods, || {
+        add_mods(staging)
+    })?;
+    println!(
+        "Staged the mods in {}/. Look them over, then run `modman apply {}` to install them.",
+        stage_dir.display(),
+        stage_dir.display()
+    );
+    Ok(())
+}
+
+/// Where a file goes in a staging directory
+fn staged_path(stage_dir: &Path, subdir: &str, mod_file_path: &Path) -> PathBuf {
+    stage_dir.join(subdir).join(mod_file_path)
+}
+
+/// Creates the directories a staged file goes in.
+fn create_staged_dirs(staged: &Path) -> Result<()> {
+    let dir = staged.parent().unwrap();
+    fs::create_dir_all(dir).with_context(|| format!("Couldn't create directory {}", dir.display()))
+}
+
+fn create_staged_file(staged: &Path) -> Result<fs::File> {
+    create_staged_dirs(staged)?;
+    create_file(staged).with_context(|| format!("Couldn't stage {}", staged.display()))
+}
+
+fn add_mods(args: Args) -> Result<()> {
+    let dry_run = args.dry_run;
+    let (mut store, mut p) = open_and_check_profile()?;
+    if !dry_run {
+        games::warn_if_running(&p);
+    }
+    if let Some(a) = &args.alias {
+        check_alias(a, &p)?;
+    }
+    for tag in &args.tags {
+        check_tag(tag)?;
+    }
+    if let Some(r) = &args.root_name {
+        if r != DEFAULT_ROOT_NAME && !p.roots.contains_key(r) {
+            bail!("The profile has no root named {}", r);
+        }
+    }
+
+    // Dry runs show what they'd do by comparing the profile before and after.
+    let before = (dry_run && !plan::is_planning()).then(|| p.clone());
+
+    // Download any mods given as URLs.
+    let mut sources = BTreeMap::new();
+    let mut to_add = Vec::with_capacity(args.mod_names.len());
+    for mod_name in args.mod_names.iter().cloned() {
+        let mod_names = match mod_name.to_str().filter(|n| is_url(n)) {
+            Some(url) => {
+                let path = fetch(url)?;
+                let picked = sub_mods(&path, &args.select)?;
+                for sub_mod in &picked {
+                    sources.insert(sub_mod.clone(), url.to_owned());
+                }
+                picked
+            }
+            None => vec![mod_name],
+        };
+
+        for mod_name in mod_names {
+            // First sanity check: we haven't already added this mod.
+            if p.mods.contains_key(&mod_name) {
+                fail!(
+                    Failure::Conflict,
+                    "{} has already been added!",
+                    mod_name.display()
+                );
+            }
+            let m = open_mod(&mod_name, p.unicode_form, args.root_name.as_deref())?;
+            to_add.push((mod_name, m));
+        }
+    }
+    if args.alias.is_some() && to_add.len() > 1 {
+        fail!(Failure::Usage, "Only one mod can be added with --alias");
+    }
+
+    // Install mods after the ones they require,
+    // and make sure they don't conflict with anything.
+    let to_add = order_mods_to_add(to_add, &p)?;
+
+    if args.verify_archive {
+        verify_mods(&to_add)?;
+    }
+
+    // Whatever's left to do when resuming takes less space than this
+    // reckons, and running out is a likely reason we're resuming.
+    if !dry_run && !args.skip_space_check && !args.resume {
+        check_free_space(&to_add, &p)?;
+    }
+
+    let resuming = if args.resume {
+        let journal = read_journal()?;
+        if journal.is_empty() {
+            bail!("There's no interrupted `modman add` to resume.");
+        }
+        Some(journal)
     } else {
-        print_profile(&p)?;
+        None
+    };
+
+    if !dry_run {
+        check_backup_dirs()?;
+    }
+
+    for (mod_name, m) in to_add {
+        info!("Activating {}...", mod_name.display());
+        let source = sources.get(&mod_name).cloned();
+        apply_mod(
+            &mod_name,
+            &*m,
+            source,
+            &args,
+            resuming.as_ref(),
+            &mut p,
+            &mut *store,
+        )?;
+    }
+
+    if !dry_run {
+        remove_empty_tree(&tempdir(), RemoveRoot(false))
+            .context("Couldn't clean up temp directory")?;
+        if games::note_steam_build(&mut p, false)? {
+            store.save_settings(&p)?;
+        }
+    } else if let Some(before) = before {
+        print_changes(&before, &p)?;
     }
 
     Ok(())
 }
 
+/// Reads each mod's files end to end (which checks zip entries' CRCs
+/// and sizes), and fails with every problem found.
+fn verify_mods(to_add: &[(PathBuf, ModBox)]) -> Result<()> {
+    let mut problems = String::new();
+    for (mod_name, m) in to_add {
+        info!("Verifying {}...", mod_name.display());
+        let paths = m.paths()?;
+        progress::start("verify-archive", mod_name, paths.len());
+        let verify = |path: &PathBuf| {
+            let problem = verify_mod_file(&**m, path).err();
+            progress::file_done(FileEvent::Checked, path);
+            problem.map(|e| format!("{}: {:#}", path.display(), e))
+        };
+        let mut found: Vec<String> = if m.streaming_only() {
+            paths.iter().filter_map(verify).collect()
+        } else {
+            paths.par_iter().filter_map(verify).collect()
+        };
+        if !found.is_empty() {
+            found.sort();
+            problems += &format!("\n{}:", mod_name.display());
+            for problem in found {
+                problems += &format!("\n\t{}", problem);
+            }
+        }
+    }
+    if !problems.is_empty() {
+        fail!(
+            Failure::VerificationFailed,
+            "Some mod files couldn't be read:{}\nNothing was installed.",
+            problems
+        );
+    }
+    Ok(())
+}
+
+fn verify_mod_file(m: &dyn Mod, path: &Path) -> Result<()> {
+    if m.symlink(path)?.is_some() {
+        return Ok(());
+    }
+    let expected = m.size(path)?;
+    let read = io::copy(&mut m.read_file(path)?, &mut io::sink())?;
+    if read != expected {
+        bail!("expected {} bytes, but read {}", expected, read);
+    }
+    Ok(())
+}
+
+/// Makes sure there's room for the mods' files (and backups of the game files
+/// they replace) before we start copying any of them.
+fn check_free_space(to_add: &[(PathBuf, ModBox)], p: &Profile) -> Result<()> {
+    let mut needed = SpaceNeeded::default();
+    for (mod_path, m) in to_add {
+        let patches = &m.config().patch;
+        for path in install_paths(mod_path, &**m)? {
+            let game_path = p.game_path(&path);
+            let existing = fs::metadata(&game_path).ok().map(|meta| meta.len());
+            // Patched files come out about the size of what they patch.
+            let size = if patches.contains_key(&path) {
+                existing.unwrap_or(0)
+            } else {
+                m.size(&path)?
+            };
+            needed.add(&game_path, size)?;
+            if let Some(backup_size) = existing {
+                needed.add(&backup_dir(), backup_size)?;
+            }
+        }
+    }
+    needed.check()
+}
+
 /// Given a mod's path and a profile, apply a given mod.
-/// If dry_run is set, no writes are made.
-fn apply_mod(mod_path: &Path, p: &mut Profile, dry_run: bool) -> Result<()> {
-    let m = open_mod(mod_path)?;
+/// If it's a dry run, no writes are made.
+///
+/// If we're resuming an interrupted `modman add`, its journal says which files
+/// it had gotten to; we use the backups it made of those
+/// and skip any it already installed.
+fn apply_mod(
+    mod_path: &Path,
+    m: &dyn Mod,
+    source: Option<String>,
+    args: &Args,
+    resuming: Option<&JournalMap>,
+    p: &mut Profile,
+    store: &mut dyn ProfileStore,
+) -> Result<()> {
+    let dry_run = args.dry_run;
+    let keep_mtimes = args.keep_mtimes;
+    if !dry_run {
+        history::note_mod(mod_path);
+    }
 
-    let mod_file_paths = m.paths()?;
+    let mod_file_paths = install_paths(mod_path, m)?;
+    check_roots(mod_path, &mod_file_paths, m.config().patch.keys(), p)?;
 
     // Look at all the paths we currently have,
-    // and make sure the new file doesn't contain any of them.
-    check_for_profile_conflicts(mod_path, &mod_file_paths, &p)?;
+    // and make sure the new file doesn't contain any of them
+    // (unless it's the same file, which the mods can share,
+    // or we're told to install over them).
+    let (shared, covered) =
+        check_for_profile_conflicts(mod_path, m, &mod_file_paths, p, args.overwrite)?;
+
+    if let Some(journal) = resuming {
+        check_resumable(mod_path, &mod_file_paths, journal)?;
+    }
+
+    let patches = &m.config().patch;
+    for (target, patch) in patches {
+        let original_path = original_path(target, resuming, p);
+        check_patch_target(patch, target, &original_path)?;
+    }
+
+    let merges = &m.config().merge;
+    if let Some(missing) = merges.keys().find(|f| !mod_file_paths.contains(f)) {
+        bail!(
+            "{}'s {} says to merge {}, but it doesn't have that file",
+            mod_path.display(),
+            MOD_CONFIG_NAME,
+            missing.display()
+        );
+    }
+
+    let mod_hooks = &m.config().hooks;
+    run_hooks(
+        Event::PreInstall,
+        mod_path,
+        m.version(),
+        mod_hooks,
+        p,
+        dry_run,
+    )?;
 
     // We want to install mod files in a way that minimizes the risk of
     // losing data if this program is interrupted or crashes.
@@ -78,14 +706,17 @@ fn apply_mod(mod_path: &Path, p: &mut Profile, dry_run: bool) -> Result<()> {
     //    (This wouldn't be necessary if we were only overwriting files,
     //    but without a journal, there's no way to know what files we've
     //    added to the game directory if this gets interrupted.)
-    // 2. Then, back it up to a temporary file
+    // 2. Then, back it up to a temporary file, hashing it as we go
     //    (and sync it, for what that's worth).
+    //    If the mod patches or merges into it, keep its contents too,
+    //    so that step 4 doesn't have to read it again.
     // 3. Once it's completed, move this temporary file to its actual path
     //    in the backup directory. Since moves are as close as we can get
     //    to atomic ops on the filesystem, this should guarantee that
     //    the backup directory only contains _complete_ copies of files
     //    we've replaced.
-    // 4. Then, overwrite the original location with our mod file.
+    // 4. Then, once the journal is synced, overwrite the original location
+    //    with our mod file, hashing it as we go.
     // 5. Once we've done so for all files, we'll write out the updated profile.
     //
     // If any of this is interrupted, the profile won't mention the mod
@@ -96,36 +727,389 @@ fn apply_mod(mod_path: &Path, p: &mut Profile, dry_run: bool) -> Result<()> {
     // We'll add this to the profile once we've applied all files.
     let mut manifest = ModManifest {
         version: m.version().clone(),
+        version_string: Some(m.version_string().to_owned())
+            .filter(|raw| *raw != m.version().to_string()),
+        source,
+        archive_hash: None,
+        archive_size: None,
+        archive_mtime: None,
+        requires: m.config().requires.clone(),
+        conflicts: m.config().conflicts.clone(),
+        hooks: mod_hooks.clone(),
+        keep_mtimes,
+        root: args.root_name.clone(),
+        alias: args.alias.clone(),
+        tags: m.config().tags.iter().chain(&args.tags).cloned().collect(),
+        directories: new_directories(&mod_file_paths, p),
         files: BTreeMap::new(),
     };
+    record_mod_archive(mod_path, &mut manifest)?;
 
     let (tx, rx) = channel();
 
-    let journal_mutex = Mutex::new(create_journal(dry_run)?);
+    // Backups in the journal we're resuming aren't leftovers; they're ours.
+    // (Backups of shared and covered files are the other mods'.)
+    if !args.adopt_existing_backup && resuming.is_none() {
+        let unshared: Vec<PathBuf> = mod_file_paths
+            .iter()
+            .filter(|f| !shared.contains_key(*f) && !covered.contains_key(*f))
+            .cloned()
+            .collect();
+        check_for_leftover_backups(&unshared, p)?;
+    }
+
+    progress::start("add", mod_path, mod_file_paths.len());
+
+    let diffs = Mutex::new(BTreeMap::new());
+    let journal_mutex = Mutex::new(match resuming {
+        Some(_) => resume_journal(dry_run)?,
+        None => create_journal(dry_run, mod_path)?,
+    });
     let journal: &Mutex<_> = &journal_mutex;
 
-    mod_file_paths
-        .into_par_iter()
-        .try_for_each_with::<_, _, Result<()>>(tx, |tx, mod_file_path| {
-            // 1-4: Back up the original, if there was one.
-            let original_hash: Option<FileHash> =
-                try_hash_and_backup(&mod_file_path, &p, journal, dry_run)?;
-
-            if original_hash.is_none() {
-                info!("Adding {}", mod_file_path.display());
+    // Have the mod start reading its files in the order we'll install them.
+    // (Patched files are read from their deltas.)
+    let to_read: Vec<PathBuf> = mod_file_paths
+        .iter()
+        .filter(|f| !shared.contains_key(*f))
+        .map(|f| patches.get(f).map_or(f, |patch| &patch.file).clone())
+        .collect();
+    m.prefetch(&to_read);
+
+    let install = |tx: &mut Sender<(PathBuf, ModFileMetadata)>,
+                   mod_file_path: PathBuf|
+     -> Result<()> {
+        // Another mod already installed this file, so leave it be.
+        if let Some((owner, owner_meta)) = shared.get(&mod_file_path) {
+            debug!(
+                "Sharing {} with {}",
+                mod_file_path.display(),
+                owner.display()
+            );
+            let meta = ModFileMetadata {
+                mod_hash: owner_meta.mod_hash.clone(),
+                original_hash: None,
+                merge: None,
+                symlink: None,
+                mode: owner_meta.mode,
+                original_mode: None,
+                mod_file: None,
+                size: owner_meta.size,
+                original_size: None,
+                backup_mtime: None,
+                shared: true,
+                identical: false,
+            };
+            progress::file_done(FileEvent::Installed, &mod_file_path);
+            tx.send((mod_file_path.clone(), meta))
+                .expect("Couldn't send");
+            return Ok(());
+        }
+
+        let resumed = resuming
+            .and_then(|j| j.get(&mod_file_path))
+            .map(|e| e.action);
+
+        // Keep the file we're installing over for when this mod is removed.
+        let covering = covered.get(&mod_file_path);
+        if let Some((below, below_meta)) = covering {
+            debug!(
+                "Installing {} over {}'s",
+                mod_file_path.display(),
+                below.display()
+            );
+            cover_file(&mod_file_path, below_meta, p, journal, dry_run)?;
+            plan::note(
+                mod_path,
+                Operation::Backup {
+                    path: mod_file_path.clone(),
+                },
+            );
+        }
+
+        if args.skip_identical && resumed.is_none() && covering.is_none() {
+            if let Some(meta) = identical_to_game_file(&mod_file_path, m, p)? {
+                debug!(
+                    "{} is identical to the game's file; leaving it be",
+                    mod_file_path.display()
+                );
+                progress::file_done(FileEvent::Installed, &mod_file_path);
+                tx.send((mod_file_path.clone(), meta))
+                    .expect("Couldn't send");
+                return Ok(());
+            }
+        }
+
+        let backup_path = mod_path_to_backup_path(&mod_file_path);
+        // If the interrupted run finished backing this up,
+        // the game file might already be the mod's, so go by the backup.
+        let adopted = resumed == Some(JournalAction::Replaced) && backup_path.exists();
+
+        // 1-3: Back up the original, if there was one.
+        // (Covered files' originals belong to the mods under them.)
+        let keep_original =
+            merges.contains_key(&mod_file_path) || patches.contains_key(&mod_file_path);
+        let (original_hash, original_contents) = match resumed {
+            _ if covering.is_some() => (None, None),
+            Some(JournalAction::Added) => (None, None),
+            _ if adopted => {
+                debug!("Using the existing backup of {}", mod_file_path.display());
+                let hash = hash_file(&backup_path)?;
+                let journaled = resuming
+                    .and_then(|j| j.get(&mod_file_path))
+                    .and_then(|e| e.original_hash.as_ref());
+                if journaled.is_some_and(|j| *j != hash) {
+                    bail!(
+                        "{} doesn't match the game file the interrupted `modman add` \
+                         backed up. Run `modman repair` to sort things out.",
+                        backup_path.display()
+                    );
+                }
+                (Some(hash), None)
+            }
+            _ => {
+                let original = try_hash_and_backup(
+                    &mod_file_path,
+                    p,
+                    journal,
+                    args.adopt_existing_backup,
+                    keep_original,
+                    dry_run,
+                )?;
+                match original {
+                    Some(o) => {
+                        plan::note(
+                            mod_path,
+                            Operation::Backup {
+                                path: mod_file_path.clone(),
+                            },
+                        );
+                        (Some(o.hash), o.contents)
+                    }
+                    None => (None, None),
+                }
+            }
+        };
+
+        if original_hash.is_none() {
+            debug!("Adding {}", mod_file_path.display());
+        } else {
+            debug!("Replacing {}", mod_file_path.display());
+        }
+
+        // Open and hash the mod file.
+        // If this isn't a dry run, overwrite the game file.
+        let full_mod_path = mod_path.join(mod_file_path.as_path());
+        let game_file_path = p.game_path(&mod_file_path);
+        let original_path = original_path(&mod_file_path, resuming, p);
+
+        // Remember if the original was executable - in the profile,
+        // and on the backup itself for `modman repair`,
+        // which has no profile entry to go on.
+        // The backup keeps the original's modification time too.
+        let (original_mode, original_size) = match original_hash {
+            Some(_) => (
+                executable_mode(&original_path)?,
+                Some(file_size(&original_path)?),
+            ),
+            None => (None, None),
+        };
+        if original_hash.is_some() {
+            progress::file(FileEvent::BackedUp, &mod_file_path);
+        }
+        if original_hash.is_some() && !adopted && !dry_run {
+            copy_mtime(&game_file_path, &backup_path)?;
+            if let Some(mode) = original_mode {
+                set_mode(&backup_path, mode)?;
+            }
+        }
+        // Note when the backup was last touched, so `modman check`
+        // can tell it hasn't been since without hashing it.
+        let backup_mtime = match original_hash {
+            Some(_) if p.quick_check && !dry_run => Some(file_mtime(&backup_path)?),
+            _ => None,
+        };
+        // Stage a copy of what we'd replace, to compare against.
+        let staging = args.stage.as_deref().filter(|_| !args.checking_stage);
+        if let (Some(stage_dir), Some(_)) = (staging, &original_hash) {
+            let staged = staged_path(stage_dir, STAGED_ORIGINALS_DIR, &mod_file_path);
+            create_staged_dirs(&staged)?;
+            copy_file(&original_path, &staged).with_context(|| {
+                format!(
+                    "Couldn't copy {} to {}",
+                    original_path.display(),
+                    staged.display()
+                )
+            })?;
+        }
+
+        let symlink = m.symlink(&mod_file_path)?;
+        let merge = merges.get(&mod_file_path).copied();
+
+        // When resuming, skip files the interrupted run already installed.
+        let already_installed = if resumed.is_some()
+            && symlink.is_none()
+            && merge.is_none()
+            && !patches.contains_key(&mod_file_path)
+            && game_file_path.is_file()
+        {
+            let mod_hash = hash_contents(&mut m.read_file(&mod_file_path)?)?;
+            if hash_file(&game_file_path)? == mod_hash {
+                Some(mod_hash)
             } else {
-                info!("Replacing {}", mod_file_path.display());
+                None
             }
+        } else {
+            None
+        };
+        // Anything else it added might be half-written; start it over.
+        if resumed == Some(JournalAction::Added)
+            && already_installed.is_none()
+            && !dry_run
+            && fs::symlink_metadata(&game_file_path).is_ok()
+        {
+            remove_file(&game_file_path)
+                .with_context(|| format!("Couldn't remove {}", game_file_path.display()))?;
+        }
 
-            // Open and hash the mod file.
-            // If this isn't a dry run, overwrite the game file.
-            let full_mod_path = mod_path.join(mod_file_path.as_path());
-            let mut mod_file_reader = m.read_file(&mod_file_path)?;
+        // What we journaled about this file has to be on disk
+        // before we change it in the game directory.
+        sync_journal(journal)?;
 
-            let game_file_path = mod_path_to_game_path(&mod_file_path, &p.root_directory);
+        // Links to install as links have no contents to copy.
+        if let Some(target) = symlink {
+            if let Some(stage_dir) = staging {
+                let staged = staged_path(stage_dir, STAGED_FILES_DIR, &mod_file_path);
+                debug!(
+                    "Staging {} (a link to {})",
+                    staged.display(),
+                    target.display()
+                );
+                create_staged_dirs(&staged)?;
+                make_symlink(&target, &staged)?;
+            } else if dry_run {
+                debug!(
+                    "Would link {} to {}",
+                    game_file_path.display(),
+                    target.display()
+                );
+            } else {
+                debug!(
+                    "Linking {} to {}",
+                    game_file_path.display(),
+                    target.display()
+                );
+                let game_file_dir = game_file_path.parent().unwrap();
+                fs::create_dir_all(game_file_dir).with_context(|| {
+                    format!("Couldn't create directory {}", game_file_dir.display())
+                })?;
+                history::note_file(&mod_file_path);
+                // (It's been backed up.)
+                if original_hash.is_some() && fs::symlink_metadata(&game_file_path).is_ok() {
+                    remove_file(&game_file_path)
+                        .with_context(|| format!("Couldn't remove {}", game_file_path.display()))?;
+                }
+                make_symlink(&target, &game_file_path)?;
+            }
+            plan::note(
+                mod_path,
+                Operation::Install {
+                    path: mod_file_path.clone(),
+                },
+            );
+            let size = if dry_run {
+                None
+            } else {
+                Some(file_size(&game_file_path)?)
+            };
+            let meta = ModFileMetadata {
+                mod_hash: hash_link_target(&target)?,
+                original_hash,
+                merge: None,
+                symlink: Some(target),
+                mode: None,
+                original_mode,
+                mod_file: None,
+                size,
+                original_size,
+                backup_mtime,
+                shared: false,
+                identical: false,
+            };
+            progress::file_done(FileEvent::Installed, &mod_file_path);
+            tx.send((mod_file_path.clone(), meta))
+                .expect("Couldn't send");
+            return Ok(());
+        }
+
+        // Patched and merged files are still the game's,
+        // so they keep its permissions.
+        let file_mode = if merge.is_some() || patches.contains_key(&mod_file_path) {
+            None
+        } else {
+            m.mode(&mod_file_path)?
+        };
+
+        let mod_hash = if let Some(mod_hash) = already_installed {
+            debug!("{} is already installed", game_file_path.display());
+            mod_hash
+        } else {
+            // Patch (or merge) before we (possibly) overwrite the game file below.
+            let mut mod_file_reader: Box<dyn Read + Send> = match patches.get(&mod_file_path) {
+                Some(patch) => {
+                    debug!(
+                        "Patching {} with {}",
+                        game_file_path.display(),
+                        patch.file.display()
+                    );
+                    let mut delta = m.read_file(&patch.file)?;
+                    let patched = match &original_contents {
+                        Some(original) => patch_contents(patch, original, &mut delta)?,
+                        None => apply_patch(patch, &original_path, &mut delta)?,
+                    };
+                    Box::new(io::Cursor::new(patched))
+                }
+                None => m.read_file(&mod_file_path)?,
+            };
+
+            if let Some(mode) = merge {
+                debug!("Merging {} ({:?})", full_mod_path.display(), mode);
+                let merged = match &original_contents {
+                    Some(original) => merge_into(mode, original, &mut mod_file_reader)
+                        .with_context(|| {
+                            format!("Couldn't merge into {}", game_file_path.display())
+                        })?,
+                    None => merge_into_game_file(mode, &original_path, &mut mod_file_reader)?,
+                };
+                mod_file_reader = Box::new(io::Cursor::new(merged));
+            }
+
+            // Dry runs show how they'd change (small) text files.
+            if dry_run && !plan::is_planning() && original_size.is_some_and(|s| s <= MAX_DIFF_SIZE)
+            {
+                let mut contents = Vec::new();
+                (&mut mod_file_reader)
+                    .take(MAX_DIFF_SIZE + 1)
+                    .read_to_end(&mut contents)?;
+                if contents.len() as u64 <= MAX_DIFF_SIZE {
+                    if let Some(diff) = diff_text(&mod_file_path, &original_path, &contents)? {
+                        diffs.lock().unwrap().insert(mod_file_path.clone(), diff);
+                    }
+                }
+                // Hash it all below, like we would otherwise.
+                mod_file_reader = Box::new(io::Cursor::new(contents).chain(mod_file_reader));
+            }
 
             let mut game_file: Box<dyn Write> =
-                if dry_run {
+                if let Some(stage_dir) = staging {
+                    let staged = staged_path(stage_dir, STAGED_FILES_DIR, &mod_file_path);
+                    debug!(
+                        "Staging {} as {}",
+                        full_mod_path.display(),
+                        staged.display()
+                    );
+                    Box::new(create_staged_file(&staged)?)
+                } else if dry_run {
                     debug!(
                         "Would install {} to {}",
                         full_mod_path.display(),
@@ -141,82 +1125,624 @@ fn apply_mod(mod_path: &Path, p: &mut Profile, dry_run: bool) -> Result<()> {
 
                     // Create any needed directory structure.
                     let game_file_dir = game_file_path.parent().unwrap();
-                    fs::create_dir_all(&game_file_dir).with_context(|| {
+                    fs::create_dir_all(game_file_dir).with_context(|| {
                         format!("Couldn't create directory {}", game_file_dir.display())
                     })?;
-                    Box::new(fs::File::create(&game_file_path).with_context(|| {
+                    history::note_file(&mod_file_path);
+                    Box::new(create_file(&game_file_path).with_context(|| {
                         format!("Couldn't overwrite {}", game_file_path.display())
                     })?)
                 };
 
-            let mod_hash = hash_and_write(&mut mod_file_reader, &mut game_file)?;
+            hash_and_write(&mut mod_file_reader, &mut game_file).with_context(|| {
+                format!(
+                    "Couldn't install {} from {}",
+                    mod_file_path.display(),
+                    mod_path.display()
+                )
+            })?
+        };
+        if let Some(stage_dir) = args.stage.as_deref().filter(|_| args.checking_stage) {
+            let staged = staged_path(stage_dir, STAGED_FILES_DIR, &mod_file_path);
+            if !staged.is_file() || hash_file(&staged)? != mod_hash {
+                fail!(
+                    Failure::VerificationFailed,
+                    "{} isn't what would be installed anymore",
+                    staged.display()
+                );
+            }
+        }
+        plan::note(
+            mod_path,
+            Operation::Install {
+                path: mod_file_path.clone(),
+            },
+        );
+        let mut size = None;
+        if dry_run {
+            // (We don't know how big patched and merged files will be.)
+            if merge.is_none() && !patches.contains_key(&mod_file_path) {
+                size = Some(m.size(&mod_file_path)?);
+            }
+        } else {
+            size = Some(file_size(&game_file_path)?);
+            if let Some(file_mode) = file_mode {
+                set_mode(&game_file_path, file_mode)?;
+            }
+            if keep_mtimes && merge.is_none() && !patches.contains_key(&mod_file_path) {
+                if let Some(mtime) = m.mtime(&mod_file_path)? {
+                    set_mtime(&game_file_path, mtime)?;
+                }
+            }
+        }
 
-            trace!(
-                "Mod file {} hashed to\n{:x}",
-                full_mod_path.display(),
-                mod_hash.bytes
-            );
+        trace!(
+            "Mod file {} hashed to\n{:x}",
+            full_mod_path.display(),
+            mod_hash.bytes
+        );
 
-            let meta = ModFileMetadata {
-                mod_hash,
-                original_hash,
-            };
+        let meta = ModFileMetadata {
+            mod_hash,
+            original_hash,
+            merge,
+            symlink: None,
+            mode: file_mode,
+            original_mode,
+            mod_file: None,
+            size,
+            original_size,
+            backup_mtime,
+            shared: false,
+            identical: false,
+        };
 
-            tx.send((mod_file_path.clone(), meta))
-                .expect("Couldn't send");
-            Ok(())
-        })?;
+        progress::file_done(FileEvent::Installed, &mod_file_path);
+        tx.send((mod_file_path.clone(), meta))
+            .expect("Couldn't send");
+        Ok(())
+    };
+
+    if m.streaming_only() {
+        let mut tx = tx;
+        for mod_file_path in mod_file_paths {
+            install(&mut tx, mod_file_path)?;
+        }
+    } else {
+        // par_bridge() hands files to workers in order,
+        // so they ask for them about as soon as they're prefetched.
+        mod_file_paths
+            .into_iter()
+            .par_bridge()
+            .try_for_each_with(tx, install)?;
+    }
 
     for path_and_meta in rx {
         manifest.files.insert(path_and_meta.0, path_and_meta.1);
     }
+    report_files(&manifest, &shared, &covered);
+    for diff in diffs.into_inner().unwrap().values() {
+        print_diff(diff)?;
+    }
+    report_identical_files(mod_path, &manifest, patches);
 
-    // Update our profile with a manifest of the mod we just applied.
-    p.mods.insert(mod_path.to_owned(), manifest);
+    // Layer the mod over the ones whose files it covered.
+    for (file, (below, _)) in &covered {
+        p.layers
+            .entry(path_key(file, p.case_insensitive))
+            .or_insert_with(|| vec![below.clone()])
+            .push(mod_path.to_owned());
+    }
 
-    // If it's not a dry run, overwrite the profile file
-    // after each mod we apply.
+    // If it's not a dry run, save the profile after each mod we apply.
     if !dry_run {
-        update_profile_file(&p)?;
+        if covered.is_empty() {
+            store.save_mod(mod_path, &manifest)?;
+        } else {
+            // Save the mod and its layers together,
+            // so the profile never has one without the other.
+            store.defer_writes()?;
+            store.save_mod(mod_path, &manifest)?;
+            store.save_settings(p)?;
+            store.flush()?;
+        }
         // With that successfully done, we can axe the journal.
         delete_journal(journal_mutex.into_inner().unwrap())?;
     }
 
+    // Update our profile with a manifest of the mod we just applied.
+    p.mods.insert(mod_path.to_owned(), manifest);
+
+    run_hooks(
+        Event::PostInstall,
+        mod_path,
+        m.version(),
+        mod_hooks,
+        p,
+        dry_run,
+    )
+}
+
+/// For dry runs: diffs the game file the mod file would replace
+/// against the mod file's contents, if they're both text.
+fn diff_text(
+    mod_file_path: &Path,
+    original_path: &Path,
+    contents: &[u8],
+) -> Result<Option<String>> {
+    let original = fs::read(original_path)
+        .with_context(|| format!("Couldn't read {}", original_path.display()))?;
+    let (old, new) = match (as_text(&original), as_text(contents)) {
+        (Some(old), Some(new)) if old != new => (old, new),
+        _ => return Ok(None),
+    };
+    Ok(Some(unified_diff(
+        old,
+        new,
+        &format!("a/{}", mod_file_path.display()),
+        &format!("b/{}", mod_file_path.display()),
+    )))
+}
+
+/// For `--skip-identical`: if the game already has the mod file's exact
+/// contents (and permissions) at its path, returns metadata for leaving it be.
+fn identical_to_game_file(
+    mod_file_path: &Path,
+    m: &dyn Mod,
+    p: &Profile,
+) -> Result<Option<ModFileMetadata>> {
+    // Links, merges, and patches aren't simple copies of the mod file.
+    if m.symlink(mod_file_path)?.is_some()
+        || m.config().merge.contains_key(mod_file_path)
+        || m.config().patch.contains_key(mod_file_path)
+    {
+        return Ok(None);
+    }
+    let game_file_path = p.game_path(mod_file_path);
+    if is_symlink(&game_file_path) || !game_file_path.is_file() {
+        return Ok(None);
+    }
+    let mode = m.mode(mod_file_path)?;
+    if mode.is_some() && mode != executable_mode(&game_file_path)? {
+        return Ok(None);
+    }
+    let size = file_size(&game_file_path)?;
+    // Don't read either file if they can't be the same.
+    if m.size(mod_file_path)? != size {
+        return Ok(None);
+    }
+    let mod_hash = hash_contents(&mut m.read_file(mod_file_path)?)?;
+    if hash_file(&game_file_path)? != mod_hash {
+        return Ok(None);
+    }
+    Ok(Some(ModFileMetadata {
+        mod_hash,
+        original_hash: None,
+        merge: None,
+        symlink: None,
+        mode,
+        original_mode: None,
+        mod_file: None,
+        size: Some(size),
+        original_size: None,
+        backup_mtime: None,
+        shared: false,
+        identical: true,
+    }))
+}
+
+/// Says what we did with each of the mod's files, in order.
+/// (They're installed in parallel, so this waits until they're all done.)
+fn report_files(manifest: &ModManifest, shared: &Claimed, covered: &Claimed) {
+    for (file, meta) in &manifest.files {
+        if let Some((owner, _)) = shared.get(file) {
+            info!("Sharing {} with {}", file.display(), owner.display());
+        } else if let Some((below, _)) = covered.get(file) {
+            info!("Installing {} over {}'s", file.display(), below.display());
+        } else if meta.identical {
+            // (See report_identical_files())
+            continue;
+        } else if meta.original_hash.is_none() {
+            info!("Adding {}", file.display());
+        } else {
+            info!("Replacing {}", file.display());
+        }
+    }
+}
+
+/// Lets the user know about mod files that were identical to the game files
+/// they replaced, since backing those up and installing them is wasted work
+/// (and might mean the mod isn't doing what its author thinks it is).
+fn report_identical_files(
+    mod_path: &Path,
+    manifest: &ModManifest,
+    patches: &BTreeMap<PathBuf, PatchConfig>,
+) {
+    let skipped = manifest.files.values().filter(|m| m.identical).count();
+    if skipped > 0 {
+        info!(
+            "Left {} game files alone that were identical to {}'s",
+            skipped,
+            mod_path.display()
+        );
+    }
+
+    let replaced_with_same: Vec<&PathBuf> = manifest
+        .files
+        .iter()
+        .filter(|(f, m)| {
+            m.merge.is_none()
+                && !patches.contains_key(*f)
+                && m.original_hash.as_ref() == Some(&m.mod_hash)
+        })
+        .map(|(f, _)| f)
+        .collect();
+    if !replaced_with_same.is_empty() {
+        let mut message = format!(
+            "{} of {}'s files were identical to the game files they replaced:",
+            replaced_with_same.len(),
+            mod_path.display()
+        );
+        for f in &replaced_with_same {
+            message += &format!("\n\t{}", f.display());
+        }
+        message += "\n(Pass --skip-identical to leave files like these alone.)";
+        warn!("{}", message);
+    }
+}
+
+/// Makes sure every root the mod installs to is in the profile.
+fn check_roots<'a, I: Iterator<Item = &'a PathBuf>>(
+    mod_path: &Path,
+    mod_file_paths: &'a [PathBuf],
+    patch_targets: I,
+    p: &Profile,
+) -> Result<()> {
+    for path in mod_file_paths.iter().chain(patch_targets) {
+        if let (Some(name), _) = split_root(path) {
+            if !p.roots.contains_key(name) {
+                bail!(
+                    "{} installs {} to the {} root, which the profile doesn't have.\n\
+                     Add it with `modman roots add {} <DIR>`.",
+                    mod_path.display(),
+                    path.display(),
+                    name,
+                    name
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Makes sure the interrupted `modman add` we're resuming was for this mod
+/// (or at least, only touched files it installs).
+fn check_resumable(
+    mod_path: &Path,
+    mod_file_paths: &[PathBuf],
+    journal: &JournalMap,
+) -> Result<()> {
+    if let Some(header) = read_journal_header()? {
+        if header.mod_path != mod_path {
+            bail!(
+                "The interrupted `modman add` was adding {}, not {}.\n\
+                 Resume that one, or run `modman repair` to undo it.",
+                header.mod_path.display(),
+                mod_path.display()
+            );
+        }
+    }
+    if let Some(stray) = journal.keys().find(|f| !mod_file_paths.contains(f)) {
+        bail!(
+            "The interrupted `modman add` changed {}, which {} doesn't install.\n\
+             Was it adding a different mod? If so, resume that one, \
+             or run `modman repair` to undo it.",
+            stray.display(),
+            mod_path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Where to find the game's original copy of a file
+/// (usually in the game directory, but if we're resuming
+/// and already backed it up, the interrupted run might have replaced it).
+fn original_path(mod_file_path: &Path, resuming: Option<&JournalMap>, p: &Profile) -> PathBuf {
+    let backup_path = mod_path_to_backup_path(mod_file_path);
+    let replaced = resuming
+        .and_then(|j| j.get(mod_file_path))
+        .map(|e| e.action)
+        == Some(JournalAction::Replaced);
+    if replaced && backup_path.exists() {
+        backup_path
+    } else {
+        p.game_path(mod_file_path)
+    }
+}
+
+/// Returns the directories installing the given files would create
+/// in the game directory.
+fn new_directories(mod_file_paths: &[PathBuf], p: &Profile) -> BTreeSet<PathBuf> {
+    mod_file_paths
+        .iter()
+        .flat_map(|f| f.ancestors().skip(1))
+        // (Not the roots themselves)
+        .filter(|d| !split_root(d).1.as_os_str().is_empty())
+        .filter(|d| fs::symlink_metadata(p.game_path(d)).is_err())
+        .map(Path::to_owned)
+        .collect()
+}
+
+/// Makes sure no other mod has the alias
+/// (or could be confused with it, by the name of the mod).
+pub fn check_alias(alias: &str, p: &Profile) -> Result<()> {
+    if alias.is_empty() {
+        bail!("A mod's alias can't be empty");
+    }
+    for (mod_path, manifest) in &p.mods {
+        let taken = manifest
+            .alias
+            .as_deref()
+            .is_some_and(|a| a.eq_ignore_ascii_case(alias));
+        if taken || mod_name(mod_path).eq_ignore_ascii_case(alias) {
+            fail!(
+                Failure::Conflict,
+                "{} is already called {}",
+                mod_path.display(),
+                alias
+            );
+        }
+    }
     Ok(())
 }
 
+/// Returns the paths of the game files a mod would install:
+/// its files, except for binary patches, which install the files they patch.
+pub fn install_paths(mod_path: &Path, m: &dyn Mod) -> Result<Vec<PathBuf>> {
+    let mut paths = m.paths()?;
+    let patches = &m.config().patch;
+
+    for patch in patches.values() {
+        let i = paths.iter().position(|f| *f == patch.file).ok_or_else(|| {
+            format_err!(
+                "{}'s {} lists a patch {}, but it doesn't have that file",
+                mod_path.display(),
+                MOD_CONFIG_NAME,
+                patch.file.display()
+            )
+        })?;
+        paths.swap_remove(i);
+    }
+    for target in patches.keys() {
+        if paths.contains(target) {
+            bail!(
+                "{} both patches and replaces {}",
+                mod_path.display(),
+                target.display()
+            );
+        }
+        paths.push(target.clone());
+    }
+    Ok(paths)
+}
+
+/// Files other mods installed, with the mod that has each and its metadata
+type Claimed = BTreeMap<PathBuf, (PathBuf, ModFileMetadata)>;
+
 /// Checks the given profile for file paths from a mod we wish to apply,
 /// and returns an error if it already contains them.
+///
+/// Files that another mod already installed with the same contents
+/// aren't conflicts; we return those (along with the mod that has them)
+/// so the mods can share them. With `overwrite`, neither are other files;
+/// we return those too, so the mod can be installed over them.
 fn check_for_profile_conflicts(
     mod_path: &Path,
+    m: &dyn Mod,
     mod_file_paths: &[PathBuf],
     p: &Profile,
-) -> Result<()> {
-    for mod_file_path in mod_file_paths {
-        for (active_mod_name, active_mod) in &p.mods {
-            if active_mod.files.contains_key(&*mod_file_path) {
-                bail!(
-                    "{} from {} would overwrite the same file from {}",
+    overwrite: bool,
+) -> Result<(Claimed, Claimed)> {
+    let case_insensitive = p.case_insensitive;
+
+    // If the game directory ignores case, a mod could even conflict with itself.
+    if case_insensitive {
+        let mut seen: BTreeMap<PathBuf, &Path> = BTreeMap::new();
+        for mod_file_path in mod_file_paths {
+            if let Some(other) = seen.insert(fold_case(mod_file_path), mod_file_path) {
+                fail!(
+                    Failure::Conflict,
+                    "{} and {} from {} would overwrite each other \
+                     in a case-insensitive game directory",
+                    other.display(),
                     mod_file_path.display(),
-                    mod_path.display(),
-                    active_mod_name.display()
+                    mod_path.display()
                 );
             }
         }
     }
+
+    let installed_by_key = p.installed_by_key();
+    let mut shared = Claimed::new();
+    let mut covered = Claimed::new();
+    for mod_file_path in mod_file_paths {
+        let key = path_key(mod_file_path, case_insensitive);
+        let installed = match installed_by_key.get(&key) {
+            Some(installed) => installed,
+            None => continue,
+        };
+        // If mods were layered over the file, the last one has it installed.
+        let layers = p.layers.get(&key);
+        let active = match layers {
+            Some(layers) => installed
+                .iter()
+                .find(|(active_mod_name, _, _)| layers.last() == Some(*active_mod_name)),
+            None => installed.first(),
+        };
+        let (active_mod_name, _, active_meta) = match active {
+            Some(a) => *a,
+            None => continue,
+        };
+        // (Layered files can't be shared too; there'd be no telling
+        // which mods to put back when.)
+        if layers.is_none() && is_same_file(mod_file_path, m, active_meta)? {
+            shared.insert(
+                mod_file_path.clone(),
+                (active_mod_name.clone(), active_meta.clone()),
+            );
+            continue;
+        }
+        if !overwrite {
+            fail!(
+                Failure::Conflict,
+                "{} from {} would overwrite the same file from {}\n\
+                 (Pass --overwrite to install it over that one.)",
+                mod_file_path.display(),
+                mod_path.display(),
+                active_mod_name.display()
+            );
+        }
+        if layers.is_none() && installed.len() > 1 {
+            fail!(
+                Failure::Conflict,
+                "{} from {} would overwrite the same file from {}, which other mods share. \
+                 Only a file one mod installed can be overwritten.",
+                mod_file_path.display(),
+                mod_path.display(),
+                active_mod_name.display()
+            );
+        }
+        let config = m.config();
+        if active_meta.symlink.is_some()
+            || active_meta.merge.is_some()
+            || config.merge.contains_key(mod_file_path)
+            || config.patch.contains_key(mod_file_path)
+            || m.symlink(mod_file_path)?.is_some()
+        {
+            fail!(
+                Failure::Conflict,
+                "{} from {} would overwrite the same file from {}, \
+                 but links, merges, and patches can't be installed over each other.",
+                mod_file_path.display(),
+                mod_path.display(),
+                active_mod_name.display()
+            );
+        }
+        covered.insert(
+            mod_file_path.clone(),
+            (active_mod_name.clone(), active_meta.clone()),
+        );
+    }
+    Ok((shared, covered))
+}
+
+/// Returns true if the mod would install exactly what another mod
+/// already installed at the same path.
+fn is_same_file(mod_file_path: &Path, m: &dyn Mod, installed: &ModFileMetadata) -> Result<bool> {
+    let config = m.config();
+    // Links, merges, and patches depend on more than the mod file.
+    if installed.symlink.is_some()
+        || installed.merge.is_some()
+        || config.merge.contains_key(mod_file_path)
+        || config.patch.contains_key(mod_file_path)
+        || m.symlink(mod_file_path)?.is_some()
+    {
+        return Ok(false);
+    }
+    // Sizes are a quick way to rule it out before hashing.
+    if let Some(size) = installed.size {
+        if size != m.size(mod_file_path)? {
+            return Ok(false);
+        }
+    }
+    Ok(hash_contents(&mut m.read_file(mod_file_path)?)? == installed.mod_hash)
+}
+
+/// For `--overwrite`: keeps the file another mod installed (which `below`
+/// describes) in covered/, so it can be put back once the mod going over it
+/// is removed. Mods with the same file share its copy there.
+fn cover_file(
+    mod_file_path: &Path,
+    below: &ModFileMetadata,
+    p: &Profile,
+    journal: &Mutex<Box<dyn Journal>>,
+    dry_run: bool,
+) -> Result<()> {
+    journal.lock().unwrap().cover_file(mod_file_path)?;
+    let covered = covered_path(&below.mod_hash);
+    if dry_run || covered.exists() {
+        return Ok(());
+    }
+
+    let game_file_path = p.game_path(mod_file_path);
+    debug!(
+        "Keeping {} as {}",
+        game_file_path.display(),
+        covered.display()
+    );
+    let mut game_file = fs::File::open(&game_file_path)
+        .with_context(|| format!("Couldn't open {}", game_file_path.display()))?;
+    let temp_file_path = mod_path_to_temp_path(mod_file_path);
+    let hash = hash_and_write_temporary(&temp_file_path, &mut game_file)?;
+    if hash != below.mod_hash {
+        remove_file(&temp_file_path)
+            .with_context(|| format!("Couldn't remove {}", temp_file_path.display()))?;
+        bail!(
+            "{} isn't the file that was installed there. \
+             Run `modman update` first, then try again.",
+            game_file_path.display()
+        );
+    }
+    copy_mtime(&game_file_path, &temp_file_path)?;
+    fs::create_dir_all(covered_dir())
+        .with_context(|| format!("Couldn't create directory {}", covered_dir().display()))?;
+    replace_file(&temp_file_path, &covered).with_context(|| {
+        format!(
+            "Couldn't rename {} to {}",
+            temp_file_path.display(),
+            covered.display()
+        )
+    })?;
     Ok(())
 }
 
+/// A game file we're replacing, once it's backed up
+struct Original {
+    hash: FileHash,
+    /// Its contents, if we were asked to keep them (to patch or merge into)
+    contents: Option<Vec<u8>>,
+}
+
+/// Reads from the inner reader, keeping a copy of everything it reads
+/// if it has somewhere to.
+struct KeepingReader<R> {
+    inner: R,
+    kept: Option<Vec<u8>>,
+}
+
+impl<R: Read> Read for KeepingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        if let Some(kept) = &mut self.kept {
+            kept.extend_from_slice(&buf[..count]);
+        }
+        Ok(count)
+    }
+}
+
 /// Given a mod file's path, back up the game file if one exists.
-/// Returns the hash of the game file, or None if no file existed at that path.
+/// Returns its hash (and contents, if `keep_contents` is set),
+/// or None if no file existed at that path.
 /// If dry_run is set, just hash and don't actually backup.
 fn try_hash_and_backup(
     mod_file_path: &Path,
     p: &Profile,
     journal: &Mutex<Box<dyn Journal>>,
+    adopt_existing_backup: bool,
+    keep_contents: bool,
     dry_run: bool,
-) -> Result<Option<FileHash>> {
-    let game_file_path = mod_path_to_game_path(mod_file_path, &p.root_directory);
+) -> Result<Option<Original>> {
+    let game_file_path = p.game_path(mod_file_path);
 
     // Try to open a file in the game directory at mod_file_path,
     // to see if it exists.
@@ -237,34 +1763,50 @@ fn try_hash_and_backup(
                     .context(format!("Couldn't open {}", game_file_path.display())))
             }
         }
-        Ok(mut game_file) => {
+        Ok(game_file) => {
             journal.lock().unwrap().replace_file(mod_file_path)?;
 
+            let mut reader = KeepingReader {
+                inner: game_file,
+                kept: keep_contents.then(Vec::new),
+            };
             let hash = if !dry_run {
                 debug!("Backing up {}", game_file_path.display());
-                hash_and_backup(mod_file_path, &mut game_file)
+                hash_and_backup(mod_file_path, &mut reader, adopt_existing_backup)
             } else {
-                hash_contents(&mut game_file)
+                hash_contents(&mut reader)
             }?;
             trace!(
                 "Game file {} hashed to\n{:x}",
                 game_file_path.display(),
                 hash.bytes
             );
-            Ok(Some(hash))
+            journal.lock().unwrap().backed_up(mod_file_path, &hash)?;
+            Ok(Some(Original {
+                hash,
+                contents: reader.kept,
+            }))
         }
     }
 }
 
 /// Given a mod file's path and a reader of the game file it's replacing,
 /// backup said game file and return its hash.
-fn hash_and_backup<R: Read>(mod_file_path: &Path, reader: &mut R) -> Result<FileHash> {
+///
+/// If there's already a backup, we fail unless we're told to adopt it,
+/// in which case we keep it if it has the same contents as the game file,
+/// and move it to the quarantine directory if it doesn't.
+fn hash_and_backup<R: Read>(
+    mod_file_path: &Path,
+    reader: &mut R,
+    adopt_existing_backup: bool,
+) -> Result<FileHash> {
     // First, copy the file to a temporary location, hashing it as we go.
     let temp_file_path = mod_path_to_temp_path(mod_file_path);
     let temp_hash = hash_and_write_temporary(&temp_file_path, reader)?;
 
     // Next, create any needed directory structure.
-    let mut backup_file_dir = PathBuf::from(BACKUP_PATH);
+    let mut backup_file_dir = backup_dir();
     if let Some(parent) = mod_file_path.parent() {
         backup_file_dir.push(parent);
     }
@@ -291,11 +1833,27 @@ fn hash_and_backup<R: Read>(mod_file_path: &Path, reader: &mut R) -> Result<File
     //    already exists, so we'd have to write OS-specific code for
     //    Linux, Windows, and <other POSIX friends>.
     if backup_path.exists() {
-        // TODO: Offer corrective action once `modman rescue`
-        // or whatever we want to call it exists.
-        bail!(
-            "{} already exists (was `modman add` previously interrupted?)",
-            backup_path.display()
+        // (check_for_leftover_backups() should have caught this.)
+        if !adopt_existing_backup {
+            bail!(
+                "{} already exists (was `modman add` previously interrupted?)",
+                backup_path.display()
+            );
+        }
+        if hash_file(&backup_path)? == temp_hash {
+            info!(
+                "{} already exists and matches the game's file; using it",
+                backup_path.display()
+            );
+            remove_file(&temp_file_path)
+                .with_context(|| format!("Couldn't remove {}", temp_file_path.display()))?;
+            return Ok(temp_hash);
+        }
+        let quarantined = quarantine(&backup_path, mod_file_path)?;
+        warn!(
+            "{} already existed, but didn't match the game's file. Moved it to {}",
+            backup_path.display(),
+            quarantined.display()
         );
     }
 
@@ -307,7 +1865,7 @@ fn hash_and_backup<R: Read>(mod_file_path: &Path, reader: &mut R) -> Result<File
 
     // Move the backup from the temporary location to its final spot
     // in the backup directory.
-    fs::rename(&temp_file_path, &backup_path).with_context(|| {
+    replace_file(&temp_file_path, &backup_path).with_context(|| {
         format!(
             "Couldn't rename {} to {}",
             temp_file_path.display(),
@@ -318,6 +1876,74 @@ fn hash_and_backup<R: Read>(mod_file_path: &Path, reader: &mut R) -> Result<File
     Ok(temp_hash)
 }
 
+/// Fails if there are already backups of the game files a mod would replace,
+/// before we start (and leave a journal that `modman repair` would use
+/// to "restore" them).
+///
+/// They're usually left over from an interrupted `modman add`
+/// whose journal is gone, so say if they still match the game's files.
+fn check_for_leftover_backups(mod_file_paths: &[PathBuf], p: &Profile) -> Result<()> {
+    // If the journal's still around, that's for `modman repair`,
+    // which creating a new journal will point out.
+    if get_journal_path().exists() {
+        return Ok(());
+    }
+    let mut leftovers = String::new();
+    for mod_file_path in mod_file_paths {
+        let backup_path = mod_path_to_backup_path(mod_file_path);
+        let game_path = p.game_path(mod_file_path);
+        if !backup_path.exists() || !game_path.exists() {
+            continue;
+        }
+        let matches = hash_file(&backup_path)? == hash_file(&game_path)?;
+        leftovers += &format!(
+            "\n\t{} ({})",
+            backup_path.display(),
+            if matches {
+                "matches the game's file"
+            } else {
+                "doesn't match the game's file"
+            }
+        );
+    }
+    if !leftovers.is_empty() {
+        bail!(
+            "There are already backups of files this would replace \
+             (was `modman add` previously interrupted?):{}\n\
+             Pass --adopt-existing-backup to keep the ones that match the game's files \
+             and move the rest aside to {}/.",
+            leftovers,
+            quarantine_dir().display()
+        );
+    }
+    Ok(())
+}
+
+/// Moves a backup we don't know what to do with out of the way,
+/// to the same path in the quarantine directory
+/// (with a number on the end if something's already there).
+fn quarantine(backup_path: &Path, mod_file_path: &Path) -> Result<PathBuf> {
+    let mut quarantined = quarantine_dir().join(mod_file_path);
+    let mut n = 1;
+    while quarantined.exists() {
+        let mut name = mod_file_path.file_name().unwrap().to_owned();
+        name.push(format!(".{}", n));
+        quarantined.set_file_name(name);
+        n += 1;
+    }
+    let dir = quarantined.parent().unwrap();
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Couldn't create directory {}", dir.display()))?;
+    rename_file(backup_path, &quarantined).with_context(|| {
+        format!(
+            "Couldn't move {} to {}",
+            backup_path.display(),
+            quarantined.display()
+        )
+    })?;
+    Ok(quarantined)
+}
+
 /// Given a path for a temporary file and a buffered reader of the game file it's replacing,
 /// copy the game file to our temp directory,
 /// then return its hash
@@ -329,12 +1955,12 @@ fn hash_and_write_temporary<R: Read>(temp_file_path: &Path, reader: &mut R) -> R
 
     // Create temporary subdirectories as needed
     if let Some(parent) = temp_file_path.parent() {
-        fs::create_dir_all(&parent)
+        fs::create_dir_all(parent)
             .with_context(|| format!("Couldn't create temp directory {}", parent.display()))?;
     }
 
     // Because it's a temp file, we're fine if this truncates an existing file.
-    let mut temp_file = fs::File::create(&temp_file_path)
+    let mut temp_file = fs::File::create(temp_file_path)
         .with_context(|| format!("Couldn't create {}", temp_file_path.display()))?;
 
     let hash = hash_and_write(reader, &mut temp_file)?;
@@ -0,0 +1,306 @@
+//! Rebuilding a lost profile from what's left (`modman init --adopt`):
+//! the backup directory, the game files, and the mods that were installed.
+//!
+//! A mod is adopted if every file it would install is in the game directory
+//! with the mod's contents. Backups at those files' paths are taken as the
+//! game files they replaced. Mods with files missing or changed are left out,
+//! since we can't tell what state they're in.
+//! Directories with nothing but adopted mods' files in them are taken
+//! as ones those mods created.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use log::*;
+use rayon::prelude::*;
+
+use crate::add::install_paths;
+use crate::file_utils::*;
+use crate::journal::get_journal_path;
+use crate::mod_config::SymlinkMode;
+use crate::modification::*;
+use crate::profile::*;
+
+/// What we found at one of a mod's file paths
+enum Found {
+    /// The mod's file is installed there.
+    Installed(ModFileMetadata),
+    /// Something else (or nothing) is there.
+    Missing,
+}
+
+/// Adds each of the given mods to the profile that's installed,
+/// along with the backups of the game files it replaced.
+pub fn adopt_mods(p: &mut Profile, mod_names: &[PathBuf]) -> Result<()> {
+    if get_journal_path().exists() {
+        warn!(
+            "An interrupted `modman add` left its journal in the backup directory.\n\
+             Run `modman repair` once the profile is created."
+        );
+    }
+
+    let ci = p.case_insensitive;
+    let backup_dir = &backup_dir();
+    // Backups by their (case-folded, if need be) paths
+    let backups: BTreeMap<PathBuf, PathBuf> = if backup_dir.is_dir() {
+        collect_file_paths_in_dir(backup_dir)?
+            .into_iter()
+            .map(|f| (path_key(&f, ci), f))
+            .collect()
+    } else {
+        BTreeMap::new()
+    };
+    // Files adopted mods installed (by their case-folded path, if need be),
+    // so later mods with the same files share them.
+    let mut claimed: BTreeMap<PathBuf, FileHash> = BTreeMap::new();
+    let mut not_installed = String::new();
+
+    for mod_name in mod_names {
+        if p.mods.contains_key(mod_name) {
+            bail!("{} was given more than once", mod_name.display());
+        }
+        info!("Looking for {}'s files...", mod_name.display());
+        let m = open_mod(mod_name, p.unicode_form, None)?;
+        let mod_file_paths = install_paths(mod_name, &*m)?;
+
+        let found: Vec<(PathBuf, Found)> = mod_file_paths
+            .into_par_iter()
+            .map(|f| {
+                let found = find_installed(&f, &*m, p, &backups, &claimed)?;
+                Ok((f, found))
+            })
+            .collect::<Result<_>>()?;
+
+        let total = found.len();
+        let missing: Vec<&PathBuf> = found
+            .iter()
+            .filter(|(_, found)| matches!(found, Found::Missing))
+            .map(|(f, _)| f)
+            .collect();
+        if !missing.is_empty() {
+            debug!(
+                "{}'s files that aren't installed: {:?}",
+                mod_name.display(),
+                missing
+            );
+            not_installed += &format!(
+                "\n\t{} ({} of {} files aren't installed)",
+                mod_name.display(),
+                missing.len(),
+                total
+            );
+            continue;
+        }
+
+        let mut files = BTreeMap::new();
+        for (f, found) in found {
+            if let Found::Installed(meta) = found {
+                claimed
+                    .entry(path_key(&f, ci))
+                    .or_insert_with(|| meta.mod_hash.clone());
+                files.insert(f, meta);
+            }
+        }
+        let backed_up = files.values().filter(|m| m.original_hash.is_some()).count();
+        println!(
+            "Adopted {} ({} files, {} backed up)",
+            mod_name.display(),
+            total,
+            backed_up
+        );
+        let mut manifest = ModManifest {
+            version: m.version().clone(),
+            version_string: Some(m.version_string().to_owned())
+                .filter(|raw| *raw != m.version().to_string()),
+            source: None,
+            archive_hash: None,
+            archive_size: None,
+            archive_mtime: None,
+            requires: m.config().requires.clone(),
+            conflicts: m.config().conflicts.clone(),
+            hooks: m.config().hooks.clone(),
+            keep_mtimes: false,
+            root: None,
+            alias: None,
+            tags: m.config().tags.iter().cloned().collect(),
+            // (Filled in below, once we know every mod's files.)
+            directories: BTreeSet::new(),
+            files,
+        };
+        record_mod_archive(mod_name, &mut manifest)?;
+        p.mods.insert(mod_name.clone(), manifest);
+    }
+
+    if !not_installed.is_empty() {
+        warn!(
+            "These mods were left out, since they aren't (completely) installed:{}\n\
+             Once the profile is created, `modman add` them again if you'd like.",
+            not_installed
+        );
+    }
+
+    find_directories(p, mod_names)?;
+
+    let adopted: BTreeSet<PathBuf> = p
+        .mods
+        .values()
+        .flat_map(|m| m.files.iter())
+        .filter(|(_, meta)| meta.original_hash.is_some())
+        .map(|(f, _)| path_key(f, ci))
+        .collect();
+    let unclaimed: Vec<&PathBuf> = backups
+        .iter()
+        .filter(|(key, _)| !adopted.contains(*key))
+        .map(|(_, b)| b)
+        .collect();
+    if !unclaimed.is_empty() {
+        let mut warning = "These backups don't belong to any adopted mod:".to_owned();
+        for b in &unclaimed {
+            warning += &format!("\n\t{}", mod_path_to_backup_path(b).display());
+        }
+        warning += "\nAdopt the mods that replaced them, \
+                    or put them back in the game directory yourself.";
+        warn!("{}", warning);
+    }
+    Ok(())
+}
+
+/// Gives each adopted mod the directories its files are in
+/// that only have adopted mods' files in them,
+/// which are presumably the ones installing it created.
+/// (Directories with several mods' files go to the first of them.)
+fn find_directories(p: &mut Profile, mod_names: &[PathBuf]) -> Result<()> {
+    let ci = p.case_insensitive;
+    let installed: BTreeSet<PathBuf> = p
+        .mods
+        .values()
+        .flat_map(|m| m.files.keys())
+        .map(|f| path_key(f, ci))
+        .collect();
+    let mut seen = BTreeSet::new();
+    for mod_name in mod_names {
+        let manifest = match p.mods.get(mod_name) {
+            Some(m) => m,
+            None => continue,
+        };
+        let mut directories = BTreeSet::new();
+        let candidates = manifest
+            .files
+            .keys()
+            .flat_map(|f| f.ancestors().skip(1))
+            // (Not the roots themselves)
+            .filter(|d| !split_root(d).1.as_os_str().is_empty());
+        for dir in candidates {
+            if !seen.insert(path_key(dir, ci)) {
+                continue;
+            }
+            let game_dir = p.game_path(dir);
+            let only_installed = collect_mod_file_paths_in_dir(&game_dir, SymlinkMode::Preserve)?
+                .iter()
+                .all(|f| installed.contains(&path_key(&dir.join(f), ci)));
+            if only_installed {
+                directories.insert(dir.to_owned());
+            }
+        }
+        p.mods.get_mut(mod_name).unwrap().directories = directories;
+    }
+    Ok(())
+}
+
+/// Checks if the mod's file is installed at its path,
+/// and if so, returns its metadata (and that of its backup, if there is one).
+fn find_installed(
+    mod_file_path: &Path,
+    m: &dyn Mod,
+    p: &Profile,
+    backups: &BTreeMap<PathBuf, PathBuf>,
+    claimed: &BTreeMap<PathBuf, FileHash>,
+) -> Result<Found> {
+    let key = path_key(mod_file_path, p.case_insensitive);
+    let game_path = p.game_path(mod_file_path);
+    let backup_path = backups.get(&key).map(|b| mod_path_to_backup_path(b));
+    let config = m.config();
+    let merge = config.merge.get(mod_file_path).copied();
+    let patched = config.patch.contains_key(mod_file_path);
+    let symlink = m.symlink(mod_file_path)?;
+
+    let (mod_hash, mode) = if let Some(target) = &symlink {
+        if fs::read_link(&game_path).ok().as_ref() != Some(target) {
+            return Ok(Found::Missing);
+        }
+        (hash_link_target(target)?, None)
+    } else if is_symlink(&game_path) || !game_path.is_file() {
+        return Ok(Found::Missing);
+    } else if let (true, Some(backup_path)) = (merge.is_some() || patched, &backup_path) {
+        // We'd have to redo the merge (or patch) to know what it made,
+        // so go with the game's file, so long as it isn't the original.
+        let game_hash = hash_file(&game_path)?;
+        if game_hash == hash_file(backup_path)? {
+            return Ok(Found::Missing);
+        }
+        (game_hash, None)
+    } else if patched {
+        // (Patches need an original.)
+        return Ok(Found::Missing);
+    } else {
+        if file_size(&game_path)? != m.size(mod_file_path)? {
+            return Ok(Found::Missing);
+        }
+        let mod_hash = hash_contents(&mut m.read_file(mod_file_path)?)?;
+        if hash_file(&game_path)? != mod_hash {
+            return Ok(Found::Missing);
+        }
+        let mode = match merge {
+            Some(_) => None,
+            None => m.mode(mod_file_path)?,
+        };
+        (mod_hash, mode)
+    };
+    let size = Some(file_size(&game_path)?);
+
+    // An earlier mod installed the same file; it owns it (and its backup).
+    if claimed.get(&key) == Some(&mod_hash) {
+        return Ok(Found::Installed(ModFileMetadata {
+            mod_hash,
+            original_hash: None,
+            merge: None,
+            symlink: None,
+            mode,
+            original_mode: None,
+            mod_file: None,
+            size,
+            original_size: None,
+            backup_mtime: None,
+            shared: true,
+            identical: false,
+        }));
+    } else if claimed.contains_key(&key) {
+        return Ok(Found::Missing);
+    }
+
+    let (original_hash, original_mode, original_size, backup_mtime) = match &backup_path {
+        Some(backup_path) => (
+            Some(hash_file(backup_path)?),
+            executable_mode(backup_path)?,
+            Some(file_size(backup_path)?),
+            Some(file_mtime(backup_path)?).filter(|_| p.quick_check),
+        ),
+        None => (None, None, None, None),
+    };
+    Ok(Found::Installed(ModFileMetadata {
+        mod_hash,
+        original_hash,
+        merge,
+        symlink,
+        mode,
+        original_mode,
+        mod_file: None,
+        size,
+        original_size,
+        backup_mtime,
+        shared: false,
+        identical: false,
+    }))
+}
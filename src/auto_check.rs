@@ -0,0 +1,120 @@
+//! A quick check before `add`, `remove`, and `update` change anything,
+//! so that they don't pile more changes on top of an interrupted command
+//! or files something else changed.
+//!
+//! It's much faster than `modman check` because it doesn't hash anything:
+//! it just looks for a leftover journal and compares every installed file's
+//! (and backup's) size to what the profile recorded.
+
+use std::path::PathBuf;
+
+use anyhow::*;
+use log::*;
+use structopt::*;
+
+use crate::failure::*;
+use crate::file_utils::*;
+use crate::journal::get_journal_path;
+use crate::profile::*;
+
+/// Shows or sets whether to check for problems before changing files
+///
+/// When it's on, `modman add`, `remove`, and `update` first make sure
+/// no earlier `modman add` was interrupted, and that installed mod files
+/// and backups are still the sizes they were (without hashing them,
+/// so it's quick). If not, they stop unless given --force.
+/// (`update` just warns about changed files, since it's how you fix them.)
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    #[structopt(name = "SETTING", possible_values = &["on", "off"])]
+    setting: Option<String>,
+}
+
+impl Args {
+    /// Returns true if we'll change the profile (not just show it).
+    pub fn is_changing(&self) -> bool {
+        self.setting.is_some()
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let (mut store, mut p) = open_profile()?;
+    match args.setting.as_deref() {
+        Some(s) => {
+            p.auto_check = s == "on";
+            store.save_settings(&p)
+        }
+        None => {
+            println!("{}", if p.auto_check { "on" } else { "off" });
+            Ok(())
+        }
+    }
+}
+
+/// Runs the quick check if the profile has it on.
+///
+/// Fails if it finds problems, unless `force` is set (then it just warns).
+/// Changed files are only warned about unless `changes_are_fatal` is set.
+pub fn auto_check(force: bool, changes_are_fatal: bool) -> Result<()> {
+    // If there's trouble loading the profile, the command will report it.
+    let p = match load_and_check_profile() {
+        Ok(p) if p.auto_check => p,
+        _ => return Ok(()),
+    };
+    debug!("Checking for problems before changing anything...");
+
+    if get_journal_path().exists() {
+        let message = "An interrupted `modman add` left its journal in the backup directory.";
+        if !force {
+            fail!(
+                Failure::NeedsRepair,
+                "{}\nRun `modman repair` (or `modman add --resume`) first, \
+                 or pass --force to go ahead anyways.",
+                message
+            );
+        }
+        warn!("{}", message);
+    }
+
+    let changed = changed_files(&p);
+    if !changed.is_empty() {
+        let mut message = "These files are missing or aren't the size modman recorded:".to_owned();
+        for path in &changed {
+            message += &format!("\n\t{}", path.display());
+        }
+        if changes_are_fatal && !force {
+            fail!(
+                Failure::VerificationFailed,
+                "{}\nRun `modman check` to see what's wrong, \
+                 or pass --force to go ahead anyways.",
+                message
+            );
+        }
+        warn!("{}", message);
+    }
+    Ok(())
+}
+
+/// Installed files and backups that are missing or the wrong size
+fn changed_files(p: &Profile) -> Vec<PathBuf> {
+    let mut changed = Vec::new();
+    let mut check = |path: PathBuf, expected: Option<u64>| match file_size(&path) {
+        // (Files installed before we kept track of sizes are fine if they're there.)
+        Ok(size) if expected.is_none_or(|e| e == size) => (),
+        _ => changed.push(path),
+    };
+    for (mod_path, manifest) in &p.mods {
+        for (file, meta) in &manifest.files {
+            if p.is_covered(mod_path, file) {
+                check(covered_path(&meta.mod_hash), meta.size);
+            } else {
+                check(p.game_path(file), meta.size);
+            }
+            if meta.original_hash.is_some() {
+                check(mod_path_to_backup_path(file), meta.original_size);
+            }
+        }
+    }
+    changed
+}
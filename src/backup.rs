@@ -0,0 +1,49 @@
+use std::path::Path;
+
+use anyhow::*;
+use structopt::*;
+
+use crate::export;
+use crate::import;
+
+/// Saves or restores the profile and all its backups as a single archive
+///
+/// Keep the archive somewhere safe (or synced to the cloud), and restore it
+/// on a fresh machine with `modman backup import`, which checks every backup
+/// against the profile's hashes on the way in.
+/// Older originals (see `modman keep-originals`) come along too.
+/// (These are the same commands as `modman export-profile`
+/// and `modman import-profile`, by another name.)
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    #[structopt(subcommand)]
+    action: Action,
+}
+
+#[derive(Debug, StructOpt)]
+enum Action {
+    Export(export::Args),
+    Import(import::Args),
+}
+
+impl Args {
+    pub fn resolve_paths(&mut self, base: &Path) {
+        match &mut self.action {
+            Action::Export(e) => e.resolve_paths(base),
+            Action::Import(i) => i.resolve_paths(base),
+        }
+    }
+
+    /// Returns true if this will create a profile (see `modman --profile`).
+    pub fn is_import(&self) -> bool {
+        matches!(self.action, Action::Import(_))
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    match args.action {
+        Action::Export(e) => export::run(e),
+        Action::Import(i) => import::run(i),
+    }
+}
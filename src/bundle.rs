@@ -0,0 +1,215 @@
+//! Bundling a profile and its backups into a single ZIP archive,
+//! so they can be moved to another machine (or synced somewhere safe).
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, prelude::*};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::*;
+use log::*;
+use zip::read::ZipArchive;
+use zip::write::{FileOptions, ZipWriter};
+
+use crate::failure::*;
+use crate::file_utils::*;
+use crate::older_originals::older_originals_dir;
+use crate::profile::*;
+
+/// Writes the profile (as JSON) and everything in the backup directory
+/// (besides temporary files) to a ZIP archive at archive_path,
+/// including older originals (see `modman keep-originals`).
+pub fn write_bundle(archive_path: &Path, p: &Profile) -> Result<()> {
+    let archive_file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(archive_path)
+        .with_context(|| format!("Couldn't create {}", archive_path.display()))?;
+    let mut zip = ZipWriter::new(io::BufWriter::new(archive_file));
+    let options = FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+    // Backups are put where they'd be by default (modman-backup/),
+    // wherever this profile keeps them.
+    debug!("Writing profile to {}", archive_path.display());
+    zip.start_file(PROFILE_PATH, options)?;
+    let bundled = Profile {
+        storage_directory: None,
+        ..p.clone()
+    };
+    serde_json::to_writer_pretty(&mut zip, &bundled)?;
+    zip.write_all(b"\n")?;
+
+    let storage = storage_dir();
+    let backups = backup_dir();
+    let mut backed_up = vec![backup_readme_path()];
+    backed_up.extend(
+        collect_file_paths_in_dir(&backups)?
+            .into_iter()
+            .map(|f| backups.join(f)),
+    );
+    // (Files mods were installed over are needed to remove those mods,
+    // and older originals to put back what game updates replaced.)
+    for dir in [covered_dir(), older_originals_dir()] {
+        if dir.is_dir() {
+            backed_up.extend(
+                collect_file_paths_in_dir(&dir)?
+                    .into_iter()
+                    .map(|f| dir.join(f)),
+            );
+        }
+    }
+    for file in backed_up {
+        if !file.exists() {
+            continue;
+        }
+        trace!("Adding {} to {}", file.display(), archive_path.display());
+        let name = Path::new(DEFAULT_STORAGE_PATH).join(file.strip_prefix(&storage)?);
+        zip.start_file(zip_name(&name)?, options)?;
+        let mut reader =
+            fs::File::open(&file).with_context(|| format!("Couldn't open {}", file.display()))?;
+        io::copy(&mut reader, &mut zip)
+            .with_context(|| format!("Couldn't add {} to the archive", file.display()))?;
+    }
+
+    zip.finish()?
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_data()
+        .with_context(|| format!("Couldn't sync {}", archive_path.display()))?;
+    Ok(())
+}
+
+/// ZIP archives always use forward slashes.
+fn zip_name(p: &Path) -> Result<String> {
+    let parts = p
+        .components()
+        .map(|c| {
+            c.as_os_str()
+                .to_str()
+                .ok_or_else(|| format_err!("{} isn't valid UTF-8", p.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(parts.join("/"))
+}
+
+/// Extracts the backups from a bundle made by write_bundle()
+/// into the storage directory, and returns the profile it contains.
+///
+/// The profile isn't written anywhere; that's up to the caller.
+/// Every backup the profile mentions is checked against the hash it has there,
+/// so we don't find out the archive was damaged when we go to restore one.
+pub fn read_bundle(archive_path: &Path) -> Result<Profile> {
+    let file = fs::File::open(archive_path)
+        .with_context(|| format!("Couldn't open {}", archive_path.display()))?;
+    let mut archive = ZipArchive::new(io::BufReader::new(file))
+        .with_context(|| format!("Couldn't read {}", archive_path.display()))?;
+
+    let mut profile: Option<Profile> = None;
+    let mut hashes: BTreeMap<PathBuf, FileHash> = BTreeMap::new();
+
+    for i in 0..archive.len() {
+        let reader = archive
+            .by_index(i)
+            .with_context(|| format!("Couldn't read {}", archive_path.display()))?;
+        if reader.is_dir() {
+            continue;
+        }
+        let path = PathBuf::from(reader.name());
+        let path: &Path = &path;
+        // Be paranoid about where we're extracting things.
+        let well_behaved = path.components().all(|c| matches!(c, Component::Normal(_)));
+        if !well_behaved {
+            bail!(
+                "{} contains a suspicious path: {}",
+                archive_path.display(),
+                path.display()
+            );
+        }
+
+        if path == Path::new(PROFILE_PATH) {
+            profile = Some(
+                serde_json::from_reader(io::BufReader::new(reader))
+                    .context("Couldn't parse the archive's profile")?,
+            );
+        } else if let Some(stored) = path
+            .strip_prefix(DEFAULT_STORAGE_PATH)
+            .ok()
+            .filter(|s| !s.starts_with("temp"))
+        {
+            let extracted = storage_dir().join(stored);
+            hashes.insert(extracted.clone(), extract_file(reader, &extracted)?);
+        } else {
+            warn!("Skipping unexpected file {} in the archive", path.display());
+        }
+    }
+
+    let profile = profile.ok_or_else(|| {
+        format_err!(
+            "{} doesn't contain a profile. Was it made with `modman export-profile`?",
+            archive_path.display()
+        )
+    })?;
+    verify_backups(archive_path, &profile, &hashes)?;
+    Ok(profile)
+}
+
+/// Makes sure the archive had each backup the profile mentions,
+/// with the contents it had when it was backed up.
+fn verify_backups(
+    archive_path: &Path,
+    p: &Profile,
+    hashes: &BTreeMap<PathBuf, FileHash>,
+) -> Result<()> {
+    let mut problems = String::new();
+    for manifest in p.mods.values() {
+        for (file, meta) in &manifest.files {
+            let expected = match &meta.original_hash {
+                Some(h) => h,
+                None => continue,
+            };
+            let backup_path = mod_path_to_backup_path(file);
+            match hashes.get(&backup_path) {
+                None => problems += &format!("\n\t{} is missing", backup_path.display()),
+                Some(h) if h != expected => {
+                    problems += &format!("\n\t{} has changed", backup_path.display())
+                }
+                Some(_) => trace!("{} matches the profile", backup_path.display()),
+            }
+        }
+    }
+    // Older originals are named by their hash.
+    let older = older_originals_dir();
+    for (path, hash) in hashes {
+        let name = path.strip_prefix(&older).ok().and_then(Path::to_str);
+        if let Some(name) = name.filter(|n| hex::decode(n).is_ok()) {
+            if name != hex::encode(hash.bytes) {
+                problems += &format!("\n\t{} has changed", path.display());
+            }
+        }
+    }
+    if !problems.is_empty() {
+        fail!(
+            Failure::VerificationFailed,
+            "{}'s backups don't match its profile:{}",
+            archive_path.display(),
+            problems
+        );
+    }
+    Ok(())
+}
+
+/// Extracts a file from the archive and returns its hash.
+fn extract_file<R: Read>(mut reader: R, path: &Path) -> Result<FileHash> {
+    trace!("Extracting {}", path.display());
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Couldn't create directory {}", parent.display()))?;
+    }
+    let mut extracted = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .with_context(|| format!("Couldn't create {}", path.display()))?;
+    hash_and_write(&mut reader, &mut extracted)
+        .with_context(|| format!("Couldn't extract {}", path.display()))
+}
@@ -1,44 +1,293 @@
+use std::collections::BTreeSet;
+use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
 use anyhow::*;
 use log::*;
 use rayon::prelude::*;
+use structopt::*;
 
+use crate::download::downloads_dir;
+use crate::failure::*;
 use crate::file_utils::*;
+use crate::games;
 use crate::journal::*;
+use crate::modification::archive_changed;
 use crate::profile::*;
+use crate::progress::{self, FileEvent};
+use crate::remove::remove_mods;
+use crate::stats::format_size;
 
-pub fn run() -> Result<()> {
-    let p = load_and_check_profile()?;
+/// Checks for possible problems with installed mods and backed up files.
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Finish what interrupted `modman remove`s started:
+    /// remove half-removed mods, and delete backups that were already restored.
+    /// Also deletes temporary files interrupted commands left behind.
+    #[structopt(long)]
+    fix: bool,
+
+    /// Hash every backup, even if the profile has `modman quick-check` on
+    /// and their sizes and modification times say they haven't changed.
+    #[structopt(long)]
+    deep: bool,
+}
+
+impl Args {
+    pub fn new(fix: bool) -> Self {
+        Self { fix, deep: false }
+    }
+
+    /// Returns true if we'll change the game directory (with --fix).
+    pub fn is_fixing(&self) -> bool {
+        self.fix
+    }
+}
+
+/// Shows or sets whether `modman check` trusts backups that look untouched
+///
+/// When it's on, `modman check` doesn't hash backups whose size and
+/// modification time are what they were when they were made.
+/// (Backups shouldn't change, and hashing all of them is most of what
+/// makes checking a profile with big mods slow.)
+/// `modman check --deep` hashes them anyway.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct QuickArgs {
+    #[structopt(name = "SETTING", possible_values = &["on", "off"])]
+    setting: Option<String>,
+}
+
+impl QuickArgs {
+    /// Returns true if we'll change the profile (not just show it).
+    pub fn is_changing(&self) -> bool {
+        self.setting.is_some()
+    }
+}
+
+pub fn quick(args: QuickArgs) -> Result<()> {
+    let (mut store, mut p) = open_profile()?;
+    let on = match args.setting.as_deref() {
+        Some(s) => s == "on",
+        None => {
+            println!("{}", if p.quick_check { "on" } else { "off" });
+            return Ok(());
+        }
+    };
+    p.quick_check = on;
+    if on {
+        info!("Hashing backups to mark them as checked...");
+    }
+    store.defer_writes()?;
+    for (mod_path, manifest) in p.mods.iter_mut() {
+        if mark_backups(manifest, on)? {
+            store.save_mod(mod_path, manifest)?;
+        }
+    }
+    store.save_settings(&p)?;
+    store.flush()
+}
+
+/// Records when each of the mod's backups was last modified,
+/// once it's hashed to make sure it's what the profile says it is.
+/// (Or, if `on` isn't set, forgets them.)
+///
+/// Returns true if the manifest changed.
+fn mark_backups(manifest: &mut ModManifest, on: bool) -> Result<bool> {
+    if !on {
+        let mut changed = false;
+        for meta in manifest.files.values_mut() {
+            changed |= meta.backup_mtime.take().is_some();
+        }
+        return Ok(changed);
+    }
+
+    let marks = manifest
+        .files
+        .par_iter()
+        .filter(|(_, meta)| meta.backup_mtime.is_none())
+        .filter_map(|(file, meta)| meta.original_hash.as_ref().map(|h| (file, h)))
+        .map(
+            |(file, original_hash)| -> Result<Option<(PathBuf, SystemTime)>> {
+                let backup_path = mod_path_to_backup_path(file);
+                // Get the time first, so if the backup changes while we hash it,
+                // the next check notices.
+                let mtime = match file_mtime(&backup_path) {
+                    Ok(t) => t,
+                    Err(e) if is_not_found(&e) => {
+                        warn!("The backup of {} is missing!", file.display());
+                        return Ok(None);
+                    }
+                    Err(e) => return Err(e),
+                };
+                if hash_file(&backup_path)? != *original_hash {
+                    warn!(
+                        "The backup of {} has changed, so it won't be trusted \
+                     without hashing it. Run `modman check` for details.",
+                        file.display()
+                    );
+                    return Ok(None);
+                }
+                Ok(Some((file.clone(), mtime)))
+            },
+        )
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut changed = false;
+    for (file, mtime) in marks.into_iter().flatten() {
+        manifest.files.get_mut(&file).unwrap().backup_mtime = Some(mtime);
+        changed = true;
+    }
+    Ok(changed)
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let mut p = load_and_check_profile()?;
 
     let mut ok = true;
 
-    ok &= check_for_journal();
-    ok &= find_unknown_files(&p)?;
-    ok &= verify_backups(&p)?;
+    ok &= check_steam_build(&p)?;
+    ok &= check_for_journal()?;
+    ok &= find_interrupted_removes(&p, args.fix)?;
+    if args.fix {
+        // We might have removed some mods.
+        p = load_and_check_profile()?;
+    }
+    ok &= find_unknown_files(&p, args.fix)?;
+    ok &= find_leftover_temp_files(args.fix)?;
+    ok &= verify_backups(&p, p.quick_check && !args.deep)?;
     ok &= verify_installed_mod_files(&p)?;
+    ok &= verify_mod_archives(&p)?;
 
     if ok {
         Ok(())
     } else {
-        bail!("Checks failed!")
+        fail!(Failure::VerificationFailed, "Checks failed!")
+    }
+}
+
+/// Checks if Steam updated the game since the last `modman update`,
+/// which is much faster than hashing everything to find out.
+fn check_steam_build(p: &Profile) -> Result<bool> {
+    let (app_id, last_build) = match (p.steam_app_id, &p.steam_build_id) {
+        (Some(id), Some(b)) => (id, b),
+        _ => return Ok(true),
+    };
+    info!("Checking if Steam updated the game...");
+    match games::steam_build_id(app_id)? {
+        Some(build) if build != *last_build => {
+            warn!(
+                "Steam updated the game (build {} to {}) since the last `modman update`.\n\
+                 Run `modman update` to back up the new game files and reinstall mod files.",
+                last_build, build
+            );
+            Ok(false)
+        }
+        Some(_) => Ok(true),
+        None => {
+            warn!(
+                "Couldn't find Steam app {} to see if it was updated",
+                app_id
+            );
+            Ok(true)
+        }
     }
 }
 
-fn check_for_journal() -> bool {
+fn check_for_journal() -> Result<bool> {
     info!("Checking if `modman add` was interrupted...");
-    if crate::journal::get_journal_path().exists() {
-        warn!(
-            "A journal file was found in the backup directory.\n\
-             This usually happens when `modman add` is interrupted \
-             before it can update the profile file.\n\
-             Run `modman repair` to restore files to the game directory \
-             and run `modman add` again."
+    if !get_journal_path().exists() {
+        return Ok(true);
+    }
+    // (Older journals don't say what they're from.)
+    let which = match read_journal_header()? {
+        Some(h) => format!(
+            " Here, `modman add {}` (run {}) was interrupted after changing {} files.",
+            h.mod_path.display(),
+            h.run_id,
+            read_journal()?.len()
+        ),
+        None => String::new(),
+    };
+    warn!(
+        "A journal file was found in the backup directory.\n\
+         This usually happens when `modman add` is interrupted \
+         before it can update the profile file.{}\n\
+         Run `modman repair` to restore files to the game directory \
+         and run `modman add` again.",
+        which
+    );
+    Ok(false)
+}
+
+/// Looks for mods that an interrupted `modman remove` got partway through:
+/// some of their files are already restored or removed,
+/// and the rest are still installed.
+///
+/// Returns false if any are found (and weren't fixed).
+fn find_interrupted_removes(p: &Profile, fix: bool) -> Result<bool> {
+    info!("Checking if `modman remove` was interrupted...");
+    let mut ok = true;
+
+    for (mod_path, manifest) in &p.mods {
+        let mut undone = Vec::new();
+        let mut other_changes = false;
+        for (file, meta) in &manifest.files {
+            // Covered files aren't in the game directory.
+            if p.is_covered(mod_path, file) {
+                continue;
+            }
+            let game_path = p.game_path(file);
+            let game_hash = match hash_installed_file(&game_path, meta) {
+                Ok(h) => Some(h),
+                Err(e) if is_not_found(&e) => None,
+                Err(e) => return Err(e),
+            };
+            match (&meta.original_hash, game_hash) {
+                // Still installed
+                (_, Some(h)) if h == meta.mod_hash => (),
+                // Removed, or restored from its backup
+                (None, None) => undone.push(file),
+                (Some(original), Some(h)) if *original == h => undone.push(file),
+                _ => other_changes = true,
+            }
+        }
+        if undone.is_empty() {
+            continue;
+        }
+
+        let mut warning = format!(
+            "{} is still in the profile, but these of its files were already \
+             restored from backups or removed:",
+            mod_path.display()
         );
-        false
-    } else {
-        true
+        for file in &undone {
+            warning += &format!("\n\t{}", file.display());
+        }
+        if other_changes {
+            warning += "\nSome of its other files changed too, \
+                        so this might not be from an interrupted `modman remove`. \
+                        See the problems below.";
+            warn!("{}", warning);
+            ok = false;
+        } else if fix {
+            warning += "\nFinishing removing it.";
+            warn!("{}", warning);
+            remove_mods(vec![mod_path.clone()], true, false, false)?;
+        } else {
+            warning += &format!(
+                "\nIf `modman remove` was interrupted, run `modman remove --force {}` \
+                 (or `modman check --fix`) to finish.\n\
+                 If the game put its own files back (e.g., Steam verified them), \
+                 run `modman update` to reinstall the mod's instead.",
+                mod_path.display()
+            );
+            warn!("{}", warning);
+            ok = false;
+        }
     }
+    Ok(ok)
 }
 
 /// Returns the mod_file_paths that aren't mentioned in the profile
@@ -48,24 +297,31 @@ fn collect_unknown_files(
     p: &Profile,
     jm: &JournalMap,
 ) -> Vec<PathBuf> {
+    // If the game directory ignores case, so should we.
+    // (The backup directory might not, but better safe than sorry.)
+    let ci = p.case_insensitive;
+    let known: BTreeSet<PathBuf> = jm
+        .keys()
+        .chain(p.mods.values().flat_map(|manifest| manifest.files.keys()))
+        .map(|path| path_key(path, ci))
+        .collect();
+
     mod_file_paths
         .into_iter()
         // We want things that aren't mentioned in the journal
         // Or in any of the mod manifests
-        .filter(|path| {
-            !jm.contains_key(path)
-                && !p
-                    .mods
-                    .values()
-                    .any(|manifest| manifest.files.contains_key(path))
-        })
+        .filter(|path| !known.contains(&path_key(path, ci)))
         .collect()
 }
 
 /// Checks for unknown files, and returns false if any are found.
-fn find_unknown_files(p: &Profile) -> Result<bool> {
+///
+/// Backups that match what's in the game directory were probably left
+/// by an interrupted `modman remove` after it restored them.
+/// We delete those if we're told to fix things.
+fn find_unknown_files(p: &Profile, fix: bool) -> Result<bool> {
     info!("Checking for unknown files...");
-    let backed_up_files = collect_file_paths_in_dir(Path::new(BACKUP_PATH))?;
+    let backed_up_files = collect_file_paths_in_dir(&backup_dir())?;
 
     let mut ret = true;
 
@@ -73,7 +329,36 @@ fn find_unknown_files(p: &Profile) -> Result<bool> {
     // or journal.
     let journal_files = read_journal()?;
 
-    let unknown_files = collect_unknown_files(backed_up_files, &p, &journal_files);
+    let mut unknown_files = collect_unknown_files(backed_up_files, p, &journal_files);
+
+    let (restored, others) = partition_restored(unknown_files, p)?;
+    unknown_files = others;
+    if !restored.is_empty() {
+        if fix {
+            for file in &restored {
+                let backup_path = mod_path_to_backup_path(file);
+                info!(
+                    "Removing {}, which was already restored",
+                    backup_path.display()
+                );
+                discard_file(&backup_path)
+                    .with_context(|| format!("Couldn't remove {}", backup_path.display()))?;
+                remove_empty_parents(&backup_path, &backup_dir())?;
+            }
+        } else {
+            let mut warning = "These backups of files no installed mod replaces \
+                               match the game's files, so `modman remove` was probably \
+                               interrupted after restoring them:"
+                .to_owned();
+            for file in &restored {
+                warning += &format!("\n\t{}", mod_path_to_backup_path(file).display());
+            }
+            warning += "\nRun `modman check --fix` to delete them.";
+            warn!("{}", warning);
+            ret = false;
+        }
+    }
+
     if !unknown_files.is_empty() {
         let mut warning = "The following files were found in the backup directory \
                            but aren't known by modman:"
@@ -88,92 +373,335 @@ fn find_unknown_files(p: &Profile) -> Result<bool> {
     Ok(ret)
 }
 
+/// A file an interrupted command left behind
+pub struct Leftover {
+    pub path: PathBuf,
+    pub size: u64,
+    /// How long ago it was last written
+    pub age: Duration,
+}
+
+/// Finds partial backups in the temp directory (besides the journal)
+/// and partial downloads in the download cache.
+pub fn leftover_temp_files() -> Result<Vec<Leftover>> {
+    let mut paths = Vec::new();
+    let temp_dir = &tempdir();
+    if temp_dir.is_dir() {
+        let journal = get_journal_path();
+        paths.extend(
+            collect_file_paths_in_dir(temp_dir)?
+                .into_iter()
+                .map(|f| temp_dir.join(f))
+                .filter(|f| *f != journal),
+        );
+    }
+    let downloads = downloads_dir();
+    if downloads.is_dir() {
+        paths.extend(
+            collect_file_paths_in_dir(&downloads)?
+                .into_iter()
+                .filter(|f| f.extension().is_some_and(|e| e == "part"))
+                .map(|f| downloads.join(f)),
+        );
+    }
+
+    let now = SystemTime::now();
+    paths
+        .into_iter()
+        .map(|path| {
+            let meta =
+                fs::metadata(&path).with_context(|| format!("Couldn't stat {}", path.display()))?;
+            let age = meta
+                .modified()
+                .ok()
+                .and_then(|m| now.duration_since(m).ok())
+                .unwrap_or_default();
+            Ok(Leftover {
+                path,
+                size: meta.len(),
+                age: Duration::from_secs(age.as_secs()),
+            })
+        })
+        .collect()
+}
+
+/// Looks for files interrupted commands left behind, which nothing else
+/// cleans up (and which keep `modman add` from tidying the temp directory).
+/// Deletes them if we're told to fix things.
+///
+/// Returns false if any are found (and weren't deleted).
+fn find_leftover_temp_files(fix: bool) -> Result<bool> {
+    info!("Checking for leftover temporary files...");
+    let leftovers = leftover_temp_files()?;
+    if leftovers.is_empty() {
+        return Ok(true);
+    }
+
+    let mut warning = "These temporary files were left behind by interrupted commands:".to_owned();
+    for l in &leftovers {
+        warning += &format!(
+            "\n\t{} ({}, {} old)",
+            l.path.display(),
+            format_size(l.size),
+            humantime::format_duration(l.age)
+        );
+    }
+    // Leave things be until an interrupted `modman add` is sorted out.
+    let interrupted = get_journal_path().exists();
+    if fix && !interrupted {
+        warning += "\nDeleting them.";
+        warn!("{}", warning);
+        for l in &leftovers {
+            discard_file(&l.path)
+                .with_context(|| format!("Couldn't remove {}", l.path.display()))?;
+        }
+        remove_empty_tree(&tempdir(), RemoveRoot(false))
+            .context("Couldn't clean up temp directory")?;
+        return Ok(true);
+    }
+    if interrupted {
+        warning += "\nRun `modman repair` first, then `modman check --fix` to delete them.";
+    } else {
+        warning += "\nRun `modman check --fix` (or `modman clean`) to delete them.";
+    }
+    warn!("{}", warning);
+    Ok(false)
+}
+
+/// Splits unknown backups into ones that match the game's copy of the file
+/// (so they've already been restored) and the rest.
+fn partition_restored(files: Vec<PathBuf>, p: &Profile) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let mut restored = Vec::new();
+    let mut others = Vec::new();
+    for file in files {
+        let game_path = p.game_path(&file);
+        let matches = game_path.is_file()
+            && !is_symlink(&game_path)
+            && hash_file(&game_path)? == hash_file(&mod_path_to_backup_path(&file))?;
+        if matches {
+            restored.push(file);
+        } else {
+            others.push(file);
+        }
+    }
+    Ok((restored, others))
+}
+
+/// Every installed file, along with the mod that installed it.
+///
+/// Checking these all at once (instead of a mod at a time)
+/// keeps every thread busy when there are lots of small mods.
+fn all_files(p: &Profile) -> Vec<(&Path, &Path, &ModFileMetadata)> {
+    p.mods
+        .iter()
+        .flat_map(|(mod_name, manifest)| {
+            manifest
+                .files
+                .iter()
+                .map(move |(file, meta)| (mod_name.as_path(), file.as_path(), meta))
+        })
+        .collect()
+}
+
 /// Verifies integrity of backup files,
 /// and returns false if any fail their check.
-fn verify_backups(p: &Profile) -> Result<bool> {
+/// Verifies backups haven't changed since they were made.
+/// If `quick` is set, backups whose size and modification time are what
+/// they were then are trusted without hashing them.
+fn verify_backups(p: &Profile, quick: bool) -> Result<bool> {
     info!("Verifying backup files...");
-    let mut backups_ok = true;
-
-    for manifest in p.mods.values() {
-        backups_ok &= manifest
-            .files
-            .par_iter()
-            .map(|(mod_path, metadata)| {
-                let mod_path: &Path = &**mod_path;
-
-                // If there was no backup, there's nothing to check.
-                if metadata.original_hash.is_none() {
-                    return Ok(true);
-                }
-                let original_hash = metadata.original_hash.as_ref().unwrap();
-
-                let backup_path = mod_path_to_backup_path(mod_path);
-                let backup_hash = hash_file(&backup_path)?;
-                if backup_hash != *original_hash {
-                    debug!(
-                        "{} hashed to\n{:x},\nexpected {:x}",
-                        backup_path.display(),
-                        backup_hash.bytes,
-                        original_hash.bytes
-                    );
-                    warn!(
-                        "The backup of {} has changed!\n\
-                     Please repair your game files, then run `modman update` \
-                     to make new backups.",
-                        mod_path.display()
-                    );
-                    Ok(false)
-                } else {
-                    info!("\t{} is unchanged", mod_path.display());
-                    Ok(true)
-                }
-            })
-            .reduce(
-                || -> Result<bool> { Ok(true) },
-                |left, right| Ok(left? && right?),
-            )?;
+    let files = all_files(p);
+    progress::start_all("verify-backups", files.len());
+
+    files
+        .par_iter()
+        .map(|(_mod_name, mod_path, metadata)| {
+            let ok = verify_backup(mod_path, metadata, quick);
+            progress::file_done(FileEvent::Checked, mod_path);
+            ok
+        })
+        .reduce(
+            || -> Result<bool> { Ok(true) },
+            |left, right| Ok(left? && right?),
+        )
+}
+
+fn verify_backup(mod_path: &Path, metadata: &ModFileMetadata, quick: bool) -> Result<bool> {
+    // If there was no backup, there's nothing to check.
+    let original_hash = match &metadata.original_hash {
+        Some(h) => h,
+        None => return Ok(true),
+    };
+
+    let backup_path = mod_path_to_backup_path(mod_path);
+    if quick && backup_is_untouched(&backup_path, metadata) {
+        info!("\t{} is unchanged (by size and time)", mod_path.display());
+        return Ok(true);
+    }
+    let backup_hash = match hash_file(&backup_path) {
+        Ok(h) => h,
+        Err(e) if is_not_found(&e) => {
+            warn!(
+                "The backup of {} ({}) is missing!",
+                mod_path.display(),
+                backup_path.display()
+            );
+            return Ok(false);
+        }
+        Err(e) => return Err(e),
+    };
+    if backup_hash != *original_hash {
+        debug!(
+            "{} hashed to\n{:x},\nexpected {:x}",
+            backup_path.display(),
+            backup_hash.bytes,
+            original_hash.bytes
+        );
+        warn!(
+            "The backup of {} has changed!\n\
+             Please repair your game files, then run `modman update` \
+             to make new backups.",
+            mod_path.display()
+        );
+        Ok(false)
+    } else {
+        info!("\t{} is unchanged", mod_path.display());
+        Ok(true)
     }
+}
 
-    Ok(backups_ok)
+/// Returns true if the backup is the size it was and hasn't been modified
+/// since it was made. If we don't know (or can't tell), it has to be hashed.
+fn backup_is_untouched(backup_path: &Path, metadata: &ModFileMetadata) -> bool {
+    let (size, mtime) = match (metadata.original_size, metadata.backup_mtime) {
+        (Some(s), Some(t)) => (s, t),
+        _ => return false,
+    };
+    matches!(file_size(backup_path), Ok(s) if s == size)
+        && matches!(file_mtime(backup_path), Ok(t) if t == mtime)
 }
 
 /// Verifies integrity of installed mod files,
 /// and returns false if any fail their check.
 fn verify_installed_mod_files(p: &Profile) -> Result<bool> {
     info!("Verifying installed mod files...");
-    let mut installed_files_ok = true;
-
-    for manifest in p.mods.values() {
-        installed_files_ok &= manifest
-            .files
-            .par_iter()
-            .map(|(mod_path, metadata)| {
-                let game_path = mod_path_to_game_path(&**mod_path, &p.root_directory);
-                let game_hash = hash_file(&game_path)?;
-                if game_hash != metadata.mod_hash {
-                    debug!(
-                        "{} hashed to\n{:x},\nexpected {:x}",
-                        game_path.display(),
-                        game_hash.bytes,
-                        metadata.mod_hash.bytes
-                    );
-                    warn!(
-                        "{} has changed!\n\
-                     If the game has been updated, run `modman update` \
-                     to update backups and reinstall needed files.",
-                        game_path.display()
-                    );
-                    Ok(false)
-                } else {
-                    info!("\t{} is unchanged", mod_path.display());
-                    Ok(true)
-                }
-            })
-            .reduce(
-                || -> Result<bool> { Ok(true) },
-                |left, right| Ok(left? && right?),
-            )?;
+    let files = all_files(p);
+    progress::start_all("verify-files", files.len());
+
+    files
+        .par_iter()
+        .map(|(mod_name, mod_path, metadata)| {
+            let ok = verify_installed_file(mod_name, mod_path, metadata, p);
+            progress::file_done(FileEvent::Checked, mod_path);
+            ok
+        })
+        .reduce(
+            || -> Result<bool> { Ok(true) },
+            |left, right| Ok(left? && right?),
+        )
+}
+
+/// Checks that mods' archives are the ones they were installed from,
+/// so `modman update` won't refuse to reinstall their files.
+fn verify_mod_archives(p: &Profile) -> Result<bool> {
+    info!("Verifying mod archives...");
+    let changed: Vec<&PathBuf> = p
+        .mods
+        .par_iter()
+        .map(|(mod_path, manifest)| Ok((mod_path, archive_changed(mod_path, manifest)?)))
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(mod_path, _)| mod_path)
+        .collect();
+    for mod_path in &changed {
+        warn!(
+            "{} isn't the archive it was installed from (it's changed since).\n\
+             Run `modman update --accept-mod-changes` to install its files as they are now.",
+            mod_path.display()
+        );
     }
+    Ok(changed.is_empty())
+}
+
+fn verify_installed_file(
+    mod_name: &Path,
+    mod_path: &Path,
+    metadata: &ModFileMetadata,
+    p: &Profile,
+) -> Result<bool> {
+    if p.is_covered(mod_name, mod_path) {
+        return verify_covered_file(mod_path, metadata);
+    }
+    let game_path = p.game_path(mod_path);
+    let game_hash = match hash_installed_file(&game_path, metadata) {
+        Ok(h) => h,
+        // Game updates can delete (or move) files mods replaced.
+        // Keep checking everything else.
+        Err(e) if is_not_found(&e) && metadata.original_hash.is_some() => {
+            warn!(
+                "{} is missing!\n\
+                 If the game has been updated, run `modman update` \
+                 to drop it from the profile (or follow it if it moved).",
+                game_path.display()
+            );
+            return Ok(false);
+        }
+        Err(e) if is_not_found(&e) => {
+            warn!(
+                "{} is missing! Remove {} and add it again to reinstall it.",
+                game_path.display(),
+                mod_name.display()
+            );
+            return Ok(false);
+        }
+        Err(e) => return Err(e),
+    };
+    if game_hash != metadata.mod_hash {
+        debug!(
+            "{} hashed to\n{:x},\nexpected {:x}",
+            game_path.display(),
+            game_hash.bytes,
+            metadata.mod_hash.bytes
+        );
+        warn!(
+            "{} has changed!\n\
+             If the game has been updated, run `modman update` \
+             to update backups and reinstall needed files.",
+            game_path.display()
+        );
+        Ok(false)
+    } else {
+        info!("\t{} is unchanged", mod_path.display());
+        Ok(true)
+    }
+}
 
-    Ok(installed_files_ok)
+/// Mod files other mods were installed over aren't in the game directory,
+/// but kept in covered/ until they're put back.
+fn verify_covered_file(mod_path: &Path, metadata: &ModFileMetadata) -> Result<bool> {
+    let covered = covered_path(&metadata.mod_hash);
+    match hash_file(&covered) {
+        Ok(h) if h == metadata.mod_hash => {
+            info!("\t{} (covered) is unchanged", mod_path.display());
+            Ok(true)
+        }
+        Ok(_) => {
+            warn!(
+                "The covered copy of {} ({}) has changed!",
+                mod_path.display(),
+                covered.display()
+            );
+            Ok(false)
+        }
+        Err(e) if is_not_found(&e) => {
+            warn!(
+                "The covered copy of {} ({}) is missing!",
+                mod_path.display(),
+                covered.display()
+            );
+            Ok(false)
+        }
+        Err(e) => Err(e),
+    }
 }
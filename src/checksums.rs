@@ -0,0 +1,217 @@
+//! CHECKSUMS.txt: an optional list of hashes a mod can ship in its root
+//! (next to VERSION.txt), so modman can make sure every file it installs
+//! is exactly what the mod's author packaged.
+//!
+//! It's in the format `sha224sum` prints, with paths relative to
+//! the mod's base directory:
+//!
+//! ```text
+//! 7e2d...41a9  Data/textures.pak
+//! 0c6f...d2b3  Data/sounds/boom.wav
+//! ```
+//!
+//! Every mod file must be listed. Files are checked as they're read,
+//! so a mismatch stops `modman add` before it installs that file.
+//! `modman make-checksums` writes one for a mod directory.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use log::*;
+use rayon::prelude::*;
+use sha2::{Digest, Sha224};
+use structopt::*;
+
+use crate::dir_mod::DirectoryMod;
+use crate::file_utils::hash_file;
+use crate::modification::Mod;
+use crate::profile::{FileHash, Sha224Bytes};
+
+pub static CHECKSUMS_NAME: &str = "CHECKSUMS.txt";
+
+/// Mod file paths (relative to the mod's base directory) and their hashes
+pub type Checksums = BTreeMap<PathBuf, FileHash>;
+
+pub fn parse_checksums(text: &str) -> Result<Checksums> {
+    let mut checksums = Checksums::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        let bad_line = || {
+            format_err!(
+                "Line {} of {} isn't a hash and a path",
+                i + 1,
+                CHECKSUMS_NAME
+            )
+        };
+        let (hash, path) = line.split_once(' ').ok_or_else(bad_line)?;
+        // sha224sum puts a space (or * for "binary mode") before the path.
+        let path = path
+            .strip_prefix(' ')
+            .or_else(|| path.strip_prefix('*'))
+            .ok_or_else(bad_line)?;
+        let hash = parse_hash(hash).ok_or_else(|| {
+            format_err!(
+                "Line {} of {} doesn't start with a SHA-224 hash",
+                i + 1,
+                CHECKSUMS_NAME
+            )
+        })?;
+        if checksums.insert(PathBuf::from(path), hash).is_some() {
+            bail!("{} lists {} more than once", CHECKSUMS_NAME, path);
+        }
+    }
+    Ok(checksums)
+}
+
+pub fn parse_hash(hex: &str) -> Option<FileHash> {
+    let bytes = hex::decode(hex).ok()?;
+    if bytes.len() != Sha224Bytes::default().len() {
+        return None;
+    }
+    Some(FileHash::new(Sha224Bytes::clone_from_slice(&bytes)))
+}
+
+/// Makes sure the checksums list each of the mod's files, and nothing else.
+pub fn check_listed(checksums: &Checksums, paths: &[PathBuf]) -> Result<()> {
+    if let Some(unlisted) = paths.iter().find(|p| !checksums.contains_key(*p)) {
+        bail!(
+            "{} isn't listed in {}, so it can't be checked",
+            unlisted.display(),
+            CHECKSUMS_NAME
+        );
+    }
+    if let Some(missing) = checksums.keys().find(|p| !paths.contains(p)) {
+        bail!(
+            "{} lists {}, but the mod doesn't have it (was it only partly downloaded?)",
+            CHECKSUMS_NAME,
+            missing.display()
+        );
+    }
+    Ok(())
+}
+
+/// Wraps a mod file's reader so that reading to the end fails
+/// if it doesn't match its checksum (if the mod has them).
+pub fn checked_reader<'a>(
+    inner: Box<dyn Read + Send + 'a>,
+    checksums: Option<&'a Checksums>,
+    path: &Path,
+) -> Result<Box<dyn Read + Send + 'a>> {
+    let checksums = match checksums {
+        Some(c) => c,
+        None => return Ok(inner),
+    };
+    let expected = checksums
+        .get(path)
+        .ok_or_else(|| format_err!("{} isn't listed in {}", path.display(), CHECKSUMS_NAME))?;
+    Ok(Box::new(ChecksumReader {
+        inner,
+        expected,
+        path: path.to_owned(),
+        hasher: Sha224::new(),
+    }))
+}
+
+struct ChecksumReader<'a> {
+    inner: Box<dyn Read + Send + 'a>,
+    expected: &'a FileHash,
+    path: PathBuf,
+    hasher: Sha224,
+}
+
+impl Read for ChecksumReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            let hash = FileHash::new(self.hasher.clone().finalize());
+            if hash != *self.expected {
+                debug!(
+                    "{} hashed to\n{:x},\nexpected {:x}",
+                    self.path.display(),
+                    hash.bytes,
+                    self.expected.bytes
+                );
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} doesn't match its checksum in {}; the mod is corrupt \
+                         or was changed after it was packaged. Try downloading it again.",
+                        self.path.display(),
+                        CHECKSUMS_NAME
+                    ),
+                ));
+            }
+        }
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Writes a CHECKSUMS.txt for a mod directory
+///
+/// Lists the hash of every file in the mod, so modman can make sure
+/// they're what you packaged when someone installs it.
+/// Run this again after changing the mod's files.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    #[structopt(name = "MOD_DIR")]
+    mod_dir: PathBuf,
+}
+
+impl Args {
+    pub fn resolve_paths(&mut self, base: &Path) {
+        self.mod_dir = base.join(&self.mod_dir);
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    // Whatever checksums it has now are about to be replaced.
+    let m = DirectoryMod::ignoring_checksums(&args.mod_dir)
+        .with_context(|| format!("Trouble reading mod directory {}", args.mod_dir.display()))?;
+
+    let mut paths = Vec::new();
+    for path in m.paths()? {
+        // Links are installed as links, not their contents.
+        if m.symlink(&path)?.is_none() {
+            paths.push(path);
+        }
+    }
+    let hashes = paths
+        .par_iter()
+        .map(|path| hash_file(&m.base_dir().join(path)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut listing: Vec<(String, &FileHash)> = paths
+        .iter()
+        .map(|path| {
+            // Forward slashes work everywhere.
+            path.components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+        .zip(&hashes)
+        .collect();
+    listing.sort_by(|a, b| a.0.cmp(&b.0));
+    let listing: String = listing
+        .iter()
+        .map(|(path, hash)| format!("{:x}  {}\n", hash.bytes, path))
+        .collect();
+
+    let checksums_path = args.mod_dir.join(CHECKSUMS_NAME);
+    fs::write(&checksums_path, listing)
+        .with_context(|| format!("Couldn't write {}", checksums_path.display()))?;
+    info!(
+        "Wrote checksums of {} files to {}",
+        paths.len(),
+        checksums_path.display()
+    );
+    Ok(())
+}
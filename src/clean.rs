@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use anyhow::*;
+use log::*;
+use structopt::*;
+
+use crate::check::leftover_temp_files;
+use crate::download::downloads_dir;
+use crate::failure::*;
+use crate::file_utils::*;
+use crate::journal::get_journal_path;
+use crate::profile::*;
+use crate::stats::format_size;
+
+/// Deletes temporary files and downloaded mods that aren't installed
+///
+/// Clears out the temp directory (partial backups from interrupted
+/// commands), partial profile updates, and the download cache.
+/// Downloaded mods that are installed are kept, since `modman update`
+/// and `modman outdated` need them.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    #[structopt(short = "n", long)]
+    dry_run: bool,
+}
+
+impl Args {
+    /// Makes this a dry run, for `--no-write`.
+    pub fn make_dry_run(&mut self) {
+        self.dry_run = true;
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    // The temp directory is the only record of what it was doing.
+    if get_journal_path().exists() {
+        fail!(
+            Failure::NeedsRepair,
+            "An interrupted `modman add` left its journal in the backup directory.\n\
+             Run `modman repair` (or `modman add --resume`) before cleaning up."
+        );
+    }
+    let (_store, p) = open_profile()?;
+
+    let mut to_remove: BTreeMap<PathBuf, u64> = leftover_temp_files()?
+        .into_iter()
+        .map(|l| (l.path, l.size))
+        .collect();
+
+    let downloads = downloads_dir();
+    if downloads.is_dir() {
+        for file in collect_file_paths_in_dir(&downloads)? {
+            let path = downloads.join(file);
+            if p.mods.contains_key(&path) {
+                debug!("Keeping {}, which is installed", path.display());
+                continue;
+            }
+            let size = file_size(&path)?;
+            to_remove.insert(path, size);
+        }
+    }
+
+    // Left behind if we were interrupted while saving the profile
+    let mut profile_temp = PROFILE_PATH.to_owned();
+    profile_temp.push_str(".new");
+    let profile_temp = PathBuf::from(profile_temp);
+    if profile_temp.is_file() {
+        let size = file_size(&profile_temp)?;
+        to_remove.insert(profile_temp, size);
+    }
+
+    if to_remove.is_empty() {
+        println!("Nothing to clean up.");
+        return Ok(());
+    }
+
+    let mut freed = 0;
+    for (path, size) in &to_remove {
+        if args.dry_run {
+            println!("Would remove {} ({})", path.display(), format_size(*size));
+        } else {
+            info!("Removing {} ({})", path.display(), format_size(*size));
+            discard_file(path).with_context(|| format!("Couldn't remove {}", path.display()))?;
+        }
+        freed += size;
+    }
+    if !args.dry_run {
+        for dir in &[tempdir(), downloads] {
+            if dir.is_dir() {
+                remove_empty_tree(dir, RemoveRoot(false))
+                    .with_context(|| format!("Couldn't clean up {}", dir.display()))?;
+            }
+        }
+    }
+
+    println!(
+        "{} {} file{}, freeing {}",
+        if args.dry_run {
+            "Would remove"
+        } else {
+            "Removed"
+        },
+        to_remove.len(),
+        if to_remove.len() == 1 { "" } else { "s" },
+        format_size(freed)
+    );
+    Ok(())
+}
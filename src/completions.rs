@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+use std::io::{self, prelude::*};
+
+use anyhow::*;
+use structopt::clap::{App, Shell};
+use structopt::*;
+
+use crate::deps::mod_name;
+use crate::profile::*;
+
+/// Prints a shell completion script
+///
+/// Besides commands and flags, the bash, zsh, fish, and PowerShell scripts
+/// complete installed mods' names for `remove`, `show`, `update`, and `diff`
+/// by asking modman (so they're always up to date). Those come from the profile
+/// in the working directory, or the one named by MODMAN_PROFILE.
+///
+/// For example, add this to your ~/.bashrc:
+///
+///     source <(modman completions bash)
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    /// bash, zsh, fish, powershell, or elvish
+    #[structopt(name = "SHELL", required_unless = "mods")]
+    shell: Option<Shell>,
+
+    /// Print the names of installed mods, one per line, for the scripts above.
+    #[structopt(long, hidden = true)]
+    mods: bool,
+}
+
+/// Subcommands that take installed mods' names.
+static MOD_COMMANDS: &[&str] = &["remove", "show", "update", "diff"];
+
+pub fn run(args: Args, mut app: App) -> Result<()> {
+    if args.mods {
+        return print_mod_names();
+    }
+    let shell = args.shell.unwrap();
+
+    let mut script = Vec::new();
+    app.gen_completions_to("modman", shell, &mut script);
+    let script = String::from_utf8(script).expect("clap generated a non-UTF-8 script");
+
+    let script = match shell {
+        Shell::Bash => script + BASH_MODS,
+        Shell::Zsh => zsh_with_mods(&script),
+        Shell::Fish => script + &fish_mods(),
+        Shell::PowerShell => powershell_with_mods(&script),
+        Shell::Elvish => script,
+    };
+    io::stdout().write_all(script.as_bytes())?;
+    Ok(())
+}
+
+/// Prints the shortest name `Profile::find_mod` knows each installed mod by:
+/// its alias, its name if no other mod has the same one, or its path.
+fn print_mod_names() -> Result<()> {
+    let p = load_and_check_profile()?;
+
+    let mut name_counts = BTreeMap::new();
+    for mod_path in p.mods.keys() {
+        *name_counts
+            .entry(mod_name(mod_path).to_lowercase())
+            .or_insert(0) += 1;
+    }
+
+    for (mod_path, manifest) in &p.mods {
+        let name = mod_name(mod_path);
+        if let Some(alias) = &manifest.alias {
+            println!("{}", alias);
+        } else if name_counts[&name.to_lowercase()] == 1 {
+            println!("{}", name);
+        } else {
+            println!("{}", mod_path.display());
+        }
+    }
+    Ok(())
+}
+
+// Takes over from clap's completion function for the commands that take mods.
+static BASH_MODS: &str = r#"
+_modman_with_mods() {
+    local i cmd=""
+    for i in "${COMP_WORDS[@]:1:COMP_CWORD-1}"; do
+        case "$i" in
+            remove|show|update|diff) cmd="$i" ;;
+        esac
+    done
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    if [[ -n "$cmd" && "$cur" != -* ]]; then
+        local IFS=$'\n'
+        COMPREPLY=( $(compgen -W "$(modman completions --mods 2>/dev/null)" -- "$cur") )
+        return 0
+    fi
+    _modman "$@"
+}
+
+complete -F _modman_with_mods -o bashdefault -o default modman
+"#;
+
+/// Swaps the file completion clap gives <MOD> for installed mods
+/// in the commands that take them.
+fn zsh_with_mods(script: &str) -> String {
+    let mut out = String::with_capacity(script.len());
+    let mut command = "";
+    for line in script.lines() {
+        // Each subcommand's arguments are in a case like `(remove)`.
+        let trimmed = line.trim();
+        if let Some(c) = trimmed.strip_prefix('(').and_then(|l| l.strip_suffix(')')) {
+            command = c;
+        }
+        if line.trim_end() == r#"_modman "$@""# {
+            out += "_modman_mods() {\n    \
+                    local -a mods\n    \
+                    mods=(${(f)\"$(modman completions --mods 2>/dev/null)\"})\n    \
+                    compadd -a mods\n\
+                    }\n\n";
+        }
+        if MOD_COMMANDS.contains(&command) && line.contains(":MOD:_files") {
+            out += &line.replace(":MOD:_files", ":MOD:_modman_mods");
+        } else {
+            out += line;
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn fish_mods() -> String {
+    format!(
+        "complete -c modman -n \"__fish_seen_subcommand_from {}\" -f \
+         -a \"(modman completions --mods 2>/dev/null)\"\n",
+        MOD_COMMANDS.join(" ")
+    )
+}
+
+/// Adds installed mods to the completions for the commands that take them.
+fn powershell_with_mods(script: &str) -> String {
+    let mut out = String::with_capacity(script.len());
+    for line in script.lines() {
+        out += line;
+        out.push('\n');
+        let takes_mods = MOD_COMMANDS
+            .iter()
+            .any(|c| line.trim() == format!("'modman;{}' {{", c));
+        if takes_mods {
+            out += "            modman completions --mods 2>$null | ForEach-Object {\n                \
+                    [CompletionResult]::new($_, $_, [CompletionResultType]::ParameterValue, $_)\n            \
+                    }\n";
+        }
+    }
+    out
+}
@@ -0,0 +1,114 @@
+//! Several mods' files, read as one mod
+//! (say, a base archive and a patch for it from somewhere else).
+
+use std::collections::BTreeMap;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::*;
+use semver::Version;
+
+use crate::mod_config::ModConfig;
+use crate::modification::Mod;
+
+/// Merges the files of several mods into one.
+///
+/// The first mod speaks for the whole: its version, README, CHANGELOG,
+/// and mod.toml are the composite's. No two mods can have the same file.
+pub struct CompositeMod {
+    parts: Vec<Box<dyn Mod>>,
+    /// Which part each file comes from
+    owners: BTreeMap<PathBuf, usize>,
+}
+
+impl CompositeMod {
+    pub fn new(parts: Vec<Box<dyn Mod>>) -> Result<Self> {
+        if parts.is_empty() {
+            bail!("A composite mod needs at least one mod");
+        }
+        let mut owners = BTreeMap::new();
+        for (i, part) in parts.iter().enumerate() {
+            for path in part.paths()? {
+                if owners.insert(path.clone(), i).is_some() {
+                    bail!(
+                        "{} is in more than one of the mods being combined",
+                        path.display()
+                    );
+                }
+            }
+        }
+        Ok(Self { parts, owners })
+    }
+
+    fn part(&self, p: &Path) -> Result<&dyn Mod> {
+        match self.owners.get(p) {
+            Some(i) => Ok(&*self.parts[*i]),
+            None => bail!("None of the combined mods have {}", p.display()),
+        }
+    }
+}
+
+impl Mod for CompositeMod {
+    fn paths(&self) -> Result<Vec<PathBuf>> {
+        Ok(self.owners.keys().cloned().collect())
+    }
+
+    fn read_file<'a>(&'a self, p: &Path) -> Result<Box<dyn Read + Send + 'a>> {
+        self.part(p)?.read_file(p)
+    }
+
+    fn streaming_only(&self) -> bool {
+        self.parts.iter().any(|part| part.streaming_only())
+    }
+
+    fn prefetch(&self, paths: &[PathBuf]) {
+        let mut per_part = vec![Vec::new(); self.parts.len()];
+        for p in paths {
+            if let Some(i) = self.owners.get(p) {
+                per_part[*i].push(p.clone());
+            }
+        }
+        for (part, paths) in self.parts.iter().zip(per_part) {
+            if !paths.is_empty() {
+                part.prefetch(&paths);
+            }
+        }
+    }
+
+    fn symlink(&self, p: &Path) -> Result<Option<PathBuf>> {
+        self.part(p)?.symlink(p)
+    }
+
+    fn mode(&self, p: &Path) -> Result<Option<u32>> {
+        self.part(p)?.mode(p)
+    }
+
+    fn mtime(&self, p: &Path) -> Result<Option<SystemTime>> {
+        self.part(p)?.mtime(p)
+    }
+
+    fn size(&self, p: &Path) -> Result<u64> {
+        self.part(p)?.size(p)
+    }
+
+    fn version(&self) -> &Version {
+        self.parts[0].version()
+    }
+
+    fn version_string(&self) -> &str {
+        self.parts[0].version_string()
+    }
+
+    fn readme(&self) -> &str {
+        self.parts[0].readme()
+    }
+
+    fn changelog(&self) -> Option<&str> {
+        self.parts[0].changelog()
+    }
+
+    fn config(&self) -> &ModConfig {
+        self.parts[0].config()
+    }
+}
@@ -0,0 +1,133 @@
+//! Binary patches: mods that ship a delta against a game file
+//! instead of a whole replacement for it.
+//!
+//! For big files (texture packs, audio banks, etc.), a delta can be a tiny
+//! fraction of the full file. A mod declares its patches in its mod.toml:
+//!
+//! ```toml
+//! [patch."Data/textures.pak"]
+//! file = "Data/textures.pak.bsdiff"
+//! original-hash = "<SHA-224 of the game file the patch was made against>"
+//! ```
+//!
+//! At install time, modman checks that the game file is the one the patch
+//! was made for, backs it up, and installs the patched result.
+//! (`file` is installed nowhere; it only holds the delta.)
+//!
+//! Patches are in the bsdiff format - `modman make-patch` makes them.
+
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use serde_derive::Deserialize;
+use structopt::*;
+
+use crate::file_utils::*;
+use crate::mod_config::MOD_CONFIG_NAME;
+use crate::profile::*;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PatchConfig {
+    /// The mod file (relative to its base directory) holding the delta
+    pub file: PathBuf,
+    /// The game file the patch applies to must hash to this.
+    pub original_hash: FileHash,
+}
+
+/// Makes sure the game file at game_path is the one the patch was made for.
+pub fn check_patch_target(patch: &PatchConfig, target: &Path, game_path: &Path) -> Result<()> {
+    if !game_path.exists() {
+        bail!(
+            "{} patches {}, but the game doesn't have that file",
+            patch.file.display(),
+            target.display()
+        );
+    }
+    if hash_file(game_path)? != patch.original_hash {
+        bail!(
+            "{} patches {}, but the game's copy isn't the one the patch was made for \
+             (was the game updated?)",
+            patch.file.display(),
+            target.display()
+        );
+    }
+    Ok(())
+}
+
+/// Patches the game file with the delta from patch_reader.
+/// (Check it with check_patch_target() first!)
+pub fn apply_patch<R: Read>(
+    patch: &PatchConfig,
+    game_path: &Path,
+    patch_reader: &mut R,
+) -> Result<Vec<u8>> {
+    let original =
+        fs::read(game_path).with_context(|| format!("Couldn't read {}", game_path.display()))?;
+    patch_contents(patch, &original, patch_reader)
+}
+
+/// Like apply_patch(), given the contents of the file to patch.
+pub fn patch_contents<R: Read>(
+    patch: &PatchConfig,
+    original: &[u8],
+    patch_reader: &mut R,
+) -> Result<Vec<u8>> {
+    let mut patched = Vec::new();
+    bsdiff::patch(original, patch_reader, &mut patched)
+        .with_context(|| format!("Couldn't apply {}", patch.file.display()))?;
+    Ok(patched)
+}
+
+/// Makes a binary patch for a mod
+///
+/// Writes a bsdiff patch that turns <ORIGINAL> into <MODDED> to <PATCH>,
+/// then prints what to add to the mod's mod.toml.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    #[structopt(name = "ORIGINAL")]
+    original: PathBuf,
+
+    #[structopt(name = "MODDED")]
+    modded: PathBuf,
+
+    #[structopt(name = "PATCH")]
+    patch: PathBuf,
+}
+
+impl Args {
+    pub fn resolve_paths(&mut self, base: &Path) {
+        self.original = base.join(&self.original);
+        self.modded = base.join(&self.modded);
+        self.patch = base.join(&self.patch);
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let original = fs::read(&args.original)
+        .with_context(|| format!("Couldn't read {}", args.original.display()))?;
+    let modded = fs::read(&args.modded)
+        .with_context(|| format!("Couldn't read {}", args.modded.display()))?;
+
+    let mut patch = Vec::new();
+    bsdiff::diff(&original, &modded, &mut patch).context("Couldn't diff files")?;
+    fs::write(&args.patch, &patch)
+        .with_context(|| format!("Couldn't write {}", args.patch.display()))?;
+
+    let original_hash = hash_contents(&mut original.as_slice())?;
+    println!(
+        "Add this to {} (with paths relative to the mod's base directory):\n",
+        MOD_CONFIG_NAME
+    );
+    println!("[patch.\"{}\"]", file_name(&args.original));
+    println!("file = \"{}\"", file_name(&args.patch));
+    println!("original-hash = \"{:x}\"", original_hash.bytes);
+    Ok(())
+}
+
+fn file_name(p: &Path) -> std::borrow::Cow<'_, str> {
+    p.file_name().unwrap_or_default().to_string_lossy()
+}
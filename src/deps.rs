@@ -0,0 +1,288 @@
+//! Dependencies and conflicts between mods, declared in their mod.toml:
+//!
+//! ```toml
+//! requires = ["other-mod >= 1.2"]
+//! conflicts = ["some-mod"]
+//! ```
+//!
+//! Mods are named by their file name, sans any extension
+//! (so `mods/other-mod.zip` is `other-mod`).
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use semver::{Version, VersionReq};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::failure::*;
+use crate::modification::Mod;
+use crate::profile::*;
+
+/// A mod name, optionally followed by a version requirement
+/// (`other-mod`, `other-mod >= 1.2`, `other-mod ^2`, etc.)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ModSpec {
+    spec: String,
+    pub name: String,
+    pub version: VersionReq,
+}
+
+impl ModSpec {
+    /// Returns true if the given mod is the one we're describing.
+    pub fn matches(&self, mod_path: &Path, version: &Version) -> bool {
+        mod_name(mod_path) == self.name && self.version.matches(version)
+    }
+}
+
+impl TryFrom<String> for ModSpec {
+    type Error = Error;
+
+    fn try_from(spec: String) -> Result<Self> {
+        let trimmed = spec.trim();
+        let name_end = trimmed
+            .find(|c: char| c.is_whitespace() || "<>=^~*".contains(c))
+            .unwrap_or(trimmed.len());
+        let (name, req) = trimmed.split_at(name_end);
+        if name.is_empty() {
+            bail!("{:?} doesn't start with a mod name", spec);
+        }
+        let version = if req.trim().is_empty() {
+            VersionReq::any()
+        } else {
+            VersionReq::parse(req.trim()).map_err(|e| {
+                format_err!(
+                    "Couldn't parse the version requirement in {:?}: {}",
+                    spec,
+                    e
+                )
+            })?
+        };
+        Ok(Self {
+            name: name.to_owned(),
+            version,
+            spec,
+        })
+    }
+}
+
+impl From<ModSpec> for String {
+    fn from(s: ModSpec) -> String {
+        s.spec
+    }
+}
+
+impl fmt::Display for ModSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.spec.trim())
+    }
+}
+
+/// The name mods use to refer to each other: the file name, sans extension.
+pub fn mod_name(mod_path: &Path) -> String {
+    mod_path
+        .file_stem()
+        .unwrap_or(mod_path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+pub type ModBox = Box<dyn Mod>;
+
+/// A mod we know about (installed or about to be), for checking against.
+struct KnownMod<'a> {
+    path: &'a Path,
+    version: &'a Version,
+    conflicts: &'a [ModSpec],
+}
+
+/// Orders the mods we're about to add so that each comes after the mods it requires,
+/// and makes sure that everything they require is (or will be) installed
+/// and that nothing they conflict with is.
+pub fn order_mods_to_add(
+    to_add: Vec<(PathBuf, ModBox)>,
+    p: &Profile,
+) -> Result<Vec<(PathBuf, ModBox)>> {
+    let mut known: Vec<KnownMod> = p
+        .mods
+        .iter()
+        .map(|(path, manifest)| KnownMod {
+            path,
+            version: &manifest.version,
+            conflicts: &manifest.conflicts,
+        })
+        .collect();
+
+    // Pick the first mod whose requirements are all met, over and over.
+    let mut order: Vec<usize> = Vec::with_capacity(to_add.len());
+    while order.len() < to_add.len() {
+        let ready = (0..to_add.len()).filter(|i| !order.contains(i)).find(|i| {
+            let config = to_add[*i].1.as_ref().config();
+            config
+                .requires
+                .iter()
+                .all(|req| known.iter().any(|k| req.matches(k.path, k.version)))
+        });
+        let next = match ready {
+            Some(i) => i,
+            None => {
+                let remaining: Vec<&(PathBuf, ModBox)> = to_add
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| !order.contains(i))
+                    .map(|(_, m)| m)
+                    .collect();
+                return Err(unmet_requirement(&remaining, &known));
+            }
+        };
+
+        let (path, m) = &to_add[next];
+        check_conflicts(path, m.version(), &m.config().conflicts, &known)?;
+        known.push(KnownMod {
+            path,
+            version: m.version(),
+            conflicts: &m.config().conflicts,
+        });
+        order.push(next);
+    }
+
+    let mut to_add: Vec<Option<(PathBuf, ModBox)>> = to_add.into_iter().map(Some).collect();
+    Ok(order
+        .into_iter()
+        .map(|i| to_add[i].take().unwrap())
+        .collect())
+}
+
+/// Explains why none of the remaining mods can be added.
+fn unmet_requirement(remaining: &[&(PathBuf, ModBox)], known: &[KnownMod]) -> Error {
+    let met = |req: &ModSpec| {
+        known.iter().any(|k| req.matches(k.path, k.version))
+            || remaining
+                .iter()
+                .any(|(path, m)| req.matches(path, m.version()))
+    };
+
+    for (mod_path, m) in remaining {
+        let req = match m.config().requires.iter().find(|req| !met(req)) {
+            Some(r) => r,
+            None => continue,
+        };
+        let same_name = known
+            .iter()
+            .map(|k| (k.path, k.version))
+            .chain(
+                remaining
+                    .iter()
+                    .map(|(path, m)| (path.as_path(), m.version())),
+            )
+            .find(|(path, _)| mod_name(path) == req.name);
+        return match same_name {
+            Some((path, version)) => failure(
+                Failure::Conflict,
+                format!(
+                    "{} requires {}, but {} is version {}",
+                    mod_path.display(),
+                    req,
+                    path.display(),
+                    version
+                ),
+            ),
+            None => failure(
+                Failure::Conflict,
+                format!(
+                    "{} requires {}, which isn't installed. Add it first (or along with {}).",
+                    mod_path.display(),
+                    req,
+                    mod_path.display()
+                ),
+            ),
+        };
+    }
+
+    // Everything they need is here, so they must need each other.
+    let names: Vec<String> = remaining
+        .iter()
+        .map(|(path, _)| path.display().to_string())
+        .collect();
+    failure(
+        Failure::Conflict,
+        format!("{} require each other", names.join(", ")),
+    )
+}
+
+fn check_conflicts(
+    mod_path: &Path,
+    version: &Version,
+    conflicts: &[ModSpec],
+    known: &[KnownMod],
+) -> Result<()> {
+    for k in known {
+        if let Some(c) = conflicts.iter().find(|c| c.matches(k.path, k.version)) {
+            fail!(
+                Failure::Conflict,
+                "{} can't be installed along with {}: {} conflicts with {}",
+                mod_path.display(),
+                k.path.display(),
+                mod_path.display(),
+                c
+            );
+        }
+        if let Some(c) = k.conflicts.iter().find(|c| c.matches(mod_path, version)) {
+            fail!(
+                Failure::Conflict,
+                "{} can't be installed along with {}: {} conflicts with {}",
+                mod_path.display(),
+                k.path.display(),
+                k.path.display(),
+                c
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Orders every installed mod for removal,
+/// so that each comes before the mods it requires
+/// (the reverse of an order they could have been installed in).
+pub fn order_mods_to_remove(p: &Profile) -> Vec<PathBuf> {
+    let mut order: Vec<PathBuf> = Vec::with_capacity(p.mods.len());
+    while order.len() < p.mods.len() {
+        let mut remaining = p.mods.keys().filter(|m| !order.contains(m));
+        // `modman add` won't install mods that require each other,
+        // but if someone edited the profile to do so, just take the next one.
+        let next = remaining
+            .clone()
+            .find(|m| dependents(m, p, &order).is_empty())
+            .or_else(|| remaining.next())
+            .unwrap()
+            .clone();
+        order.push(next);
+    }
+    order
+}
+
+/// Returns installed mods (besides those in `removing`)
+/// that require the given one, and how.
+pub fn dependents<'a>(
+    mod_path: &Path,
+    p: &'a Profile,
+    removing: &[PathBuf],
+) -> Vec<(&'a Path, &'a ModSpec)> {
+    let version = match p.mods.get(mod_path) {
+        Some(m) => &m.version,
+        None => return Vec::new(),
+    };
+    p.mods
+        .iter()
+        .filter(|(path, _)| !removing.contains(path))
+        .filter_map(|(path, manifest)| {
+            manifest
+                .requires
+                .iter()
+                .find(|req| req.matches(mod_path, version))
+                .map(|req| (path.as_path(), req))
+        })
+        .collect()
+}
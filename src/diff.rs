@@ -0,0 +1,152 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use log::*;
+use rayon::prelude::*;
+use structopt::*;
+
+use crate::add::install_paths;
+use crate::failure::*;
+use crate::file_utils::*;
+use crate::modification::*;
+use crate::profile::*;
+
+/// Compares a mod's installed files against the mod itself
+///
+/// Re-reads <MOD>, hashes each of its files and the installed copies,
+/// and lists the ones that don't match:
+///
+///     changed  the installed file differs from the mod's
+///     missing  the mod has the file, but it isn't installed
+///     extra    the file was installed, but the mod no longer has it
+///
+/// Files the mod merges or patches into game files can't be compared to the
+/// mod directly, so they're compared to what modman installed instead.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    #[structopt(name = "MOD")]
+    mod_name: PathBuf,
+}
+
+impl Args {
+    pub fn resolve_paths(&mut self, base: &Path) {
+        resolve_mod_name(base, &mut self.mod_name);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Difference {
+    Changed,
+    Missing,
+    Extra,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let p = load_and_check_profile()?;
+    let mod_path = p.find_mod(&args.mod_name)?;
+    let mod_path = mod_path.as_path();
+    let manifest = &p.mods[mod_path];
+
+    let m = open_mod(mod_path, p.unicode_form, manifest.root.as_deref())?;
+    if *m.version() != manifest.version {
+        warn!(
+            "{} is now version {}, but version {} was installed",
+            mod_path.display(),
+            m.version(),
+            manifest.version
+        );
+    }
+    let config = m.config();
+    let mod_files = install_paths(mod_path, &*m)?;
+    // Where each mod file is installed, if it's not where the mod says
+    // (because `modman update` followed a file the game moved).
+    let moved: BTreeMap<&Path, &Path> = manifest
+        .files
+        .iter()
+        .filter_map(|(f, meta)| meta.mod_file.as_deref().map(|source| (source, f.as_path())))
+        .collect();
+
+    let mut differences: Vec<(Difference, PathBuf)> = mod_files
+        .par_iter()
+        .map(|mod_file_path| -> Result<Option<(Difference, PathBuf)>> {
+            let installed_path = moved
+                .get(mod_file_path.as_path())
+                .copied()
+                .unwrap_or(mod_file_path);
+            let game_path = p.game_path(installed_path);
+            if !game_path.exists() && !is_symlink(&game_path) {
+                return Ok(Some((Difference::Missing, mod_file_path.clone())));
+            }
+
+            if let Some(target) = m.symlink(mod_file_path)? {
+                let installed = fs::read_link(&game_path).ok();
+                return Ok(if installed.as_ref() == Some(&target) {
+                    None
+                } else {
+                    Some((Difference::Changed, mod_file_path.clone()))
+                });
+            }
+            let game_hash = hash_file(&game_path)?;
+
+            let expected = if config.merge.contains_key(mod_file_path)
+                || config.patch.contains_key(mod_file_path)
+            {
+                match manifest.files.get(installed_path) {
+                    Some(metadata) => metadata.mod_hash.clone(),
+                    // We never installed it, so we have nothing to compare to.
+                    None => return Ok(Some((Difference::Missing, mod_file_path.clone()))),
+                }
+            } else {
+                let mut reader = m.read_file(mod_file_path)?;
+                hash_contents(&mut reader)
+                    .with_context(|| format!("Couldn't read {}", mod_file_path.display()))?
+            };
+
+            if game_hash == expected {
+                trace!("{} matches", mod_file_path.display());
+                Ok(None)
+            } else {
+                debug!(
+                    "{} hashed to\n{:x},\nexpected {:x}",
+                    game_path.display(),
+                    game_hash.bytes,
+                    expected.bytes
+                );
+                Ok(Some((Difference::Changed, mod_file_path.clone())))
+            }
+        })
+        .filter_map(Result::transpose)
+        .collect::<Result<_>>()?;
+
+    differences.extend(
+        manifest
+            .files
+            .iter()
+            .filter(|(f, meta)| !mod_files.iter().any(|m| m == meta.mod_file(f)))
+            .map(|(f, _)| (Difference::Extra, f.clone())),
+    );
+    differences.sort();
+
+    for (difference, path) in &differences {
+        let label = match difference {
+            Difference::Changed => "changed",
+            Difference::Missing => "missing",
+            Difference::Extra => "extra",
+        };
+        println!("{:8} {}", label, path.display());
+    }
+
+    if !differences.is_empty() {
+        fail!(
+            Failure::VerificationFailed,
+            "{} of {}'s files differ",
+            differences.len(),
+            mod_path.display()
+        );
+    }
+    println!("{}'s installed files match the mod", mod_path.display());
+    Ok(())
+}
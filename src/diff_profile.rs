@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use structopt::*;
+
+use crate::deps::mod_name;
+use crate::profile::*;
+
+/// Compares the installed mods against another profile
+///
+/// Mods are matched by name (their file or directory name, sans extension),
+/// so profiles from other machines compare fine even if the mods live
+/// somewhere else. Prints what to add, remove, or change to match <OTHER>:
+///
+///     + mod (version)            in <OTHER>, but not installed here
+///     - mod (version)            installed here, but not in <OTHER>
+///     ~ mod (version -> version) installed here at a different version
+///     ! mod (version)            same version, but different files
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    /// List the files that differ.
+    #[structopt(short, long)]
+    files: bool,
+
+    /// Another modman.profile (in JSON)
+    #[structopt(name = "OTHER")]
+    other: PathBuf,
+}
+
+impl Args {
+    pub fn resolve_paths(&mut self, base: &Path) {
+        self.other = base.join(&self.other);
+    }
+}
+
+fn by_name(p: &Profile) -> BTreeMap<String, &ModManifest> {
+    p.mods
+        .iter()
+        .map(|(path, manifest)| (mod_name(path), manifest))
+        .collect()
+}
+
+/// Returns mod files that were added, removed, or have different contents.
+fn differing_files<'a>(ours: &'a ModManifest, theirs: &'a ModManifest) -> Vec<&'a Path> {
+    let mut differing: Vec<&Path> = ours
+        .files
+        .iter()
+        .filter(|(f, meta)| theirs.files.get(*f).map(|m| &m.mod_hash) != Some(&meta.mod_hash))
+        .map(|(f, _)| f.as_path())
+        .collect();
+    differing.extend(
+        theirs
+            .files
+            .keys()
+            .filter(|f| !ours.files.contains_key(*f))
+            .map(PathBuf::as_path),
+    );
+    differing.sort();
+    differing
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let p = load_and_check_profile()?;
+    let other = read_profile_from(&args.other)?;
+
+    let ours = by_name(&p);
+    let theirs = by_name(&other);
+
+    let mut differences = 0;
+    for (name, manifest) in &theirs {
+        if !ours.contains_key(name) {
+            println!("+ {} ({})", name, manifest.version);
+            differences += 1;
+        }
+    }
+    for (name, manifest) in &ours {
+        let their_manifest = match theirs.get(name) {
+            Some(m) => m,
+            None => {
+                println!("- {} ({})", name, manifest.version);
+                differences += 1;
+                continue;
+            }
+        };
+        if manifest.version != their_manifest.version {
+            println!(
+                "~ {} ({} -> {})",
+                name, manifest.version, their_manifest.version
+            );
+            differences += 1;
+            continue;
+        }
+        let files = differing_files(manifest, their_manifest);
+        if !files.is_empty() {
+            println!(
+                "! {} ({}): {} files differ",
+                name,
+                manifest.version,
+                files.len()
+            );
+            if args.files {
+                for f in files {
+                    println!("\t{}", f.display());
+                }
+            }
+            differences += 1;
+        }
+    }
+
+    if differences > 0 {
+        bail!("{} mods differ from {}", differences, args.other.display());
+    }
+    println!("The installed mods match {}", args.other.display());
+    Ok(())
+}
@@ -1,28 +1,59 @@
 use std::fs;
 use std::io::prelude::*;
 use std::path::*;
+use std::time::SystemTime;
 
 use anyhow::*;
 use semver::Version;
 
-use crate::file_utils::collect_file_paths_in_dir;
+use crate::checksums::*;
+use crate::file_utils::{collect_mod_file_paths_in_dir, executable_mode};
+use crate::mod_config::*;
 use crate::modification::Mod;
+use crate::text::decode_text;
+use crate::version_serde::parse_mod_version;
 
 pub struct DirectoryMod {
     base_dir: PathBuf,
     v: Version,
+    /// VERSION.txt, as written
+    raw_v: String,
     r: String,
+    changelog: Option<String>,
+    c: ModConfig,
+    checksums: Option<Checksums>,
 }
 
 impl DirectoryMod {
     pub fn new(path: &Path) -> Result<Self> {
+        Self::open(path, true)
+    }
+
+    /// Opens the mod without reading (or checking) its CHECKSUMS.txt,
+    /// for when we're about to write a new one.
+    pub fn ignoring_checksums(path: &Path) -> Result<Self> {
+        Self::open(path, false)
+    }
+
+    /// The directory holding the mod's files
+    pub fn base_dir(&self) -> &Path {
+        &self.base_dir
+    }
+
+    fn open(path: &Path, use_checksums: bool) -> Result<Self> {
         let dir_iter = fs::read_dir(path)
             .with_context(|| format!("Could not read directory {}", path.display()))?;
 
-        let mut version_info: Option<Version> = None;
+        let mut version_info: Option<(Version, String)> = None;
 
         let mut readme: Option<String> = None;
 
+        let mut changelog: Option<String> = None;
+
+        let mut config: Option<ModConfig> = None;
+
+        let mut checksums: Option<Checksums> = None;
+
         let mut base_dir: Option<PathBuf> = None;
 
         for entry in dir_iter {
@@ -40,27 +71,40 @@ impl DirectoryMod {
                 }
                 "VERSION.txt" => {
                     assert!(version_info.is_none());
-                    let mut vf =
-                        fs::File::open(entry.path()).context("Couldn't open VERSION.txt")?;
-                    let mut version_string = String::new();
-                    vf.read_to_string(&mut version_string)?;
+                    let bytes = fs::read(entry.path()).context("Couldn't open VERSION.txt")?;
                     version_info = Some(
-                        Version::parse(&version_string).context("Couldn't parse version string")?,
+                        parse_mod_version(&decode_text(&bytes, "VERSION.txt"))
+                            .context("Couldn't parse version string")?,
                     );
                 }
                 "README.txt" => {
                     assert!(readme.is_none());
-                    let mut rf =
-                        fs::File::open(entry.path()).context("Couldn't open README.txt")?;
-                    let mut readme_string = String::new();
-                    rf.read_to_string(&mut readme_string)?;
-                    readme = Some(readme_string);
+                    let bytes = fs::read(entry.path()).context("Couldn't open README.txt")?;
+                    readme = Some(decode_text(&bytes, "README.txt"));
+                }
+                "CHANGELOG.txt" => {
+                    assert!(changelog.is_none());
+                    let bytes = fs::read(entry.path()).context("Couldn't open CHANGELOG.txt")?;
+                    changelog = Some(decode_text(&bytes, "CHANGELOG.txt"));
+                }
+                "mod.toml" => {
+                    assert!(config.is_none());
+                    let config_string = fs::read_to_string(entry.path())
+                        .with_context(|| format!("Couldn't open {}", MOD_CONFIG_NAME))?;
+                    config = Some(ModConfig::parse(&config_string)?);
+                }
+                "CHECKSUMS.txt" => {
+                    if use_checksums {
+                        let text = fs::read_to_string(entry.path())
+                            .with_context(|| format!("Couldn't open {}", CHECKSUMS_NAME))?;
+                        checksums = Some(parse_checksums(&text)?);
+                    }
                 }
                 _ => {
                     if entry.file_type()?.is_dir() && base_dir.is_none() {
                         base_dir = Some(entry.path());
                     } else {
-                        bail!("{} contains things besides a README.txt, a VERSION.txt, and one base directory.",
+                        bail!("{} contains things besides a README.txt, a VERSION.txt, a CHANGELOG.txt, a mod.toml, a CHECKSUMS.txt, and one base directory.",
                                            path.display());
                     }
                 }
@@ -77,31 +121,104 @@ impl DirectoryMod {
             bail!("Couldn't find a base directory");
         }
 
-        Ok(DirectoryMod {
+        let (v, raw_v) = version_info.unwrap();
+        let m = DirectoryMod {
             base_dir: base_dir.unwrap(),
-            v: version_info.unwrap(),
+            v,
+            raw_v,
             r: readme.unwrap(),
-        })
+            changelog,
+            c: config.unwrap_or_default(),
+            checksums,
+        };
+        if let Some(c) = &m.checksums {
+            let mut files = Vec::new();
+            for p in m.paths()? {
+                if m.symlink(&p)?.is_none() {
+                    files.push(p);
+                }
+            }
+            check_listed(c, &files)?;
+        }
+        Ok(m)
     }
 }
 
 impl Mod for DirectoryMod {
     fn paths(&self) -> Result<Vec<PathBuf>> {
-        collect_file_paths_in_dir(&self.base_dir)
+        collect_mod_file_paths_in_dir(&self.base_dir, self.c.symlinks)
+    }
+
+    fn symlink(&self, p: &Path) -> Result<Option<PathBuf>> {
+        if self.c.symlinks != SymlinkMode::Preserve {
+            return Ok(None);
+        }
+        let whole_path = self.base_dir.join(p);
+        let metadata = fs::symlink_metadata(&whole_path)
+            .with_context(|| format!("Couldn't stat mod file ({})", whole_path.display()))?;
+        if !metadata.file_type().is_symlink() {
+            return Ok(None);
+        }
+        let target = fs::read_link(&whole_path)
+            .with_context(|| format!("Couldn't read symbolic link {}", whole_path.display()))?;
+
+        // Relative links to other things in the mod work just as well
+        // once it's installed, but ones to things outside it won't.
+        // Point those at the full path instead.
+        if target.is_relative() {
+            let resolved = fs::canonicalize(whole_path.parent().unwrap().join(&target));
+            let base_dir = fs::canonicalize(&self.base_dir);
+            if let (Ok(resolved), Ok(base_dir)) = (resolved, base_dir) {
+                if !resolved.starts_with(&base_dir) {
+                    return Ok(Some(resolved));
+                }
+            }
+        }
+        Ok(Some(target))
     }
 
     fn read_file<'a>(&'a self, p: &Path) -> Result<Box<dyn Read + Send + 'a>> {
         let whole_path = self.base_dir.join(p);
         let f = fs::File::open(&whole_path)
             .with_context(|| format!("Couldn't open mod file ({})", whole_path.display()))?;
-        Ok(Box::new(f))
+        checked_reader(Box::new(f), self.checksums.as_ref(), p)
+    }
+
+    fn mode(&self, p: &Path) -> Result<Option<u32>> {
+        executable_mode(&self.base_dir.join(p))
+    }
+
+    fn mtime(&self, p: &Path) -> Result<Option<SystemTime>> {
+        let whole_path = self.base_dir.join(p);
+        let metadata = fs::metadata(&whole_path)
+            .with_context(|| format!("Couldn't stat mod file ({})", whole_path.display()))?;
+        Ok(metadata.modified().ok())
+    }
+
+    fn size(&self, p: &Path) -> Result<u64> {
+        let whole_path = self.base_dir.join(p);
+        let metadata = fs::metadata(&whole_path)
+            .with_context(|| format!("Couldn't stat mod file ({})", whole_path.display()))?;
+        Ok(metadata.len())
     }
 
     fn version(&self) -> &Version {
         &self.v
     }
 
+    fn version_string(&self) -> &str {
+        &self.raw_v
+    }
+
     fn readme(&self) -> &str {
         &self.r
     }
+
+    fn changelog(&self) -> Option<&str> {
+        self.changelog.as_deref()
+    }
+
+    fn config(&self) -> &ModConfig {
+        &self.c
+    }
 }
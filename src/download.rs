@@ -0,0 +1,110 @@
+//! Fetching mods from URLs
+//!
+//! Downloads are kept in `downloads/` in the storage directory
+//! (`modman-backup/` unless it's been moved) so that the mod
+//! stays around for `modman update`, `modman check`, and friends.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use log::*;
+use sha2::{Digest, Sha224};
+
+use crate::file_utils::*;
+use crate::profile::*;
+
+pub fn downloads_dir() -> PathBuf {
+    storage_dir().join("downloads")
+}
+
+/// Returns true if the given mod source is a URL instead of a path.
+pub fn is_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+/// Returns where the mod at the given URL is (or would be) downloaded to.
+pub fn download_path(url: &str) -> PathBuf {
+    // Name it after the URL's last segment,
+    // but prefix it with (part of) the URL's hash so that
+    // https://a.com/mod.zip and https://b.com/mod.zip don't collide.
+    let name = url
+        .split(['?', '#'])
+        .next()
+        .unwrap()
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .filter(|n| !n.is_empty())
+        .unwrap_or("mod.zip");
+    let hash = Sha224::digest(url.as_bytes());
+    downloads_dir().join(format!("{}-{}", hex::encode(&hash[..4]), name))
+}
+
+/// Downloads the mod at the given URL (unless we already have),
+/// and returns its path.
+pub fn fetch(url: &str) -> Result<PathBuf> {
+    let path = download_path(url);
+    if path.exists() {
+        debug!("Already downloaded {} to {}", url, path.display());
+        return Ok(path);
+    }
+    download(url, &path)?;
+    Ok(path)
+}
+
+/// Downloads the mod at the given URL again (even if we already have it)
+/// to a file next to our copy, and returns its path.
+///
+/// `modman outdated` uses this to see if there's a newer version.
+pub fn fetch_latest(url: &str) -> Result<PathBuf> {
+    let mut latest = download_path(url).into_os_string();
+    latest.push(".latest");
+    let latest = PathBuf::from(latest);
+    download(url, &latest)?;
+    Ok(latest)
+}
+
+#[cfg(feature = "download")]
+fn download(url: &str, path: &Path) -> Result<()> {
+    check_writable().with_context(|| format!("Can't download {}", url))?;
+    let storage = storage_dir();
+    if !storage.is_dir() {
+        bail!(
+            "Couldn't find the backup directory ({}/)",
+            storage.display()
+        );
+    }
+    let downloads = downloads_dir();
+    fs::create_dir_all(&downloads)
+        .with_context(|| format!("Couldn't create {}", downloads.display()))?;
+
+    info!("Downloading {}...", url);
+    let response = ureq::get(url)
+        .call()
+        .with_context(|| format!("Couldn't download {}", url))?;
+
+    // Download to a .part file first so we never mistake half a download
+    // for the whole thing.
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    let part = PathBuf::from(part);
+    let mut f =
+        create_file(&part).with_context(|| format!("Couldn't create {}", part.display()))?;
+    io::copy(&mut response.into_body().into_reader(), &mut f)
+        .with_context(|| format!("Couldn't download {}", url))?;
+    f.sync_all()?;
+    drop(f);
+    rename_file(&part, path).with_context(|| format!("Couldn't rename {}", part.display()))?;
+    debug!("Downloaded {} to {}", url, path.display());
+    Ok(())
+}
+
+#[cfg(not(feature = "download"))]
+fn download(url: &str, _path: &Path) -> Result<()> {
+    bail!(
+        "Can't download {}: modman was built without download support",
+        url
+    )
+}
@@ -0,0 +1,162 @@
+//! Asking for administrator rights when the game's directory can't be
+//! changed otherwise, as with games installed under Program Files.
+//!
+//! Rather than failing partway through, commands that change the game's
+//! files first check that they can write its directories (with `--elevate`
+//! or `modman elevate on`). If they can't, modman runs itself again
+//! with administrator rights (on Windows, asking through UAC),
+//! waits for it, and passes along what it printed.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use log::*;
+use structopt::*;
+
+use crate::file_utils::run_id;
+use crate::profile::*;
+use crate::profile_store::open_profile_store;
+
+/// Shows or sets whether to ask for administrator rights when needed
+///
+/// When it's on (or modman is run with --elevate), commands that change
+/// the game's files first make sure they can write to its directories.
+/// If they can't (say, the game is under Program Files), modman asks
+/// for administrator rights and runs the command again with them.
+/// (This is only possible on Windows; elsewhere, it just stops before
+/// changing anything.)
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    #[structopt(name = "SETTING", possible_values = &["on", "off"])]
+    setting: Option<String>,
+}
+
+impl Args {
+    /// Returns true if we'll change the profile (not just show it).
+    pub fn is_changing(&self) -> bool {
+        self.setting.is_some()
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let (mut store, mut p) = open_profile()?;
+    match args.setting.as_deref() {
+        Some(s) => {
+            p.elevate = s == "on";
+            store.save_settings(&p)
+        }
+        None => {
+            println!("{}", if p.elevate { "on" } else { "off" });
+            Ok(())
+        }
+    }
+}
+
+/// Returns true if the profile has elevation on.
+/// If there's no profile (or it can't be read), it doesn't.
+pub fn profile_elevates() -> bool {
+    open_profile_store()
+        .and_then(|mut s| s.load_settings())
+        .is_ok_and(|p| p.elevate)
+}
+
+/// Returns the first of the profile's directories and the game's
+/// that we aren't allowed to write to, if any.
+pub fn unwritable_dir() -> Result<Option<PathBuf>> {
+    let p = open_profile_store()?.load_settings()?;
+    let mut to_check = vec![PathBuf::from("."), p.root_directory];
+    to_check.extend(p.roots.into_values());
+    for dir in to_check {
+        let probe = dir.join(format!(".modman-write-check-{}", run_id()));
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&probe)
+        {
+            Ok(_) => {
+                let _ = fs::remove_file(&probe);
+            }
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                debug!("Can't write to {}: {}", dir.display(), e);
+                return Ok(Some(dir));
+            }
+            // Other problems (a missing directory, etc.) are the command's to report.
+            Err(_) => (),
+        }
+    }
+    Ok(None)
+}
+
+/// Runs modman again with the same arguments (from `launch_dir`,
+/// where we started) and administrator rights, printing what it printed.
+/// Returns its exit code.
+#[cfg(windows)]
+pub fn relaunch_elevated(unwritable: &Path, launch_dir: &Path) -> Result<i32> {
+    use std::env;
+    use std::process::Command;
+
+    info!(
+        "{} isn't writable without administrator rights; asking for them",
+        unwritable.display()
+    );
+    let exe = env::current_exe().context("Couldn't find modman's executable")?;
+    let output = env::temp_dir().join(format!("modman-elevated-{}.txt", run_id()));
+
+    // UAC won't hand us the elevated process's output,
+    // so have cmd redirect it to a file we can print afterwards.
+    let mut command_line = format!(
+        "cd /d {} && {} --elevated",
+        quote(launch_dir.as_os_str())?,
+        quote(exe.as_os_str())?
+    );
+    for arg in env::args_os().skip(1) {
+        command_line += " ";
+        command_line += &quote(&arg)?;
+    }
+    command_line += &format!(" > {} 2>&1", quote(output.as_os_str())?);
+    let script = format!(
+        "$p = Start-Process -FilePath cmd.exe -ArgumentList '/s /c \"{}\"' \
+         -Verb RunAs -Wait -PassThru -WindowStyle Hidden; exit $p.ExitCode",
+        command_line.replace('\'', "''")
+    );
+    let status = Command::new("powershell")
+        .args(&["-NoProfile", "-NonInteractive", "-Command", &script])
+        .status()
+        .context("Couldn't run PowerShell to ask for administrator rights")?;
+
+    let printed = match fs::read(&output) {
+        Ok(p) => p,
+        Err(_) => bail!(
+            "Couldn't get administrator rights (was the prompt declined?), \
+             so {} can't be changed.",
+            unwritable.display()
+        ),
+    };
+    let _ = fs::remove_file(&output);
+    print!("{}", String::from_utf8_lossy(&printed));
+    Ok(status.code().unwrap_or(1))
+}
+
+#[cfg(not(windows))]
+pub fn relaunch_elevated(unwritable: &Path, _launch_dir: &Path) -> Result<i32> {
+    bail!(
+        "{} isn't writable, and modman can only ask for administrator rights on Windows. \
+         Run it as a user who can write there.",
+        unwritable.display()
+    )
+}
+
+/// Quotes an argument for cmd.exe, refusing ones we can't pass through it safely.
+#[cfg(windows)]
+fn quote(arg: &std::ffi::OsStr) -> Result<String> {
+    let arg = arg
+        .to_str()
+        .ok_or_else(|| format_err!("Can't pass {:?} to an elevated modman", arg))?;
+    if arg.contains(['"', '%']) {
+        bail!("Can't pass {} to an elevated modman", arg);
+    }
+    Ok(format!("\"{}\"", arg))
+}
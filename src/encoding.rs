@@ -1,2 +1,110 @@
-pub static UTF8_ONLY: &str = "modman doesn't currently support file paths \
-                              that aren't valid UTF-8";
+//! Encoding arbitrary OS paths as plain text and back
+
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+
+/// Encodes a path as UTF-8 text without whitespace,
+/// so that it can be written to a line-based file (like the journal)
+/// and read back exactly as it was.
+///
+/// Whitespace, control characters, `%`, and anything that isn't valid Unicode
+/// are percent-escaped: as `%XX` bytes on Unix,
+/// and as `%uXXXX` UTF-16 code units on Windows
+/// (thanks, Windows "Unicode" strings!).
+pub fn encode_path(p: &Path) -> String {
+    let mut encoded = String::new();
+    encode_os_str(p.as_os_str(), &mut encoded);
+    encoded
+}
+
+/// Decodes a path encoded by encode_path().
+pub fn decode_path(s: &str) -> Result<PathBuf> {
+    decode_os_str(s)
+        .map(PathBuf::from)
+        .ok_or_else(|| format_err!("Couldn't decode path {}", s))
+}
+
+fn needs_escape(c: char) -> bool {
+    c == '%' || c.is_whitespace() || c.is_control()
+}
+
+#[cfg(unix)]
+fn encode_os_str(s: &std::ffi::OsStr, out: &mut String) {
+    use std::os::unix::ffi::OsStrExt;
+
+    for chunk in s.as_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if needs_escape(c) {
+                for b in c.encode_utf8(&mut [0; 4]).bytes() {
+                    write!(out, "%{:02X}", b).unwrap();
+                }
+            } else {
+                out.push(c);
+            }
+        }
+        for b in chunk.invalid() {
+            write!(out, "%{:02X}", b).unwrap();
+        }
+    }
+}
+
+#[cfg(unix)]
+fn decode_os_str(s: &str) -> Option<OsString> {
+    use std::os::unix::ffi::OsStringExt;
+
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    Some(OsString::from_vec(bytes))
+}
+
+#[cfg(windows)]
+fn encode_os_str(s: &std::ffi::OsStr, out: &mut String) {
+    use std::os::windows::ffi::OsStrExt;
+
+    for decoded in std::char::decode_utf16(s.encode_wide()) {
+        match decoded {
+            Ok(c) if !needs_escape(c) => out.push(c),
+            Ok(c) => {
+                for unit in c.encode_utf16(&mut [0; 2]) {
+                    write!(out, "%u{:04X}", unit).unwrap();
+                }
+            }
+            // An unpaired surrogate
+            Err(e) => write!(out, "%u{:04X}", e.unpaired_surrogate()).unwrap(),
+        }
+    }
+}
+
+#[cfg(windows)]
+fn decode_os_str(s: &str) -> Option<OsString> {
+    use std::os::windows::ffi::OsStringExt;
+
+    let mut units = Vec::with_capacity(s.len());
+    let mut iter = s.chars();
+    while let Some(c) = iter.next() {
+        if c == '%' {
+            if iter.next()? != 'u' {
+                return None;
+            }
+            let hex: String = iter.by_ref().take(4).collect();
+            if hex.len() != 4 {
+                return None;
+            }
+            units.push(u16::from_str_radix(&hex, 16).ok()?);
+        } else {
+            units.extend_from_slice(c.encode_utf16(&mut [0; 2]));
+        }
+    }
+    Some(OsString::from_wide(&units))
+}
@@ -0,0 +1,58 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use log::*;
+use structopt::*;
+
+use crate::bundle::*;
+use crate::failure::*;
+use crate::file_utils::*;
+use crate::profile::*;
+
+/// Bundles the profile and its backups into a single ZIP archive
+///
+/// Use `modman import-profile` to unpack it somewhere else,
+/// e.g., after moving the game to another machine.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    /// Store the root directory relative to the profile in the archive,
+    /// so that it can be imported anywhere with the same layout.
+    #[structopt(long)]
+    relative_root: bool,
+
+    /// The archive to create
+    #[structopt(name = "ARCHIVE")]
+    archive: PathBuf,
+}
+
+impl Args {
+    pub fn resolve_paths(&mut self, base: &Path) {
+        self.archive = base.join(&self.archive);
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let mut p = load_and_check_profile()?;
+
+    if crate::journal::get_journal_path().exists() {
+        fail!(
+            Failure::NeedsRepair,
+            "An interrupted `modman add` needs to be fixed with `modman repair` first."
+        );
+    }
+
+    if args.relative_root {
+        p.root_directory = relative_path(&p.root_directory, Path::new("."))?;
+    } else if p.root_directory.is_absolute() {
+        info!(
+            "The root directory ({}) is an absolute path; \
+             pass --root to `modman import-profile` if it's different there.",
+            p.root_directory.display()
+        );
+    }
+
+    write_bundle(&args.archive, &p)?;
+    info!("Profile and backups exported to {}", args.archive.display());
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use log::*;
+use rayon::prelude::*;
+use structopt::*;
+
+use crate::failure::*;
+use crate::file_utils::*;
+use crate::modification::*;
+use crate::profile::*;
+
+/// Copies files out of a mod without installing them
+///
+/// <MOD> can be an installed mod (named any way `modman show` takes)
+/// or the path to any mod. Files are named the way `modman show --files`
+/// lists them; give some (or directories of them) to copy just those,
+/// or none to copy every file in the mod.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    /// Where to put the files
+    #[structopt(short, long = "to", name = "DEST_DIR", default_value = ".")]
+    to: PathBuf,
+
+    /// Replace files already in <DEST_DIR>.
+    #[structopt(short, long)]
+    force: bool,
+
+    #[structopt(name = "MOD")]
+    mod_name: PathBuf,
+
+    #[structopt(name = "FILE")]
+    files: Vec<PathBuf>,
+}
+
+impl Args {
+    pub fn resolve_paths(&mut self, base: &Path) {
+        resolve_mod_name(base, &mut self.mod_name);
+        self.to = base.join(&self.to);
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let (mod_path, m) = open_named_mod(&args.mod_name)?;
+    let mod_path = mod_path.as_path();
+
+    let all = m.paths()?;
+    let wanted: Vec<&PathBuf> = if args.files.is_empty() {
+        all.iter().collect()
+    } else {
+        if let Some(missing) = args
+            .files
+            .iter()
+            .find(|f| !all.iter().any(|p| p.starts_with(f)))
+        {
+            fail!(
+                Failure::Usage,
+                "{} has no file or directory {}",
+                mod_path.display(),
+                missing.display()
+            );
+        }
+        all.iter()
+            .filter(|p| args.files.iter().any(|f| p.starts_with(f)))
+            .collect()
+    };
+
+    if !args.force {
+        if let Some(existing) = wanted
+            .iter()
+            .map(|p| args.to.join(p))
+            .find(|dest| dest.exists() || is_symlink(dest))
+        {
+            fail!(
+                Failure::Conflict,
+                "{} already exists. Pass --force to replace it.",
+                existing.display()
+            );
+        }
+    }
+
+    let extract = |p: &&PathBuf| extract_file(&*m, p, &args.to.join(p));
+    if m.streaming_only() {
+        wanted.iter().try_for_each(extract)?;
+    } else {
+        wanted.par_iter().try_for_each(extract)?;
+    }
+
+    info!(
+        "Extracted {} files from {} to {}",
+        wanted.len(),
+        mod_path.display(),
+        args.to.display()
+    );
+    Ok(())
+}
+
+/// Opens the installed mod with the given name (as it was installed),
+/// or if there isn't one, the mod at that path.
+fn open_named_mod(name: &Path) -> Result<(PathBuf, Box<dyn Mod>)> {
+    if let Ok(p) = load_and_check_profile() {
+        if let Ok(mod_path) = p.find_mod(name) {
+            let root = p.mods[&mod_path].root.as_deref();
+            let m = open_mod(&mod_path, p.unicode_form, root)?;
+            return Ok((mod_path, m));
+        }
+    }
+    let m = open_mod(name, UnicodeForm::None, None)?;
+    Ok((name.to_owned(), m))
+}
+
+fn extract_file(m: &dyn Mod, p: &Path, dest: &Path) -> Result<()> {
+    debug!("Extracting {} to {}", p.display(), dest.display());
+    let dir = dest.parent().unwrap();
+    fs::create_dir_all(dir).with_context(|| format!("Couldn't create {}", dir.display()))?;
+    if dest.exists() || is_symlink(dest) {
+        remove_file(dest).with_context(|| format!("Couldn't replace {}", dest.display()))?;
+    }
+
+    if let Some(target) = m.symlink(p)? {
+        return make_symlink(&target, dest);
+    }
+    let mut f = create_file(dest).with_context(|| format!("Couldn't create {}", dest.display()))?;
+    io::copy(&mut m.read_file(p)?, &mut f)
+        .with_context(|| format!("Couldn't extract {}", p.display()))?;
+    drop(f);
+    if let Some(mode) = m.mode(p)? {
+        set_mode(dest, mode)?;
+    }
+    if let Some(mtime) = m.mtime(p)? {
+        set_mtime(dest, mtime)?;
+    }
+    Ok(())
+}
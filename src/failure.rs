@@ -0,0 +1,72 @@
+//! Kinds of failures, each with its own exit code,
+//! so scripts can tell what went wrong without parsing error messages.
+//!
+//! Errors are still anyhow errors; the ones scripts might care about
+//! are made with `fail!` (or `failure()`) instead of `bail!`,
+//! and `exit_code()` finds them anywhere in an error's chain.
+
+use std::fmt;
+
+/// Exit codes, for `--help`. Keep this in sync with `Failure`.
+pub static EXIT_CODES_HELP: &str = "EXIT CODES:
+    0    Success
+    1    Any failure not listed below
+    2    Bad command-line arguments
+    3    There's no profile (run `modman init`)
+    4    The profile is broken, or its root directories are missing
+    5    Mods conflict with each other, or need ones that aren't installed
+    6    No installed mod matches the given name (or several do)
+    7    A check found problems (`check`, `diff`, `pack-verify`, etc.)
+    8    An interrupted command needs `modman repair`
+    9    There isn't enough disk space for the mods (and backups)
+    10   Warnings were given in strict mode (see `modman strict`)";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    Other = 1,
+    Usage = 2,
+    NoProfile = 3,
+    BadProfile = 4,
+    Conflict = 5,
+    UnknownMod = 6,
+    VerificationFailed = 7,
+    NeedsRepair = 8,
+    NoSpace = 9,
+    StrictWarnings = 10,
+}
+
+/// An error message and what kind of failure it is
+#[derive(Debug)]
+pub struct Failed {
+    pub failure: Failure,
+    message: String,
+}
+
+impl fmt::Display for Failed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failed {}
+
+/// Makes an error of the given kind, like `format_err!` would.
+pub fn failure(failure: Failure, message: String) -> anyhow::Error {
+    anyhow::Error::new(Failed { failure, message })
+}
+
+/// Like `bail!`, but says what kind of failure it is.
+#[macro_export]
+macro_rules! fail {
+    ($failure:expr, $($arg:tt)*) => {
+        return Err($crate::failure::failure($failure, format!($($arg)*)))
+    };
+}
+
+/// Returns the exit code for the given error:
+/// that of the first `Failed` in its chain, or `Failure::Other`'s.
+pub fn exit_code(e: &anyhow::Error) -> i32 {
+    e.chain()
+        .find_map(|cause| cause.downcast_ref::<Failed>())
+        .map_or(Failure::Other, |f| f.failure) as i32
+}
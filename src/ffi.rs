@@ -0,0 +1,293 @@
+//! A C API for embedding modman in game launchers (see include/modman.h).
+//!
+//! It's a thin layer over the same functions the commands use:
+//! each call reads the profile from disk, does its thing, and saves it.
+//! Calls point modman at the profile's directory (see `set_profile_dir()`)
+//! instead of changing the working directory out from under the program,
+//! and mods' paths are resolved against it, like `--profile` does.
+//! Since that's global state, calls run one at a time (from any thread).
+//!
+//! Failures return the same codes modman exits with (see `modman --help`),
+//! and `modman_last_error()` says what happened.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::Mutex;
+
+use anyhow::*;
+
+use crate::failure::*;
+use crate::profile::*;
+use crate::{add, history, list, progress, remove};
+
+/// A game directory with a modman profile, as returned by `modman_open_profile()`
+pub struct ModmanProfile {
+    dir: PathBuf,
+}
+
+/// Called with each progress event, as a line of JSON (see `--progress json`)
+pub type ModmanProgressCallback =
+    Option<unsafe extern "C" fn(event_json: *const c_char, user_data: *mut c_void)>;
+
+/// Calls set which profile modman uses, so only one runs at a time.
+static CALL: Mutex<()> = Mutex::new(());
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(e: &Error) {
+    // Interior NULs would cut the message short; C can't do better anyway.
+    let message = format!("{:#}", e).replace('\0', "");
+    LAST_ERROR.with(|l| *l.borrow_mut() = CString::new(message).ok());
+}
+
+/// Runs f on the profile in the given directory,
+/// catching panics so they don't unwind into C.
+fn in_dir<T, F: FnOnce() -> Result<T>>(dir: &Path, f: F) -> Result<T> {
+    let _call = CALL.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    set_profile_dir(Some(dir));
+    locate_storage();
+    let result = catch_unwind(AssertUnwindSafe(f))
+        .unwrap_or_else(|_| Err(format_err!("modman panicked (this is a bug)")));
+    set_profile_dir(None);
+    result
+}
+
+/// Turns a result into the code C sees, saving any error for `modman_last_error()`.
+fn to_code(result: Result<()>) -> c_int {
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            set_last_error(&e);
+            exit_code(&e)
+        }
+    }
+}
+
+unsafe fn to_path(s: *const c_char) -> Result<PathBuf> {
+    if s.is_null() {
+        fail!(Failure::Usage, "Got a null path");
+    }
+    let s = CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| failure(Failure::Usage, "Paths must be UTF-8".to_owned()))?;
+    Ok(PathBuf::from(s))
+}
+
+unsafe fn to_paths(paths: *const *const c_char, count: usize) -> Result<Vec<PathBuf>> {
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+    if paths.is_null() {
+        fail!(Failure::Usage, "Got a null array of mods");
+    }
+    std::slice::from_raw_parts(paths, count)
+        .iter()
+        .map(|p| to_path(*p))
+        .collect()
+}
+
+/// Opens the profile in the given game directory,
+/// or returns null (see `modman_last_error()`) if there isn't a usable one.
+///
+/// # Safety
+///
+/// `dir` must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn modman_open_profile(dir: *const c_char) -> *mut ModmanProfile {
+    let opened = to_path(dir).and_then(|dir| {
+        let dir = dir
+            .canonicalize()
+            .with_context(|| format!("Couldn't find {}", dir.display()))?;
+        in_dir(&dir, load_and_check_profile)?;
+        Ok(ModmanProfile { dir })
+    });
+    match opened {
+        Ok(p) => Box::into_raw(Box::new(p)),
+        Err(e) => {
+            set_last_error(&e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Frees a profile from `modman_open_profile()`. Null is fine.
+///
+/// # Safety
+///
+/// `profile` must have come from `modman_open_profile()`, and can't be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn modman_close_profile(profile: *mut ModmanProfile) {
+    if !profile.is_null() {
+        drop(Box::from_raw(profile));
+    }
+}
+
+/// Returns the installed mods as a JSON array of
+/// `{"path", "version", "alias", "tags", "files"}` objects (like `modman serve`'s `list`),
+/// or null on failure. Free it with `modman_free_string()`.
+///
+/// # Safety
+///
+/// `profile` must have come from `modman_open_profile()`.
+#[no_mangle]
+pub unsafe extern "C" fn modman_list(profile: *const ModmanProfile) -> *mut c_char {
+    let listed = in_dir(&(*profile).dir, || {
+        let json = serde_json::to_string(&list::listed_mods()?)?;
+        Ok(CString::new(json)?)
+    });
+    match listed {
+        Ok(s) => s.into_raw(),
+        Err(e) => {
+            set_last_error(&e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Installs the given mods (paths relative to the game directory, or absolute),
+/// like `modman add`. Returns 0 on success, or one of modman's exit codes.
+///
+/// # Safety
+///
+/// `profile` must have come from `modman_open_profile()`,
+/// and `mods` must point to `count` valid C strings.
+#[no_mangle]
+pub unsafe extern "C" fn modman_add(
+    profile: *const ModmanProfile,
+    mods: *const *const c_char,
+    count: usize,
+    dry_run: bool,
+) -> c_int {
+    to_code(to_paths(mods, count).and_then(|mods| {
+        let dir = &(*profile).dir;
+        in_dir(dir, || {
+            history::record(command("add", &mods, dry_run), || {
+                let mut args = add::Args::new(mods.clone(), dry_run);
+                args.resolve_paths(dir);
+                add::run(args)
+            })
+        })
+    }))
+}
+
+/// Uninstalls the given mods (by anything `modman remove` takes),
+/// restoring the game files they replaced.
+/// Returns 0 on success, or one of modman's exit codes.
+///
+/// # Safety
+///
+/// `profile` must have come from `modman_open_profile()`,
+/// and `mods` must point to `count` valid C strings.
+#[no_mangle]
+pub unsafe extern "C" fn modman_remove(
+    profile: *const ModmanProfile,
+    mods: *const *const c_char,
+    count: usize,
+    dry_run: bool,
+) -> c_int {
+    to_code(to_paths(mods, count).and_then(|mods| {
+        in_dir(&(*profile).dir, || {
+            history::record(command("remove", &mods, dry_run), || {
+                remove::remove_mods(mods.clone(), false, false, dry_run)
+            })
+        })
+    }))
+}
+
+/// What the history log says did something
+fn command(name: &str, mods: &[PathBuf], dry_run: bool) -> Vec<String> {
+    let mut command = vec!["ffi".to_owned(), name.to_owned()];
+    if dry_run {
+        command.push("--dry-run".to_owned());
+    }
+    command.extend(mods.iter().map(|m| m.display().to_string()));
+    command
+}
+
+/// Says what went wrong with the last failed call on this thread,
+/// or returns null if nothing has. The string is valid until the next call
+/// that fails on this thread.
+#[no_mangle]
+pub extern "C" fn modman_last_error() -> *const c_char {
+    LAST_ERROR.with(|l| l.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// Frees a string returned by modman. Null is fine.
+///
+/// # Safety
+///
+/// `s` must have come from modman, and can't be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn modman_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Passes progress events to a C callback.
+struct Callback {
+    callback: unsafe extern "C" fn(*const c_char, *mut c_void),
+    user_data: *mut c_void,
+}
+
+// Whoever set the callback promised it can be called from any thread.
+unsafe impl Send for Callback {}
+
+impl std::io::Write for Callback {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // The progress module writes each event, newline and all, at once.
+        let event = String::from_utf8_lossy(buf);
+        if let Ok(event) = CString::new(event.trim_end()) {
+            unsafe { (self.callback)(event.as_ptr(), self.user_data) };
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Logs nothing, so the only place warnings go is the progress callback.
+struct Quiet;
+
+impl log::Log for Quiet {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        false
+    }
+
+    fn log(&self, _: &log::Record) {}
+
+    fn flush(&self) {}
+}
+
+/// Calls `callback` with each progress event (including warnings)
+/// as a line of JSON, along with `user_data`. Pass null to stop.
+///
+/// # Safety
+///
+/// `callback` will be called from modman's worker threads,
+/// and must be safe to call with `user_data` from any of them.
+#[no_mangle]
+pub unsafe extern "C" fn modman_set_progress_callback(
+    callback: ModmanProgressCallback,
+    user_data: *mut c_void,
+) {
+    // Does nothing if the program already set up logging.
+    if log::set_boxed_logger(Box::new(progress::Logger { inner: Quiet })).is_ok() {
+        log::set_max_level(log::LevelFilter::Warn);
+    }
+    match callback {
+        Some(callback) => progress::begin_with(Box::new(Callback {
+            callback,
+            user_data,
+        })),
+        None => progress::end(),
+    }
+}
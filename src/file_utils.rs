@@ -1,22 +1,111 @@
 //! File and directory manipulation utilities
 
+use std::cell::RefCell;
 use std::fs;
 use std::io::{self, prelude::*};
 use std::path::*;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::*;
+use filetime::FileTime;
 use log::*;
+use memmap2::Mmap;
 use sha2::*;
 
+use crate::mod_config::SymlinkMode;
 use crate::profile::*;
 
+// Hashing is most of what modman spends its time doing, and io::copy()'s
+// 8 KiB buffer makes multi-gigabyte texture packs take a lot of little reads.
+// A (cached) 2 GB file hashed in 2.1 seconds that way, 2.0 a megabyte
+// at a time, and 1.8 mapped, since that skips copying it into a buffer.
+
+/// How files are read to hash them (see `--hashing`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashIo {
+    /// In small reads, like io::copy()
+    Stream,
+    /// In reads of HASH_BUFFER_SIZE
+    Buffered,
+    /// By memory-mapping files of at least MMAP_THRESHOLD (and reading
+    /// anything else like Buffered)
+    Mmap,
+}
+
+impl std::str::FromStr for HashIo {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match &*s.to_lowercase() {
+            "stream" => Ok(HashIo::Stream),
+            "buffered" => Ok(HashIo::Buffered),
+            "mmap" => Ok(HashIo::Mmap),
+            _ => bail!(
+                "Unknown hashing mode {} (expected buffered, mmap, or stream)",
+                s
+            ),
+        }
+    }
+}
+
+const HASH_BUFFER_SIZE: usize = 1 << 20;
+
+/// Smaller files aren't worth the trouble of mapping.
+const MMAP_THRESHOLD: u64 = 16 << 20;
+
+static HASH_IO: AtomicU8 = AtomicU8::new(HashIo::Buffered as u8);
+
+/// Sets how files are read to hash them.
+pub fn set_hash_io(mode: HashIo) {
+    HASH_IO.store(mode as u8, Ordering::Relaxed);
+}
+
+fn hash_io() -> HashIo {
+    match HASH_IO.load(Ordering::Relaxed) {
+        m if m == HashIo::Stream as u8 => HashIo::Stream,
+        m if m == HashIo::Mmap as u8 => HashIo::Mmap,
+        _ => HashIo::Buffered,
+    }
+}
+
 pub fn hash_file(path: &Path) -> Result<FileHash> {
     trace!("Hashing {}", path.display());
     let mut f =
-        fs::File::open(&path).with_context(|| format!("Couldn't open {}", path.display()))?;
+        fs::File::open(path).with_context(|| format!("Couldn't open {}", path.display()))?;
+    if hash_io() == HashIo::Mmap {
+        let len = f
+            .metadata()
+            .with_context(|| format!("Couldn't stat {}", path.display()))?
+            .len();
+        if len >= MMAP_THRESHOLD {
+            // If something truncates the file while we're hashing it,
+            // we crash (with SIGBUS) instead of getting an error.
+            // That's the risk --hashing=mmap takes.
+            let mapping = unsafe { Mmap::map(&f) }
+                .with_context(|| format!("Couldn't map {}", path.display()))?;
+            let mut hasher = Sha224::new();
+            for chunk in mapping.chunks(HASH_BUFFER_SIZE) {
+                hasher.update(chunk);
+            }
+            return Ok(FileHash::new(hasher.finalize()));
+        }
+    }
     hash_contents(&mut f)
 }
 
+/// Returns true if the error (or anything that caused it)
+/// is a file not being found.
+pub fn is_not_found(e: &Error) -> bool {
+    e.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|io| io.kind() == io::ErrorKind::NotFound)
+    })
+}
+
 struct HashingReader<R> {
     inner: R,
     hasher: Sha224,
@@ -49,61 +138,310 @@ impl<R: Read> Read for HashingReader<R> {
 /// Mostly used for dry runs where we want to compute hashes but skip backups.
 /// (See hash_and_backup() for the real deal.)
 pub fn hash_contents<R: Read>(reader: &mut R) -> Result<FileHash> {
-    hash_and_write(reader, &mut io::sink())
+    hash_and_copy(reader, &mut io::sink())
 }
 
+/// Hashes data from the given reader as it's written to the given writer.
+/// This is a copy, so with `--io-profile hdd`, it waits its turn.
 pub fn hash_and_write<R: Read, W: Write>(from: &mut R, to: &mut W) -> Result<FileHash> {
+    copying(|| match io_limit() {
+        0 => hash_and_copy(from, to),
+        _ => hash_and_copy(from, &mut Throttled(to)),
+    })
+}
+
+fn hash_and_copy<R: Read, W: Write>(from: &mut R, to: &mut W) -> Result<FileHash> {
     let mut hasher = HashingReader::new(from);
-    io::copy(&mut hasher, to)?;
+    match hash_io() {
+        HashIo::Stream => io::copy(&mut hasher, to)?,
+        HashIo::Buffered | HashIo::Mmap => copy_buffered(&mut hasher, to)?,
+    };
     Ok(hasher.result())
 }
 
+thread_local! {
+    static COPY_BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// io::copy(), but HASH_BUFFER_SIZE at a time.
+/// (Each thread reuses its buffer, since we copy lots of little files too.)
+fn copy_buffered<R: Read, W: Write>(from: &mut R, to: &mut W) -> io::Result<u64> {
+    COPY_BUFFER.with(|buf| {
+        let mut buf = buf.borrow_mut();
+        buf.resize(HASH_BUFFER_SIZE, 0);
+        let mut total = 0;
+        loop {
+            let count = match from.read(&mut buf) {
+                Ok(0) => return Ok(total),
+                Ok(count) => count,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            to.write_all(&buf[..count])?;
+            total += count as u64;
+        }
+    })
+}
+
+// Spinning drives spend most of their time seeking when several threads
+// copy files at once, so with `--io-profile hdd`, copies take turns.
+// Hashing stays parallel; it's reading one file (mostly) front to back
+// and a lot of CPU, so it isn't worth the wait.
+// Copies can also be limited to some total rate (`--io-limit`),
+// to leave the drive usable for whatever else is running.
+
+/// What kind of drive we're copying files on (see `--io-profile`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoProfile {
+    Ssd,
+    Hdd,
+}
+
+impl std::str::FromStr for IoProfile {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match &*s.to_lowercase() {
+            "ssd" => Ok(IoProfile::Ssd),
+            "hdd" => Ok(IoProfile::Hdd),
+            _ => bail!("Unknown I/O profile {} (expected hdd or ssd)", s),
+        }
+    }
+}
+
+static SEQUENTIAL_COPIES: AtomicBool = AtomicBool::new(false);
+
+static COPY_TURN: Mutex<()> = Mutex::new(());
+
+/// Bytes per second copies are limited to, or 0 for no limit
+static IO_LIMIT: AtomicU64 = AtomicU64::new(0);
+
+/// Sets whether copies take turns, and how fast (in bytes per second)
+/// they're allowed to go in total, if there's a limit.
+pub fn set_io_profile(profile: IoProfile, limit: Option<u64>) {
+    SEQUENTIAL_COPIES.store(profile == IoProfile::Hdd, Ordering::Relaxed);
+    IO_LIMIT.store(limit.unwrap_or(0), Ordering::Relaxed);
+}
+
+fn io_limit() -> u64 {
+    IO_LIMIT.load(Ordering::Relaxed)
+}
+
+/// Runs the given copy, waiting for any other to finish first
+/// if we're copying one file at a time.
+fn copying<T, F: FnOnce() -> T>(copy: F) -> T {
+    let _turn = SEQUENTIAL_COPIES
+        .load(Ordering::Relaxed)
+        .then(|| COPY_TURN.lock().unwrap_or_else(|e| e.into_inner()));
+    copy()
+}
+
+/// How much has been copied since `start`, for --io-limit
+struct Throttle {
+    start: Instant,
+    bytes: u64,
+}
+
+static THROTTLE: Mutex<Option<Throttle>> = Mutex::new(None);
+
+/// Waits until copying another `count` bytes would keep us under the limit.
+fn throttle(count: usize) {
+    let limit = io_limit();
+    let wait = {
+        let mut throttle = THROTTLE.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let t = throttle.get_or_insert(Throttle {
+            start: now,
+            bytes: 0,
+        });
+        let due = t.start + Duration::from_secs_f64(t.bytes as f64 / limit as f64);
+        // Don't let time spent not copying (e.g., hashing) bank a burst.
+        if due + Duration::from_secs(1) < now {
+            *t = Throttle {
+                start: now,
+                bytes: 0,
+            };
+        }
+        t.bytes += count as u64;
+        let due = t.start + Duration::from_secs_f64(t.bytes as f64 / limit as f64);
+        due.saturating_duration_since(now)
+    };
+    if !wait.is_zero() {
+        thread::sleep(wait);
+    }
+}
+
+/// A writer that keeps to --io-limit
+struct Throttled<W>(W);
+
+impl<W: Write> Write for Throttled<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.0.write(buf)?;
+        throttle(count);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// fs::copy(), but slow enough to keep to --io-limit.
+fn throttled_copy(from: &Path, to: &Path) -> io::Result<u64> {
+    let mut reader = fs::File::open(from)?;
+    let permissions = reader.metadata()?.permissions();
+    let mut writer = Throttled(fs::File::create(to)?);
+    let count = copy_buffered(&mut reader, &mut writer)?;
+    drop(writer);
+    fs::set_permissions(to, permissions)?;
+    Ok(count)
+}
+
 /// Provides a vector of file paths in base_dir, relative to base_dir.
 pub fn collect_file_paths_in_dir(base_dir: &Path) -> Result<Vec<PathBuf>> {
-    let mut ret = Vec::new();
-    dir_walker(base_dir, base_dir, &mut ret)?;
-    Ok(ret)
+    let mut walker = DirWalker::new(base_dir, None);
+    walker.walk(base_dir)?;
+    Ok(walker.files)
 }
 
-fn dir_walker(base_dir: &Path, dir: &Path, file_list: &mut Vec<PathBuf>) -> Result<()> {
-    let dir_iter =
-        fs::read_dir(dir).with_context(|| format!("Couldn't read directory {}", dir.display()))?;
-    for entry in dir_iter {
-        let entry = entry?;
-        let ft = entry.file_type()?;
-        if ft.is_dir() {
-            dir_walker(base_dir, &entry.path(), file_list)?;
-        } else if ft.is_file() {
+/// Like collect_file_paths_in_dir(), but handles symbolic links
+/// as the mod asks. (Links to preserve are listed like any other file.)
+pub fn collect_mod_file_paths_in_dir(
+    base_dir: &Path,
+    symlinks: SymlinkMode,
+) -> Result<Vec<PathBuf>> {
+    let mut walker = DirWalker::new(base_dir, Some(symlinks));
+    walker.following.push(
+        fs::canonicalize(base_dir)
+            .with_context(|| format!("Couldn't find the full path of {}", base_dir.display()))?,
+    );
+    walker.walk(base_dir)?;
+    Ok(walker.files)
+}
+
+struct DirWalker<'a> {
+    base_dir: &'a Path,
+    /// What to do with symbolic links, or None if we don't expect any.
+    symlinks: Option<SymlinkMode>,
+    files: Vec<PathBuf>,
+    /// The (canonical) linked directories we're currently walking,
+    /// so that we don't follow a loop of links forever.
+    following: Vec<PathBuf>,
+}
+
+impl<'a> DirWalker<'a> {
+    fn new(base_dir: &'a Path, symlinks: Option<SymlinkMode>) -> Self {
+        Self {
+            base_dir,
+            symlinks,
+            files: Vec::new(),
+            following: Vec::new(),
+        }
+    }
+
+    fn walk(&mut self, dir: &Path) -> Result<()> {
+        let dir_iter = fs::read_dir(dir)
+            .with_context(|| format!("Couldn't read directory {}", dir.display()))?;
+        for entry in dir_iter {
+            let entry = entry?;
             let entry_path = entry.path();
-            let from_base_dir = entry_path.strip_prefix(base_dir)?;
-            file_list.push(from_base_dir.to_owned());
+            let ft = entry.file_type()?;
+            if ft.is_dir() {
+                self.walk(&entry_path)?;
+            } else if ft.is_file() {
+                self.push_file(&entry_path)?;
+            } else if ft.is_symlink() && self.symlinks.is_some() {
+                self.walk_link(&entry_path)?;
+            }
+            // We don't expect any symbolic links or other unusual things.
+            else {
+                bail!("{} isn't a file or a directory", entry_path.display());
+            }
         }
-        // We don't expect any symbolic links or other unusual things.
-        else {
-            bail!("{} isn't a file or a directory", entry.path().display());
+        Ok(())
+    }
+
+    fn walk_link(&mut self, link: &Path) -> Result<()> {
+        match self.symlinks.unwrap() {
+            SymlinkMode::Skip => {
+                warn!("Skipping symbolic link {}", link.display());
+                Ok(())
+            }
+            SymlinkMode::Preserve => self.push_file(link),
+            SymlinkMode::Follow => {
+                let target = fs::canonicalize(link)
+                    .with_context(|| format!("Couldn't follow symbolic link {}", link.display()))?;
+                if target.is_file() {
+                    return self.push_file(link);
+                }
+                if !target.is_dir() {
+                    bail!("{} doesn't link to a file or a directory", link.display());
+                }
+                if self.following.contains(&target) {
+                    bail!("Symbolic link {} forms a loop", link.display());
+                }
+                self.following.push(target);
+                let walked = self.walk(link);
+                self.following.pop();
+                walked
+            }
         }
     }
-    Ok(())
+
+    fn push_file(&mut self, path: &Path) -> Result<()> {
+        let from_base_dir = path.strip_prefix(self.base_dir)?;
+        self.files.push(from_base_dir.to_owned());
+        Ok(())
+    }
+}
+
+/// Returns path relative to base (e.g., `../Games/Foo` for `~/Games/Foo`
+/// relative to `~/modman`), resolving symbolic links and the like along the way.
+pub fn relative_path(path: &Path, base: &Path) -> Result<PathBuf> {
+    let path = fs::canonicalize(path)
+        .with_context(|| format!("Couldn't find the full path of {}", path.display()))?;
+    let base = fs::canonicalize(base)
+        .with_context(|| format!("Couldn't find the full path of {}", base.display()))?;
+
+    let mut path_components = path.components().peekable();
+    let mut base_components = base.components().peekable();
+    // Different prefixes (e.g., Windows drive letters) mean there's no relative path.
+    if path_components.peek() != base_components.peek() {
+        bail!(
+            "{} and {} don't share a root, so there's no relative path between them",
+            path.display(),
+            base.display()
+        );
+    }
+    while path_components.peek().is_some() && path_components.peek() == base_components.peek() {
+        path_components.next();
+        base_components.next();
+    }
+
+    let mut relative: PathBuf = base_components.map(|_| Component::ParentDir).collect();
+    relative.extend(path_components);
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    Ok(relative)
 }
 
 pub fn remove_dir_if_empty(dir: &Path) -> Result<()> {
-    let removal = fs::remove_dir(&dir);
+    let removal = fs::remove_dir(dir);
     if let Err(e) = removal {
+        // POSIX can return ENOTEMPTY (39).
+        // Windows seems to return ERROR_DIR_NOT_EMPTY (145)
+        // (Newer versions of std map these to ErrorKind::DirectoryNotEmpty,
+        // older ones to ErrorKind::Other, so check the raw error.)
+        if let Some(raw_error) = e.raw_os_error() {
+            if (cfg!(unix) && raw_error == 39) || (cfg!(windows) && raw_error == 145) {
+                return Ok(());
+            }
+        }
         match e.kind() {
             // If we're doing removes in parallel, there's a chance
             // another thread got it already
             io::ErrorKind::NotFound => Ok(()),
-            // If the directory isn't empty...
-            io::ErrorKind::Other => {
-                let raw_error = e.raw_os_error().expect("No errno");
-                // POSIX can return ENOTEMPTY (39).
-                // Windows seems to return ERROR_DIR_NOT_EMPTY (145)
-                if (cfg!(unix) && raw_error == 39) || (cfg!(windows) && raw_error == 145) {
-                    Ok(())
-                } else {
-                    Err(Error::from(e))
-                }
-            }
             // Windows seems to return access denied (error 5)
             // sometimes as well. Maybe there's an I/O lock while
             // another thread is trying to remove it?
@@ -122,17 +460,25 @@ pub fn remove_dir_if_empty(dir: &Path) -> Result<()> {
     }
 }
 
-pub fn remove_empty_parents(mut p: &Path, up_to: &Path) -> Result<()> {
-    while let Some(parent) = p.parent() {
-        // Avoid removing the root directory entirely on a clean sweep.
-        if *parent == *up_to {
-            return Ok(());
-        }
-        remove_dir_if_empty(&parent)?;
+/// Removes the directories containing `p` that are empty,
+/// up to (but never including) `up_to`.
+pub fn remove_empty_parents(p: &Path, up_to: &Path) -> Result<()> {
+    // Don't go wandering around the rest of the filesystem.
+    if !p.starts_with(up_to) || p == up_to {
+        bail!(
+            "Won't remove empty directories above {}: it isn't in {}",
+            p.display(),
+            up_to.display()
+        );
+    }
+    let mut dir = p.parent();
+    // Avoid removing the root directory entirely on a clean sweep.
+    while let Some(parent) = dir.filter(|d| *d != up_to) {
+        remove_dir_if_empty(parent)?;
         debug!("Removed empty directory {}", parent.display());
-        p = parent;
+        dir = parent.parent();
     }
-    unreachable!("remove_empty_parents() got to a filesystem root");
+    Ok(())
 }
 
 pub struct RemoveRoot(pub bool);
@@ -157,3 +503,445 @@ pub fn remove_empty_tree(dir: &Path, remove_root: RemoveRoot) -> Result<()> {
     }
     Ok(())
 }
+
+// Antivirus and indexing services (looking at you, Windows Defender)
+// like to briefly lock files right as we're trying to overwrite, rename,
+// or remove them. Rather than failing halfway through an install,
+// retry those operations a few times with exponential backoff.
+
+static RETRY_ATTEMPTS: AtomicU32 = AtomicU32::new(5);
+static RETRY_BACKOFF_MS: AtomicU64 = AtomicU64::new(100);
+
+/// Sets how many times destructive filesystem operations are attempted
+/// when they fail with a (likely transient) sharing violation,
+/// and how long to wait before the first retry.
+/// Each subsequent retry waits twice as long as the last.
+pub fn set_retry_policy(attempts: u32, backoff: Duration) {
+    RETRY_ATTEMPTS.store(attempts.max(1), Ordering::Relaxed);
+    RETRY_BACKOFF_MS.store(backoff.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// Returns true if the error looks like someone else briefly holding the file.
+fn is_transient_lock(e: &io::Error) -> bool {
+    if !cfg!(windows) {
+        return false;
+    }
+    // ERROR_ACCESS_DENIED (5), ERROR_SHARING_VIOLATION (32),
+    // and ERROR_LOCK_VIOLATION (33)
+    matches!(e.raw_os_error(), Some(5) | Some(32) | Some(33))
+}
+
+/// Runs the given filesystem operation,
+/// retrying it according to the retry policy if it hits a transient lock.
+/// (These all change files, so they fail with `--no-write`.)
+pub fn with_retries<T, F: FnMut() -> io::Result<T>>(mut op: F) -> io::Result<T> {
+    check_writable()?;
+    let attempts = RETRY_ATTEMPTS.load(Ordering::Relaxed);
+    let mut backoff = Duration::from_millis(RETRY_BACKOFF_MS.load(Ordering::Relaxed));
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Err(e) if attempt < attempts && is_transient_lock(&e) => {
+                debug!(
+                    "{} (attempt {} of {}), retrying in {:?}",
+                    e, attempt, attempts, backoff
+                );
+                thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Hashes an installed mod file,
+/// or for one installed as a symbolic link, the path it points to.
+pub fn hash_installed_file(game_path: &Path, meta: &ModFileMetadata) -> Result<FileHash> {
+    if meta.symlink.is_none() {
+        return hash_file(game_path);
+    }
+    match fs::read_link(game_path) {
+        Ok(target) => hash_link_target(&target),
+        // Someone replaced the link with a regular file.
+        Err(e) if e.kind() == io::ErrorKind::InvalidInput => hash_file(game_path),
+        Err(e) => {
+            Err(Error::from(e).context(format!("Couldn't read link {}", game_path.display())))
+        }
+    }
+}
+
+/// What we record as the hash of a symbolic link: the hash of where it points.
+pub fn hash_link_target(target: &Path) -> Result<FileHash> {
+    hash_contents(&mut target.to_string_lossy().as_bytes())
+}
+
+/// Returns the size of the file at the given path, in bytes.
+/// (Symbolic links are sized as links, not by what they point to.)
+pub fn file_size(path: &Path) -> Result<u64> {
+    let meta =
+        fs::symlink_metadata(path).with_context(|| format!("Couldn't stat {}", path.display()))?;
+    Ok(meta.len())
+}
+
+/// Returns the modification time of the file at the given path.
+/// (Like `file_size()`, symbolic links aren't followed.)
+pub fn file_mtime(path: &Path) -> Result<SystemTime> {
+    let meta =
+        fs::symlink_metadata(path).with_context(|| format!("Couldn't stat {}", path.display()))?;
+    meta.modified()
+        .with_context(|| format!("Couldn't get the modification time of {}", path.display()))
+}
+
+/// Returns true if there's a symbolic link at the given path.
+pub fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path)
+        .map(|m| m.file_type().is_symlink())
+        .unwrap_or(false)
+}
+
+/// Makes a symbolic link at `link` pointing to `target`.
+#[cfg(unix)]
+pub fn make_symlink(target: &Path, link: &Path) -> Result<()> {
+    check_writable()?;
+    std::os::unix::fs::symlink(target, link)
+        .with_context(|| format!("Couldn't link {} to {}", link.display(), target.display()))
+}
+
+#[cfg(windows)]
+pub fn make_symlink(target: &Path, link: &Path) -> Result<()> {
+    check_writable()?;
+    use std::os::windows::fs::{symlink_dir, symlink_file};
+    // Windows needs to know if it's linking to a directory.
+    // (Relative targets are relative to the link's directory.)
+    let resolved = link.parent().unwrap_or(Path::new("")).join(target);
+    let result = if resolved.is_dir() {
+        symlink_dir(target, link)
+    } else {
+        symlink_file(target, link)
+    };
+    result.with_context(|| {
+        format!(
+            "Couldn't link {} to {} (making symbolic links on Windows needs \
+             Developer Mode or administrator rights)",
+            link.display(),
+            target.display()
+        )
+    })
+}
+
+/// Returns true if the two (existing) paths are on the same filesystem,
+/// so files can be renamed from one to the other.
+#[cfg(unix)]
+pub fn same_filesystem(a: &Path, b: &Path) -> Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let device = |p: &Path| {
+        fs::metadata(p)
+            .map(|m| m.dev())
+            .with_context(|| format!("Couldn't stat {}", p.display()))
+    };
+    Ok(device(a)? == device(b)?)
+}
+
+/// Returns true if the two (existing) paths are on the same drive,
+/// which is the best we can do without platform-specific volume IDs.
+#[cfg(not(unix))]
+pub fn same_filesystem(a: &Path, b: &Path) -> Result<bool> {
+    let prefix = |p: &Path| -> Result<Option<std::ffi::OsString>> {
+        let full = fs::canonicalize(p)
+            .with_context(|| format!("Couldn't find the full path of {}", p.display()))?;
+        Ok(match full.components().next() {
+            Some(Component::Prefix(prefix)) => Some(prefix.as_os_str().to_ascii_lowercase()),
+            _ => None,
+        })
+    };
+    Ok(prefix(a)? == prefix(b)?)
+}
+
+// Installing a mod can replace scripts and other executables
+// (in native Linux games, Proton prefixes, etc.), so we keep track of
+// execute bits. Other permissions get whatever new files get.
+
+/// Returns the Unix permissions of the file at the given path
+/// if it's executable. (Always None on other platforms.)
+#[cfg(unix)]
+pub fn executable_mode(path: &Path) -> Result<Option<u32>> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = fs::metadata(path)
+        .with_context(|| format!("Couldn't stat {}", path.display()))?
+        .permissions()
+        .mode();
+    Ok(executable(mode))
+}
+
+#[cfg(not(unix))]
+pub fn executable_mode(_path: &Path) -> Result<Option<u32>> {
+    Ok(None)
+}
+
+/// Returns the given permissions (sans file type bits) if they're executable.
+pub fn executable(mode: u32) -> Option<u32> {
+    let mode = mode & 0o7777;
+    if mode & 0o111 != 0 {
+        Some(mode)
+    } else {
+        None
+    }
+}
+
+/// Sets the file's Unix permissions. (Does nothing on other platforms.)
+#[cfg(unix)]
+pub fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    check_writable()?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Couldn't set {}'s permissions", path.display()))
+}
+
+#[cfg(not(unix))]
+pub fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+/// Clears the file's execute bits. (Does nothing on other platforms.)
+#[cfg(unix)]
+pub fn clear_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = fs::metadata(path)
+        .with_context(|| format!("Couldn't stat {}", path.display()))?
+        .permissions()
+        .mode();
+    set_mode(path, mode & 0o7777 & !0o111)
+}
+
+#[cfg(not(unix))]
+pub fn clear_executable(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Gives `to` the same modification time as `from`.
+///
+/// Some games use timestamps to decide when to rebuild caches,
+/// so we keep backed up files' times and put them back on restore.
+pub fn copy_mtime(from: &Path, to: &Path) -> Result<()> {
+    let metadata =
+        fs::metadata(from).with_context(|| format!("Couldn't stat {}", from.display()))?;
+    set_mtime(to, FileTime::from_last_modification_time(&metadata))
+}
+
+/// Sets the file's modification time.
+pub fn set_mtime<T: Into<FileTime>>(path: &Path, mtime: T) -> Result<()> {
+    check_writable()?;
+    filetime::set_file_mtime(path, mtime.into())
+        .with_context(|| format!("Couldn't set {}'s modification time", path.display()))
+}
+
+/// fs::File::create(), but retried on transient locks.
+pub fn create_file(path: &Path) -> io::Result<fs::File> {
+    changing(path, || fs::File::create(path))
+}
+
+/// fs::rename(), but retried on transient locks.
+pub fn rename_file(from: &Path, to: &Path) -> io::Result<()> {
+    changing(to, || fs::rename(from, to))
+}
+
+/// Like `rename_file()`, but for replacing an existing file that we care
+/// about keeping intact (the profile, backups) if we crash partway through.
+/// On Windows, renaming over a file isn't always atomic
+/// (depending on the filesystem, or antivirus getting in the way),
+/// so use ReplaceFileW there, which is meant for exactly this.
+pub fn replace_file(from: &Path, to: &Path) -> io::Result<()> {
+    changing(to, || replace(from, to))
+}
+
+#[cfg(windows)]
+fn replace(from: &Path, to: &Path) -> io::Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr;
+    use windows_sys::Win32::Storage::FileSystem::*;
+
+    fn wide(path: &Path) -> Vec<u16> {
+        path.as_os_str().encode_wide().chain(Some(0)).collect()
+    }
+
+    let replaced = wide(to);
+    let replacement = wide(from);
+    let ok = unsafe {
+        ReplaceFileW(
+            replaced.as_ptr(),
+            replacement.as_ptr(),
+            ptr::null(),
+            REPLACEFILE_WRITE_THROUGH | REPLACEFILE_IGNORE_MERGE_ERRORS,
+            ptr::null(),
+            ptr::null(),
+        )
+    };
+    if ok != 0 {
+        return Ok(());
+    }
+    let e = io::Error::last_os_error();
+    match e.raw_os_error() {
+        // ERROR_INVALID_FUNCTION (1): the filesystem doesn't support it.
+        // ERROR_FILE_NOT_FOUND (2): there's nothing to replace yet.
+        // ERROR_UNABLE_TO_REMOVE_REPLACED (1175) and
+        // ERROR_UNABLE_TO_MOVE_REPLACEMENT (1176): both files are
+        // still where they were (or the replaced one is gone),
+        // so a plain rename can still do the job.
+        Some(1) | Some(2) | Some(1175) | Some(1176) => {
+            debug!(
+                "Couldn't replace {} ({}); renaming {} over it instead",
+                to.display(),
+                e,
+                from.display()
+            );
+            fs::rename(from, to)
+        }
+        _ => Err(e),
+    }
+}
+
+#[cfg(not(windows))]
+fn replace(from: &Path, to: &Path) -> io::Result<()> {
+    // rename() replaces atomically on POSIX systems.
+    fs::rename(from, to)
+}
+
+/// fs::remove_file(), but retried on transient locks.
+pub fn remove_file(path: &Path) -> io::Result<()> {
+    changing(path, || fs::remove_file(path))
+}
+
+// Some launchers install games with read-only files (or, on Windows,
+// ACLs that only let administrators change them). The former we can
+// work around; the latter we can at least explain.
+
+/// Runs the given operation that changes the file at `path`, with retries.
+/// If the file is read-only, it's made writable first,
+/// and its permissions are put back afterwards if it's still there.
+fn changing<T, F: FnMut() -> io::Result<T>>(path: &Path, op: F) -> io::Result<T> {
+    let read_only = fs::symlink_metadata(path)
+        .ok()
+        .filter(|m| !m.file_type().is_symlink() && m.permissions().readonly())
+        .map(|m| m.permissions());
+    if let Some(permissions) = &read_only {
+        check_writable()?;
+        debug!("{} is read-only; making it writable", path.display());
+        fs::set_permissions(path, writable(permissions)).map_err(explain_permission_error)?;
+    }
+    let result = with_retries(op);
+    if let Some(permissions) = read_only {
+        if fs::symlink_metadata(path).is_ok() {
+            if let Err(e) = fs::set_permissions(path, permissions) {
+                warn!("Couldn't make {} read-only again: {}", path.display(), e);
+            }
+        }
+    }
+    result.map_err(explain_permission_error)
+}
+
+/// The given permissions, plus the owner's permission to write.
+#[cfg(unix)]
+fn writable(permissions: &fs::Permissions) -> fs::Permissions {
+    use std::os::unix::fs::PermissionsExt;
+    fs::Permissions::from_mode(permissions.mode() | 0o200)
+}
+
+#[cfg(not(unix))]
+#[allow(clippy::permissions_set_readonly_false)] // There's just the one attribute.
+fn writable(permissions: &fs::Permissions) -> fs::Permissions {
+    let mut permissions = permissions.clone();
+    permissions.set_readonly(false);
+    permissions
+}
+
+/// Adds what to do about it to a "permission denied" error.
+fn explain_permission_error(e: io::Error) -> io::Error {
+    // (--no-write's errors are "permission denied" too, but say why.)
+    if e.kind() != io::ErrorKind::PermissionDenied || no_write() {
+        return e;
+    }
+    let advice = if cfg!(windows) {
+        "Some launchers install games so that only administrators can change their files. \
+         Try running modman as an administrator, or give your user Full control \
+         of the game's directory (in its Properties, under Security)."
+    } else {
+        "Make sure you own the game's directory, \
+         or run modman as a user who can write to it."
+    };
+    io::Error::new(e.kind(), format!("{}. {}", e, advice))
+}
+
+/// A (probably) unique ID for this run of modman, from the time and our PID.
+/// It's in every line we write to the log file,
+/// and in the paths of our temp files, so that two runs never write the same one.
+pub fn run_id() -> &'static str {
+    static RUN_ID: OnceLock<String> = OnceLock::new();
+    RUN_ID.get_or_init(|| {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos() ^ d.as_secs() as u32)
+            .unwrap_or(0);
+        format!("{:08x}", nanos ^ std::process::id().rotate_left(16))
+    })
+}
+
+static NO_WRITE: AtomicBool = AtomicBool::new(false);
+
+/// Forbids writing anything (for `--no-write`): functions here that would
+/// change a file, the profile, or a download fail instead.
+pub fn set_no_write(no_write: bool) {
+    NO_WRITE.store(no_write, Ordering::Relaxed);
+}
+
+/// Returns true if we were told not to write anything.
+pub fn no_write() -> bool {
+    NO_WRITE.load(Ordering::Relaxed)
+}
+
+/// Fails if we were told not to write anything.
+/// Commands should have been made dry runs; this is a last line of defense.
+pub fn check_writable() -> io::Result<()> {
+    if no_write() {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "modman was told not to write anything (--no-write)",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+static USE_TRASH: AtomicBool = AtomicBool::new(false);
+
+/// Sets whether discard_file() sends files to the system's trash
+/// (the Recycle Bin, or the XDG trash on Linux) instead of deleting them.
+pub fn set_use_trash(use_trash: bool) {
+    USE_TRASH.store(use_trash, Ordering::Relaxed);
+}
+
+/// Gets rid of a game file or backup we're done with,
+/// by moving it to the trash if we were asked to, or removing it otherwise.
+pub fn discard_file(path: &Path) -> io::Result<()> {
+    if !USE_TRASH.load(Ordering::Relaxed) {
+        return remove_file(path);
+    }
+    // Fail the same way removing it would if it's not there,
+    // since callers check for that.
+    fs::symlink_metadata(path)?;
+    trace!("Moving {} to the trash", path.display());
+    with_retries(|| {
+        trash::delete(path)
+            .map_err(|e| io::Error::other(format!("Couldn't move it to the trash: {}", e)))
+    })
+}
+
+/// fs::copy(), but retried on transient locks.
+pub fn copy_file(from: &Path, to: &Path) -> io::Result<u64> {
+    changing(to, || {
+        copying(|| match io_limit() {
+            0 => fs::copy(from, to),
+            _ => throttled_copy(from, to),
+        })
+    })
+}
@@ -0,0 +1,403 @@
+//! Presets for known games, so `modman init --game <GAME>` can find them
+//! and fill in their settings.
+//!
+//! Besides the built-in presets below, you can add your own (or override
+//! ours) in `games.toml` in your config directory
+//! (e.g., ~/.config/modman/ or %APPDATA%\modman\), in the same format.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use log::*;
+use serde_derive::Deserialize;
+
+use crate::profile::Profile;
+use crate::vdf;
+use crate::wine;
+
+static BUILTIN_PRESETS: &str = r#"
+[dcs]
+name = "DCS World"
+steam-app-id = 223750
+registry = [
+    'HKCU\Software\Eagle Dynamics\DCS World\Path',
+    'HKCU\Software\Eagle Dynamics\DCS World OpenBeta\Path',
+]
+paths = ['C:\Program Files\Eagle Dynamics\DCS World']
+process = "DCS.exe"
+case-insensitive = true
+roots = { saved = "~/Saved Games/DCS" }
+
+[il2]
+name = "IL-2 Sturmovik: Great Battles"
+steam-app-id = 307960
+paths = [
+    'C:\Program Files\IL-2 Sturmovik Great Battles',
+    'C:\Program Files\IL-2 Sturmovik Battle of Stalingrad',
+]
+process = "Il-2.exe"
+case-insensitive = true
+
+[skyrim-se]
+name = "The Elder Scrolls V: Skyrim Special Edition"
+steam-app-id = 489830
+registry = ['HKLM\SOFTWARE\WOW6432Node\Bethesda Softworks\Skyrim Special Edition\Installed Path']
+process = "SkyrimSE.exe"
+case-insensitive = true
+roots = { saved = "~/Documents/My Games/Skyrim Special Edition" }
+
+[fallout4]
+name = "Fallout 4"
+steam-app-id = 377160
+registry = ['HKLM\SOFTWARE\WOW6432Node\Bethesda Softworks\Fallout4\Installed Path']
+process = "Fallout4.exe"
+case-insensitive = true
+roots = { saved = "~/Documents/My Games/Fallout4" }
+"#;
+
+pub static USER_PRESETS_NAME: &str = "games.toml";
+
+/// What we know about a game
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GamePreset {
+    pub name: String,
+    /// Used to find the game in Steam libraries
+    #[serde(default)]
+    pub steam_app_id: Option<u32>,
+    /// Windows registry values holding its install directory,
+    /// as `<KEY>\<VALUE NAME>`
+    #[serde(default)]
+    pub registry: Vec<String>,
+    /// Other places it's usually installed (`~` is your home directory)
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// The game's executable, so we can warn if it's running
+    #[serde(default)]
+    pub process: Option<String>,
+    #[serde(default)]
+    pub case_insensitive: bool,
+    /// Other directories mods install to, by name (see `modman roots`)
+    #[serde(default)]
+    pub roots: BTreeMap<String, String>,
+}
+
+/// Returns the built-in presets, plus any from the user's games.toml.
+pub fn presets() -> Result<BTreeMap<String, GamePreset>> {
+    let mut presets: BTreeMap<String, GamePreset> =
+        toml::from_str(BUILTIN_PRESETS).expect("Built-in game presets are invalid");
+
+    if let Some(user_path) = dirs::config_dir().map(|d| d.join("modman").join(USER_PRESETS_NAME)) {
+        if user_path.exists() {
+            debug!("Reading game presets from {}", user_path.display());
+            let text = fs::read_to_string(&user_path)
+                .with_context(|| format!("Couldn't read {}", user_path.display()))?;
+            let user_presets: BTreeMap<String, GamePreset> = toml::from_str(&text)
+                .with_context(|| format!("Couldn't parse {}", user_path.display()))?;
+            presets.extend(user_presets);
+        }
+    }
+    Ok(presets)
+}
+
+/// Looks up the preset for the given game, listing the known ones if it's not there.
+pub fn preset(game: &str) -> Result<GamePreset> {
+    let mut presets = presets()?;
+    match presets.remove(game) {
+        Some(p) => Ok(p),
+        None => {
+            let mut known = String::new();
+            for (key, p) in &presets {
+                known += &format!("\n\t{} ({})", key, p.name);
+            }
+            bail!("Unknown game {}. Known games are:{}", game, known)
+        }
+    }
+}
+
+/// Looks for the game in Steam libraries, then the registry,
+/// then wherever it's usually installed (in the Wine prefix, if given).
+pub fn find_install(preset: &GamePreset, wine_prefix: Option<&Path>) -> Option<PathBuf> {
+    let from_steam = preset.steam_app_id.and_then(find_steam_app);
+    let from_registry = || {
+        preset
+            .registry
+            .iter()
+            .filter_map(|r| read_registry(r))
+            .map(PathBuf::from)
+            .find(|p| p.is_dir())
+    };
+    let from_paths = || {
+        preset
+            .paths
+            .iter()
+            .map(|p| preset_path(p, wine_prefix))
+            .find(|p| p.is_dir())
+    };
+    from_steam.or_else(from_registry).or_else(from_paths)
+}
+
+/// Expands a leading `~` to the user's home directory.
+pub fn expand_home(path: &str) -> PathBuf {
+    let rest = path.strip_prefix("~/").or_else(|| path.strip_prefix("~\\"));
+    match (rest, dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Expands a preset's path, which is a Windows one if there's a Wine prefix:
+/// `~` is the Windows user's directory there, and `C:\` is its drive_c/.
+pub fn preset_path(path: &str, wine_prefix: Option<&Path>) -> PathBuf {
+    let prefix = match wine_prefix {
+        Some(p) => p,
+        None => return expand_home(path),
+    };
+    let rest = path.strip_prefix("~/").or_else(|| path.strip_prefix("~\\"));
+    match (rest, wine::user_dir(prefix)) {
+        (Some(rest), Some(user)) => {
+            let mut expanded = user;
+            expanded.extend(rest.split(['\\', '/']).filter(|c| !c.is_empty()));
+            expanded
+        }
+        _ => wine::in_prefix(prefix, path).unwrap_or_else(|| PathBuf::from(path)),
+    }
+}
+
+/// Returns the directories Steam might be installed in.
+fn steam_roots() -> Vec<PathBuf> {
+    let mut roots = Vec::new();
+    if let Some(path) = read_registry(r"HKCU\Software\Valve\Steam\SteamPath") {
+        roots.push(PathBuf::from(path));
+    }
+    if cfg!(windows) {
+        roots.push(PathBuf::from(r"C:\Program Files (x86)\Steam"));
+    }
+    if let Some(home) = dirs::home_dir() {
+        roots.push(home.join(".steam/steam"));
+        roots.push(home.join(".local/share/Steam"));
+        roots.push(home.join(".var/app/com.valvesoftware.Steam/.local/share/Steam"));
+        roots.push(home.join("Library/Application Support/Steam"));
+    }
+    roots.retain(|r| r.is_dir());
+    roots
+}
+
+/// Returns every Steam library folder, from Steam's libraryfolders.vdf.
+pub fn steam_libraries() -> Vec<PathBuf> {
+    let mut libraries = Vec::new();
+    for root in steam_roots() {
+        libraries.push(root.clone());
+        let vdf_path = root.join("steamapps").join("libraryfolders.vdf");
+        if !vdf_path.exists() {
+            continue;
+        }
+        let folders = match vdf::read_file(&vdf_path) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("{:#}", e);
+                continue;
+            }
+        };
+        let folders = folders
+            .get("libraryfolders")
+            .map(vdf::Value::pairs)
+            .unwrap_or_default();
+        // (Older versions also put a few settings alongside the numbered libraries.)
+        for (_index, folder) in folders.iter().filter(|(i, _)| i.parse::<u32>().is_ok()) {
+            // Newer versions of Steam give each library an object with its path;
+            // older ones just give the path.
+            let path = match folder {
+                vdf::Value::String(p) => Some(p.as_str()),
+                obj => obj.get("path").and_then(vdf::Value::as_str),
+            };
+            if let Some(p) = path {
+                libraries.push(PathBuf::from(p));
+            }
+        }
+    }
+    // Symlinks like ~/.steam/steam make the same library show up a few times.
+    let mut seen = Vec::new();
+    libraries.retain(|l| {
+        let canonical = l.canonicalize().unwrap_or_else(|_| l.clone());
+        if seen.contains(&canonical) {
+            false
+        } else {
+            seen.push(canonical);
+            true
+        }
+    });
+    libraries
+}
+
+/// Finds the app's manifest (`appmanifest_<ID>.acf`) in whichever
+/// Steam library it's installed in.
+pub fn app_manifest_path(app_id: u32) -> Option<PathBuf> {
+    steam_libraries()
+        .into_iter()
+        .map(|l| {
+            l.join("steamapps")
+                .join(format!("appmanifest_{}.acf", app_id))
+        })
+        .find(|m| m.exists())
+}
+
+/// Finds where Steam installed the app.
+pub fn find_steam_app(app_id: u32) -> Option<PathBuf> {
+    let manifest_path = app_manifest_path(app_id)?;
+    let manifest = vdf::read_file(&manifest_path)
+        .map_err(|e| warn!("{:#}", e))
+        .ok()?;
+    let install_dir = manifest.get("AppState")?.get("installdir")?.as_str()?;
+    // The manifest is in <library>/steamapps/
+    let dir = manifest_path.parent()?.join("common").join(install_dir);
+    debug!("Steam has app {} in {}", app_id, dir.display());
+    Some(dir).filter(|d| d.is_dir())
+}
+
+/// Returns the app's current build, from its Steam app manifest.
+pub fn steam_build_id(app_id: u32) -> Result<Option<String>> {
+    let manifest_path = match app_manifest_path(app_id) {
+        Some(m) => m,
+        None => return Ok(None),
+    };
+    let manifest = vdf::read_file(&manifest_path)?;
+    Ok(manifest
+        .get("AppState")
+        .and_then(|a| a.get("buildid"))
+        .and_then(vdf::Value::as_str)
+        .map(str::to_owned))
+}
+
+/// Records the game's current Steam build in the profile.
+///
+/// `modman update` does this after it checks every installed file;
+/// `modman add` only does the first time, since it doesn't check
+/// the files other mods installed.
+/// Returns true if the profile changed.
+pub fn note_steam_build(p: &mut Profile, always: bool) -> Result<bool> {
+    let app_id = match p.steam_app_id {
+        Some(id) => id,
+        None => return Ok(false),
+    };
+    if p.steam_build_id.is_some() && !always {
+        return Ok(false);
+    }
+    let build = steam_build_id(app_id)?;
+    if build.is_none() || build == p.steam_build_id {
+        return Ok(false);
+    }
+    debug!("Noting Steam build {}", build.as_deref().unwrap());
+    p.steam_build_id = build;
+    Ok(true)
+}
+
+/// Reads a string from the Windows registry, given as `<KEY>\<VALUE NAME>`.
+#[cfg(windows)]
+fn read_registry(key_and_value: &str) -> Option<String> {
+    use std::process::Command;
+
+    let (key, value) = key_and_value.rsplit_once('\\')?;
+    let output = Command::new("reg")
+        .args(&["query", key, "/v", value])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // Output looks like "    <VALUE NAME>    REG_SZ    <DATA>"
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|l| {
+            l.split_once("REG_SZ")
+                .map(|(_, data)| data.trim().to_owned())
+        })
+}
+
+#[cfg(not(windows))]
+fn read_registry(_key_and_value: &str) -> Option<String> {
+    None
+}
+
+/// Returns true if a process with the given executable name is running.
+#[cfg(target_os = "linux")]
+fn is_running(process: &str) -> bool {
+    // /proc/<PID>/comm is truncated to 15 bytes.
+    let truncated = &process.as_bytes()[..process.len().min(15)];
+    let procs = match fs::read_dir("/proc") {
+        Ok(p) => p,
+        Err(_) => return false,
+    };
+    procs.filter_map(|e| e.ok()).any(|e| {
+        let comm = match fs::read(e.path().join("comm")) {
+            Ok(c) => c,
+            Err(_) => return false,
+        };
+        let comm = comm.trim_ascii_end();
+        if comm.eq_ignore_ascii_case(truncated) {
+            return true;
+        }
+        // Windows games run under Wine (or Proton) sometimes show up as
+        // its preloader, with the game's executable in their arguments.
+        if !comm.starts_with(b"wine") {
+            return false;
+        }
+        fs::read(e.path().join("cmdline"))
+            .map(|cmdline| {
+                cmdline.split(|b| *b == 0).any(|arg| {
+                    arg.rsplit(|b| *b == b'/' || *b == b'\\')
+                        .next()
+                        .is_some_and(|exe| exe.eq_ignore_ascii_case(process.as_bytes()))
+                })
+            })
+            .unwrap_or(false)
+    })
+}
+
+#[cfg(windows)]
+fn is_running(process: &str) -> bool {
+    use std::process::Command;
+
+    Command::new("tasklist")
+        .args(&["/FI", &format!("IMAGENAME eq {}", process), "/NH"])
+        .output()
+        .map(|o| {
+            String::from_utf8_lossy(&o.stdout)
+                .to_lowercase()
+                .contains(&process.to_lowercase())
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn is_running(process: &str) -> bool {
+    use std::process::Command;
+
+    Command::new("ps")
+        .args(&["-A", "-o", "comm="])
+        .output()
+        .map(|o| {
+            String::from_utf8_lossy(&o.stdout).lines().any(|l| {
+                std::path::Path::new(l.trim())
+                    .file_name()
+                    .map(|n| n.to_string_lossy().eq_ignore_ascii_case(process))
+                    .unwrap_or(false)
+            })
+        })
+        .unwrap_or(false)
+}
+
+/// Warns if the profile's game is running,
+/// since it might have files open that we're about to change.
+pub fn warn_if_running(p: &Profile) {
+    if let Some(process) = &p.process {
+        if is_running(process) {
+            warn!(
+                "{} is running! Close it first, or it might be using files modman changes.",
+                process
+            );
+        }
+    }
+}
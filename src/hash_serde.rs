@@ -13,7 +13,7 @@ impl Serialize for FileHash {
     where
         S: Serializer,
     {
-        let as_hex = hex::encode(&self.bytes);
+        let as_hex = hex::encode(self.bytes);
         serializer.serialize_str(&as_hex)
     }
 }
@@ -33,7 +33,10 @@ impl<'de> Visitor<'de> for FileHashVisitor {
     {
         let decoded = hex::decode(s);
         match decoded {
-            Ok(byte_vec) => Ok(FileHash::new(Sha224Bytes::clone_from_slice(&byte_vec))),
+            Ok(byte_vec) if byte_vec.len() == Sha224Bytes::default().len() => {
+                Ok(FileHash::new(Sha224Bytes::clone_from_slice(&byte_vec)))
+            }
+            Ok(_) => Err(de::Error::invalid_length(s.len(), &self)),
             Err(invalid_hex) => Err(match invalid_hex {
                 hex::FromHexError::InvalidHexCharacter { c, .. } => {
                     de::Error::invalid_value(de::Unexpected::Char(c), &self)
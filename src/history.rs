@@ -0,0 +1,168 @@
+//! An audit log of everything modman has done to the game directory,
+//! for answering "what changed my game files?"
+//!
+//! Each mutating command (add, remove, update, repair) appends a record
+//! to `history.log` in the backup directory (usually `modman-backup/`),
+//! one JSON object per line, once it finishes (or fails).
+
+use std::fs;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use anyhow::*;
+use log::*;
+use serde_derive::{Deserialize, Serialize};
+use structopt::*;
+
+use crate::profile::*;
+
+pub fn history_path() -> PathBuf {
+    storage_dir().join("history.log")
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// When the command finished, as RFC 3339
+    pub timestamp: String,
+    /// The command line, minus the program name
+    pub command: Vec<String>,
+    pub mods: Vec<PathBuf>,
+    /// Game files that were added, replaced, restored, or removed
+    pub files: Vec<PathBuf>,
+    /// "ok", or the error that stopped the command
+    pub result: String,
+}
+
+/// The entry for the command we're currently running, if it's one we log.
+static CURRENT: Mutex<Option<HistoryEntry>> = Mutex::new(None);
+
+/// Starts recording a mutating command, given its arguments.
+pub fn begin(command: Vec<String>) {
+    let entry = HistoryEntry {
+        command,
+        ..Default::default()
+    };
+    *CURRENT.lock().unwrap() = Some(entry);
+}
+
+/// Notes that the current command is working on the given mod.
+pub fn note_mod(mod_path: &Path) {
+    if let Some(entry) = &mut *CURRENT.lock().unwrap() {
+        entry.mods.push(mod_path.to_owned());
+    }
+}
+
+/// Notes that the current command touched the given game file.
+pub fn note_file(file: &Path) {
+    if let Some(entry) = &mut *CURRENT.lock().unwrap() {
+        entry.files.push(file.to_owned());
+    }
+}
+
+/// Finishes recording the current command (if any) and appends it to the log.
+///
+/// Commands that didn't touch any files (dry runs, no-op updates, etc.)
+/// aren't worth logging.
+pub fn finish<T>(result: &Result<T>) -> Result<()> {
+    let mut entry = match CURRENT.lock().unwrap().take() {
+        Some(e) => e,
+        None => return Ok(()),
+    };
+    if entry.files.is_empty() || !storage_dir().is_dir() {
+        return Ok(());
+    }
+
+    entry.timestamp = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+    entry.result = match result {
+        Ok(_) => "ok".to_owned(),
+        Err(e) => format!("{:#}", e),
+    };
+    // Parallel commands note files in whatever order they finish.
+    entry.files.sort();
+    entry.files.dedup();
+
+    let path = history_path();
+    trace!("Appending to {}", path.display());
+    let mut log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Couldn't open {}", path.display()))?;
+    let mut line = serde_json::to_string(&entry)?;
+    line.push('\n');
+    log.write_all(line.as_bytes())
+        .with_context(|| format!("Couldn't append to {}", path.display()))?;
+    log.sync_data()
+        .with_context(|| format!("Couldn't sync {}", path.display()))?;
+    Ok(())
+}
+
+/// Runs a command that changes the game directory on behalf of something
+/// other than our command line (`serve`, the C API), recording it
+/// in the history log like it would be from the command line.
+pub fn record<T, F: FnOnce() -> Result<T>>(command: Vec<String>, f: F) -> Result<T> {
+    new_profile_generation();
+    begin(command);
+    let result = f();
+    if let Err(e) = finish(&result) {
+        warn!("Couldn't update the history log: {:#}", e);
+    }
+    result
+}
+
+pub fn read_history() -> Result<Vec<HistoryEntry>> {
+    let path = history_path();
+    let f = match fs::File::open(&path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(Error::from(e).context(format!("Couldn't open {}", path.display()))),
+    };
+    BufReader::new(f)
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let line = line.with_context(|| format!("Couldn't read {}", path.display()))?;
+            serde_json::from_str(&line)
+                .with_context(|| format!("Couldn't parse line {} of {}", i + 1, path.display()))
+        })
+        .collect()
+}
+
+/// Shows what modman has done to the game directory.
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Show the files each command touched.
+    #[structopt(short, long)]
+    files: bool,
+
+    /// Only show the last <N> commands.
+    #[structopt(short = "n", long, name = "N")]
+    last: Option<usize>,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let history = read_history()?;
+    let skip = args
+        .last
+        .map(|n| history.len().saturating_sub(n))
+        .unwrap_or(0);
+
+    for entry in history.iter().skip(skip) {
+        println!(
+            "{} modman {} ({} files): {}",
+            entry.timestamp,
+            entry.command.join(" "),
+            entry.files.len(),
+            entry.result
+        );
+        if args.files {
+            for f in &entry.files {
+                println!("\t{}", f.display());
+            }
+        }
+    }
+    Ok(())
+}
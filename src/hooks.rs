@@ -0,0 +1,251 @@
+//! User commands to run before and after installing or removing mods,
+//! e.g., to clear shader caches or regenerate a game's file index.
+//!
+//! Hooks come from two places: the profile (see `modman hooks`),
+//! which run for every mod, and a mod's own `mod.toml`:
+//!
+//! ```toml
+//! [[hooks.post-install]]
+//! command = "rm -rf shadercache"
+//! abort-on-failure = false
+//! ```
+//!
+//! Commands are run by the system shell from the profile's directory,
+//! with these environment variables set:
+//!
+//! - `MODMAN_HOOK`: the event (`pre-install`, `post-remove`, etc.)
+//! - `MODMAN_MOD`: the mod being installed or removed
+//! - `MODMAN_MOD_VERSION`: its version
+//! - `MODMAN_ROOT`: the game's root directory
+
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::*;
+use log::*;
+use semver::Version;
+use serde_derive::{Deserialize, Serialize};
+use structopt::*;
+
+use crate::profile::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    PreInstall,
+    PostInstall,
+    PreRemove,
+    PostRemove,
+}
+
+impl Event {
+    pub fn name(self) -> &'static str {
+        match self {
+            Event::PreInstall => "pre-install",
+            Event::PostInstall => "post-install",
+            Event::PreRemove => "pre-remove",
+            Event::PostRemove => "post-remove",
+        }
+    }
+}
+
+impl std::str::FromStr for Event {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pre-install" => Ok(Event::PreInstall),
+            "post-install" => Ok(Event::PostInstall),
+            "pre-remove" => Ok(Event::PreRemove),
+            "post-remove" => Ok(Event::PostRemove),
+            _ => bail!(
+                "Unknown hook {} (expected pre-install, post-install, pre-remove, or post-remove)",
+                s
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Hook {
+    pub command: String,
+    /// If set (the default), a failing hook stops the install or removal.
+    /// Otherwise we just warn about it.
+    #[serde(default = "yes", skip_serializing_if = "is_true")]
+    pub abort_on_failure: bool,
+}
+
+fn yes() -> bool {
+    true
+}
+
+fn is_true(b: &bool) -> bool {
+    *b
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Hooks {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_install: Vec<Hook>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_install: Vec<Hook>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_remove: Vec<Hook>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_remove: Vec<Hook>,
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn get(&self, event: Event) -> &Vec<Hook> {
+        match event {
+            Event::PreInstall => &self.pre_install,
+            Event::PostInstall => &self.post_install,
+            Event::PreRemove => &self.pre_remove,
+            Event::PostRemove => &self.post_remove,
+        }
+    }
+
+    pub fn get_mut(&mut self, event: Event) -> &mut Vec<Hook> {
+        match event {
+            Event::PreInstall => &mut self.pre_install,
+            Event::PostInstall => &mut self.post_install,
+            Event::PreRemove => &mut self.pre_remove,
+            Event::PostRemove => &mut self.post_remove,
+        }
+    }
+}
+
+/// Runs the profile's hooks for the given event, then the mod's.
+/// If dry_run is set, we just say what we'd run.
+pub fn run_hooks(
+    event: Event,
+    mod_path: &Path,
+    mod_version: &Version,
+    mod_hooks: &Hooks,
+    p: &Profile,
+    dry_run: bool,
+) -> Result<()> {
+    for hook in p.hooks.get(event).iter().chain(mod_hooks.get(event)) {
+        if dry_run {
+            info!("Would run {} hook `{}`", event.name(), hook.command);
+            continue;
+        }
+        info!("Running {} hook `{}`", event.name(), hook.command);
+
+        let status = shell_command(&hook.command)
+            .env("MODMAN_HOOK", event.name())
+            .env("MODMAN_MOD", mod_path)
+            .env("MODMAN_MOD_VERSION", mod_version.to_string())
+            .env("MODMAN_ROOT", in_profile_dir(&p.root_directory))
+            .current_dir(in_profile_dir("."))
+            .status()
+            .with_context(|| format!("Couldn't run {} hook `{}`", event.name(), hook.command));
+
+        let failure = match status {
+            Ok(s) if s.success() => continue,
+            Ok(s) => format_err!("{} hook `{}` failed ({})", event.name(), hook.command, s),
+            Err(e) => e,
+        };
+        if hook.abort_on_failure {
+            return Err(failure);
+        }
+        warn!("{:#}", failure);
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn shell_command(command: &str) -> Command {
+    let mut c = Command::new("sh");
+    c.arg("-c").arg(command);
+    c
+}
+
+#[cfg(windows)]
+fn shell_command(command: &str) -> Command {
+    let mut c = Command::new("cmd");
+    c.arg("/C").arg(command);
+    c
+}
+
+/// Lists or changes the profile's hooks
+///
+/// Profile hooks run for every mod, before any hooks from the mod's
+/// own mod.toml. See the README for the environment variables they get.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    #[structopt(subcommand)]
+    action: Option<Action>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Action {
+    /// Adds a hook that runs <COMMAND> on <EVENT>
+    /// (pre-install, post-install, pre-remove, or post-remove).
+    Add {
+        /// Warn instead of stopping if the command fails.
+        #[structopt(long)]
+        keep_going: bool,
+
+        #[structopt(name = "EVENT")]
+        event: Event,
+
+        #[structopt(name = "COMMAND")]
+        command: String,
+    },
+    /// Removes all hooks for <EVENT>.
+    Clear {
+        #[structopt(name = "EVENT")]
+        event: Event,
+    },
+}
+
+const EVENTS: [Event; 4] = [
+    Event::PreInstall,
+    Event::PostInstall,
+    Event::PreRemove,
+    Event::PostRemove,
+];
+
+impl Args {
+    /// Returns true if we'll change the profile (not just show it).
+    pub fn is_changing(&self) -> bool {
+        self.action.is_some()
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let (mut store, mut p) = open_and_check_profile()?;
+
+    match args.action {
+        None => {
+            for event in &EVENTS {
+                for hook in p.hooks.get(*event) {
+                    let note = if hook.abort_on_failure {
+                        ""
+                    } else {
+                        " (keep going on failure)"
+                    };
+                    println!("{}: {}{}", event.name(), hook.command, note);
+                }
+            }
+            return Ok(());
+        }
+        Some(Action::Add {
+            keep_going,
+            event,
+            command,
+        }) => p.hooks.get_mut(event).push(Hook {
+            command,
+            abort_on_failure: !keep_going,
+        }),
+        Some(Action::Clear { event }) => p.hooks.get_mut(event).clear(),
+    }
+    store.save_settings(&p)
+}
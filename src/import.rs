@@ -0,0 +1,100 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use log::*;
+use structopt::*;
+
+use crate::bundle::*;
+use crate::file_utils::*;
+use crate::profile::*;
+use crate::profile_store::*;
+
+/// Unpacks a profile and its backups made by `modman export-profile`
+/// here (or wherever -C or --profile gave)
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    /// Use <DIR> as the root directory instead of the one in the archive
+    #[structopt(long, name = "DIR")]
+    root: Option<PathBuf>,
+
+    /// Store the root directory relative to the profile.
+    #[structopt(long)]
+    relative_root: bool,
+
+    /// Store the imported profile as json or sqlite.
+    #[structopt(long, name = "FORMAT", default_value = "json")]
+    format: Backend,
+
+    /// The archive to import
+    #[structopt(name = "ARCHIVE")]
+    archive: PathBuf,
+}
+
+impl Args {
+    pub fn resolve_paths(&mut self, base: &Path) {
+        self.archive = base.join(&self.archive);
+        if let Some(root) = &mut self.root {
+            *root = base.join(&root);
+        }
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    if existing_backend().is_some() {
+        bail!("A profile already exists.");
+    }
+    let storage = storage_dir();
+    if storage.exists() {
+        bail!(
+            "A backup directory ({}/) already exists.\n\
+             Please move or remove it, then try again.",
+            storage.display()
+        );
+    }
+
+    info!("Extracting backups from {}...", args.archive.display());
+    let mut p = match read_bundle(&args.archive) {
+        Ok(p) => p,
+        Err(e) => {
+            // Don't leave a half-imported backup directory in the way
+            // of trying again.
+            if let Err(rm) = fs::remove_dir_all(&storage) {
+                warn!("Couldn't clean up {}/: {}", storage.display(), rm);
+            }
+            return Err(e);
+        }
+    };
+    let temp = tempdir();
+    fs::create_dir_all(&temp).with_context(|| {
+        format!(
+            "Couldn't create temporary storage directory ({}/)",
+            temp.display()
+        )
+    })?;
+    let backups = backup_dir();
+    fs::create_dir_all(&backups)
+        .with_context(|| format!("Couldn't create backup directory ({}/)", backups.display()))?;
+
+    if let Some(root) = args.root {
+        p.root_directory = root;
+    }
+    if !p.root_directory.is_dir() {
+        warn!(
+            "The root directory {} doesn't exist here. \
+             Run `modman relocate-root <NEW_DIR>` to point the profile at the game.",
+            p.root_directory.display()
+        );
+    } else if args.relative_root {
+        p.root_directory = relative_path(&p.root_directory, Path::new("."))?;
+    }
+
+    create_profile_store(&p, args.format)?;
+    info!(
+        "Imported {} mods into {}",
+        p.mods.len(),
+        backend_path(args.format)
+    );
+    Ok(())
+}
@@ -1,62 +1,269 @@
+use std::collections::BTreeMap;
 use std::default::Default;
 use std::fs;
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::*;
 use log::*;
 use structopt::*;
 
+use crate::adopt::adopt_mods;
+use crate::file_utils::*;
+use crate::games;
 use crate::profile::*;
+use crate::profile_store::*;
+use crate::wine;
 
 /// Create a new mod directory here (or wherever -C gave)
 #[derive(Debug, StructOpt)]
 pub struct Args {
     /// The root directory where mod files will be installed
+    #[structopt(long, required_unless = "GAME")]
+    root: Option<PathBuf>,
+
+    /// Find a known game (dcs, il2, etc.) and use its settings.
+    /// Add your own games to games.toml in your config directory
+    /// (e.g., ~/.config/modman/ or %APPDATA%\modman\).
+    #[structopt(long, name = "GAME")]
+    game: Option<String>,
+
+    /// Treat paths in the root directory as case-insensitive,
+    /// like Windows (NTFS) and macOS (APFS) do by default.
+    #[structopt(long)]
+    case_insensitive: bool,
+
+    /// The Wine (or Proton) prefix the game runs in, for Windows games on Linux.
+    /// With --game, Proton's is found by itself. See `modman wine-prefix --help`.
+    #[structopt(long, name = "PREFIX")]
+    wine_prefix: Option<PathBuf>,
+
+    /// Store the root directory relative to the profile
+    /// so that the two can be moved (or synced) together.
+    #[structopt(long)]
+    relative_root: bool,
+
+    /// Unicode normalization form (nfc, nfd, or none) for mod file paths.
+    /// NFC matches what Windows and Linux games expect.
+    #[structopt(long, name = "FORM", default_value = "nfc")]
+    unicode_form: UnicodeForm,
+
+    /// Store the profile as json (modman.profile)
+    /// or in a sqlite database (modman.profile.db),
+    /// which is faster for large mod sets.
+    #[structopt(long, name = "FORMAT", default_value = "json")]
+    format: Backend,
+
+    /// Keep backups (and temporary files, downloads, and logs)
+    /// in <STORAGE_DIR> instead of modman-backup/ next to the profile.
+    /// Relative paths are relative to the profile's directory.
+    #[structopt(long, name = "STORAGE_DIR")]
+    storage_dir: Option<PathBuf>,
+
+    /// Use the backup directory that's already here (say, after losing
+    /// the profile), and add each <MOD> that's installed to the profile,
+    /// matching its files to the game's and their backups by hash.
     #[structopt(long)]
-    root: PathBuf,
+    adopt: bool,
+
+    /// Mods to look for with --adopt
+    #[structopt(name = "MOD", requires = "adopt")]
+    mods: Vec<PathBuf>,
+}
+
+impl Args {
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root: Some(root),
+            game: None,
+            case_insensitive: false,
+            wine_prefix: None,
+            relative_root: false,
+            unicode_form: Default::default(),
+            format: Backend::Json,
+            storage_dir: None,
+            adopt: false,
+            mods: Vec::new(),
+        }
+    }
+
+    pub fn resolve_paths(&mut self, base: &Path) {
+        if let Some(root) = &mut self.root {
+            *root = base.join(&root);
+        }
+        if let Some(prefix) = &mut self.wine_prefix {
+            *prefix = base.join(&prefix);
+        }
+        if let Some(storage) = &mut self.storage_dir {
+            *storage = base.join(&storage);
+        }
+        for m in &mut self.mods {
+            *m = base.join(&m);
+        }
+    }
 }
 
 pub fn run(args: Args) -> Result<()> {
-    debug!("Checking if the given --root exists...");
+    let preset = args.game.as_deref().map(games::preset).transpose()?;
+
+    let wine_prefix = match (args.wine_prefix, &preset) {
+        (Some(prefix), _) => {
+            wine::check_prefix(&prefix)?;
+            Some(prefix)
+        }
+        (None, Some(preset)) if !cfg!(windows) => {
+            let found = preset.steam_app_id.and_then(wine::find_proton_prefix);
+            if let Some(prefix) = &found {
+                info!(
+                    "Found {}'s Proton prefix in {}",
+                    preset.name,
+                    prefix.display()
+                );
+            }
+            found
+        }
+        _ => None,
+    };
+
+    let mut root_path = match (args.root, &preset) {
+        (Some(r), _) => r,
+        (None, Some(preset)) => {
+            let found = games::find_install(preset, wine_prefix.as_deref()).ok_or_else(|| {
+                format_err!(
+                    "Couldn't find {}. Pass --root <DIR> to say where it is.",
+                    preset.name
+                )
+            })?;
+            info!("Found {} in {}", preset.name, found.display());
+            found
+        }
+        (None, None) => unreachable!("--root is required without --game"),
+    };
 
-    let root_path = args.root;
+    debug!("Checking if the root directory exists...");
     if !root_path.is_dir() {
         bail!("{} is not an existing directory!", root_path.display());
     }
+    if args.relative_root {
+        root_path = relative_path(&root_path, Path::new("."))?;
+        debug!("Root directory is {} from here", root_path.display());
+    }
 
-    debug!("Writing an empty profile file...");
+    let mut roots = BTreeMap::new();
+    if let Some(preset) = &preset {
+        for (name, dir) in &preset.roots {
+            let dir = games::preset_path(dir, wine_prefix.as_deref());
+            if dir.is_dir() {
+                info!("Adding the {} root ({})", name, dir.display());
+                roots.insert(name.clone(), dir);
+            } else {
+                warn!(
+                    "{} doesn't exist (yet?). Once it does, run `modman roots add {} {}`",
+                    dir.display(),
+                    name,
+                    dir.display()
+                );
+            }
+        }
+    }
 
-    let p = Profile {
+    let mut p = Profile {
         root_directory: root_path,
+        roots,
+        case_insensitive: args.case_insensitive
+            || preset.as_ref().is_some_and(|g| g.case_insensitive)
+            // Wine emulates Windows' case-insensitivity.
+            || wine_prefix.is_some(),
+        unicode_form: args.unicode_form,
+        steam_app_id: preset.as_ref().and_then(|g| g.steam_app_id),
+        steam_build_id: None,
+        process: preset.and_then(|g| g.process),
+        hooks: Default::default(),
+        auto_check: false,
+        storage_directory: args.storage_dir,
+        keep_originals: None,
+        strict: false,
+        quick_check: false,
+        elevate: false,
+        wine_prefix,
+        sets: Default::default(),
+        layers: Default::default(),
         mods: Default::default(),
     };
-    create_new_profile_file(&p)?;
+    set_storage_dir(p.storage_directory.as_deref());
+
+    if args.adopt {
+        // Don't bother looking at mods if we can't write the profile anyway.
+        if existing_backend().is_some() {
+            bail!("A profile already exists.");
+        }
+        adopt_mods(&mut p, &args.mods)?;
+        debug!("Writing the profile file...");
+    } else {
+        debug!("Writing an empty profile file...");
+    }
+    create_profile_store(&p, args.format)?;
 
-    info!("Profile written to {}", PROFILE_PATH);
+    info!("Profile written to {}", backend_path(args.format));
 
-    if let Some(mkdir_err) = fs::create_dir(STORAGE_PATH).err() {
-        if mkdir_err.kind() == std::io::ErrorKind::AlreadyExists {
+    let storage = storage_dir();
+    if let Some(parent) = storage.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Couldn't create directory {}", parent.display()))?;
+    }
+    if let Some(mkdir_err) = fs::create_dir(&storage).err() {
+        if mkdir_err.kind() == std::io::ErrorKind::AlreadyExists && args.adopt {
+            // Fill in anything that's missing.
+            create_backup_dirs(|d| fs::create_dir_all(d))?;
+            if !backup_readme_path().exists() {
+                write_backup_readme()?;
+            }
+            info!("Adopted the backup directory ({}/)", storage.display());
+            return Ok(());
+        } else if mkdir_err.kind() == std::io::ErrorKind::AlreadyExists {
             // Let's remove the profile file we just created so that
             // the user doesn't get an error that it exists next time.
-            fs::remove_file(PROFILE_PATH).context(
+            delete_profile_store(args.format).context(
                 "Failed to remove profile file after discovering a backup directory already exists.")?;
             bail!(
                 "A backup directory ({}/) already exists.\n\
-                 Please move or remove it, then run modman init again.",
-                STORAGE_PATH
+                 Please move or remove it, then run modman init again\n\
+                 (or run `modman init --adopt` to use it).",
+                storage.display()
             );
         } else {
             return Err(Error::from(mkdir_err));
         }
     }
 
-    fs::create_dir(TEMPDIR_PATH).context("Couldn't create temporary storage directory ({}/)")?;
-    fs::create_dir(BACKUP_PATH).context("Couldn't create backup directory ({}/)")?;
+    create_backup_dirs(|d| fs::create_dir(d))?;
+    write_backup_readme()?;
+
+    info!("Backup directory ({}/) created", storage.display());
+
+    Ok(())
+}
+
+/// Creates the temp and backup directories with the given function.
+fn create_backup_dirs(create: fn(&Path) -> std::io::Result<()>) -> Result<()> {
+    let temp = tempdir();
+    create(&temp).with_context(|| {
+        format!(
+            "Couldn't create temporary storage directory ({}/)",
+            temp.display()
+        )
+    })?;
+    let backups = backup_dir();
+    create(&backups)
+        .with_context(|| format!("Couldn't create backup directory ({}/)", backups.display()))
+}
+
+fn write_backup_readme() -> Result<()> {
+    let readme = backup_readme_path();
     fs::OpenOptions::new()
         .write(true)
         .create_new(true)
-        .open(BACKUP_README)?
+        .open(&readme)?
         .write_all(
             format!(
                 r#"modman backs up the game files here.
@@ -68,13 +275,10 @@ This ensures that {1}/ only contains complete backups.
 If modman is closed while performing a backup, some leftover files
 might be found in {0}/.
 Feel free to delete them."#,
-                TEMPDIR_PATH, BACKUP_PATH
+                tempdir().display(),
+                backup_dir().display()
             )
             .as_bytes(),
         )
-        .with_context(|| format!("Couldn't create backup README ({})", BACKUP_README))?;
-
-    info!("Backup directory ({}/) created", STORAGE_PATH);
-
-    Ok(())
+        .with_context(|| format!("Couldn't create backup README ({})", readme.display()))
 }
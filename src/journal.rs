@@ -1,15 +1,51 @@
+//! The activation journal `modman add` keeps in the temp directory,
+//! so `modman repair` can undo what it did if it's interrupted.
+//!
+//! It starts with a header saying which run of modman wrote it,
+//! and the mod it was adding. Then there's a line for each file it added
+//! or replaced, and the hash of each game file it backed up:
+//!
+//! ```text
+//! Journal 2 6f3a91c2 mod1.zip
+//! Replace A.txt
+//! Backed-up 36ff4c95f706b203a843b42a17bed28e471d092379f167c71b1849f2 A.txt
+//! Add C.txt
+//! Cover D.txt
+//! ```
+//!
+//! (`Cover` is for files another mod installed, which `modman add --overwrite`
+//! keeps in covered/ instead of backing up.)
+//! Journals from older versions of modman have no header or hashes.
+//!
+//! Syncing the journal after every line made it most of the time spent
+//! adding mods with thousands of small files. Instead, entries are just
+//! written, and before `modman add` changes a file in the game directory,
+//! it syncs what's been written so far (see `sync_journal()`).
+//! Files are installed in parallel, so one sync usually covers
+//! the entries of several threads that were waiting on it.
+
 use std::collections::*;
 use std::fs;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
 use anyhow::*;
+use log::*;
 
+use crate::checksums::parse_hash;
+use crate::encoding::*;
+use crate::failure::*;
+use crate::file_utils::{check_writable, run_id};
 use crate::profile::*;
 
 static JOURNAL_NAME: &str = "activate.journal";
 
+/// The version of the journal format we write
+const JOURNAL_VERSION: u32 = 2;
+
 /// A journal (fake or otherwise, see DryRunJournal)
 /// that (as best we can, standard caveats apply)
 /// records files we're adding or replacing in the game directory.
@@ -23,21 +59,66 @@ pub trait Journal: Send {
         self.entry("Replace", p)
     }
 
-    /// Adds a line to the journal
+    fn cover_file(&mut self, p: &Path) -> Result<()> {
+        self.entry("Cover", p)
+    }
+
+    /// Notes the hash of the game file we backed up
+    /// (once the backup is in place), so it can be checked before it's restored.
+    fn backed_up(&mut self, p: &Path, original_hash: &FileHash) -> Result<()> {
+        self.entry(&format!("Backed-up {:x}", original_hash.bytes), p)
+    }
+
+    /// Adds a line to the journal.
+    /// (It isn't necessarily on disk until it's synced; see `sync_journal()`.)
     fn entry(&mut self, kind: &str, p: &Path) -> Result<()>;
+
+    /// Returns what needs syncing for everything written so far to be on disk,
+    /// or None if it already is.
+    fn pending_sync(&self) -> Option<PendingSync>;
+}
+
+/// Makes sure everything journaled so far is on disk,
+/// so it's safe to change the files it's about.
+///
+/// The journal is only locked long enough to see what needs syncing,
+/// so other threads can keep adding entries while we sync.
+/// If another thread's sync gets ours on disk first, we don't sync at all.
+pub fn sync_journal(journal: &Mutex<Box<dyn Journal>>) -> Result<()> {
+    let pending = journal.lock().unwrap().pending_sync();
+    match pending {
+        Some(p) => p.sync(),
+        None => Ok(()),
+    }
 }
 
-pub fn create_journal(dry_run: bool) -> Result<Box<dyn Journal>> {
+/// Starts a journal for adding the given mod.
+pub fn create_journal(dry_run: bool, mod_path: &Path) -> Result<Box<dyn Journal>> {
+    let mut journal: Box<dyn Journal> = if dry_run {
+        Box::new(DryRunJournal::new())
+    } else {
+        check_writable()?;
+        Box::new(ActivationJournal::new()?)
+    };
+    journal.entry(
+        &format!("Journal {} {}", JOURNAL_VERSION, run_id()),
+        mod_path,
+    )?;
+    Ok(journal)
+}
+
+/// Reopens the journal an interrupted `modman add` left behind,
+/// to add to it as we finish the job.
+pub fn resume_journal(dry_run: bool) -> Result<Box<dyn Journal>> {
     if dry_run {
         Ok(Box::new(DryRunJournal::new()))
     } else {
-        let real_deal = ActivationJournal::new()?;
-        Ok(Box::new(real_deal))
+        Ok(Box::new(ActivationJournal::resume()?))
     }
 }
 
 pub fn get_journal_path() -> PathBuf {
-    Path::new(TEMPDIR_PATH).join(JOURNAL_NAME)
+    tempdir().join(JOURNAL_NAME)
 }
 
 pub fn delete_journal(j: Box<dyn Journal>) -> Result<()> {
@@ -50,11 +131,52 @@ pub fn delete_journal(j: Box<dyn Journal>) -> Result<()> {
 pub enum JournalAction {
     Added,
     Replaced,
+    /// Installed over another mod's file (see `Profile::layers`)
+    Covered,
+}
+
+/// What the journal says we did to a file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub action: JournalAction,
+    /// The hash of the game file we replaced, if the journal has it,
+    /// so `modman repair` can check the backup before restoring it.
+    pub original_hash: Option<FileHash>,
+}
+
+impl JournalEntry {
+    fn new(action: JournalAction) -> Self {
+        Self {
+            action,
+            original_hash: None,
+        }
+    }
 }
 
-pub type JournalMap = BTreeMap<PathBuf, JournalAction>;
+pub type JournalMap = BTreeMap<PathBuf, JournalEntry>;
+
+/// Which `modman add` wrote a journal, from its header
+#[derive(Clone, Debug)]
+pub struct JournalHeader {
+    pub version: u32,
+    /// The run that started it (see `file_utils::run_id()`)
+    pub run_id: String,
+    /// The mod it was adding
+    pub mod_path: PathBuf,
+}
 
+/// Reads the files the journal says we added and replaced,
+/// or nothing if there's no journal.
 pub fn read_journal() -> Result<JournalMap> {
+    Ok(parse_journal()?.1)
+}
+
+/// Reads the journal's header, if there's a journal (and it has one).
+pub fn read_journal_header() -> Result<Option<JournalHeader>> {
+    Ok(parse_journal()?.0)
+}
+
+fn parse_journal() -> Result<(Option<JournalHeader>, JournalMap)> {
     // Could be Result::or_else except we want to return from the
     // function inside the Err arm.
     let f = match fs::File::open(get_journal_path()) {
@@ -62,60 +184,175 @@ pub fn read_journal() -> Result<JournalMap> {
         Err(open_err) => {
             // No problem if there's no journal
             if open_err.kind() == std::io::ErrorKind::NotFound {
-                return Ok(BTreeMap::new());
+                return Ok((None, BTreeMap::new()));
             } else {
                 bail!("Couldn't open activation journal");
             }
         }
     };
 
-    BufReader::new(f)
-        .lines()
-        .map(|l| {
-            let line = l.context("Couldn't read activation journal")?;
-            read_journal_line(line)
-        })
-        .collect()
+    let mut header = None;
+    let mut entries = JournalMap::new();
+    for (i, l) in BufReader::new(f).lines().enumerate() {
+        let line = l.context("Couldn't read activation journal")?;
+        read_journal_line(&line, i == 0, &mut header, &mut entries)
+            .with_context(|| format!("Couldn't understand activation journal line:\n{}", line))?;
+    }
+    Ok((header, entries))
 }
 
-fn read_journal_line(line: String) -> Result<(PathBuf, JournalAction)> {
+fn read_journal_line(
+    line: &str,
+    first: bool,
+    header: &mut Option<JournalHeader>,
+    entries: &mut JournalMap,
+) -> Result<()> {
     let tokens: Vec<&str> = line
         .split(char::is_whitespace)
         .filter(|t| !t.is_empty())
         .collect();
-    if tokens.len() != 2 {
-        bail!("Couldn't understand activation journal line:\n{}", line);
-    }
-    match tokens[0] {
-        "Add" => Ok((PathBuf::from(tokens[1]), JournalAction::Added)),
-        "Replace" => Ok((PathBuf::from(tokens[1]), JournalAction::Replaced)),
-        _ => Err(format_err!(
-            "Couldn't understand activation journal line:\n{}",
-            line
-        )),
+    match tokens.as_slice() {
+        // (Older journals don't have a header, and start right in with files.)
+        ["Journal", version, run_id, mod_path] if first => {
+            let version: u32 = version.parse().context("Bad journal version")?;
+            if version > JOURNAL_VERSION {
+                bail!(
+                    "The journal is version {}; this version of modman only knows up to {}",
+                    version,
+                    JOURNAL_VERSION
+                );
+            }
+            *header = Some(JournalHeader {
+                version,
+                run_id: run_id.to_string(),
+                mod_path: decode_path(mod_path)?,
+            });
+        }
+        ["Add", path] => {
+            entries.insert(decode_path(path)?, JournalEntry::new(JournalAction::Added));
+        }
+        ["Replace", path] => {
+            entries.insert(
+                decode_path(path)?,
+                JournalEntry::new(JournalAction::Replaced),
+            );
+        }
+        ["Cover", path] => {
+            entries.insert(
+                decode_path(path)?,
+                JournalEntry::new(JournalAction::Covered),
+            );
+        }
+        ["Backed-up", hash, path] => {
+            let path = decode_path(path)?;
+            let entry = entries
+                .get_mut(&path)
+                .filter(|e| e.action == JournalAction::Replaced)
+                .ok_or_else(|| format_err!("{} wasn't replaced", path.display()))?;
+            entry.original_hash =
+                Some(parse_hash(hash).ok_or_else(|| format_err!("Bad hash {}", hash))?);
+        }
+        _ => bail!("Unknown entry"),
     }
+    Ok(())
 }
 
-/// A fake journal that writes to stderr instead of applying sync'd writes
+/// A fake journal that just logs entries instead of applying sync'd writes
 /// to a file.
-struct DryRunJournal {}
+///
+/// Files are installed in parallel, so entries are logged all at once
+/// when it's dropped, sorted by path, instead of in whatever order they come.
+struct DryRunJournal {
+    entries: Vec<(String, PathBuf)>,
+}
 
 impl DryRunJournal {
     fn new() -> Self {
-        DryRunJournal {}
+        DryRunJournal {
+            entries: Vec::new(),
+        }
     }
 }
 
 impl Journal for DryRunJournal {
     fn entry(&mut self, kind: &str, p: &Path) -> Result<()> {
-        let path_str = p.display();
-        eprintln!("{} {}", kind, path_str);
+        self.entries.push((kind.to_owned(), p.to_owned()));
         Ok(())
     }
+
+    fn pending_sync(&self) -> Option<PendingSync> {
+        None
+    }
+}
+
+impl Drop for DryRunJournal {
+    fn drop(&mut self) {
+        if self.entries.is_empty() {
+            return;
+        }
+        // Keep the header (if this isn't a resumed journal) first,
+        // and each file's entries in order.
+        let header_len = usize::from(self.entries[0].0.starts_with("Journal "));
+        let (header, files) = self.entries.split_at_mut(header_len);
+        files.sort_by(|a, b| a.1.cmp(&b.1));
+        let mut message = "Would journal:".to_owned();
+        for (kind, p) in header.iter().chain(files.iter()) {
+            message += &format!("\n\t{} {}", kind, p.display());
+        }
+        debug!("{}", message);
+    }
 }
 
 struct ActivationJournal {
+    file: Arc<JournalFile>,
+}
+
+/// The activation journal's file, shared with the threads syncing it
+struct JournalFile {
     fd: fs::File,
+    /// How many entries have been written
+    written: AtomicU64,
+    /// How many of them are known to be on disk
+    synced: AtomicU64,
+    /// Held while syncing, so threads take turns instead of piling up syncs.
+    syncing: Mutex<()>,
+}
+
+impl JournalFile {
+    fn new(fd: fs::File) -> Arc<Self> {
+        Arc::new(Self {
+            fd,
+            written: AtomicU64::new(0),
+            synced: AtomicU64::new(0),
+            syncing: Mutex::new(()),
+        })
+    }
+}
+
+/// Journal entries that still need to be synced (see `sync_journal()`)
+pub struct PendingSync {
+    file: Arc<JournalFile>,
+    /// How many entries need to be on disk
+    through: u64,
+}
+
+impl PendingSync {
+    fn sync(self) -> Result<()> {
+        let _turn = self.file.syncing.lock().unwrap();
+        // Whoever synced while we waited might have gotten ours too.
+        if self.file.synced.load(Ordering::Acquire) >= self.through {
+            return Ok(());
+        }
+        // Everything written by now is covered by this sync,
+        // not just what we were waiting on.
+        let written = self.file.written.load(Ordering::Acquire);
+        self.file
+            .fd
+            .sync_data()
+            .context("Couldn't sync activation journal")?;
+        self.file.synced.store(written, Ordering::Release);
+        Ok(())
+    }
 }
 
 impl ActivationJournal {
@@ -126,17 +363,32 @@ impl ActivationJournal {
             .open(get_journal_path())
             .map_err(|e| {
                 if e.kind() == std::io::ErrorKind::AlreadyExists {
-                    format_err!(
-                        "An activation journal already exists at {}.\n\
+                    failure(
+                        Failure::NeedsRepair,
+                        format!(
+                            "An activation journal already exists at {}.\n\
                          If a previous run of `modman add` was interrupted,\n\
                          run `modman repair`.",
-                        get_journal_path().display()
+                            get_journal_path().display()
+                        ),
                     )
                 } else {
                     Error::from(e).context("Couldn't create activation journal")
                 }
             })?;
-        Ok(ActivationJournal { fd })
+        Ok(ActivationJournal {
+            file: JournalFile::new(fd),
+        })
+    }
+
+    fn resume() -> Result<Self> {
+        let fd = fs::OpenOptions::new()
+            .append(true)
+            .open(get_journal_path())
+            .context("Couldn't open activation journal")?;
+        Ok(ActivationJournal {
+            file: JournalFile::new(fd),
+        })
     }
 }
 
@@ -145,16 +397,21 @@ impl Journal for ActivationJournal {
     fn entry(&mut self, kind: &str, p: &Path) -> Result<()> {
         // In all other places, we've used display(),
         // since they're just for user-facing messages.
-        // Here, demand that paths be UTF-8,
-        // because reading this back in becomes a cross-platform nightmare
-        // (thanks, Windows "Unicode" strings!) otherwise.
-        let path_str = p.to_str().expect(crate::encoding::UTF8_ONLY);
-        self.fd
+        // Here, escape the path so that we can read back exactly what we wrote,
+        // even if it has spaces or isn't valid Unicode.
+        let path_str = encode_path(p);
+        (&self.file.fd)
             .write_all(format!("{} {}\n", kind, path_str).as_bytes())
             .context("Couldn't append to activation journal")?;
-        self.fd
-            .sync_data()
-            .context("Couldn't sync activation journal")?;
+        self.file.written.fetch_add(1, Ordering::Release);
         Ok(())
     }
+
+    fn pending_sync(&self) -> Option<PendingSync> {
+        let written = self.file.written.load(Ordering::Acquire);
+        (written > self.file.synced.load(Ordering::Acquire)).then(|| PendingSync {
+            file: self.file.clone(),
+            through: written,
+        })
+    }
 }
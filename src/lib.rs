@@ -0,0 +1,81 @@
+//! modman's guts, shared by the `modman` command
+//! and (with the `ffi` feature) a C library for embedding it in game launchers.
+
+// First, so the others can use its macros.
+#[macro_use]
+pub mod failure;
+#[macro_use]
+pub mod strict;
+
+pub mod add;
+pub mod adopt;
+pub mod auto_check;
+pub mod backup;
+pub mod bundle;
+pub mod check;
+pub mod checksums;
+pub mod clean;
+pub mod completions;
+pub mod composite_mod;
+pub mod delta;
+pub mod deps;
+pub mod diff;
+pub mod diff_profile;
+pub mod dir_mod;
+pub mod download;
+pub mod elevate;
+pub mod encoding;
+pub mod export;
+pub mod extract;
+pub mod file_utils;
+pub mod games;
+pub mod hash_serde;
+pub mod history;
+pub mod hooks;
+pub mod import;
+pub mod init;
+pub mod journal;
+pub mod list;
+pub mod log_file;
+pub mod merge;
+pub mod migrate;
+pub mod mod_config;
+pub mod mode_serde;
+pub mod modification;
+pub mod move_storage;
+pub mod mtime_serde;
+pub mod older_originals;
+pub mod outdated;
+pub mod output;
+pub mod pack;
+pub mod pager;
+pub mod plan;
+pub mod profile;
+pub mod profile_command;
+pub mod profile_store;
+pub mod progress;
+pub mod rebuild;
+pub mod recover;
+pub mod relocate;
+pub mod remove;
+pub mod rename;
+pub mod repair;
+pub mod rollback;
+pub mod roots;
+pub mod serve;
+pub mod sets;
+pub mod show;
+pub mod space;
+pub mod stats;
+pub mod steam_app;
+pub mod tag;
+pub mod text;
+pub mod update;
+pub mod vdf;
+pub mod version_serde;
+pub mod watch;
+pub mod wine;
+pub mod zip_mod;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
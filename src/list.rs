@@ -1,9 +1,17 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::path::PathBuf;
+
 use anyhow::*;
 use log::*;
+use serde_derive::Serialize;
 use structopt::*;
 
 use crate::modification::*;
+use crate::pager;
 use crate::profile::*;
+use crate::stats::*;
+use crate::tag::format_tags;
 
 /// List installed mods.
 #[derive(Debug, StructOpt)]
@@ -12,36 +20,117 @@ pub struct Args {
     #[structopt(short, long)]
     files: bool,
 
-    /// Print each mod's README
+    /// Print each mod's README (through $PAGER if it's long)
     #[structopt(short, long)]
     readme: bool,
+
+    /// Show how much disk space each mod's files (and backups) take up.
+    #[structopt(short, long)]
+    size: bool,
+
+    /// Only list mods with the given tag (see `modman tag`).
+    #[structopt(long, name = "TAG")]
+    tag: Option<String>,
 }
 
 pub fn run(args: Args) -> Result<()> {
     let p = load_and_check_profile()?;
 
-    for (mod_name, mod_manifest) in p.mods {
-        println!("{} (v{})", mod_name.display(), mod_manifest.version);
+    // Build it all up so READMEs can go through a pager.
+    let mut out = String::new();
+    for (mod_name, mod_manifest) in &p.mods {
+        if let Some(t) = &args.tag {
+            if !mod_manifest.tags.contains(t) {
+                continue;
+            }
+        }
+        // (Besides the version)
+        let mut details = match &mod_manifest.alias {
+            Some(a) => format!(", alias {}", a),
+            None => String::new(),
+        };
+        if !mod_manifest.tags.is_empty() {
+            write!(details, ", tagged {}", format_tags(&mod_manifest.tags))?;
+        }
+        if args.size {
+            let size = mod_size(&p, mod_manifest);
+            writeln!(
+                out,
+                "{} (v{}{}) - {} installed, {} of backups",
+                mod_name.display(),
+                mod_manifest.version,
+                details,
+                format_size(size.installed),
+                format_size(size.backups)
+            )?;
+        } else {
+            writeln!(
+                out,
+                "{} (v{}{})",
+                mod_name.display(),
+                mod_manifest.version,
+                details
+            )?;
+        }
+        if archive_changed(mod_name, mod_manifest)? {
+            warn!(
+                "{} isn't the archive it was installed from (it's changed since)",
+                mod_name.display()
+            );
+        }
         if args.readme {
             // We don't store READMEs in the manifest, so go get the mod itself.
-            match open_mod(&mod_name) {
+            match open_mod(mod_name, p.unicode_form, mod_manifest.root.as_deref()) {
                 Ok(m) => {
                     let opened_version = m.version();
                     if opened_version != &mod_manifest.version {
-                        warn!("Mod file has a different version ({}) than the one that was installed ({})",
+                        strict_warn!(VersionDrift, "Mod file has a different version ({}) than the one that was installed ({})",
                               opened_version, mod_manifest.version);
                     }
-                    println!("{}", m.readme());
+                    writeln!(out, "{}", m.readme())?;
                 }
                 Err(e) => warn!("Couldn't open mod {}:\n{:#}", mod_name.display(), e),
             }
         }
         if args.files {
             for f in mod_manifest.files.keys() {
-                println!("\t{}", f.display());
+                writeln!(out, "\t{}", f.display())?;
             }
         }
     }
 
+    if args.readme {
+        pager::page(&out)?;
+    } else {
+        print!("{}", out);
+    }
+
     Ok(())
 }
+
+/// An installed mod, as `serve` and the C API list them
+#[derive(Debug, Serialize)]
+pub struct ListedMod {
+    pub path: PathBuf,
+    pub version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+    /// How many files it installed
+    pub files: usize,
+}
+
+pub fn listed_mods() -> Result<Vec<ListedMod>> {
+    let p = load_and_check_profile()?;
+    Ok(p.mods
+        .into_iter()
+        .map(|(path, manifest)| ListedMod {
+            path,
+            version: manifest.version.to_string(),
+            alias: manifest.alias,
+            tags: manifest.tags,
+            files: manifest.files.len(),
+        })
+        .collect())
+}
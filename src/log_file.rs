@@ -0,0 +1,150 @@
+//! A detailed log of what modman did (`--log-file`), for figuring out
+//! what went wrong after the fact without asking anyone to run it again
+//! with `-vvv`.
+//!
+//! Each line has a timestamp, an ID for the modman run that wrote it
+//! (so lines from different runs can be told apart), the level, and
+//! where it came from:
+//!
+//! ```text
+//! 2026-10-16T18:03:12.417Z 6f3a91c2 DEBUG modman::add: Backing up rootdir/A.txt
+//! ```
+//!
+//! Once the file gets big, it's moved aside to `<LOG_FILE>.1`
+//! (and that to `<LOG_FILE>.2`, and so on), keeping the last few.
+//!
+//! Like the history log and progress events, this is global state;
+//! it does nothing unless `--log-file` was given.
+
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use anyhow::*;
+
+use crate::file_utils::{rename_file, run_id};
+use crate::profile::storage_dir;
+
+/// Where the log goes if `--log-file` isn't given a path
+pub fn default_log_path() -> PathBuf {
+    storage_dir().join("modman.log")
+}
+
+/// How big the log gets before it's rotated
+const MAX_LOG_SIZE: u64 = 1024 * 1024;
+
+/// How many rotated logs to keep
+const OLD_LOGS_KEPT: usize = 3;
+
+struct LogFile {
+    file: fs::File,
+    level: log::LevelFilter,
+}
+
+static LOG_FILE: Mutex<Option<LogFile>> = Mutex::new(None);
+
+/// Starts writing log messages up to the given level to the given file,
+/// or the default one (if there's a backup directory to put it in).
+pub fn begin(path: Option<&Path>, level: log::LevelFilter, command: &[String]) -> Result<()> {
+    let path = match path {
+        Some(p) => p.to_owned(),
+        None if storage_dir().is_dir() => default_log_path(),
+        None => {
+            log::debug!("No backup directory to keep a log file in");
+            return Ok(());
+        }
+    };
+    rotate(&path)?;
+    let file = fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&path)
+        .with_context(|| format!("Couldn't open log file {}", path.display()))?;
+
+    *LOG_FILE.lock().unwrap() = Some(LogFile { file, level });
+    write_line(
+        log::Level::Info,
+        "modman",
+        &format!("Running `modman {}`", command.join(" ")),
+    );
+    Ok(())
+}
+
+/// The level the log file wants messages at, if there is one.
+pub fn level() -> Option<log::LevelFilter> {
+    LOG_FILE.lock().unwrap().as_ref().map(|l| l.level)
+}
+
+/// Notes how the command ended.
+pub fn finish<T>(result: &Result<T>, exit_code: i32) {
+    let message = match result {
+        Ok(_) => "Finished".to_owned(),
+        Err(e) => format!("Failed with exit code {}: {:#}", exit_code, e),
+    };
+    write_line(log::Level::Info, "modman", &message);
+}
+
+/// Moves the log (and older ones) aside if it's gotten too big.
+fn rotate(path: &Path) -> Result<()> {
+    match fs::metadata(path) {
+        Ok(m) if m.len() >= MAX_LOG_SIZE => (),
+        _ => return Ok(()),
+    }
+    let numbered = |n: usize| {
+        let mut p = path.as_os_str().to_owned();
+        p.push(format!(".{}", n));
+        PathBuf::from(p)
+    };
+    for n in (1..OLD_LOGS_KEPT).rev() {
+        let from = numbered(n);
+        if from.exists() {
+            rename_file(&from, &numbered(n + 1))
+                .with_context(|| format!("Couldn't rotate log file {}", from.display()))?;
+        }
+    }
+    rename_file(path, &numbered(1))
+        .with_context(|| format!("Couldn't rotate log file {}", path.display()))
+}
+
+fn write_line(level: log::Level, target: &str, message: &str) {
+    if let Some(log) = &mut *LOG_FILE.lock().unwrap() {
+        if level > log.level {
+            return;
+        }
+        let line = format!(
+            "{} {} {:5} {}: {}\n",
+            humantime::format_rfc3339_millis(SystemTime::now()),
+            run_id(),
+            level,
+            target,
+            message
+        );
+        // Like progress, logging is best-effort.
+        let _ = log.file.write_all(line.as_bytes());
+    }
+}
+
+/// Passes log messages on to another logger,
+/// and writes them to the log file too (if there is one).
+pub struct Logger<L> {
+    pub inner: L,
+}
+
+impl<L: log::Log> log::Log for Logger<L> {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        level().is_some_and(|l| metadata.level() <= l) || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if level().is_some_and(|l| record.level() <= l) {
+            write_line(record.level(), record.target(), &record.args().to_string());
+        }
+        self.inner.log(record)
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
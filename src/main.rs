@@ -1,37 +1,146 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use anyhow::*;
 use atty::*;
 use structopt::*;
 
-mod add;
-mod check;
-mod dir_mod;
-mod encoding;
-mod file_utils;
-mod hash_serde;
-mod init;
-mod journal;
-mod list;
-mod modification;
-mod profile;
-mod remove;
-mod repair;
-mod update;
-mod version_serde;
-mod zip_mod;
+use modman::failure::Failure;
+use modman::*;
 
 /// An OVGME-like mod manager with exciting 21st century tech - like threads!
 #[derive(Debug, StructOpt)]
+#[structopt(after_help = failure::EXIT_CODES_HELP)]
 struct Options {
     /// Print progress to stderr. Pass multiple times for more verbosity (info, debug, trace)
     #[structopt(short, long, parse(from_occurrences))]
     verbosity: usize,
 
+    /// Only print errors, not warnings.
+    #[structopt(short, long, conflicts_with = "verbosity")]
+    quiet: bool,
+
+    /// When to color output: auto (when it's going to a terminal), always, or never.
+    #[structopt(
+        long,
+        name = "WHEN",
+        default_value = "auto",
+        possible_values = &["auto", "always", "never"]
+    )]
+    color: String,
+
     /// Do everything with <DIR> as the working directory.
     #[structopt(short = "C", long, name = "DIR")]
     directory: Option<PathBuf>,
 
+    /// Use the profile <NAME> from your user data directory
+    /// (e.g., ~/.local/share/modman/ or %APPDATA%\modman\)
+    /// instead of the one in the working directory.
+    #[structopt(
+        short,
+        long,
+        name = "NAME",
+        env = "MODMAN_PROFILE",
+        conflicts_with = "DIR"
+    )]
+    profile: Option<String>,
+
+    /// How many times to try overwriting, moving, or removing a file
+    /// that's locked by another program (e.g., antivirus) before giving up.
+    #[structopt(long, name = "N", default_value = "5")]
+    retries: u32,
+
+    /// Milliseconds to wait before retrying a locked file.
+    /// Doubles after each attempt.
+    #[structopt(long, name = "MS", default_value = "100")]
+    retry_delay: u64,
+
+    /// Move files that remove, repair, and update get rid of
+    /// (mod files and backups of the game's) to the system's trash
+    /// instead of deleting them, as a last resort if something goes wrong.
+    #[structopt(long)]
+    trash: bool,
+
+    /// Don't write anything at all: turns every command into a dry run
+    /// (and refuses to run ones that can't be), so it's safe to run
+    /// whatever someone helping you asks for.
+    #[structopt(long)]
+    no_write: bool,
+
+    /// How many files to work on at once. Defaults to the number of CPUs.
+    /// Pass 1 to do everything sequentially, which can be much faster
+    /// on spinning hard drives.
+    #[structopt(short, long, name = "JOBS", env = "MODMAN_JOBS")]
+    jobs: Option<usize>,
+
+    /// How to read files to hash them: a megabyte at a time (buffered),
+    /// by memory-mapping big ones (mmap, which can be faster still on SSDs),
+    /// or in small reads, like older versions (stream).
+    #[structopt(
+        long,
+        name = "HASHING",
+        env = "MODMAN_HASHING",
+        default_value = "buffered",
+        possible_values = &["buffered", "mmap", "stream"]
+    )]
+    hashing: file_utils::HashIo,
+
+    /// What kind of drive the game (and its backups) are on.
+    /// With hdd, files are copied one at a time (but still hashed in parallel),
+    /// since spinning drives slow to a crawl seeking between several at once.
+    #[structopt(
+        long,
+        name = "IO_PROFILE",
+        env = "MODMAN_IO_PROFILE",
+        default_value = "ssd",
+        possible_values = &["hdd", "ssd"]
+    )]
+    io_profile: file_utils::IoProfile,
+
+    /// Copy files no faster than <MBPS> megabytes per second (all together),
+    /// to leave the drive usable for other programs.
+    #[structopt(long, name = "MBPS", env = "MODMAN_IO_LIMIT")]
+    io_limit: Option<u64>,
+
+    /// When installing from a zip, decompress up to <PREFETCH> files ahead
+    /// on a background thread while others are written out. 0 turns this off.
+    #[structopt(long, name = "PREFETCH", env = "MODMAN_PREFETCH", default_value = "8")]
+    prefetch: usize,
+
+    /// Also write progress events (files backed up, installed, etc.)
+    /// as JSON lines, for GUIs and other programs running modman.
+    #[structopt(long, name = "FORMAT", possible_values = &["json"])]
+    progress: Option<String>,
+
+    /// Write progress events to <PROGRESS_FILE> (e.g., /dev/fd/3)
+    /// instead of stderr.
+    #[structopt(long, name = "PROGRESS_FILE", requires = "FORMAT")]
+    progress_to: Option<PathBuf>,
+
+    /// Also write a detailed log (debug messages, or trace with -vvv)
+    /// to modman.log in the backup directory (modman-backup/, unless it's moved),
+    /// or <LOG_FILE> with --log-file=<LOG_FILE>.
+    /// It's rotated once it gets big, keeping the last few.
+    #[structopt(long, name = "LOG_FILE", require_equals = true)]
+    log_file: Option<Option<PathBuf>>,
+
+    /// Fail (with exit code 10) if the command gives certain warnings,
+    /// like a restored backup not matching its hash.
+    /// See `modman strict --help` for the list, and to turn this on for the profile.
+    #[structopt(long)]
+    strict: bool,
+
+    /// If the game's directories can't be changed without administrator rights
+    /// (e.g., it's under Program Files), ask for them (through UAC on Windows)
+    /// instead of failing. See `modman elevate --help` to turn this on for the profile.
+    #[structopt(long)]
+    elevate: bool,
+
+    /// Set when we run ourselves with administrator rights for --elevate,
+    /// so that we don't try again.
+    #[structopt(long, hidden = true)]
+    elevated: bool,
+
     #[structopt(subcommand)]
     subcommand: Subcommand,
 }
@@ -42,37 +151,381 @@ enum Subcommand {
     Add(add::Args),
     Remove(remove::Args),
     List(list::Args),
-    /// Check for possible problems with installed mods and backed up files.
-    Check,
+    Show(show::Args),
+    Rename(rename::Args),
+    Tag(tag::Args),
+    Check(check::Args),
+    QuickCheck(check::QuickArgs),
+    /// Show how much disk space installed mods and their backups take up.
+    Stats,
     Update(update::Args),
     Repair(repair::Args),
+    RebuildBackups(rebuild::Args),
+    Clean(clean::Args),
+    Outdated(outdated::Args),
+    MigrateProfile(migrate::Args),
+    Profile(profile_command::Args),
+    RelocateRoot(relocate::Args),
+    MoveStorage(move_storage::Args),
+    ExportProfile(export::Args),
+    ImportProfile(import::Args),
+    Backup(backup::Args),
+    History(history::Args),
+    Apply(plan::Args),
+    Hooks(hooks::Args),
+    KeepOriginals(older_originals::KeepArgs),
+    RestoreOriginal(older_originals::RestoreArgs),
+    AutoCheck(auto_check::Args),
+    Strict(strict::Args),
+    Elevate(elevate::Args),
+    WinePrefix(wine::Args),
+    Roots(roots::Args),
+    SteamApp(steam_app::Args),
+    MakePatch(delta::Args),
+    MakeChecksums(checksums::Args),
+    PackInstall(pack::InstallArgs),
+    PackVerify(pack::VerifyArgs),
+    PackSync(pack::SyncArgs),
+    PackExport(pack::ExportArgs),
+    Set(sets::Args),
+    DiffProfile(diff_profile::Args),
+    Diff(diff::Args),
+    Extract(extract::Args),
+    Watch(watch::Args),
+    Completions(completions::Args),
+    Serve(serve::Args),
 }
 
-fn main() -> Result<()> {
-    let args = Options::from_args();
+impl Subcommand {
+    /// Returns true for commands that change the game directory,
+    /// which we record in the history log.
+    fn is_logged(&self) -> bool {
+        matches!(
+            self,
+            Subcommand::Add(_)
+                | Subcommand::Remove(_)
+                | Subcommand::Update(_)
+                | Subcommand::Repair(_)
+                | Subcommand::RebuildBackups(_)
+                | Subcommand::Apply(_)
+                | Subcommand::PackInstall(_)
+                | Subcommand::PackSync(_)
+        ) || matches!(self, Subcommand::Check(c) if c.is_fixing())
+            || matches!(self, Subcommand::Outdated(o) if o.is_upgrading())
+            || matches!(self, Subcommand::Set(s) if s.is_applying())
+    }
+
+    /// For --no-write: makes the subcommand a dry run if it has one.
+    /// Returns false if it would write something regardless.
+    fn make_dry_run(&mut self) -> bool {
+        match self {
+            Subcommand::Add(a) => return a.make_dry_run(),
+            Subcommand::Remove(r) => return r.make_dry_run(),
+            Subcommand::Update(u) => return u.make_dry_run(),
+            Subcommand::Repair(r) => r.make_dry_run(),
+            Subcommand::RebuildBackups(r) => r.make_dry_run(),
+            Subcommand::Clean(c) => c.make_dry_run(),
+            Subcommand::RelocateRoot(r) => r.make_dry_run(),
+            Subcommand::MoveStorage(m) => m.make_dry_run(),
+            Subcommand::Outdated(o) => o.make_dry_run(),
+            Subcommand::PackInstall(i) => i.make_dry_run(),
+            Subcommand::PackSync(s) => s.make_dry_run(),
+            Subcommand::Check(c) => return !c.is_fixing(),
+            Subcommand::Hooks(h) => return !h.is_changing(),
+            Subcommand::KeepOriginals(k) => return !k.is_changing(),
+            Subcommand::RestoreOriginal(r) => return !r.is_changing(),
+            Subcommand::AutoCheck(a) => return !a.is_changing(),
+            Subcommand::QuickCheck(q) => return !q.is_changing(),
+            Subcommand::Strict(s) => return !s.is_changing(),
+            Subcommand::Elevate(e) => return !e.is_changing(),
+            Subcommand::WinePrefix(w) => return !w.is_changing(),
+            Subcommand::Roots(r) => return !r.is_changing(),
+            Subcommand::Profile(p) => return p.make_dry_run(),
+            Subcommand::Tag(t) => return !t.is_changing(),
+            Subcommand::Set(s) => return s.make_dry_run(),
+            Subcommand::SteamApp(s) => return !s.is_changing(),
+            Subcommand::List(_)
+            | Subcommand::Show(_)
+            | Subcommand::Stats
+            | Subcommand::History(_)
+            | Subcommand::PackVerify(_)
+            | Subcommand::DiffProfile(_)
+            | Subcommand::Diff(_)
+            | Subcommand::Watch(_)
+            | Subcommand::Completions(_) => (),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Makes any relative paths the subcommand was given relative to base.
+    fn resolve_paths(&mut self, base: &Path) {
+        match self {
+            Subcommand::Init(i) => i.resolve_paths(base),
+            Subcommand::Add(a) => a.resolve_paths(base),
+            Subcommand::Remove(r) => r.resolve_paths(base),
+            Subcommand::Update(u) => u.resolve_paths(base),
+            Subcommand::Show(s) => s.resolve_paths(base),
+            Subcommand::Rename(r) => r.resolve_paths(base),
+            Subcommand::Tag(t) => t.resolve_paths(base),
+            Subcommand::Set(s) => s.resolve_paths(base),
+            Subcommand::RestoreOriginal(r) => r.resolve_paths(base),
+            Subcommand::Apply(a) => a.resolve_paths(base),
+            Subcommand::RelocateRoot(r) => r.resolve_paths(base),
+            Subcommand::Profile(p) => p.resolve_paths(base),
+            Subcommand::MoveStorage(m) => m.resolve_paths(base),
+            Subcommand::Roots(r) => r.resolve_paths(base),
+            Subcommand::WinePrefix(w) => w.resolve_paths(base),
+            Subcommand::Serve(s) => s.resolve_paths(base),
+            Subcommand::ExportProfile(e) => e.resolve_paths(base),
+            Subcommand::ImportProfile(i) => i.resolve_paths(base),
+            Subcommand::Backup(b) => b.resolve_paths(base),
+            Subcommand::MakePatch(m) => m.resolve_paths(base),
+            Subcommand::MakeChecksums(m) => m.resolve_paths(base),
+            Subcommand::PackInstall(i) => i.resolve_paths(base),
+            Subcommand::PackVerify(v) => v.resolve_paths(base),
+            Subcommand::PackSync(s) => s.resolve_paths(base),
+            Subcommand::PackExport(e) => e.resolve_paths(base),
+            Subcommand::DiffProfile(d) => d.resolve_paths(base),
+            Subcommand::Diff(d) => d.resolve_paths(base),
+            Subcommand::Extract(e) => e.resolve_paths(base),
+            _ => (),
+        }
+    }
+}
+
+fn main() {
+    let args = match Options::clap().get_matches_safe() {
+        Ok(matches) => Options::from_clap(&matches),
+        // Errors go to stderr; --help and --version go to stdout.
+        Err(e) if e.use_stderr() => {
+            eprintln!("{}", e.message);
+            std::process::exit(Failure::Usage as i32);
+        }
+        Err(e) => e.exit(),
+    };
+
+    let result = run(args);
+    let code = match &result {
+        Ok(()) => 0,
+        Err(e) => failure::exit_code(e),
+    };
+    progress::finish(&result, code);
+    log_file::finish(&result, code);
+    if let Err(e) = result {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(code);
+    }
+}
 
+fn run(args: Options) -> Result<()> {
     let mut errlog = stderrlog::new();
     // The +1 is because we want -v to give info, not warn.
-    errlog.verbosity(args.verbosity + 1);
-    if atty::is(Stream::Stdout) {
-        errlog.color(stderrlog::ColorChoice::Auto);
+    errlog.verbosity(if args.quiet { 0 } else { args.verbosity + 1 });
+    // (We can't use stderrlog's init(), so do what it would with colors.)
+    let color_for = |stream| match args.color.as_str() {
+        "always" => stderrlog::ColorChoice::Always,
+        "never" => stderrlog::ColorChoice::Never,
+        _ if atty::is(stream) => stderrlog::ColorChoice::Auto,
+        _ => stderrlog::ColorChoice::Never,
+    };
+    errlog.color(color_for(Stream::Stderr));
+    output::set_color(color_for(Stream::Stdout));
+    let level = match args.verbosity {
+        _ if args.quiet => log::LevelFilter::Error,
+        0 => log::LevelFilter::Warn,
+        1 => log::LevelFilter::Info,
+        2 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    };
+    // The log file gets at least debug messages.
+    let file_level = level.max(log::LevelFilter::Debug);
+    if args.log_file.is_some() {
+        log::set_max_level(file_level);
+    } else if args.progress.is_some() {
+        // Warnings are progress events even if we're --quiet.
+        log::set_max_level(level.max(log::LevelFilter::Warn));
     } else {
-        errlog.color(stderrlog::ColorChoice::Never);
+        log::set_max_level(level);
+    }
+    // Pass warnings along as progress events too (if anyone's listening).
+    log::set_boxed_logger(Box::new(log_file::Logger {
+        inner: progress::Logger { inner: errlog },
+    }))?;
+
+    if args.progress.is_some() {
+        progress::begin(args.progress_to.as_deref())?;
     }
-    errlog.init()?;
+
+    file_utils::set_retry_policy(args.retries, Duration::from_millis(args.retry_delay));
+    file_utils::set_use_trash(args.trash);
+    file_utils::set_no_write(args.no_write);
+    file_utils::set_hash_io(args.hashing);
+    if args.io_limit == Some(0) {
+        fail!(Failure::Usage, "--io-limit must be at least 1");
+    }
+    file_utils::set_io_profile(args.io_profile, args.io_limit.map(|mb| mb * 1_000_000));
+    zip_mod::set_prefetch_window(args.prefetch);
+
+    // (In case we need to run ourselves again; see --elevate)
+    let launch_dir = std::env::current_dir().context("Couldn't get working directory")?;
 
     if let Some(chto) = args.directory {
         std::env::set_current_dir(&chto)
             .with_context(|| format!("Couldn't set working directory to {}", chto.display()))?;
     }
 
-    match args.subcommand {
+    // Paths we're given are relative to the working directory,
+    // which we might change to the profile's below.
+    let log_path = match &args.log_file {
+        Some(Some(path)) => Some(
+            std::env::current_dir()
+                .context("Couldn't get working directory")?
+                .join(path),
+        ),
+        _ => None,
+    };
+
+    let mut subcommand = args.subcommand;
+    if args.no_write && !subcommand.make_dry_run() {
+        fail!(
+            Failure::Usage,
+            "That command can't be run without writing anything, so it can't be used \
+             with --no-write. (Did you give it an option like --plan or --fix?)"
+        );
+    }
+
+    if let Some(name) = args.profile {
+        let profile_dir = profile::user_profile_dir(&name)?;
+        let creates_profile = match &subcommand {
+            Subcommand::Init(_) | Subcommand::ImportProfile(_) => true,
+            Subcommand::Backup(b) => b.is_import(),
+            _ => false,
+        };
+        if creates_profile {
+            std::fs::create_dir_all(&profile_dir).with_context(|| {
+                format!(
+                    "Couldn't create profile directory {}",
+                    profile_dir.display()
+                )
+            })?;
+        } else if !profile_dir.is_dir() {
+            fail!(
+                Failure::NoProfile,
+                "There's no profile named {} (run `modman --profile {} init`)",
+                name,
+                name
+            );
+        }
+        log::debug!("Using profile {}", profile_dir.display());
+
+        // We're about to change directories,
+        // so paths the user gave us need to be relative to where they were.
+        let cwd = std::env::current_dir().context("Couldn't get working directory")?;
+        subcommand.resolve_paths(&cwd);
+        std::env::set_current_dir(&profile_dir).with_context(|| {
+            format!(
+                "Couldn't set working directory to {}",
+                profile_dir.display()
+            )
+        })?;
+    }
+
+    let mut pool = rayon::ThreadPoolBuilder::new();
+    if let Some(jobs) = args.jobs {
+        if jobs == 0 {
+            fail!(Failure::Usage, "--jobs must be at least 1");
+        }
+        pool = pool.num_threads(jobs);
+    }
+    let pool = pool.build().context("Couldn't start worker threads")?;
+
+    // The log file and history log go in the profile's storage directory.
+    profile::locate_storage();
+    strict::set_strict(args.strict || strict::profile_is_strict());
+
+    // Make sure we can change the game's files before starting to,
+    // and if we can't, run ourselves again with the rights to.
+    if subcommand.is_logged()
+        && !args.no_write
+        && !args.elevated
+        && (args.elevate || elevate::profile_elevates())
+    {
+        if let Some(dir) = elevate::unwritable_dir()? {
+            // That run of modman did (and reported) everything.
+            std::process::exit(elevate::relaunch_elevated(&dir, &launch_dir)?);
+        }
+    }
+
+    if args.log_file.is_some() && !args.no_write {
+        let command: Vec<String> = std::env::args().skip(1).collect();
+        log_file::begin(log_path.as_deref(), file_level, &command)?;
+    }
+
+    let logged = subcommand.is_logged() && !args.no_write;
+    if logged {
+        history::begin(std::env::args().skip(1).collect());
+    }
+
+    let result = pool
+        .install(|| run_subcommand(subcommand))
+        .and_then(|()| strict::finish());
+
+    if logged {
+        if let Err(e) = history::finish(&result) {
+            log::warn!("Couldn't update the history log: {:#}", e);
+        }
+    }
+    result
+}
+
+fn run_subcommand(subcommand: Subcommand) -> Result<()> {
+    match subcommand {
         Subcommand::Init(i) => init::run(i),
         Subcommand::Add(a) => add::run(a),
         Subcommand::Remove(r) => remove::run(r),
         Subcommand::List(l) => list::run(l),
-        Subcommand::Check => check::run(),
+        Subcommand::Show(s) => show::run(s),
+        Subcommand::Rename(r) => rename::run(r),
+        Subcommand::Tag(t) => tag::run(t),
+        Subcommand::Check(c) => check::run(c),
+        Subcommand::QuickCheck(q) => check::quick(q),
+        Subcommand::Stats => stats::run(),
         Subcommand::Update(u) => update::run(u),
         Subcommand::Repair(r) => repair::run(r),
+        Subcommand::RebuildBackups(r) => rebuild::run(r),
+        Subcommand::Clean(c) => clean::run(c),
+        Subcommand::Outdated(o) => outdated::run(o),
+        Subcommand::MigrateProfile(m) => migrate::run(m),
+        Subcommand::Profile(p) => profile_command::run(p),
+        Subcommand::RelocateRoot(r) => relocate::run(r),
+        Subcommand::MoveStorage(m) => move_storage::run(m),
+        Subcommand::ExportProfile(e) => export::run(e),
+        Subcommand::ImportProfile(i) => import::run(i),
+        Subcommand::Backup(b) => backup::run(b),
+        Subcommand::History(h) => history::run(h),
+        Subcommand::Apply(a) => plan::run(a),
+        Subcommand::Hooks(h) => hooks::run(h),
+        Subcommand::KeepOriginals(k) => older_originals::keep(k),
+        Subcommand::RestoreOriginal(r) => older_originals::restore(r),
+        Subcommand::AutoCheck(a) => auto_check::run(a),
+        Subcommand::Strict(s) => strict::run(s),
+        Subcommand::Elevate(e) => elevate::run(e),
+        Subcommand::WinePrefix(w) => wine::run(w),
+        Subcommand::Roots(r) => roots::run(r),
+        Subcommand::SteamApp(s) => steam_app::run(s),
+        Subcommand::MakePatch(m) => delta::run(m),
+        Subcommand::MakeChecksums(m) => checksums::run(m),
+        Subcommand::PackInstall(i) => pack::install(i),
+        Subcommand::PackVerify(v) => pack::verify(v),
+        Subcommand::PackSync(s) => pack::sync(s),
+        Subcommand::PackExport(e) => pack::export(e),
+        Subcommand::Set(s) => sets::run(s),
+        Subcommand::DiffProfile(d) => diff_profile::run(d),
+        Subcommand::Diff(d) => diff::run(d),
+        Subcommand::Extract(e) => extract::run(e),
+        Subcommand::Watch(w) => watch::run(w),
+        Subcommand::Completions(c) => completions::run(c, Options::clap()),
+        Subcommand::Serve(s) => serve::run(s),
     }
 }
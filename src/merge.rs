@@ -0,0 +1,280 @@
+//! Merging mod files into game files instead of replacing them.
+//!
+//! Lots of mods just need to tweak a few lines of a game's config files.
+//! Rather than shipping (and overwriting) the whole file,
+//! they can declare a merge mode for it in their mod.toml:
+//!
+//! ```toml
+//! [merge]
+//! "Config/options.ini" = "ini"
+//! "Scripts/init.lua" = "append"
+//! ```
+//!
+//! The mod's file is then applied to the game's file at install time.
+//! The game's file is backed up like any other, and `modman update`
+//! merges the mod's changes again if a game update replaces it.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::prelude::*;
+use std::path::Path;
+
+use anyhow::*;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeMode {
+    /// Add the mod file to the end of the game file.
+    /// Good for scripts (Lua, etc.) and other line-based files.
+    Append,
+    /// Set each `key = value` from the mod file in the matching `[section]`
+    /// of the game file, adding any keys and sections it doesn't have.
+    Ini,
+    /// Apply the mod file to the game file as a JSON merge patch (RFC 7386):
+    /// objects are merged recursively, `null` removes a key,
+    /// and anything else replaces what was there.
+    Json,
+}
+
+/// Reads the game file at game_path (if there is one),
+/// and merges the mod file into it.
+pub fn merge_into_game_file(
+    mode: MergeMode,
+    game_path: &Path,
+    mod_file: &mut dyn Read,
+) -> Result<Vec<u8>> {
+    let original = match fs::read(game_path) {
+        Ok(o) => o,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => {
+            return Err(Error::from(e).context(format!("Couldn't read {}", game_path.display())))
+        }
+    };
+    merge_into(mode, &original, mod_file)
+        .with_context(|| format!("Couldn't merge into {}", game_path.display()))
+}
+
+/// Like merge_into_game_file(), given the game file's contents.
+pub fn merge_into(mode: MergeMode, original: &[u8], mod_file: &mut dyn Read) -> Result<Vec<u8>> {
+    let mut patch = Vec::new();
+    mod_file.read_to_end(&mut patch)?;
+    merge(mode, original, &patch)
+}
+
+pub fn merge(mode: MergeMode, original: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    match mode {
+        MergeMode::Append => Ok(append(original, patch)),
+        MergeMode::Ini => merge_ini(as_text(original)?, as_text(patch)?).map(String::into_bytes),
+        MergeMode::Json => merge_json(original, patch),
+    }
+}
+
+fn as_text(b: &[u8]) -> Result<&str> {
+    std::str::from_utf8(b).context("File isn't UTF-8 text")
+}
+
+fn append(original: &[u8], patch: &[u8]) -> Vec<u8> {
+    let mut merged = original.to_vec();
+    if !merged.is_empty() && !merged.ends_with(b"\n") {
+        merged.extend_from_slice(line_ending(original).as_bytes());
+    }
+    merged.extend_from_slice(patch);
+    merged
+}
+
+/// Matches whatever line endings the game file already uses.
+fn line_ending(original: &[u8]) -> &'static str {
+    if original.windows(2).any(|w| w == b"\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    }
+}
+
+/// Returns the section name if the line is an INI `[section]` header.
+fn ini_section(line: &str) -> Option<&str> {
+    let line = line.trim();
+    if line.starts_with('[') && line.ends_with(']') {
+        Some(line[1..line.len() - 1].trim())
+    } else {
+        None
+    }
+}
+
+/// Returns the key if the line is an INI `key = value` line.
+fn ini_key(line: &str) -> Option<&str> {
+    line.find('=').map(|i| line[..i].trim())
+}
+
+fn is_blank_or_comment(line: &str) -> bool {
+    let line = line.trim_start();
+    line.is_empty() || line.starts_with(';') || line.starts_with('#')
+}
+
+// INI keys and section names are usually case-insensitive (thanks, Windows).
+fn ini_eq(a: &str, b: &str) -> bool {
+    a.eq_ignore_ascii_case(b)
+}
+
+/// A section's worth of settings from the mod file.
+struct IniSection<'a> {
+    name: &'a str,
+    /// Each key and the line that sets it, in the order given
+    settings: Vec<(&'a str, &'a str)>,
+}
+
+fn parse_ini_patch(patch: &str) -> Result<Vec<IniSection<'_>>> {
+    // Settings before any [section] go at the top of the file.
+    let mut sections = vec![IniSection {
+        name: "",
+        settings: Vec::new(),
+    }];
+    let mut current = 0;
+    for line in patch.lines() {
+        if is_blank_or_comment(line) {
+            continue;
+        }
+        if let Some(name) = ini_section(line) {
+            current = match sections.iter().position(|s| ini_eq(s.name, name)) {
+                Some(i) => i,
+                None => {
+                    sections.push(IniSection {
+                        name,
+                        settings: Vec::new(),
+                    });
+                    sections.len() - 1
+                }
+            };
+        } else if let Some(key) = ini_key(line) {
+            let settings = &mut sections[current].settings;
+            match settings.iter_mut().find(|(k, _)| ini_eq(k, key)) {
+                Some(setting) => setting.1 = line,
+                None => settings.push((key, line)),
+            }
+        } else {
+            bail!("Expected a [section] or key = value, found {}", line);
+        }
+    }
+    Ok(sections)
+}
+
+fn merge_ini(original: &str, patch: &str) -> Result<String> {
+    let patch = parse_ini_patch(patch)?;
+    let eol = line_ending(original.as_bytes());
+
+    let find_section = |name: &str| patch.iter().position(|s| ini_eq(s.name, name));
+
+    // (section, setting) indexes from the patch that we've written
+    let mut applied = BTreeSet::new();
+
+    // Adds any settings for the given section that the game file didn't have
+    // to the end of it (but before any blank lines separating it from the next).
+    let finish_section =
+        |section: &str, merged: &mut Vec<String>, applied: &mut BTreeSet<(usize, usize)>| {
+            let si = match find_section(section) {
+                Some(i) => i,
+                None => return,
+            };
+            let mut blanks = 0;
+            while merged.last().map(|l| l.trim().is_empty()) == Some(true) {
+                merged.pop();
+                blanks += 1;
+            }
+            for (ki, (_, line)) in patch[si].settings.iter().enumerate() {
+                if applied.insert((si, ki)) {
+                    merged.push(line.to_string());
+                }
+            }
+            merged.extend(std::iter::repeat_n(String::new(), blanks));
+        };
+
+    let mut merged: Vec<String> = Vec::new();
+    let mut sections_seen = vec![""];
+    let mut current = "";
+    for line in original.lines() {
+        if let Some(name) = ini_section(line) {
+            finish_section(current, &mut merged, &mut applied);
+            current = name;
+            sections_seen.push(name);
+            merged.push(line.to_string());
+            continue;
+        }
+
+        let replacement = if is_blank_or_comment(line) {
+            None
+        } else {
+            ini_key(line).and_then(|key| {
+                let si = find_section(current)?;
+                let ki = patch[si]
+                    .settings
+                    .iter()
+                    .position(|(k, _)| ini_eq(k, key))?;
+                Some((si, ki))
+            })
+        };
+        match replacement {
+            Some((si, ki)) => {
+                applied.insert((si, ki));
+                merged.push(patch[si].settings[ki].1.to_string());
+            }
+            None => merged.push(line.to_string()),
+        }
+    }
+    finish_section(current, &mut merged, &mut applied);
+
+    // Finally, add any sections the game file didn't have.
+    for s in &patch {
+        if s.settings.is_empty() || sections_seen.iter().any(|n| ini_eq(n, s.name)) {
+            continue;
+        }
+        if !merged.is_empty() {
+            merged.push(String::new());
+        }
+        merged.push(format!("[{}]", s.name));
+        merged.extend(s.settings.iter().map(|(_, line)| line.to_string()));
+    }
+
+    let mut merged = merged.join(eol);
+    if !merged.is_empty() {
+        merged.push_str(eol);
+    }
+    Ok(merged)
+}
+
+fn merge_json(original: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    let mut target: Value = if original.iter().all(u8::is_ascii_whitespace) {
+        Value::Null
+    } else {
+        serde_json::from_slice(original).context("Couldn't parse the game's JSON")?
+    };
+    let patch: Value = serde_json::from_slice(patch).context("Couldn't parse the mod's JSON")?;
+    merge_patch(&mut target, patch);
+
+    let mut merged = serde_json::to_vec_pretty(&target)?;
+    merged.push(b'\n');
+    Ok(merged)
+}
+
+/// RFC 7386, more or less verbatim
+fn merge_patch(target: &mut Value, patch: Value) {
+    let patch = match patch {
+        Value::Object(p) => p,
+        other => {
+            *target = other;
+            return;
+        }
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    let target = target.as_object_mut().unwrap();
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(&key);
+        } else {
+            merge_patch(target.entry(key).or_insert(Value::Null), value);
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::*;
+use log::*;
+use structopt::*;
+
+use crate::profile::*;
+use crate::profile_store::*;
+
+/// Converts the profile to another storage format
+///
+/// SQLite (modman.profile.db) is much faster than JSON (modman.profile)
+/// when thousands of mod files are installed, since changes don't require
+/// rewriting the whole profile.
+/// The old profile is kept with a .bak suffix.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    /// The format to convert to (json or sqlite)
+    #[structopt(long, name = "FORMAT", default_value = "sqlite")]
+    to: Backend,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let from = existing_backend().ok_or_else(|| format_err!("No profile found to migrate."))?;
+    if from == args.to {
+        info!("The profile is already stored as {:?}.", from);
+        return Ok(());
+    }
+
+    let p = load_and_check_profile()?;
+
+    // Move the old profile out of the way first,
+    // so that we can't end up with two profiles that disagree.
+    let old_path = Path::new(backend_path(from));
+    let mut backup_path = old_path.as_os_str().to_owned();
+    backup_path.push(".bak");
+    let backup_path = Path::new(&backup_path);
+    fs::rename(old_path, backup_path).with_context(|| {
+        format!(
+            "Couldn't rename {} to {}",
+            old_path.display(),
+            backup_path.display()
+        )
+    })?;
+
+    if let Err(e) = create_profile_store(&p, args.to) {
+        // Put things back how they were.
+        let _ = fs::remove_file(backend_path(args.to));
+        fs::rename(backup_path, old_path).with_context(|| {
+            format!(
+                "Couldn't restore {} to {}",
+                backup_path.display(),
+                old_path.display()
+            )
+        })?;
+        return Err(e);
+    }
+
+    info!(
+        "Profile migrated to {} (the old one is at {})",
+        backend_path(args.to),
+        backup_path.display()
+    );
+    Ok(())
+}
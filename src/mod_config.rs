@@ -0,0 +1,120 @@
+//! Optional per-mod settings from a `mod.toml` next to VERSION.txt and README.txt
+
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::*;
+use serde_derive::Deserialize;
+
+use crate::delta::PatchConfig;
+use crate::deps::ModSpec;
+use crate::hooks::Hooks;
+use crate::merge::MergeMode;
+use crate::profile::DEFAULT_ROOT_NAME;
+use crate::tag::check_tag;
+
+pub static MOD_CONFIG_NAME: &str = "mod.toml";
+
+/// Everything a mod can tell us about itself in its `mod.toml`.
+/// Mods without one get the defaults.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ModConfig {
+    /// Mods that must be installed before this one
+    #[serde(default)]
+    pub requires: Vec<ModSpec>,
+    /// Mods that can't be installed alongside this one
+    #[serde(default)]
+    pub conflicts: Vec<ModSpec>,
+    #[serde(default)]
+    pub hooks: Hooks,
+    /// Mod files to merge into the game's files instead of replacing them
+    /// (relative to the mod's base directory)
+    #[serde(default)]
+    pub merge: BTreeMap<PathBuf, MergeMode>,
+    /// Game files to patch, and the binary patches to apply to them
+    #[serde(default)]
+    pub patch: BTreeMap<PathBuf, PatchConfig>,
+    /// Mod files (or directories) to install somewhere other than where
+    /// they are in the mod, relative to the mod's base directory
+    /// and the game's root directory, respectively
+    #[serde(default)]
+    pub remap: BTreeMap<PathBuf, PathBuf>,
+    /// The profile root to install the mod to, if not the game directory
+    #[serde(default)]
+    pub root: Option<String>,
+    /// Mod files (or directories) to install to other profile roots,
+    /// relative to the mod's base directory
+    #[serde(default)]
+    pub roots: BTreeMap<PathBuf, String>,
+    /// What to do with symbolic links in the mod (if it's a directory)
+    #[serde(default)]
+    pub symlinks: SymlinkMode,
+    /// Tags to group the mod with others (see `modman tag`)
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkMode {
+    /// Install whatever the link points to, as if it were in the mod.
+    #[default]
+    Follow,
+    /// Install the link itself, pointing wherever it points in the mod.
+    Preserve,
+    /// Leave it out (with a warning).
+    Skip,
+}
+
+impl ModConfig {
+    pub fn parse(s: &str) -> Result<Self> {
+        let config: Self =
+            toml::from_str(s).with_context(|| format!("Couldn't parse {}", MOD_CONFIG_NAME))?;
+        for to in config.remap.values() {
+            if !to.components().all(|c| matches!(c, Component::Normal(_))) {
+                bail!(
+                    "{} remaps a file to {}, which isn't a plain relative path",
+                    MOD_CONFIG_NAME,
+                    to.display()
+                );
+            }
+        }
+        for tag in &config.tags {
+            check_tag(tag).with_context(|| format!("Bad tag in {}", MOD_CONFIG_NAME))?;
+        }
+        Ok(config)
+    }
+
+    /// Returns where the given mod file is installed, per `remap`.
+    /// (The longest matching file or directory wins.)
+    pub fn remapped(&self, p: &Path) -> PathBuf {
+        let longest_match = self
+            .remap
+            .iter()
+            .filter(|(from, _)| p.starts_with(from))
+            .max_by_key(|(from, _)| from.components().count());
+        match longest_match {
+            Some((from, to)) if p == from => to.clone(),
+            Some((from, to)) => to.join(p.strip_prefix(from).unwrap()),
+            None => p.to_owned(),
+        }
+    }
+
+    /// Returns the name of the profile root the given mod file is installed to,
+    /// per `roots` (where the longest matching file or directory wins) and `root`.
+    pub fn root_of(&self, p: &Path) -> &str {
+        self.roots
+            .iter()
+            .filter(|(from, _)| p.starts_with(from))
+            .max_by_key(|(from, _)| from.components().count())
+            .map(|(_, root)| root.as_str())
+            .or(self.root.as_deref())
+            .unwrap_or(DEFAULT_ROOT_NAME)
+    }
+
+    /// True if the mod installs anything somewhere other than where it is in the mod.
+    pub fn moves_files(&self) -> bool {
+        !self.remap.is_empty() || self.root.is_some() || !self.roots.is_empty()
+    }
+}
@@ -0,0 +1,27 @@
+//! Unix permissions are much easier to read in octal (`"755"`)
+//! than as the decimal numbers JSON would otherwise give us.
+
+use std::result::Result;
+
+pub fn serialize_mode<S>(mode: &Option<u32>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match mode {
+        Some(m) => serializer.serialize_str(&format!("{:o}", m)),
+        None => serializer.serialize_none(),
+    }
+}
+
+pub fn deserialize_mode<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Deserialize;
+    match Option::<String>::deserialize(deserializer)? {
+        Some(s) => u32::from_str_radix(&s, 8)
+            .map(Some)
+            .map_err(|err| serde::de::Error::custom(format!("Bad file mode {:?}: {}", s, err))),
+        None => Ok(None),
+    }
+}
@@ -1,28 +1,124 @@
+use std::collections::BTreeMap;
 use std::fs;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::SystemTime;
 
 use anyhow::*;
 use semver::Version;
 
 use crate::dir_mod::*;
+use crate::file_utils::{file_mtime, file_size, hash_file};
+use crate::mod_config::*;
+use crate::profile::{in_root, ModManifest, UnicodeForm, DEFAULT_ROOT_NAME};
 use crate::zip_mod::*;
 
-pub trait Mod {
+/// A mod, wherever its files come from.
+///
+/// modman reads mods from zips and directories, but anything that can list
+/// and read files can be one (see `register_opener()`).
+/// Mods are read from many threads at once, hence Send + Sync.
+/// Reads block; there's no async interface, so a mod backed by
+/// an async client has to block on it when asked for a file.
+pub trait Mod: Send + Sync {
     /// Returns a vector of the mod files' paths, with the base directory
     /// stripped away
     fn paths(&self) -> Result<Vec<PathBuf>>;
 
+    /// Reads the given file. Readers can be sent to other threads
+    /// to be read there.
     fn read_file<'a>(&'a self, p: &Path) -> Result<Box<dyn Read + Send + 'a>>;
 
+    /// Whether the mod can only read one file at a time, in order
+    /// (say, from a single network stream), instead of many at once.
+    fn streaming_only(&self) -> bool {
+        false
+    }
+
+    /// Starts reading the given files in the background (in that order),
+    /// since they're about to be read. Mods that are just files on disk
+    /// don't bother.
+    fn prefetch(&self, _paths: &[PathBuf]) {}
+
+    /// Where the file points, if it's a symbolic link to install as a link.
+    fn symlink(&self, p: &Path) -> Result<Option<PathBuf>>;
+
+    /// The file's Unix permissions, if the mod has them and it's executable.
+    fn mode(&self, p: &Path) -> Result<Option<u32>>;
+
+    /// When the file was last modified, if the mod knows.
+    fn mtime(&self, p: &Path) -> Result<Option<SystemTime>>;
+
+    /// The file's size, in bytes
+    fn size(&self, p: &Path) -> Result<u64>;
+
     fn version(&self) -> &Version;
 
+    /// VERSION.txt as the mod wrote it (sans whitespace),
+    /// which might not be exactly how `version()` prints.
+    fn version_string(&self) -> &str;
+
     fn readme(&self) -> &str;
+
+    /// The mod's CHANGELOG.txt, if it has one.
+    fn changelog(&self) -> Option<&str>;
+
+    /// The mod's mod.toml, or the defaults if it doesn't have one.
+    fn config(&self) -> &ModConfig;
+}
+
+/// Opens the mod at the given path,
+/// remapping its file paths (and choosing their roots) as its mod.toml asks
+/// and normalizing them to the given Unicode form.
+///
+/// If a root is given, it overrides the one the mod.toml asks for.
+pub fn open_mod(p: &Path, form: UnicodeForm, root: Option<&str>) -> Result<Box<dyn Mod>> {
+    let m = open_raw_mod(p)?;
+    if form == UnicodeForm::None && root.is_none() && !m.config().moves_files() {
+        Ok(m)
+    } else {
+        let mapped = MappedMod::new(m, form, root)
+            .with_context(|| format!("Trouble reading mod {}", p.display()))?;
+        Ok(Box::new(mapped))
+    }
+}
+
+/// Opens mods modman doesn't know how to read itself,
+/// returning None for paths that aren't its kind of mod.
+/// (They're closures so they can hold onto whatever they read mods with,
+/// like an HTTP client and its credentials.)
+pub type ModOpener = Box<dyn Fn(&Path) -> Option<Result<Box<dyn Mod>>> + Send + Sync>;
+
+static OPENERS: RwLock<Vec<ModOpener>> = RwLock::new(Vec::new());
+
+/// Has modman try the given opener (before zips and directories)
+/// on every mod it opens, so programs embedding it can add their own kinds
+/// (archives over HTTP, content stores, and so on).
+pub fn register_opener(opener: ModOpener) {
+    OPENERS.write().unwrap().push(opener);
 }
 
-pub fn open_mod(p: &Path) -> Result<Box<dyn Mod + Sync>> {
+fn open_raw_mod(p: &Path) -> Result<Box<dyn Mod>> {
+    for opener in OPENERS.read().unwrap().iter() {
+        if let Some(opened) = opener(p) {
+            return opened.with_context(|| format!("Trouble reading mod {}", p.display()));
+        }
+    }
+
     // Alright, let's stat the thing:
-    let stat = fs::metadata(p).with_context(|| format!("Couldn't find {}", p.display()))?;
+    let stat = match fs::metadata(p) {
+        Ok(s) => s,
+        // It might be one of several mods in an archive (see `add --select`).
+        Err(e) => match split_archive_path(p) {
+            Some((archive, inner)) => {
+                let z = ZipMod::within(archive, inner)
+                    .with_context(|| format!("trouble reading mod {}", p.display()))?;
+                return Ok(Box::new(z));
+            }
+            None => return Err(e).with_context(|| format!("Couldn't find {}", p.display())),
+        },
+    };
 
     if stat.is_file() {
         let z =
@@ -39,3 +135,199 @@ pub fn open_mod(p: &Path) -> Result<Box<dyn Mod + Sync>> {
         ))
     }
 }
+
+/// Splits a path to a directory inside an archive (archive.zip/Some Mod)
+/// into the archive and the directory.
+pub fn split_archive_path(p: &Path) -> Option<(&Path, &Path)> {
+    let archive = p.ancestors().skip(1).find(|a| a.is_file())?;
+    Some((archive, p.strip_prefix(archive).unwrap()))
+}
+
+/// The archive the mod at the given path is read from, if it's in one
+/// (it's an archive, or one of several in one).
+pub fn mod_archive(mod_path: &Path) -> Option<&Path> {
+    if mod_path.is_file() {
+        Some(mod_path)
+    } else if mod_path.exists() {
+        None
+    } else {
+        split_archive_path(mod_path).map(|(archive, _)| archive)
+    }
+}
+
+/// Records the hash of the archive the mod at the given path is read from
+/// (if it's in one) in its manifest, along with the archive's size
+/// and modification time.
+pub fn record_mod_archive(mod_path: &Path, manifest: &mut ModManifest) -> Result<()> {
+    let archive = match mod_archive(mod_path) {
+        Some(a) => a,
+        None => return Ok(()),
+    };
+    manifest.archive_size = Some(file_size(archive)?);
+    manifest.archive_mtime = Some(file_mtime(archive)?);
+    manifest.archive_hash =
+        Some(hash_file(archive).with_context(|| format!("Couldn't hash {}", archive.display()))?);
+    Ok(())
+}
+
+/// Returns true if the archive the installed mod is read from isn't the one
+/// it was installed from (say, it was downloaded again).
+/// Mods installed before we recorded archives' hashes, and ones whose archive
+/// is gone, get the benefit of the doubt.
+/// Archives that are the same size and were last modified at the same time
+/// are taken to be the same without hashing them (like `modman quick-check`).
+pub fn archive_changed(mod_path: &Path, manifest: &ModManifest) -> Result<bool> {
+    let recorded = match &manifest.archive_hash {
+        Some(h) => h,
+        None => return Ok(false),
+    };
+    let archive = match mod_archive(mod_path) {
+        Some(a) => a,
+        None => return Ok(false),
+    };
+    if manifest.archive_size == Some(file_size(archive)?)
+        && manifest.archive_mtime == Some(file_mtime(archive)?)
+    {
+        return Ok(false);
+    }
+    let hash =
+        hash_file(archive).with_context(|| format!("Couldn't hash {}", archive.display()))?;
+    Ok(hash != *recorded)
+}
+
+/// Wraps a mod, presenting its paths where they'll be installed
+/// (remapped and put in profile roots per its mod.toml,
+/// and in a given Unicode normalization form)
+/// and mapping them back to what the mod actually contains when reading.
+struct MappedMod {
+    inner: Box<dyn Mod>,
+    /// Mapped paths to the ones in the mod itself.
+    originals: BTreeMap<PathBuf, PathBuf>,
+    /// The mod's config, with its mod file paths mapped too
+    config: ModConfig,
+}
+
+impl MappedMod {
+    fn new(inner: Box<dyn Mod>, form: UnicodeForm, root: Option<&str>) -> Result<Self> {
+        let mut inner_config = inner.config().clone();
+        if let Some(r) = root {
+            inner_config.root = Some(r.to_owned());
+            inner_config.roots.clear();
+        }
+        let map =
+            |p: &Path| form.normalize(&in_root(inner_config.root_of(p), &inner_config.remapped(p)));
+
+        let inner_paths = inner.paths()?;
+        let unused = |from: &&PathBuf| !inner_paths.iter().any(|p| p.starts_with(from));
+        if let Some(from) = inner_config.remap.keys().find(unused) {
+            bail!(
+                "{} says to remap {}, but the mod doesn't have that file or directory",
+                MOD_CONFIG_NAME,
+                from.display()
+            );
+        }
+        if let Some(from) = inner_config.roots.keys().find(unused) {
+            bail!(
+                "{} gives a root for {}, but the mod doesn't have that file or directory",
+                MOD_CONFIG_NAME,
+                from.display()
+            );
+        }
+
+        let mut originals = BTreeMap::new();
+        for original in inner_paths {
+            let mapped = map(&original);
+            if let Some(other) = originals.insert(mapped.clone(), original.clone()) {
+                bail!(
+                    "{} and {} would both be installed to {}",
+                    other.display(),
+                    original.display(),
+                    mapped.display()
+                );
+            }
+        }
+
+        // Merges and patches name mod files, so they move too.
+        let mut config = inner_config.clone();
+        config.merge = inner_config
+            .merge
+            .iter()
+            .map(|(f, mode)| (map(f), *mode))
+            .collect();
+        for patch in config.patch.values_mut() {
+            patch.file = map(&patch.file);
+        }
+        // Patched game files are in the mod's root.
+        let mod_root = inner_config.root.as_deref().unwrap_or(DEFAULT_ROOT_NAME);
+        config.patch = config
+            .patch
+            .into_iter()
+            .map(|(target, patch)| (in_root(mod_root, &target), patch))
+            .collect();
+
+        Ok(Self {
+            inner,
+            originals,
+            config,
+        })
+    }
+
+    fn original<'a>(&'a self, p: &'a Path) -> &'a Path {
+        self.originals.get(p).map(PathBuf::as_path).unwrap_or(p)
+    }
+}
+
+impl Mod for MappedMod {
+    fn paths(&self) -> Result<Vec<PathBuf>> {
+        Ok(self.originals.keys().cloned().collect())
+    }
+
+    fn read_file<'a>(&'a self, p: &Path) -> Result<Box<dyn Read + Send + 'a>> {
+        self.inner.read_file(self.original(p))
+    }
+
+    fn streaming_only(&self) -> bool {
+        self.inner.streaming_only()
+    }
+
+    fn prefetch(&self, paths: &[PathBuf]) {
+        let originals: Vec<PathBuf> = paths.iter().map(|p| self.original(p).to_owned()).collect();
+        self.inner.prefetch(&originals)
+    }
+
+    fn symlink(&self, p: &Path) -> Result<Option<PathBuf>> {
+        self.inner.symlink(self.original(p))
+    }
+
+    fn mode(&self, p: &Path) -> Result<Option<u32>> {
+        self.inner.mode(self.original(p))
+    }
+
+    fn mtime(&self, p: &Path) -> Result<Option<SystemTime>> {
+        self.inner.mtime(self.original(p))
+    }
+
+    fn size(&self, p: &Path) -> Result<u64> {
+        self.inner.size(self.original(p))
+    }
+
+    fn version(&self) -> &Version {
+        self.inner.version()
+    }
+
+    fn version_string(&self) -> &str {
+        self.inner.version_string()
+    }
+
+    fn readme(&self) -> &str {
+        self.inner.readme()
+    }
+
+    fn changelog(&self) -> Option<&str> {
+        self.inner.changelog()
+    }
+
+    fn config(&self) -> &ModConfig {
+        &self.config
+    }
+}
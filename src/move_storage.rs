@@ -0,0 +1,189 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use log::*;
+use structopt::*;
+
+use crate::failure::*;
+use crate::file_utils::*;
+use crate::journal::get_journal_path;
+use crate::profile::*;
+use crate::stats::format_size;
+
+/// Moves the backup directory (and everything else modman keeps there)
+///
+/// Backups are kept in modman-backup/, next to the profile, until they're
+/// moved somewhere else (say, another drive) with this.
+/// If <NEW_DIR> is on another filesystem, everything is copied and checked
+/// before the profile is pointed at it and the old directory is removed.
+/// Relative paths are relative to the profile's directory.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    #[structopt(short = "n", long)]
+    dry_run: bool,
+
+    /// Where to keep backups from now on (which must not exist or be empty)
+    #[structopt(name = "NEW_DIR")]
+    new_dir: PathBuf,
+}
+
+impl Args {
+    /// Makes this a dry run, for `--no-write`.
+    pub fn make_dry_run(&mut self) {
+        self.dry_run = true;
+    }
+
+    pub fn resolve_paths(&mut self, base: &Path) {
+        self.new_dir = base.join(&self.new_dir);
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    // Don't pull the rug out from under a `modman add --resume`.
+    if get_journal_path().exists() {
+        fail!(
+            Failure::NeedsRepair,
+            "An interrupted `modman add` left its journal in the backup directory.\n\
+             Run `modman repair` (or `modman add --resume`) before moving it."
+        );
+    }
+    let (mut store, mut p) = open_profile()?;
+    let old_dir = storage_dir();
+    let new_dir = args.new_dir;
+    if !old_dir.is_dir() {
+        bail!(
+            "The backup directory ({}/) doesn't exist!",
+            old_dir.display()
+        );
+    }
+    if new_dir.exists() && fs::read_dir(&new_dir)?.next().is_some() {
+        bail!(
+            "{} already exists and isn't empty. Pick somewhere else.",
+            new_dir.display()
+        );
+    }
+    let old_canonical = fs::canonicalize(&old_dir)?;
+    if absolute(&new_dir)?.starts_with(&old_canonical) {
+        bail!(
+            "Can't move the backup directory ({}/) into itself",
+            old_dir.display()
+        );
+    }
+
+    let files = collect_file_paths_in_dir(&old_dir)?;
+    if args.dry_run {
+        let mut size = 0;
+        for f in &files {
+            size += file_size(&old_dir.join(f))?;
+        }
+        println!(
+            "Would move {}/ ({} files, {}) to {}/",
+            old_dir.display(),
+            files.len(),
+            format_size(size),
+            new_dir.display()
+        );
+        return Ok(());
+    }
+
+    if let Some(parent) = new_dir.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Couldn't create directory {}", parent.display()))?;
+    }
+    if new_dir.exists() {
+        fs::remove_dir(&new_dir)
+            .with_context(|| format!("Couldn't replace empty {}", new_dir.display()))?;
+    }
+
+    info!("Moving {}/ to {}/", old_dir.display(), new_dir.display());
+    let copied = match rename_file(&old_dir, &new_dir) {
+        Ok(()) => false,
+        Err(e) => {
+            debug!("Couldn't rename it ({}); copying instead", e);
+            copy_storage(&old_dir, &new_dir, &files)?;
+            true
+        }
+    };
+
+    // Point the profile at the new directory before we remove the old one,
+    // so we always have one the profile knows about.
+    p.storage_directory = if new_dir == Path::new(DEFAULT_STORAGE_PATH) {
+        None
+    } else {
+        Some(new_dir.clone())
+    };
+    store.save_settings(&p)?;
+    set_storage_dir(p.storage_directory.as_deref());
+
+    if copied {
+        debug!("Removing {}/", old_dir.display());
+        fs::remove_dir_all(&old_dir).with_context(|| {
+            format!(
+                "Moved the backup directory, but couldn't remove the old one ({}/)",
+                old_dir.display()
+            )
+        })?;
+    }
+    println!(
+        "Moved the backup directory ({} files) to {}/",
+        files.len(),
+        new_dir.display()
+    );
+    Ok(())
+}
+
+/// Like fs::canonicalize(), but for paths that might not exist yet.
+fn absolute(path: &Path) -> Result<PathBuf> {
+    match (path.parent(), path.file_name()) {
+        _ if path.exists() => Ok(fs::canonicalize(path)?),
+        (Some(parent), Some(name)) if !parent.as_os_str().is_empty() => {
+            Ok(absolute(parent)?.join(name))
+        }
+        _ => Ok(std::env::current_dir()?.join(path)),
+    }
+}
+
+/// Copies everything in the old storage directory to the new one,
+/// checking each copy against the original.
+/// If anything goes wrong, the new directory is removed.
+fn copy_storage(old_dir: &Path, new_dir: &Path, files: &[PathBuf]) -> Result<()> {
+    let copy_all = || -> Result<()> {
+        fs::create_dir(new_dir)
+            .with_context(|| format!("Couldn't create {}", new_dir.display()))?;
+        for f in files {
+            let from = old_dir.join(f);
+            let to = new_dir.join(f);
+            trace!("Copying {} to {}", from.display(), to.display());
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Couldn't create directory {}", parent.display()))?;
+            }
+            copy_file(&from, &to)
+                .with_context(|| format!("Couldn't copy {} to {}", from.display(), to.display()))?;
+            copy_mtime(&from, &to)?;
+            if hash_file(&from)? != hash_file(&to)? {
+                bail!(
+                    "{} didn't match {} after copying it",
+                    to.display(),
+                    from.display()
+                );
+            }
+        }
+        // (These might be empty, so they weren't copied above.)
+        for dir in &[tempdir(), backup_dir()] {
+            let to = new_dir.join(dir.strip_prefix(old_dir)?);
+            fs::create_dir_all(&to)
+                .with_context(|| format!("Couldn't create directory {}", to.display()))?;
+        }
+        Ok(())
+    };
+    let result = copy_all();
+    if result.is_err() && new_dir.exists() {
+        if let Err(rm) = fs::remove_dir_all(new_dir) {
+            warn!("Couldn't clean up {}/: {}", new_dir.display(), rm);
+        }
+    }
+    result
+}
@@ -0,0 +1,28 @@
+//! Modification times as RFC 3339 timestamps (`"2024-05-01T12:34:56.789Z"`),
+//! down to the nanosecond so they compare exactly with what `stat` says later.
+
+use std::result::Result;
+use std::time::SystemTime;
+
+pub fn serialize_mtime<S>(mtime: &Option<SystemTime>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match mtime {
+        Some(t) => serializer.serialize_str(&humantime::format_rfc3339_nanos(*t).to_string()),
+        None => serializer.serialize_none(),
+    }
+}
+
+pub fn deserialize_mtime<'de, D>(deserializer: D) -> Result<Option<SystemTime>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Deserialize;
+    match Option::<String>::deserialize(deserializer)? {
+        Some(s) => humantime::parse_rfc3339(&s)
+            .map(Some)
+            .map_err(|err| serde::de::Error::custom(format!("Bad timestamp {:?}: {}", s, err))),
+        None => Ok(None),
+    }
+}
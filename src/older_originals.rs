@@ -0,0 +1,299 @@
+//! Older originals: the game files that `modman update` replaced in the
+//! backup directory when a game update changed them.
+//!
+//! Normally the previous original is discarded, but a profile can keep
+//! the last few generations of each file (see `modman keep-originals`)
+//! to recover older game versions' files with `modman restore-original`.
+//! They're kept by their hash in older-originals/, so a file that flips
+//! back and forth between versions is only stored once per version,
+//! along with an index of each file's generations, newest first.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::*;
+use log::*;
+use structopt::*;
+
+use crate::failure::*;
+use crate::file_utils::*;
+use crate::profile::*;
+use crate::stats::format_size;
+
+/// Each file's older originals, newest first
+type Index = BTreeMap<PathBuf, Vec<FileHash>>;
+
+/// update_file() runs in parallel, so take turns changing the index.
+static INDEX_LOCK: Mutex<()> = Mutex::new(());
+
+pub fn older_originals_dir() -> PathBuf {
+    storage_dir().join("older-originals")
+}
+
+fn index_path() -> PathBuf {
+    older_originals_dir().join("index.json")
+}
+
+fn blob_path(hash: &FileHash) -> PathBuf {
+    older_originals_dir().join(hex::encode(hash.bytes))
+}
+
+fn read_index() -> Result<Index> {
+    let path = index_path();
+    if !path.exists() {
+        return Ok(Index::new());
+    }
+    let f = fs::File::open(&path).with_context(|| format!("Couldn't open {}", path.display()))?;
+    serde_json::from_reader(std::io::BufReader::new(f))
+        .with_context(|| format!("Couldn't parse {}", path.display()))
+}
+
+/// Writes the index, and removes any kept files it no longer mentions.
+/// (If it's empty, the whole directory goes.)
+fn write_index(index: &Index) -> Result<()> {
+    let path = index_path();
+    if index.is_empty() {
+        if path.exists() {
+            remove_file(&path).with_context(|| format!("Couldn't remove {}", path.display()))?;
+        }
+    } else {
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".new");
+        let temp = PathBuf::from(temp);
+        fs::write(&temp, serde_json::to_string_pretty(index)?)
+            .with_context(|| format!("Couldn't write {}", temp.display()))?;
+        rename_file(&temp, &path)
+            .with_context(|| format!("Couldn't replace {}", path.display()))?;
+    }
+
+    let kept: BTreeSet<String> = index
+        .values()
+        .flatten()
+        .map(|h| hex::encode(h.bytes))
+        .collect();
+    for entry in fs::read_dir(older_originals_dir())? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.path() != path && !kept.contains(&name) {
+            debug!("Removing older original {}", name);
+            discard_file(&entry.path())
+                .with_context(|| format!("Couldn't remove {}", entry.path().display()))?;
+        }
+    }
+    if index.is_empty() {
+        remove_empty_tree(&older_originals_dir(), RemoveRoot(true))?;
+    }
+    Ok(())
+}
+
+/// Before `modman update` replaces a file's backup, keeps a copy of it
+/// (whose contents hash to `hash`) as the newest older original,
+/// keeping at most `generations` of them.
+pub fn keep_older_original(
+    mod_file_path: &Path,
+    hash: &FileHash,
+    generations: usize,
+) -> Result<()> {
+    let _lock = INDEX_LOCK.lock().unwrap();
+    let backup_path = mod_path_to_backup_path(mod_file_path);
+    let blob = blob_path(hash);
+    if !blob.exists() {
+        fs::create_dir_all(older_originals_dir()).with_context(|| {
+            format!(
+                "Couldn't create directory {}",
+                older_originals_dir().display()
+            )
+        })?;
+        debug!("Keeping {} as {}", backup_path.display(), blob.display());
+        copy_file(&backup_path, &blob).with_context(|| {
+            format!(
+                "Couldn't copy {} to {}",
+                backup_path.display(),
+                blob.display()
+            )
+        })?;
+    }
+
+    let mut index = read_index()?;
+    let generations_kept = index.entry(mod_file_path.to_owned()).or_default();
+    generations_kept.retain(|h| h != hash);
+    generations_kept.insert(0, hash.clone());
+    generations_kept.truncate(generations);
+    write_index(&index)
+}
+
+/// Shows or sets how many older originals of each file to keep
+///
+/// When `modman update` finds that a game update changed a file a mod
+/// replaced, it backs up the new version. With this set, the last
+/// <GENERATIONS> versions it replaced are kept too, for
+/// `modman restore-original`. 0 (the default) keeps none.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct KeepArgs {
+    #[structopt(name = "GENERATIONS")]
+    generations: Option<usize>,
+}
+
+impl KeepArgs {
+    /// Returns true if we'll change the profile (not just show it).
+    pub fn is_changing(&self) -> bool {
+        self.generations.is_some()
+    }
+}
+
+pub fn keep(args: KeepArgs) -> Result<()> {
+    let (mut store, mut p) = open_profile()?;
+    let generations = match args.generations {
+        Some(g) => g,
+        None => {
+            println!("{}", p.keep_originals.unwrap_or(0));
+            return Ok(());
+        }
+    };
+    p.keep_originals = Some(generations).filter(|g| *g > 0);
+    store.save_settings(&p)?;
+
+    // Forget any generations we no longer want.
+    let _lock = INDEX_LOCK.lock().unwrap();
+    if index_path().exists() {
+        let mut index = read_index()?;
+        for kept in index.values_mut() {
+            kept.truncate(generations);
+        }
+        index.retain(|_, kept| !kept.is_empty());
+        write_index(&index)?;
+    }
+    Ok(())
+}
+
+/// Recovers an older version of a game file kept by `modman keep-originals`
+///
+/// <FILE> is the file's path in the root directory (like `modman list --files`
+/// prints it). Without --generation, lists the versions kept,
+/// newest (generation 1) first.
+/// The file is written back to the game directory unless --output is given,
+/// but not over a mod's file, or over anything else without --force.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct RestoreArgs {
+    /// Which version to restore (1 is the newest)
+    #[structopt(short, long, name = "N")]
+    generation: Option<usize>,
+
+    /// Where to write it, instead of the game directory
+    #[structopt(short, long, name = "PATH", requires = "N")]
+    output: Option<PathBuf>,
+
+    /// Replace the game's file if there is one.
+    #[structopt(short, long, requires = "N")]
+    force: bool,
+
+    #[structopt(name = "FILE")]
+    file: PathBuf,
+}
+
+impl RestoreArgs {
+    /// Returns true if we'll write a file (not just list versions).
+    pub fn is_changing(&self) -> bool {
+        self.generation.is_some()
+    }
+
+    pub fn resolve_paths(&mut self, base: &Path) {
+        if let Some(output) = &mut self.output {
+            *output = base.join(&output);
+        }
+    }
+}
+
+pub fn restore(args: RestoreArgs) -> Result<()> {
+    let p = load_and_check_profile()?;
+    let index = read_index()?;
+    let kept = index.get(&args.file).map(Vec::as_slice).unwrap_or(&[]);
+
+    let generation = match args.generation {
+        Some(g) => g,
+        None => {
+            if kept.is_empty() {
+                println!("No older originals of {} are kept.", args.file.display());
+            }
+            for (i, hash) in kept.iter().enumerate() {
+                let size = file_size(&blob_path(hash))?;
+                println!(
+                    "{}: {} ({})",
+                    i + 1,
+                    &hex::encode(hash.bytes)[..12],
+                    format_size(size)
+                );
+            }
+            return Ok(());
+        }
+    };
+    let hash = match generation.checked_sub(1).and_then(|i| kept.get(i)) {
+        Some(h) => h,
+        None => fail!(
+            Failure::Usage,
+            "{} has {} older original{} kept, not {}",
+            args.file.display(),
+            kept.len(),
+            if kept.len() == 1 { "" } else { "s" },
+            generation
+        ),
+    };
+
+    let output = match &args.output {
+        Some(o) => o.clone(),
+        None => {
+            if let Some((mod_path, _)) = p
+                .mods
+                .iter()
+                .find(|(_, m)| m.files.contains_key(&args.file))
+            {
+                bail!(
+                    "{} installed {}. Use --output to write the older original somewhere else.",
+                    mod_path.display(),
+                    args.file.display()
+                );
+            }
+            p.game_path(&args.file)
+        }
+    };
+    if fs::symlink_metadata(&output).is_ok() {
+        if !args.force {
+            bail!(
+                "{} already exists. Pass --force to replace it.",
+                output.display()
+            );
+        }
+        discard_file(&output).with_context(|| format!("Couldn't remove {}", output.display()))?;
+    }
+    if let Some(parent) = output.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Couldn't create directory {}", parent.display()))?;
+    }
+    copy_file(&blob_path(hash), &output).with_context(|| {
+        format!(
+            "Couldn't copy generation {} of {} to {}",
+            generation,
+            args.file.display(),
+            output.display()
+        )
+    })?;
+    if hash_file(&output)? != *hash {
+        fail!(
+            Failure::VerificationFailed,
+            "The kept copy of {} (generation {}) is damaged",
+            args.file.display(),
+            generation
+        );
+    }
+    println!(
+        "Restored generation {} of {} to {}",
+        generation,
+        args.file.display(),
+        output.display()
+    );
+    Ok(())
+}
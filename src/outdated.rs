@@ -0,0 +1,152 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use log::*;
+use semver::Version;
+use structopt::*;
+
+use crate::add;
+use crate::download::*;
+use crate::file_utils::*;
+use crate::modification::*;
+use crate::profile::*;
+use crate::remove::remove_mods;
+
+/// List installed mods that have newer versions where they came from
+///
+/// Mods added from a directory or archive are checked there
+/// (e.g., after unzipping a new release over the old one),
+/// and mods added from a URL are downloaded again.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    /// Replace each outdated mod with its newer version,
+    /// keeping its alias and root.
+    #[structopt(long)]
+    upgrade_all: bool,
+
+    #[structopt(short = "n", long, requires = "upgrade-all")]
+    dry_run: bool,
+}
+
+impl Args {
+    /// Makes this a dry run, for `--no-write`.
+    pub fn make_dry_run(&mut self) {
+        self.dry_run = true;
+    }
+
+    /// Returns true if we'll change the game directory (with --upgrade-all).
+    pub fn is_upgrading(&self) -> bool {
+        self.upgrade_all && !self.dry_run
+    }
+}
+
+/// A mod with a newer version at its source
+struct Outdated {
+    mod_path: PathBuf,
+    manifest: ModManifest,
+    latest: Version,
+    /// Where we downloaded the newer version, for mods from URLs
+    downloaded: Option<PathBuf>,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let p = load_and_check_profile()?;
+
+    let mut outdated = Vec::new();
+    for (mod_path, manifest) in &p.mods {
+        let (latest, downloaded) = match latest_version(mod_path, manifest, &p) {
+            Ok(l) => l,
+            Err(e) => {
+                warn!(
+                    "Couldn't check {} for a newer version: {:#}",
+                    mod_path.display(),
+                    e
+                );
+                continue;
+            }
+        };
+        if latest > manifest.version {
+            outdated.push(Outdated {
+                mod_path: mod_path.clone(),
+                manifest: manifest.clone(),
+                latest,
+                downloaded,
+            });
+        } else {
+            debug!("{} is up to date", mod_path.display());
+            if let Some(d) = downloaded {
+                fs::remove_file(&d).with_context(|| format!("Couldn't remove {}", d.display()))?;
+            }
+        }
+    }
+
+    if outdated.is_empty() {
+        println!("All installed mods are up to date.");
+        return Ok(());
+    }
+    for o in &outdated {
+        println!(
+            "{}: v{} -> v{}",
+            o.mod_path.display(),
+            o.manifest.version,
+            o.latest
+        );
+    }
+
+    if args.upgrade_all {
+        for o in outdated {
+            if args.dry_run {
+                println!("Would upgrade {} to v{}", o.mod_path.display(), o.latest);
+            } else {
+                upgrade(o)?;
+            }
+        }
+    } else {
+        println!("Run `modman outdated --upgrade-all` to upgrade them.");
+    }
+    Ok(())
+}
+
+/// Finds the version of the mod at its source,
+/// along with where we downloaded it (if it's from a URL).
+fn latest_version(
+    mod_path: &Path,
+    manifest: &ModManifest,
+    p: &Profile,
+) -> Result<(Version, Option<PathBuf>)> {
+    let (path, downloaded) = match &manifest.source {
+        Some(url) => {
+            let latest = fetch_latest(url)?;
+            (latest.clone(), Some(latest))
+        }
+        None => (mod_path.to_owned(), None),
+    };
+    let m = open_mod(&path, p.unicode_form, manifest.root.as_deref())?;
+    Ok((m.version().clone(), downloaded))
+}
+
+/// Removes the installed version of the mod and adds the newer one.
+fn upgrade(o: Outdated) -> Result<()> {
+    info!("Upgrading {} to v{}...", o.mod_path.display(), o.latest);
+    remove_mods(vec![o.mod_path.clone()], false, false, false)?;
+
+    let source = match (&o.manifest.source, &o.downloaded) {
+        (Some(url), Some(latest)) => {
+            // Replace our copy of the old version so `add` picks up the new one.
+            rename_file(latest, &o.mod_path)
+                .with_context(|| format!("Couldn't rename {}", latest.display()))?;
+            PathBuf::from(url)
+        }
+        _ => o.mod_path.clone(),
+    };
+    add::run(add::Args::new(vec![source], false).like(&o.manifest)).with_context(|| {
+        format!(
+            "Removed v{} of {}, but couldn't add v{}",
+            o.manifest.version,
+            o.mod_path.display(),
+            o.latest
+        )
+    })
+}
@@ -0,0 +1,245 @@
+//! Output for people: colors (`--color`), and summaries of what
+//! dry runs would change.
+//!
+//! (Plans, from `--plan`, are the machine-readable equivalent.)
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::*;
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
+
+use crate::profile::*;
+use crate::stats::format_size;
+
+static COLOR: Mutex<ColorChoice> = Mutex::new(ColorChoice::Never);
+
+/// Sets whether what we print to stdout is colored.
+pub fn set_color(choice: ColorChoice) {
+    *COLOR.lock().unwrap() = choice;
+}
+
+/// stdout, colored (or not) as we were told.
+pub fn stdout() -> StandardStream {
+    StandardStream::stdout(*COLOR.lock().unwrap())
+}
+
+/// What a dry run would do to a game file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Change {
+    /// Install a mod file where there wasn't one.
+    Add,
+    /// Back up a game file and install a mod file over it.
+    Replace,
+    /// Install a different mod file over one a mod already installed.
+    Reinstall,
+    /// Put a backed up game file back.
+    Restore,
+    /// Remove a mod file that didn't replace anything.
+    Delete,
+}
+
+const CHANGES: [Change; 5] = [
+    Change::Add,
+    Change::Replace,
+    Change::Reinstall,
+    Change::Restore,
+    Change::Delete,
+];
+
+impl Change {
+    fn symbol(self) -> char {
+        match self {
+            Change::Add => '+',
+            Change::Replace => '~',
+            Change::Reinstall => '*',
+            Change::Restore => '<',
+            Change::Delete => '-',
+        }
+    }
+
+    fn color(self) -> Color {
+        match self {
+            Change::Add => Color::Green,
+            Change::Replace => Color::Yellow,
+            Change::Reinstall => Color::Yellow,
+            Change::Restore => Color::Cyan,
+            Change::Delete => Color::Red,
+        }
+    }
+
+    fn verb(self) -> &'static str {
+        match self {
+            Change::Add => "added",
+            Change::Replace => "replaced",
+            Change::Reinstall => "reinstalled",
+            Change::Restore => "restored",
+            Change::Delete => "deleted",
+        }
+    }
+}
+
+/// A change to one game file
+struct FileChange<'a> {
+    path: &'a Path,
+    change: Change,
+    /// How big the file written (or removed) is, if we know
+    size: Option<u64>,
+}
+
+/// Prints what a dry run would do, given the profile before and after it.
+pub fn print_changes(before: &Profile, after: &Profile) -> Result<()> {
+    let mut out = stdout();
+    // Files that some mod still has (or will have) aren't going anywhere.
+    let after_files: BTreeSet<PathBuf> = after
+        .mods
+        .values()
+        .flat_map(|m| m.files.keys())
+        .map(|f| path_key(f, after.case_insensitive))
+        .collect();
+    let before_files: BTreeSet<PathBuf> = before
+        .mods
+        .values()
+        .flat_map(|m| m.files.keys())
+        .map(|f| path_key(f, before.case_insensitive))
+        .collect();
+
+    let mod_paths: BTreeSet<&PathBuf> = before.mods.keys().chain(after.mods.keys()).collect();
+    let mut totals = BTreeMap::new();
+    let mut to_install = 0;
+    let mut to_back_up = 0;
+    for mod_path in mod_paths {
+        let old = before.mods.get(mod_path);
+        let new = after.mods.get(mod_path);
+        let mut changes = Vec::new();
+
+        // Files the mod would install
+        if let Some(new) = new {
+            for (file, meta) in &new.files {
+                // Shared and identical files are already there.
+                if meta.shared || meta.identical {
+                    continue;
+                }
+                let change = match old.and_then(|o| o.files.get(file)) {
+                    Some(old_meta) if old_meta.mod_hash == meta.mod_hash => continue,
+                    Some(_) => Change::Reinstall,
+                    None if before_files.contains(&path_key(file, before.case_insensitive)) => {
+                        Change::Reinstall
+                    }
+                    None if meta.original_hash.is_some() => {
+                        to_back_up += meta.original_size.unwrap_or(0);
+                        Change::Replace
+                    }
+                    None => Change::Add,
+                };
+                to_install += meta.size.unwrap_or(0);
+                changes.push(FileChange {
+                    path: file,
+                    change,
+                    size: meta.size,
+                });
+            }
+        }
+        // Files the mod would no longer have
+        if let Some(old) = old {
+            for (file, meta) in &old.files {
+                if new.is_some_and(|n| n.files.contains_key(file))
+                    || meta.identical
+                    || after_files.contains(&path_key(file, after.case_insensitive))
+                {
+                    continue;
+                }
+                let (change, size) = if meta.original_hash.is_some() {
+                    (Change::Restore, meta.original_size)
+                } else {
+                    (Change::Delete, meta.size)
+                };
+                changes.push(FileChange {
+                    path: file,
+                    change,
+                    size,
+                });
+            }
+        }
+        if changes.is_empty() {
+            continue;
+        }
+
+        let (symbol, color, manifest) = match (old, new) {
+            (None, Some(n)) => ('+', Color::Green, n),
+            (Some(o), None) => ('-', Color::Red, o),
+            (_, Some(n)) => ('~', Color::Yellow, n),
+            (None, None) => unreachable!(),
+        };
+        out.set_color(ColorSpec::new().set_fg(Some(color)).set_bold(true))?;
+        write!(out, "{} {}", symbol, mod_path.display())?;
+        out.reset()?;
+        writeln!(out, " (v{})", manifest.version)?;
+
+        changes.sort_by(|a, b| a.path.cmp(b.path));
+        for c in &changes {
+            out.set_color(ColorSpec::new().set_fg(Some(c.change.color())))?;
+            write!(out, "  {} {}", c.change.symbol(), c.path.display())?;
+            out.reset()?;
+            match c.size {
+                Some(s) => writeln!(out, " ({})", format_size(s))?,
+                None => writeln!(out)?,
+            }
+            *totals.entry(c.change).or_insert(0) += 1;
+        }
+    }
+
+    if totals.is_empty() {
+        writeln!(out, "Nothing would change.")?;
+        return Ok(());
+    }
+    let counts: Vec<String> = CHANGES
+        .iter()
+        .filter_map(|c| totals.get(c).map(|n| format!("{} {}", n, c.verb())))
+        .collect();
+    let files: usize = totals.values().sum();
+    write!(
+        out,
+        "{} file{}: {}",
+        files,
+        if files == 1 { "" } else { "s" },
+        counts.join(", ")
+    )?;
+    if to_install > 0 {
+        write!(out, "; {} to install", format_size(to_install))?;
+    }
+    if to_back_up > 0 {
+        write!(
+            out,
+            "; {} of game files to back up",
+            format_size(to_back_up)
+        )?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Prints a unified diff, with removed lines in red and added ones in green.
+pub fn print_diff(diff: &str) -> Result<()> {
+    let mut out = stdout();
+    for line in diff.lines() {
+        let color = if line.starts_with("---") || line.starts_with("+++") {
+            None
+        } else if line.starts_with('-') {
+            Some(Color::Red)
+        } else if line.starts_with('+') {
+            Some(Color::Green)
+        } else if line.starts_with("@@") {
+            Some(Color::Cyan)
+        } else {
+            None
+        };
+        out.set_color(ColorSpec::new().set_fg(color))?;
+        write!(out, "{}", line)?;
+        out.reset()?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
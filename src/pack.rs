@@ -0,0 +1,449 @@
+//! Modpacks: an ordered list of mods to install together, in a TOML file.
+//!
+//! ```toml
+//! [[mod]]
+//! source = "mods/base-fixes.zip"  # A path (relative to this file) or a URL
+//! version = ">= 1.2"              # Optional
+//!
+//! [[mod]]
+//! source = "https://example.com/textures.zip"
+//! sha224 = "..."                  # Optional, checks the archive itself
+//! ```
+//!
+//! Mods are installed in the order they're listed.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::*;
+use log::*;
+use semver::VersionReq;
+use serde_derive::{Deserialize, Serialize};
+use structopt::*;
+
+use crate::add;
+use crate::download::*;
+use crate::failure::*;
+use crate::file_utils::*;
+use crate::modification::*;
+use crate::profile::*;
+use crate::remove::remove_mods;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ModPack {
+    #[serde(rename = "mod", default)]
+    pub mods: Vec<PackEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PackEntry {
+    pub source: String,
+    /// A version requirement (`1.2.3`, `>= 1.2`, etc.)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// The hash of the mod's archive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha224: Option<FileHash>,
+}
+
+impl PackEntry {
+    fn version_req(&self) -> Result<VersionReq> {
+        match &self.version {
+            None => Ok(VersionReq::any()),
+            Some(v) => VersionReq::parse(v).map_err(|e| {
+                format_err!(
+                    "Couldn't parse {}'s version requirement {:?}: {}",
+                    self.source,
+                    v,
+                    e
+                )
+            }),
+        }
+    }
+}
+
+pub fn read_pack(pack_path: &Path) -> Result<ModPack> {
+    let s = fs::read_to_string(pack_path)
+        .with_context(|| format!("Couldn't read {}", pack_path.display()))?;
+    toml::from_str(&s).with_context(|| format!("Couldn't parse {}", pack_path.display()))
+}
+
+/// Where a pack's mod is (or would be) in the profile.
+fn entry_path(pack_path: &Path, entry: &PackEntry) -> PathBuf {
+    if is_url(&entry.source) {
+        download_path(&entry.source)
+    } else {
+        let joined = pack_path
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(&entry.source);
+        // Tidy up packs/../mods/foo.zip into mods/foo.zip
+        let mut tidied = PathBuf::new();
+        for c in joined.components() {
+            match c {
+                Component::ParentDir
+                    if matches!(tidied.components().next_back(), Some(Component::Normal(_))) =>
+                {
+                    tidied.pop();
+                }
+                Component::CurDir => {}
+                c => tidied.push(c),
+            }
+        }
+        tidied
+    }
+}
+
+/// Makes sure each of the pack's mods is there (downloading them as needed)
+/// and what the pack says it should be, then returns their paths.
+fn fetch_pack(pack_path: &Path, pack: &ModPack, unicode_form: UnicodeForm) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::with_capacity(pack.mods.len());
+    for entry in &pack.mods {
+        let path = if is_url(&entry.source) {
+            fetch(&entry.source)?
+        } else {
+            entry_path(pack_path, entry)
+        };
+
+        if let Some(expected) = &entry.sha224 {
+            if path.is_dir() {
+                bail!(
+                    "{} has a sha224, but it's a directory (only archives can be hashed)",
+                    entry.source
+                );
+            }
+            if hash_file(&path)? != *expected {
+                fail!(
+                    Failure::VerificationFailed,
+                    "{} doesn't match the sha224 in the pack",
+                    entry.source
+                );
+            }
+        }
+
+        let version = open_mod(&path, unicode_form, None)?.version().clone();
+        if !entry.version_req()?.matches(&version) {
+            bail!(
+                "The pack wants {} {}, but it's version {}",
+                entry.source,
+                entry.version.as_deref().unwrap(),
+                version
+            );
+        }
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Adds the given mods, in order.
+/// If any of them fail, the ones that were installed are removed.
+fn install_in_order(mods: Vec<PathBuf>, dry_run: bool) -> Result<()> {
+    if mods.is_empty() {
+        return Ok(());
+    }
+    let before: BTreeSet<PathBuf> = load_and_check_profile()?.mods.into_keys().collect();
+
+    let err = match add::run(add::Args::new(mods.clone(), dry_run)) {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
+    };
+    if dry_run {
+        return Err(err);
+    }
+
+    let p = load_and_check_profile().context(format!(
+        "{:#}\nCouldn't load the profile to roll back the pack",
+        err
+    ))?;
+    let installed: Vec<PathBuf> = mods
+        .into_iter()
+        .rev()
+        .filter(|m| p.mods.contains_key(m) && !before.contains(m))
+        .collect();
+    if !installed.is_empty() {
+        warn!("Removing the pack's mods that were already installed...");
+        remove_mods(installed, false, false, false)
+            .context(format!("{:#}\nCouldn't roll back the pack's mods", err))?;
+    }
+    Err(err)
+}
+
+/// Installs the mods in a modpack, in order
+///
+/// If any mod fails to install, the pack's mods that were installed
+/// are removed again. Mods from the pack that are already installed
+/// are skipped.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct InstallArgs {
+    #[structopt(short = "n", long)]
+    dry_run: bool,
+
+    #[structopt(name = "MODPACK")]
+    pack: PathBuf,
+}
+
+impl InstallArgs {
+    /// Makes this a dry run, for `--no-write`.
+    pub fn make_dry_run(&mut self) {
+        self.dry_run = true;
+    }
+
+    pub fn resolve_paths(&mut self, base: &Path) {
+        self.pack = base.join(&self.pack);
+    }
+}
+
+pub fn install(args: InstallArgs) -> Result<()> {
+    let pack = read_pack(&args.pack)?;
+    let p = load_and_check_profile()?;
+    let paths = fetch_pack(&args.pack, &pack, p.unicode_form)?;
+
+    let to_install: Vec<PathBuf> = paths
+        .into_iter()
+        .filter(|path| {
+            let installed = p.mods.contains_key(path);
+            if installed {
+                info!("{} is already installed", path.display());
+            }
+            !installed
+        })
+        .collect();
+    install_in_order(to_install, args.dry_run)
+}
+
+/// Differences between a pack and the profile
+#[derive(Debug, Default)]
+pub struct PackDiff {
+    /// Mods in the pack that aren't installed
+    missing: Vec<PathBuf>,
+    /// Mods in the pack that are installed, but at the wrong version
+    wrong_version: Vec<PathBuf>,
+    /// Installed mods that aren't in the pack
+    extra: Vec<PathBuf>,
+}
+
+impl PackDiff {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.wrong_version.is_empty() && self.extra.is_empty()
+    }
+}
+
+pub fn diff_pack(pack_path: &Path, pack: &ModPack, p: &Profile) -> Result<PackDiff> {
+    let mut diff = PackDiff::default();
+    let mut listed = BTreeSet::new();
+    for entry in &pack.mods {
+        let path = entry_path(pack_path, entry);
+        match p.mods.get(&path) {
+            None => diff.missing.push(path.clone()),
+            Some(m) if !entry.version_req()?.matches(&m.version) => {
+                diff.wrong_version.push(path.clone())
+            }
+            Some(_) => {}
+        }
+        listed.insert(path);
+    }
+    diff.extra = p
+        .mods
+        .keys()
+        .filter(|m| !listed.contains(*m))
+        .cloned()
+        .collect();
+    Ok(diff)
+}
+
+/// Checks that the installed mods match a modpack
+#[derive(Debug, StructOpt)]
+pub struct VerifyArgs {
+    #[structopt(name = "MODPACK")]
+    pack: PathBuf,
+}
+
+impl VerifyArgs {
+    pub fn resolve_paths(&mut self, base: &Path) {
+        self.pack = base.join(&self.pack);
+    }
+}
+
+pub fn verify(args: VerifyArgs) -> Result<()> {
+    let pack = read_pack(&args.pack)?;
+    let p = load_and_check_profile()?;
+    let diff = diff_pack(&args.pack, &pack, &p)?;
+
+    for m in &diff.missing {
+        println!("{} isn't installed", m.display());
+    }
+    for m in &diff.wrong_version {
+        println!(
+            "{} is installed, but not the version the pack wants",
+            m.display()
+        );
+    }
+    for m in &diff.extra {
+        println!("{} is installed, but isn't in the pack", m.display());
+    }
+    if !diff.is_empty() {
+        fail!(
+            Failure::VerificationFailed,
+            "The installed mods don't match {}",
+            args.pack.display()
+        );
+    }
+    info!("The installed mods match {}", args.pack.display());
+    Ok(())
+}
+
+/// Adds and removes mods to match a modpack
+///
+/// Mods that aren't in the pack (or are the wrong version) are removed,
+/// then any mods from the pack that aren't installed are added, in order.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct SyncArgs {
+    #[structopt(short = "n", long)]
+    dry_run: bool,
+
+    #[structopt(name = "MODPACK")]
+    pack: PathBuf,
+}
+
+impl SyncArgs {
+    /// Makes this a dry run, for `--no-write`.
+    pub fn make_dry_run(&mut self) {
+        self.dry_run = true;
+    }
+
+    pub fn resolve_paths(&mut self, base: &Path) {
+        self.pack = base.join(&self.pack);
+    }
+}
+
+pub fn sync(args: SyncArgs) -> Result<()> {
+    let pack = read_pack(&args.pack)?;
+    sync_to(
+        &args.pack,
+        &pack,
+        &args.pack.display().to_string(),
+        args.dry_run,
+    )
+}
+
+/// Removes the installed mods that aren't in the pack (or are the wrong version),
+/// then adds the pack's mods that aren't installed, leaving the rest alone.
+/// (`name` is what to call the pack in messages.)
+pub fn sync_to(pack_path: &Path, pack: &ModPack, name: &str, dry_run: bool) -> Result<()> {
+    let p = load_and_check_profile()?;
+    let diff = diff_pack(pack_path, pack, &p)?;
+    if diff.is_empty() {
+        info!("The installed mods already match {}", name);
+        return Ok(());
+    }
+    // Make sure we can install everything before we remove anything.
+    let paths = fetch_pack(pack_path, pack, p.unicode_form)?;
+
+    let to_remove: Vec<PathBuf> = diff
+        .extra
+        .iter()
+        .chain(&diff.wrong_version)
+        .cloned()
+        .collect();
+    if !to_remove.is_empty() {
+        if dry_run {
+            for m in &to_remove {
+                println!("Would remove {}", m.display());
+            }
+        } else {
+            remove_mods(to_remove, false, false, false)?;
+        }
+    }
+
+    let to_install: Vec<PathBuf> = paths
+        .into_iter()
+        .filter(|path| diff.missing.contains(path) || diff.wrong_version.contains(path))
+        .collect();
+    if dry_run {
+        for m in &to_install {
+            println!("Would add {}", m.display());
+        }
+        return Ok(());
+    }
+    install_in_order(to_install, false)
+}
+
+/// Writes the installed mods to a modpack
+///
+/// Each mod's version is pinned to the installed one.
+/// Mods are listed after the mods they require.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct ExportArgs {
+    #[structopt(name = "MODPACK")]
+    pack: PathBuf,
+}
+
+impl ExportArgs {
+    pub fn resolve_paths(&mut self, base: &Path) {
+        self.pack = base.join(&self.pack);
+    }
+}
+
+pub fn export(args: ExportArgs) -> Result<()> {
+    let p = load_and_check_profile()?;
+    let pack_dir = args
+        .pack
+        .parent()
+        .filter(|d| !d.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+
+    let mut pack = ModPack::default();
+    for (mod_path, manifest) in install_order(&p) {
+        let source = match &manifest.source {
+            Some(url) => url.clone(),
+            // Paths in the pack are relative to it.
+            None => relative_path(mod_path, pack_dir)
+                .unwrap_or_else(|_| mod_path.to_owned())
+                .to_string_lossy()
+                .into_owned(),
+        };
+        let sha224 = if mod_path.is_file() {
+            Some(hash_file(mod_path)?)
+        } else {
+            None
+        };
+        pack.mods.push(PackEntry {
+            source,
+            version: Some(format!("={}", manifest.version)),
+            sha224,
+        });
+    }
+
+    let s = toml::to_string_pretty(&pack).context("Couldn't serialize the modpack")?;
+    fs::write(&args.pack, s).with_context(|| format!("Couldn't write {}", args.pack.display()))?;
+    info!("Wrote {} mods to {}", pack.mods.len(), args.pack.display());
+    Ok(())
+}
+
+/// Orders the installed mods so that each comes after any it requires.
+pub fn install_order(p: &Profile) -> Vec<(&Path, &ModManifest)> {
+    let mut remaining: Vec<(&Path, &ModManifest)> =
+        p.mods.iter().map(|(k, v)| (k.as_path(), v)).collect();
+    let mut ordered: Vec<(&Path, &ModManifest)> = Vec::with_capacity(remaining.len());
+    while !remaining.is_empty() {
+        // A mod is ready once none of the mods it requires are left to place.
+        let ready = remaining
+            .iter()
+            .position(|(_, m)| {
+                m.requires.iter().all(|req| {
+                    !remaining
+                        .iter()
+                        .any(|(path, other)| req.matches(path, &other.version))
+                })
+            })
+            // A cycle? Just take them in order.
+            .unwrap_or(0);
+        ordered.push(remaining.remove(ready));
+    }
+    ordered
+}
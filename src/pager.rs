@@ -0,0 +1,88 @@
+//! Shows long text (READMEs, changelogs) a screenful at a time.
+
+use std::env;
+use std::io::{self, prelude::*};
+use std::process::{Command, Stdio};
+
+use anyhow::*;
+use atty::Stream;
+use log::*;
+
+/// Prints the given text, through a pager if it's too long for the terminal.
+///
+/// We use $PAGER if it's set (set it to `cat` or nothing to never page),
+/// or `less`/`more` if it isn't, or our own (very basic) pager
+/// if none of those run.
+/// Text that isn't going to a terminal is printed as-is.
+pub fn page(text: &str) -> Result<()> {
+    let height = terminal_height();
+    if !atty::is(Stream::Stdout) || text.lines().count() < height {
+        io::stdout().write_all(text.as_bytes())?;
+        return Ok(());
+    }
+
+    let pager = match env::var("PAGER") {
+        Ok(p) if p.trim().is_empty() || p.trim() == "cat" => {
+            io::stdout().write_all(text.as_bytes())?;
+            return Ok(());
+        }
+        Ok(p) => p,
+        Err(_) if cfg!(windows) => "more".to_owned(),
+        Err(_) => "less".to_owned(),
+    };
+    match run_pager(&pager, text) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            debug!("Couldn't run {}: {:#}; using our own pager", pager, e);
+            internal_pager(text, height)
+        }
+    }
+}
+
+/// Pipes the text to the given pager command (which can have arguments,
+/// like `less -R`).
+fn run_pager(pager: &str, text: &str) -> Result<()> {
+    let mut words = pager.split_whitespace();
+    let program = words.next().unwrap();
+    let mut child = Command::new(program)
+        .args(words)
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Couldn't start {}", program))?;
+    // Quitting the pager before reading everything is fine.
+    let _ = child.stdin.take().unwrap().write_all(text.as_bytes());
+    child.wait()?;
+    Ok(())
+}
+
+/// Prints a screenful at a time, waiting for Enter in between.
+fn internal_pager(text: &str, height: usize) -> Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let stdin = io::stdin();
+    let lines: Vec<&str> = text.lines().collect();
+    // Leave a line for the prompt.
+    for (i, screen) in lines.chunks(height - 1).enumerate() {
+        if i > 0 {
+            write!(out, "-- More (Enter to continue, q to quit) --")?;
+            out.flush()?;
+            let mut answer = String::new();
+            if stdin.lock().read_line(&mut answer)? == 0 || answer.trim() == "q" {
+                return Ok(());
+            }
+        }
+        for line in screen {
+            writeln!(out, "{}", line)?;
+        }
+    }
+    Ok(())
+}
+
+/// Guesses how many lines the terminal has.
+fn terminal_height() -> usize {
+    env::var("LINES")
+        .ok()
+        .and_then(|l| l.parse().ok())
+        .filter(|&l| l > 1)
+        .unwrap_or(24)
+}
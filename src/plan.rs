@@ -0,0 +1,228 @@
+//! Plans: machine-readable lists of every file operation a command would make.
+//!
+//! `modman add/remove/update --plan <FILE>` does a dry run and writes
+//! what it would do to <FILE> (or stdout, given `-`), grouped by mod
+//! and sorted by path. After reviewing it (or showing it in a GUI),
+//! `modman apply <FILE>` carries it out - but only if the same command
+//! would still do exactly the same thing.
+//!
+//! `modman add --stage <DIR>` goes a step further, writing the files it would
+//! install to <DIR> alongside its plan, and `modman apply <DIR>` also checks
+//! that those are still the files it would install.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::*;
+use log::*;
+use serde_derive::{Deserialize, Serialize};
+use structopt::*;
+
+use crate::{add, remove, update};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanCommand {
+    Add,
+    Remove,
+    Update,
+}
+
+/// Something we'd do to a file in the game directory.
+/// Paths are relative to the root directory.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Operation {
+    /// Copy the game file to the backup directory.
+    Backup { path: PathBuf },
+    /// Write a mod file into the game directory.
+    Install { path: PathBuf },
+    /// Put a backed up game file back.
+    Restore { path: PathBuf },
+    /// Remove a mod file that didn't replace anything.
+    Delete { path: PathBuf },
+}
+
+impl Operation {
+    fn path(&self) -> &Path {
+        match self {
+            Operation::Backup { path }
+            | Operation::Install { path }
+            | Operation::Restore { path }
+            | Operation::Delete { path } => path,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Plan {
+    pub command: PlanCommand,
+    pub mods: Vec<PathBuf>,
+    /// What we'd do for each mod
+    pub operations: BTreeMap<PathBuf, Vec<Operation>>,
+}
+
+impl Plan {
+    fn len(&self) -> usize {
+        self.operations.values().map(Vec::len).sum()
+    }
+}
+
+/// What to give `--plan` to print the plan instead of writing it to a file
+const STDOUT: &str = "-";
+
+/// Returns true if the plan should be printed instead of written to a file.
+pub fn is_stdout(plan_path: &Path) -> bool {
+    plan_path == Path::new(STDOUT)
+}
+
+/// Operations noted by the dry run we're currently planning, if any.
+static PLANNED: Mutex<Option<BTreeMap<PathBuf, Vec<Operation>>>> = Mutex::new(None);
+
+/// Starts recording operations from a dry run.
+pub fn begin() {
+    *PLANNED.lock().unwrap() = Some(BTreeMap::new());
+}
+
+/// Returns true if we're recording a plan.
+pub fn is_planning() -> bool {
+    PLANNED.lock().unwrap().is_some()
+}
+
+/// Notes an operation that the current dry run would make for the given mod.
+pub fn note(mod_path: &Path, op: Operation) {
+    if let Some(ops) = &mut *PLANNED.lock().unwrap() {
+        ops.entry(mod_path.to_owned()).or_default().push(op);
+    }
+}
+
+/// Stops recording and returns the plan.
+pub fn finish(command: PlanCommand, mods: Vec<PathBuf>) -> Plan {
+    let mut operations = PLANNED.lock().unwrap().take().unwrap_or_default();
+    // Operations are noted from many threads in no particular order.
+    // Group them by file, in the order we'd do them.
+    for ops in operations.values_mut() {
+        ops.sort_by(|a, b| a.path().cmp(b.path()).then(a.cmp(b)));
+    }
+    Plan {
+        command,
+        mods,
+        operations,
+    }
+}
+
+/// Plans the given command with a dry run (see make_plan()),
+/// then writes the plan to plan_path (or prints it, see is_stdout()).
+pub fn write_plan<F>(
+    plan_path: &Path,
+    command: PlanCommand,
+    mods: Vec<PathBuf>,
+    dry_run: F,
+) -> Result<()>
+where
+    F: FnOnce() -> Result<()>,
+{
+    let plan = make_plan(command, mods, dry_run)?;
+
+    if is_stdout(plan_path) {
+        let stdout = std::io::stdout();
+        let mut out = stdout.lock();
+        serde_json::to_writer_pretty(&mut out, &plan)?;
+        out.write_all(b"\n")?;
+        return Ok(());
+    }
+    let mut f = fs::File::create(plan_path)
+        .with_context(|| format!("Couldn't create plan file {}", plan_path.display()))?;
+    serde_json::to_writer_pretty(&mut f, &plan)?;
+    f.write_all(b"\n")?;
+    info!("Wrote {} operations to {}", plan.len(), plan_path.display());
+    Ok(())
+}
+
+fn make_plan<F>(command: PlanCommand, mods: Vec<PathBuf>, dry_run: F) -> Result<Plan>
+where
+    F: FnOnce() -> Result<()>,
+{
+    begin();
+    let result = dry_run();
+    let plan = finish(command, mods);
+    result?;
+    Ok(plan)
+}
+
+pub fn read_plan(plan_path: &Path) -> Result<Plan> {
+    let f = fs::File::open(plan_path)
+        .with_context(|| format!("Couldn't open plan file {}", plan_path.display()))?;
+    serde_json::from_reader(std::io::BufReader::new(f))
+        .with_context(|| format!("Couldn't parse plan file {}", plan_path.display()))
+}
+
+/// Carries out a plan made with `--plan`
+///
+/// Before changing anything, the plan is made again and compared
+/// against <PLAN_FILE>. If the game directory, profile, or mods
+/// have changed so that the command would do anything different,
+/// nothing is done.
+/// <PLAN_FILE> can also be a directory from `modman add --stage`.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    #[structopt(name = "PLAN_FILE")]
+    plan: PathBuf,
+}
+
+impl Args {
+    pub fn resolve_paths(&mut self, base: &Path) {
+        self.plan = base.join(&self.plan);
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let (plan_path, stage_dir) = if args.plan.is_dir() {
+        (add::staged_plan_path(&args.plan), Some(args.plan.as_path()))
+    } else {
+        (args.plan.clone(), None)
+    };
+    let plan = read_plan(&plan_path)?;
+
+    info!(
+        "Checking that {} is still up to date...",
+        args.plan.display()
+    );
+    let mods = plan.mods.clone();
+    let current = make_plan(plan.command, mods.clone(), || {
+        check_command(plan.command, mods.clone(), stage_dir)
+    })
+    .with_context(|| format!("{} is out of date", args.plan.display()))?;
+    if current != plan {
+        bail!(
+            "{} is out of date - the game directory, profile, or mods have changed \
+             since it was made. Please make a new plan.",
+            args.plan.display()
+        );
+    }
+
+    info!("Applying {}...", args.plan.display());
+    run_command(plan.command, mods, false)
+}
+
+/// Plans the command again with a dry run,
+/// checking what it would install against any staged files.
+fn check_command(command: PlanCommand, mods: Vec<PathBuf>, stage_dir: Option<&Path>) -> Result<()> {
+    match (command, stage_dir) {
+        (PlanCommand::Add, Some(stage)) => add::run(add::Args::new(mods, true).check_stage(stage)),
+        (_, Some(stage)) => bail!("{} wasn't staged by `modman add`", stage.display()),
+        (_, None) => run_command(command, mods, true),
+    }
+}
+
+fn run_command(command: PlanCommand, mods: Vec<PathBuf>, dry_run: bool) -> Result<()> {
+    match command {
+        PlanCommand::Add => add::run(add::Args::new(mods, dry_run)),
+        PlanCommand::Remove => remove::run(remove::Args::new(mods, dry_run)),
+        PlanCommand::Update => update::run(update::Args::new(mods, dry_run)),
+    }
+}
@@ -1,25 +1,110 @@
 use std::collections::*;
-use std::default::Default;
 use std::fs;
 use std::io::prelude::*;
 use std::io::{BufReader, BufWriter};
 use std::path::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
 
 use anyhow::*;
 use log::*;
 use semver::Version;
 use serde_derive::{Deserialize, Serialize};
 use sha2::{digest, Digest, Sha224};
+use unicode_normalization::UnicodeNormalization;
 
+use crate::deps::{mod_name, ModSpec};
+use crate::failure::*;
+use crate::file_utils::{check_writable, rename_file, replace_file, run_id, same_filesystem};
+use crate::hooks::Hooks;
+use crate::merge::MergeMode;
+use crate::mode_serde::*;
+use crate::mtime_serde::*;
+use crate::pack::PackEntry;
+use crate::profile_store::*;
 use crate::version_serde::*;
 
 pub static PROFILE_PATH: &str = "modman.profile";
 
-// Directories for persisting the files that modman is replacing.
-pub static STORAGE_PATH: &str = "modman-backup";
-pub static BACKUP_README: &str = "modman-backup/README.txt";
-pub static TEMPDIR_PATH: &str = "modman-backup/temp";
-pub static BACKUP_PATH: &str = "modman-backup/originals";
+/// How many earlier versions of the profile file we keep
+/// (see `modman profile rollback`)
+pub const PROFILE_GENERATIONS: usize = 5;
+
+/// Where we persist the files that modman is replacing (and everything
+/// else besides the profile), unless the profile says otherwise.
+pub static DEFAULT_STORAGE_PATH: &str = "modman-backup";
+
+/// The directory the profile is in, if it isn't the working directory
+/// (as when modman is embedded in another program; see the ffi module).
+static PROFILE_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Finds the profile (and everything relative to it) in the given directory
+/// instead of the working directory, or in the working directory given None.
+pub fn set_profile_dir(dir: Option<&Path>) {
+    *PROFILE_DIR.lock().unwrap() = dir.map(Path::to_owned);
+}
+
+/// Returns where a path relative to the profile's directory is.
+/// That's the path itself, unless set_profile_dir() moved it.
+pub fn in_profile_dir<P: AsRef<Path>>(path: P) -> PathBuf {
+    match &*PROFILE_DIR.lock().unwrap() {
+        Some(dir) => dir.join(path),
+        None => path.as_ref().to_owned(),
+    }
+}
+
+/// Where the JSON profile file is
+pub fn profile_path() -> PathBuf {
+    in_profile_dir(PROFILE_PATH)
+}
+
+/// The storage directory of the profile we loaded, if it isn't the default.
+/// Like `--no-write`, this is global state, since it's needed everywhere.
+static STORAGE_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Keeps backups, etc. in the given directory
+/// (relative to the profile's), or the default one given None.
+pub fn set_storage_dir(dir: Option<&Path>) {
+    *STORAGE_DIR.lock().unwrap() = dir.map(Path::to_owned);
+}
+
+/// The directory we keep backups, temporary files, downloads, and logs in
+pub fn storage_dir() -> PathBuf {
+    let dir = match &*STORAGE_DIR.lock().unwrap() {
+        Some(dir) => dir.clone(),
+        None => PathBuf::from(DEFAULT_STORAGE_PATH),
+    };
+    in_profile_dir(dir)
+}
+
+pub fn backup_readme_path() -> PathBuf {
+    storage_dir().join("README.txt")
+}
+
+pub fn tempdir() -> PathBuf {
+    storage_dir().join("temp")
+}
+
+pub fn backup_dir() -> PathBuf {
+    storage_dir().join("originals")
+}
+
+/// Where `add --adopt-existing-backup` moves leftover backups
+/// that don't match the game's files
+pub fn quarantine_dir() -> PathBuf {
+    storage_dir().join("quarantine")
+}
+
+/// Where we keep mod files that other mods' files were installed over
+/// (see `Profile::layers`), by their hash
+pub fn covered_dir() -> PathBuf {
+    storage_dir().join("covered")
+}
+
+pub fn covered_path(hash: &FileHash) -> PathBuf {
+    covered_dir().join(hex::encode(hash.bytes))
+}
 
 pub type Sha224Bytes = digest::generic_array::GenericArray<u8, <Sha224 as Digest>::OutputSize>;
 
@@ -37,9 +122,328 @@ impl FileHash {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Profile {
     pub root_directory: PathBuf,
+    /// Other directories mods can install to, by name
+    /// (e.g., a game's folder in the user's documents).
+    /// The root directory is always named `game`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub roots: BTreeMap<String, PathBuf>,
+    /// Set for games on filesystems that ignore case (NTFS, APFS, etc.),
+    /// where `Textures/foo.dds` and `textures/FOO.dds` are the same file.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub case_insensitive: bool,
+    /// How mod file paths are Unicode-normalized before we store them.
+    #[serde(default, skip_serializing_if = "UnicodeForm::is_default")]
+    pub unicode_form: UnicodeForm,
+    /// The game's executable, so we can warn if it's running
+    /// when we change its files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process: Option<String>,
+    /// The game's Steam app ID, so we can tell when Steam updates it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub steam_app_id: Option<u32>,
+    /// The game's Steam build when we last checked every installed file
+    /// (see `modman update`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub steam_build_id: Option<String>,
+    /// Commands to run before and after installing or removing any mod
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    pub hooks: Hooks,
+    /// Quickly check for interrupted commands and changed files
+    /// before adding, removing, or updating mods (see `modman auto-check`)
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub auto_check: bool,
+    /// Where backups, etc. are kept (relative to the profile or absolute),
+    /// if not modman-backup/ (see `modman move-storage`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage_directory: Option<PathBuf>,
+    /// How many older originals of each file `modman update` keeps
+    /// when it backs up a new one (see `modman keep-originals`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_originals: Option<usize>,
+    /// Fail commands that give certain warnings (see `modman strict`)
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub strict: bool,
+    /// Don't hash backups in `modman check` if their size and modification
+    /// time haven't changed since they were made (see `modman quick-check`)
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub quick_check: bool,
+    /// Ask for administrator rights if the game's directories
+    /// can't be changed without them (see `modman elevate`)
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub elevate: bool,
+    /// The Wine (or Proton) prefix a Windows game runs in,
+    /// when it's managed from Linux (see `modman wine-prefix`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wine_prefix: Option<PathBuf>,
+    /// Named sets of mods to switch between (see `modman set`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sets: BTreeMap<String, Vec<PackEntry>>,
+    /// Files more than one mod installed (see `modman add --overwrite`),
+    /// by path (case-folded if the profile is case-insensitive),
+    /// and the mods that installed them, first to last.
+    /// The first one has the game's original, if there was one;
+    /// each after it covered the file of the one before,
+    /// which is kept in covered/ until it's needed again.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub layers: BTreeMap<PathBuf, Vec<PathBuf>>,
     pub mods: BTreeMap<PathBuf, ModManifest>,
 }
 
+impl Profile {
+    pub fn game_dirs(&self) -> GameDirs<'_> {
+        GameDirs {
+            root_directory: &self.root_directory,
+            roots: &self.roots,
+            case_insensitive: self.case_insensitive,
+        }
+    }
+
+    /// Given a recorded mod file path, returns where it is in the game directory
+    /// (or whichever root it was installed to).
+    pub fn game_path(&self, mod_path: &Path) -> PathBuf {
+        self.game_dirs().game_path(mod_path)
+    }
+
+    /// Finds the installed mod the user means by `name`.
+    ///
+    /// Mods are keyed by the path they were installed from, but nobody wants
+    /// to type that out again. So try, in order: that path, the mod's alias,
+    /// its name (file name sans extension, ignoring case), and finally any
+    /// part of its path (ignoring case). The first that matches exactly one mod wins.
+    pub fn find_mod(&self, name: &Path) -> Result<PathBuf> {
+        if self.mods.contains_key(name) {
+            return Ok(name.to_owned());
+        }
+        let name_str = name.to_string_lossy();
+        let lower = name_str.to_lowercase();
+
+        let by_alias = |_: &Path, manifest: &ModManifest| {
+            manifest
+                .alias
+                .as_deref()
+                .is_some_and(|a| a.eq_ignore_ascii_case(&name_str))
+        };
+        let by_name = |path: &Path, _: &ModManifest| mod_name(path).to_lowercase() == lower;
+        let by_substring =
+            |path: &Path, _: &ModManifest| path.to_string_lossy().to_lowercase().contains(&lower);
+        type Matcher<'a> = &'a dyn Fn(&Path, &ModManifest) -> bool;
+        let matchers: [Matcher; 3] = [&by_alias, &by_name, &by_substring];
+
+        for matches in &matchers {
+            let found: Vec<&PathBuf> = self
+                .mods
+                .iter()
+                .filter(|(path, manifest)| matches(path, manifest))
+                .map(|(path, _)| path)
+                .collect();
+            match found.as_slice() {
+                [] => continue,
+                [one] => {
+                    debug!("{} is {}", name_str, one.display());
+                    return Ok((*one).clone());
+                }
+                several => {
+                    let mut candidates = String::new();
+                    for c in several {
+                        candidates += &format!("\n\t{}", c.display());
+                    }
+                    fail!(
+                        Failure::UnknownMod,
+                        "{} could mean any of these mods:{}\nUse more of its path (or its alias).",
+                        name_str,
+                        candidates
+                    );
+                }
+            }
+        }
+        fail!(Failure::UnknownMod, "{} hasn't been added.", name.display())
+    }
+
+    /// Returns the mods that installed the given file, first to last,
+    /// if more than one did.
+    pub fn layers_of(&self, file: &Path) -> Option<&Vec<PathBuf>> {
+        self.layers.get(&path_key(file, self.case_insensitive))
+    }
+
+    /// Returns true if another mod's file is installed over the given mod's,
+    /// so that the game directory has that one instead.
+    pub fn is_covered(&self, mod_path: &Path, file: &Path) -> bool {
+        self.layers_of(file)
+            .is_some_and(|layers| layers.last().is_some_and(|top| top != mod_path))
+    }
+
+    /// Returns the given mod's metadata for the file, matching paths
+    /// the way the game directory does.
+    pub fn file_meta(&self, mod_path: &Path, file: &Path) -> Option<&ModFileMetadata> {
+        let files = &self.mods.get(mod_path)?.files;
+        if let Some(meta) = files.get(file) {
+            return Some(meta);
+        }
+        if !self.case_insensitive {
+            return None;
+        }
+        let key = fold_case(file);
+        files
+            .iter()
+            .find(|(f, _)| fold_case(f) == key)
+            .map(|(_, meta)| meta)
+    }
+
+    /// Indexes every installed file by its path_key(),
+    /// so that checking many files doesn't search every mod for each.
+    pub fn installed_by_key(&self) -> InstalledFiles<'_> {
+        let mut index: BTreeMap<PathBuf, Vec<_>> = BTreeMap::new();
+        for (mod_path, manifest) in &self.mods {
+            for (file, meta) in &manifest.files {
+                index
+                    .entry(path_key(file, self.case_insensitive))
+                    .or_default()
+                    .push((mod_path, file, meta));
+            }
+        }
+        index
+    }
+
+    /// Returns true if some covered file has the given contents,
+    /// so its copy in covered/ is still needed.
+    pub fn needs_covered(&self, hash: &FileHash) -> bool {
+        self.layers.iter().any(|(file, layers)| {
+            layers[..layers.len() - 1]
+                .iter()
+                .filter_map(|m| self.file_meta(m, file))
+                .any(|meta| meta.mod_hash == *hash)
+        })
+    }
+}
+
+/// Every installed file by its path_key(), with the mods that installed it
+/// (in the order of `mods`), what they call it, and their metadata for it
+pub type InstalledFiles<'a> =
+    BTreeMap<PathBuf, Vec<(&'a PathBuf, &'a PathBuf, &'a ModFileMetadata)>>;
+
+/// Makes a mod name the user gave relative to base, like other paths,
+/// unless it's not a path to anything (and so is probably a short name
+/// for `Profile::find_mod`).
+pub fn resolve_mod_name(base: &Path, name: &mut PathBuf) {
+    let resolved = base.join(&name);
+    if resolved.exists() {
+        *name = resolved;
+    }
+}
+
+/// The name of the root directory when mods choose where to install.
+pub static DEFAULT_ROOT_NAME: &str = "game";
+
+/// Where a profile's mod files go: its root directory and any named roots.
+///
+/// Mod files installed to a named root are recorded as `@<name>/<path>`,
+/// so that they don't collide with (or get backed up over)
+/// files with the same path in other roots.
+pub struct GameDirs<'a> {
+    pub root_directory: &'a Path,
+    pub roots: &'a BTreeMap<String, PathBuf>,
+    pub case_insensitive: bool,
+}
+
+impl GameDirs<'_> {
+    /// Given a recorded mod file path, returns where it is.
+    pub fn game_path(&self, mod_path: &Path) -> PathBuf {
+        let (root, path) = self.split(mod_path);
+        mod_path_to_game_path(path, &root, self.case_insensitive)
+    }
+
+    /// Returns the directory the given mod file path is installed to.
+    pub fn root_of(&self, mod_path: &Path) -> PathBuf {
+        self.split(mod_path).0
+    }
+
+    fn split<'p>(&self, mod_path: &'p Path) -> (PathBuf, &'p Path) {
+        let (root, path) = match split_root(mod_path) {
+            (Some(name), path) if self.roots.contains_key(name) => {
+                (self.roots[name].as_path(), path)
+            }
+            _ => (self.root_directory, mod_path),
+        };
+        (in_profile_dir(root), path)
+    }
+}
+
+/// Returns how to record a file with the given path in the given root.
+pub fn in_root(root_name: &str, path: &Path) -> PathBuf {
+    if root_name == DEFAULT_ROOT_NAME {
+        path.to_owned()
+    } else {
+        Path::new(&format!("@{}", root_name)).join(path)
+    }
+}
+
+/// Splits a recorded mod file path into the name of the root it's in
+/// (unless it's the root directory) and its path in that root.
+pub fn split_root(mod_path: &Path) -> (Option<&str>, &Path) {
+    let mut components = mod_path.components();
+    if let Some(Component::Normal(first)) = components.next() {
+        if let Some(name) = first.to_str().and_then(|f| f.strip_prefix('@')) {
+            return (Some(name), components.as_path());
+        }
+    }
+    (None, mod_path)
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+/// The Unicode normalization form we convert mod file paths to.
+///
+/// Archives made on macOS tend to use NFD, while Windows and Linux
+/// almost always expect NFC. Without normalizing,
+/// the "same" path can show up as two different files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnicodeForm {
+    #[default]
+    Nfc,
+    Nfd,
+    /// Leave paths exactly as the mod has them.
+    None,
+}
+
+impl UnicodeForm {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Normalizes the given path.
+    /// (Non-UTF-8 paths are left as-is.)
+    pub fn normalize(self, p: &Path) -> PathBuf {
+        let s = match (self, p.to_str()) {
+            (UnicodeForm::None, _) | (_, None) => return p.to_owned(),
+            (_, Some(s)) => s,
+        };
+        match self {
+            UnicodeForm::Nfc => PathBuf::from(s.nfc().collect::<String>()),
+            UnicodeForm::Nfd => PathBuf::from(s.nfd().collect::<String>()),
+            UnicodeForm::None => unreachable!(),
+        }
+    }
+}
+
+impl std::str::FromStr for UnicodeForm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match &*s.to_lowercase() {
+            "nfc" => Ok(UnicodeForm::Nfc),
+            "nfd" => Ok(UnicodeForm::Nfd),
+            "none" => Ok(UnicodeForm::None),
+            _ => bail!(
+                "Unknown Unicode normalization form {} (expected nfc, nfd, or none)",
+                s
+            ),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ModManifest {
     #[serde(
@@ -47,6 +451,56 @@ pub struct ModManifest {
         deserialize_with = "deserialize_version"
     )]
     pub version: Version,
+    /// The mod's VERSION.txt, if it isn't how `version` prints
+    /// (e.g., `v1.2` instead of `1.2.0`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version_string: Option<String>,
+    /// The URL the mod was downloaded from, if it was
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// The hash of the archive the mod was installed from, if it's in one,
+    /// to tell if it's been replaced since (see `archive_changed()`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_hash: Option<FileHash>,
+    /// The archive's size and modification time when it was hashed.
+    /// If they're the same, we trust it's the same archive without hashing it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_size: Option<u64>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_mtime",
+        deserialize_with = "deserialize_mtime"
+    )]
+    pub archive_mtime: Option<SystemTime>,
+    /// From the mod's mod.toml, so we can check them against other mods later.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub requires: Vec<ModSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conflicts: Vec<ModSpec>,
+    /// The mod's own hooks, from its mod.toml.
+    /// (We keep them so they can run when it's removed.)
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    pub hooks: Hooks,
+    /// Set if the mod's files got the modification times they have in the mod
+    /// (so `modman update` can do the same when it reinstalls them).
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub keep_mtimes: bool,
+    /// The profile root the mod was installed to with `--root-name`,
+    /// overriding its mod.toml
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root: Option<String>,
+    /// A short name to refer to the mod by, from `modman add --alias`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    /// Tags grouping the mod with others, from its mod.toml,
+    /// `modman add --tag`, and `modman tag`
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+    /// Directories installing the mod created (with paths like its files'),
+    /// so they can be removed along with it.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub directories: BTreeSet<PathBuf>,
     pub files: BTreeMap<PathBuf, ModFileMetadata>,
 }
 
@@ -54,33 +508,77 @@ pub struct ModManifest {
 pub struct ModFileMetadata {
     pub mod_hash: FileHash,
     pub original_hash: Option<FileHash>,
+    /// Set if the mod file was merged into the game file
+    /// instead of replacing it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merge: Option<MergeMode>,
+    /// Set if the mod file was installed as a symbolic link to this path.
+    /// (mod_hash is then the hash of the path.)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symlink: Option<PathBuf>,
+    /// The mod file's Unix permissions, if it was executable
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_mode",
+        deserialize_with = "deserialize_mode"
+    )]
+    pub mode: Option<u32>,
+    /// The replaced game file's Unix permissions, if it was executable
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_mode",
+        deserialize_with = "deserialize_mode"
+    )]
+    pub original_mode: Option<u32>,
+    /// The mod file installed here, if it isn't the one with this path.
+    /// (Set when a game update moved the file it replaced,
+    /// and `modman update` moved the mod file along with it.)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mod_file: Option<PathBuf>,
+    /// How many bytes the installed file takes up
+    /// (None for files installed before we kept track)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// How many bytes the replaced game file's backup takes up
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_size: Option<u64>,
+    /// When the replaced game file's backup was last modified, as of when
+    /// it was made. If it still is (and is still the same size),
+    /// `modman check` can trust it's unchanged without hashing it
+    /// (see `modman quick-check`).
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_mtime",
+        deserialize_with = "deserialize_mtime"
+    )]
+    pub backup_mtime: Option<SystemTime>,
+    /// Set if another mod installed this file first, with the same contents.
+    /// That mod owns it (and its backup) until it's removed;
+    /// then it's handed off to a mod still sharing it.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub shared: bool,
+    /// Set if the mod's file was identical to the game's,
+    /// so it was left alone instead of being backed up and installed
+    /// (see `modman add --skip-identical`). Removing the mod leaves it too.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub identical: bool,
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
-pub struct Meta {
-    // I suck as a developer if it takes over 255 tries to get the correct
-    // on-disk format.
-    pub version: u8,
-}
-
-// Always default to the latest version number
-impl Default for Meta {
-    fn default() -> Self {
-        Meta { version: 1 }
+impl ModFileMetadata {
+    /// Returns the path of the mod file installed at the given game path.
+    pub fn mod_file<'a>(&'a self, game_path: &'a Path) -> &'a Path {
+        self.mod_file.as_deref().unwrap_or(game_path)
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct ProfileFileData {
-    pub profile: Profile,
-    pub meta: Meta,
-}
-
 pub fn create_new_profile_file(p: &Profile) -> Result<()> {
     let mut f = fs::OpenOptions::new()
         .write(true)
         .create_new(true)
-        .open(PROFILE_PATH)
+        .open(profile_path())
         .map_err(|e| {
             if e.kind() == std::io::ErrorKind::AlreadyExists {
                 format_err!("A profile already exists.")
@@ -93,36 +591,196 @@ pub fn create_new_profile_file(p: &Profile) -> Result<()> {
     Ok(())
 }
 
+/// Loads the profile (in whatever format it's stored)
+/// and makes sure it looks sane.
 pub fn load_and_check_profile() -> Result<Profile> {
+    open_and_check_profile().map(|(_store, p)| p)
+}
+
+/// Like load_and_check_profile(), but also hands back the store
+/// so that changes can be saved.
+pub fn open_and_check_profile() -> Result<(Box<dyn ProfileStore>, Profile)> {
+    let (store, p) = open_profile()?;
+    sanity_check_profile(&p)?;
+    Ok((store, p))
+}
+
+/// Loads the profile and hands back its store, without any sanity checks.
+/// Only useful for fixing profiles that fail them.
+pub fn open_profile() -> Result<(Box<dyn ProfileStore>, Profile)> {
     info!("Loading profile...");
-    let f = fs::File::open(PROFILE_PATH)
+    let mut store = open_profile_store()?;
+    let p = store.load()?;
+    set_storage_dir(p.storage_directory.as_deref());
+    Ok((store, p))
+}
+
+/// Finds the profile's storage directory (see set_storage_dir()),
+/// for things that need it before we load the profile.
+/// If there's no profile (or it can't be read), that's the default.
+pub fn locate_storage() {
+    let settings = open_profile_store().and_then(|mut s| s.load_settings());
+    match settings {
+        Ok(p) => set_storage_dir(p.storage_directory.as_deref()),
+        Err(e) => debug!("Using the default storage directory: {:#}", e),
+    }
+}
+
+/// Whether to keep the JSON profile in memory between operations
+/// (see cache_profile())
+static CACHING_PROFILE: AtomicBool = AtomicBool::new(false);
+
+/// The profile we last read or wrote, when caching it
+static CACHED_PROFILE: Mutex<Option<CachedProfile>> = Mutex::new(None);
+
+struct CachedProfile {
+    path: PathBuf,
+    /// The file's modification time and size when we read or wrote it
+    stamp: (SystemTime, u64),
+    profile: Profile,
+}
+
+/// Keeps the JSON profile in memory and only re-reads it when the file
+/// changes (by its modification time and size), for long-running processes
+/// like `modman serve`.
+pub fn cache_profile() {
+    CACHING_PROFILE.store(true, Ordering::Relaxed);
+}
+
+fn profile_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+fn remember_profile(path: PathBuf, stamp: Option<(SystemTime, u64)>, p: &Profile) {
+    let mut cached = CACHED_PROFILE.lock().unwrap();
+    *cached = stamp.map(|stamp| CachedProfile {
+        path,
+        stamp,
+        profile: p.clone(),
+    });
+}
+
+/// Reads and parses the JSON profile file.
+pub fn read_profile_file() -> Result<Profile> {
+    let path = profile_path();
+    // Look before we read, so that if the file changes in between,
+    // we'll read it again next time.
+    let caching = CACHING_PROFILE.load(Ordering::Relaxed);
+    let stamp = if caching { profile_stamp(&path) } else { None };
+    if let (Some(stamp), Some(cached)) = (stamp, &*CACHED_PROFILE.lock().unwrap()) {
+        if cached.path == path && cached.stamp == stamp {
+            debug!("Profile file hasn't changed, using the one we have");
+            return Ok(cached.profile.clone());
+        }
+    }
+
+    let f = fs::File::open(&path)
         .with_context(|| format!("Couldn't open profile file ({})", PROFILE_PATH))?;
 
-    let p: Profile =
-        serde_json::from_reader(BufReader::new(f)).context("Couldn't parse profile file")?;
-    sanity_check_profile(&p)?;
+    let p: Profile = serde_json::from_reader(BufReader::new(f)).map_err(|e| {
+        failure(
+            Failure::BadProfile,
+            format!(
+                "Couldn't parse profile file ({}): {}\n\
+                 Run `modman profile recover` to salvage what you can from it.",
+                PROFILE_PATH, e
+            ),
+        )
+    })?;
+    if caching {
+        remember_profile(path, stamp, &p);
+    }
     Ok(p)
 }
 
+/// Reads and parses a JSON profile file at the given path.
+pub fn read_profile_from(path: &Path) -> Result<Profile> {
+    let f = fs::File::open(path)
+        .with_context(|| format!("Couldn't open profile file ({})", path.display()))?;
+
+    serde_json::from_reader(BufReader::new(f))
+        .with_context(|| format!("Couldn't parse profile file ({})", path.display()))
+}
+
 fn sanity_check_profile(profile: &Profile) -> Result<()> {
-    if !profile.root_directory.exists() {
-        bail!(
+    if !in_profile_dir(&profile.root_directory).exists() {
+        fail!(
+            Failure::BadProfile,
             "The root directory {} doesn't exist!\n\
-             Has it moved since you ran `modman init`?",
+             Has it moved since you ran `modman init`?\n\
+             If so, run `modman relocate-root <NEW_DIR>`.",
             profile.root_directory.display()
         );
     }
+    for (name, dir) in &profile.roots {
+        if !in_profile_dir(dir).exists() {
+            fail!(
+                Failure::BadProfile,
+                "The {} root ({}) doesn't exist!\n\
+                 If it's moved, run `modman roots add {} <NEW_DIR>`.",
+                name,
+                dir.display(),
+                name
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Where the given generation of the profile file is kept
+/// (1 is the newest).
+pub fn profile_generation_path(generation: usize) -> PathBuf {
+    in_profile_dir(format!("{}.{}", PROFILE_PATH, generation))
+}
 
+/// Whether the current operation has kept the profile file it started with
+static GENERATION_KEPT: AtomicBool = AtomicBool::new(false);
+
+/// Starts a new operation, whose first change to the profile file
+/// keeps the one it replaces. Each run of modman is one operation,
+/// but long-running ones (`modman serve`, the C API) run many.
+pub fn new_profile_generation() {
+    GENERATION_KEPT.store(false, Ordering::Relaxed);
+}
+
+/// The first time we change the profile file each operation, keeps the one
+/// we're replacing as generation 1 (moving older ones back a generation).
+/// Commands often save the profile several times as they go,
+/// so keeping every one would leave only the last command or two.
+fn keep_profile_generation() -> Result<()> {
+    if GENERATION_KEPT.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    if !profile_path().exists() {
+        GENERATION_KEPT.store(true, Ordering::Relaxed);
+        return Ok(());
+    }
+    for generation in (1..PROFILE_GENERATIONS).rev() {
+        let from = profile_generation_path(generation);
+        if from.exists() {
+            rename_file(&from, &profile_generation_path(generation + 1))
+                .with_context(|| format!("Couldn't move back {}", from.display()))?;
+        }
+    }
+    let newest = profile_generation_path(1);
+    trace!("Keeping the current profile as {}", newest.display());
+    fs::copy(profile_path(), &newest)
+        .with_context(|| format!("Couldn't copy {} to {}", PROFILE_PATH, newest.display()))?;
+    GENERATION_KEPT.store(true, Ordering::Relaxed);
     Ok(())
 }
 
 pub fn update_profile_file(p: &Profile) -> Result<()> {
+    check_writable()?;
     debug!("Updating profile file...");
+    keep_profile_generation()?;
     // Let's write an update profile file in a few steps to minimize the chance
     // of corruption:
 
     // 1. Write to a temporary file, adjacent to the real deal.
-    let mut temp_filename = std::ffi::OsString::from(PROFILE_PATH);
+    let mut temp_filename = profile_path().into_os_string();
     temp_filename.push(".new");
     let temp_filename = Path::new(&temp_filename);
 
@@ -130,7 +788,7 @@ pub fn update_profile_file(p: &Profile) -> Result<()> {
         "Writing updated profile to temp file {}",
         temp_filename.display()
     );
-    let temp_file = fs::File::create(&temp_filename)
+    let temp_file = fs::File::create(temp_filename)
         .with_context(|| format!("Couldn't create temp file {}", temp_filename.display()))?;
     let mut temp_file = BufWriter::new(temp_file);
     serde_json::to_writer_pretty(&mut temp_file, p)?;
@@ -141,43 +799,150 @@ pub fn update_profile_file(p: &Profile) -> Result<()> {
         .into_inner()?
         .sync_data()
         .with_context(|| format!("Couldn't sync {}", temp_filename.display()))?;
+    // (Renaming it keeps its modification time and size.)
+    let caching = CACHING_PROFILE.load(Ordering::Relaxed);
+    let stamp = if caching {
+        profile_stamp(temp_filename)
+    } else {
+        None
+    };
 
     // 3. Rename it to the real deal.
     trace!("Renaming updated profile to {}", PROFILE_PATH);
-    fs::rename(&temp_filename, PROFILE_PATH).with_context(|| {
+    replace_file(temp_filename, &profile_path()).with_context(|| {
         format!(
             "Couldn't rename {} to {}.",
             temp_filename.display(),
             PROFILE_PATH
         )
     })?;
+    if caching {
+        remember_profile(profile_path(), stamp, p);
+    }
 
     Ok(())
 }
 
-pub fn print_profile(p: &Profile) -> Result<()> {
-    serde_json::ser::to_writer_pretty(std::io::stdout().lock(), &p)
-        .context("Couldn't serialize profile to JSON")?;
-    println!();
-    Ok(())
+/// Returns the directory for the named profile in the user's data directory,
+/// e.g., `~/.local/share/modman/<name>/` or `%APPDATA%\modman\<name>\`.
+pub fn user_profile_dir(name: &str) -> Result<PathBuf> {
+    if name.is_empty() || Path::new(name).components().count() != 1 || name == ".." {
+        bail!("{} isn't a valid profile name", name);
+    }
+    let data_dir =
+        dirs::data_dir().ok_or_else(|| format_err!("Couldn't find your user data directory"))?;
+    Ok(data_dir.join("modman").join(name))
 }
 
 /// Given a relative mod file path,
 /// return its game file path, i.e., it appended to the profile's root directory.
-pub fn mod_path_to_game_path(mod_path: &Path, root_directory: &Path) -> PathBuf {
-    root_directory.join(mod_path)
+///
+/// If the game directory is case-insensitive, reuse the casing of any
+/// existing files and directories along the way, so that we find them
+/// even if we're running on a case-sensitive filesystem (e.g., Wine).
+pub fn mod_path_to_game_path(
+    mod_path: &Path,
+    root_directory: &Path,
+    case_insensitive: bool,
+) -> PathBuf {
+    if !case_insensitive {
+        return root_directory.join(mod_path);
+    }
+
+    let mut resolved = root_directory.to_owned();
+    let mut components = mod_path.components();
+    for component in &mut components {
+        let exact = resolved.join(component);
+        if exact.exists() {
+            resolved = exact;
+            continue;
+        }
+        match find_entry_ignoring_case(&resolved, component.as_os_str()) {
+            Some(existing) => resolved.push(existing),
+            // Nothing's there; the rest of the path is new.
+            None => {
+                resolved.push(component);
+                break;
+            }
+        }
+    }
+    let rest = components.as_path();
+    if !rest.as_os_str().is_empty() {
+        resolved.push(rest);
+    }
+    resolved
+}
+
+/// Looks in the given directory for an entry with the given name,
+/// ignoring case.
+fn find_entry_ignoring_case(dir: &Path, name: &std::ffi::OsStr) -> Option<std::ffi::OsString> {
+    let folded = fold_case(Path::new(name));
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name())
+        .find(|entry_name| fold_case(Path::new(entry_name)) == folded)
+}
+
+/// Folds the case of the given path so that paths can be compared
+/// case-insensitively.
+///
+/// (Non-UTF-8 paths are left as-is.)
+pub fn fold_case(p: &Path) -> PathBuf {
+    match p.to_str() {
+        Some(s) => PathBuf::from(s.to_lowercase()),
+        None => p.to_owned(),
+    }
+}
+
+/// Returns the path as-is, or case-folded if case_insensitive is set.
+/// Useful for comparing paths the way the game directory does.
+pub fn path_key(p: &Path, case_insensitive: bool) -> PathBuf {
+    if case_insensitive {
+        fold_case(p)
+    } else {
+        p.to_owned()
+    }
 }
 
 /// Given a relative mod file path,
 /// return its backup path, i.e., it appended to our backup directory.
 pub fn mod_path_to_backup_path(mod_path: &Path) -> PathBuf {
-    Path::new(BACKUP_PATH).join(mod_path)
+    backup_dir().join(mod_path)
 }
 
 /// Given a relative mod file path,
 /// return its temporary path, i.e.,
-/// its file name appended to our temp directory,
+/// it appended to a directory for this run in our temp directory
+/// (so that another modman running at the same time can't write it too),
 /// with a `.part` suffix.
+///
+/// (The suffix keeps a mod file from ever landing on the journal,
+/// which is in the temp directory too.)
 pub fn mod_path_to_temp_path(mod_path: &Path) -> PathBuf {
-    Path::new(TEMPDIR_PATH).join(mod_path)
+    let mut temp_path = tempdir().join(run_id()).join(mod_path).into_os_string();
+    temp_path.push(".part");
+    PathBuf::from(temp_path)
+}
+
+/// Makes sure the temp directory and backup directory exist
+/// and are on the same filesystem, since backups are made by copying
+/// game files to the former, then renaming them into the latter.
+/// (A rename is as close as we get to atomically writing a file.)
+pub fn check_backup_dirs() -> Result<()> {
+    let temp = tempdir();
+    let backups = backup_dir();
+    for dir in &[&temp, &backups] {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Couldn't create directory {}", dir.display()))?;
+    }
+    if !same_filesystem(&temp, &backups)? {
+        bail!(
+            "{}/ and {}/ are on different filesystems, so backups can't be \
+             moved from one to the other safely. Put them back on the same one.",
+            temp.display(),
+            backups.display()
+        );
+    }
+    Ok(())
 }
@@ -0,0 +1,45 @@
+use std::path::Path;
+
+use anyhow::*;
+use structopt::*;
+
+use crate::{recover, rollback};
+
+/// Salvages or rolls back the profile itself
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    #[structopt(subcommand)]
+    action: Action,
+}
+
+#[derive(Debug, StructOpt)]
+enum Action {
+    Recover(recover::Args),
+    Rollback(rollback::Args),
+}
+
+impl Args {
+    /// For --no-write: makes the command a dry run if it has one.
+    /// Returns false if it would write something regardless.
+    pub fn make_dry_run(&mut self) -> bool {
+        match &mut self.action {
+            Action::Recover(r) => r.make_dry_run(),
+            Action::Rollback(r) => return !r.is_changing(),
+        }
+        true
+    }
+
+    pub fn resolve_paths(&mut self, base: &Path) {
+        match &mut self.action {
+            Action::Recover(r) => r.resolve_paths(base),
+            Action::Rollback(_) => (),
+        }
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    match args.action {
+        Action::Recover(r) => recover::run(r),
+        Action::Rollback(r) => rollback::run(r),
+    }
+}
@@ -0,0 +1,428 @@
+//! Where (and how) we persist the profile.
+//!
+//! Most setups are fine with the pretty-printed JSON in `modman.profile`,
+//! but rewriting a multi-megabyte JSON file after every mod gets slow
+//! once thousands of files are installed.
+//! For those, we can keep the profile in a SQLite database instead,
+//! and only write what changed.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::*;
+use log::*;
+
+use crate::failure::*;
+use crate::profile::*;
+
+#[cfg(feature = "sqlite")]
+pub static PROFILE_DB_PATH: &str = "modman.profile.db";
+
+/// The formats we can store a profile in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Json,
+    Sqlite,
+}
+
+impl std::str::FromStr for Backend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match &*s.to_lowercase() {
+            "json" => Ok(Backend::Json),
+            "sqlite" => Ok(Backend::Sqlite),
+            _ => bail!("Unknown profile format {} (expected json or sqlite)", s),
+        }
+    }
+}
+
+/// Somewhere we can load a profile from and save changes to.
+///
+/// Changes are made a mod at a time so that backends that can
+/// write incrementally don't have to rewrite the whole profile.
+/// (It's Send so `modman update` can save each mod from whichever thread
+/// finishes it.)
+pub trait ProfileStore: Send {
+    /// Loads the whole profile.
+    fn load(&mut self) -> Result<Profile>;
+
+    /// Loads everything besides the mods (which might be left out).
+    fn load_settings(&mut self) -> Result<Profile> {
+        self.load()
+    }
+
+    /// Records a newly-added (or updated) mod.
+    fn save_mod(&mut self, mod_path: &Path, manifest: &ModManifest) -> Result<()>;
+
+    /// Forgets a removed mod.
+    fn remove_mod(&mut self, mod_path: &Path) -> Result<()>;
+
+    /// Records changes to everything besides the mods (root directory, etc.)
+    fn save_settings(&mut self, p: &Profile) -> Result<()>;
+
+    /// Holds onto changes until flush(), when we're making lots of them at once.
+    fn defer_writes(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Writes any changes held since defer_writes().
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Finds the backend of the current directory's profile,
+/// or None if there isn't one.
+pub fn existing_backend() -> Option<Backend> {
+    #[cfg(feature = "sqlite")]
+    {
+        if in_profile_dir(PROFILE_DB_PATH).exists() {
+            return Some(Backend::Sqlite);
+        }
+    }
+    if profile_path().exists() {
+        Some(Backend::Json)
+    } else {
+        None
+    }
+}
+
+/// Opens the current directory's profile, whatever format it's in.
+pub fn open_profile_store() -> Result<Box<dyn ProfileStore>> {
+    match existing_backend() {
+        Some(backend) => open_backend(backend),
+        None => fail!(
+            Failure::NoProfile,
+            "There's no profile ({}) here. Run `modman init` to make one.",
+            PROFILE_PATH
+        ),
+    }
+}
+
+fn open_backend(backend: Backend) -> Result<Box<dyn ProfileStore>> {
+    match backend {
+        Backend::Json => Ok(Box::new(JsonProfileStore::open()?)),
+        #[cfg(feature = "sqlite")]
+        Backend::Sqlite => Ok(Box::new(sqlite::SqliteProfileStore::open()?)),
+        #[cfg(not(feature = "sqlite"))]
+        Backend::Sqlite => bail!("modman was built without SQLite support"),
+    }
+}
+
+/// Creates a new profile in the given format.
+/// Fails if a profile (in any format) already exists.
+pub fn create_profile_store(p: &Profile, backend: Backend) -> Result<()> {
+    if existing_backend().is_some() {
+        bail!("A profile already exists.");
+    }
+    match backend {
+        Backend::Json => create_new_profile_file(p),
+        #[cfg(feature = "sqlite")]
+        Backend::Sqlite => sqlite::SqliteProfileStore::create(p),
+        #[cfg(not(feature = "sqlite"))]
+        Backend::Sqlite => bail!("modman was built without SQLite support"),
+    }
+}
+
+/// Returns the path of the file that holds a profile in the given format.
+pub fn backend_path(backend: Backend) -> &'static str {
+    match backend {
+        Backend::Json => PROFILE_PATH,
+        #[cfg(feature = "sqlite")]
+        Backend::Sqlite => PROFILE_DB_PATH,
+        #[cfg(not(feature = "sqlite"))]
+        Backend::Sqlite => "modman.profile.db",
+    }
+}
+
+/// Deletes the profile in the given format.
+pub fn delete_profile_store(backend: Backend) -> Result<()> {
+    let path = backend_path(backend);
+    fs::remove_file(path).with_context(|| format!("Couldn't remove {}", path))
+}
+
+/// The classic: the whole profile as pretty-printed JSON.
+/// Every change rewrites the file (unless they're deferred).
+struct JsonProfileStore {
+    profile: Profile,
+    deferred: bool,
+}
+
+impl JsonProfileStore {
+    fn open() -> Result<Self> {
+        Ok(Self {
+            profile: read_profile_file()?,
+            deferred: false,
+        })
+    }
+
+    fn write(&self) -> Result<()> {
+        if self.deferred {
+            return Ok(());
+        }
+        update_profile_file(&self.profile)
+    }
+}
+
+impl ProfileStore for JsonProfileStore {
+    fn load(&mut self) -> Result<Profile> {
+        Ok(self.profile.clone())
+    }
+
+    fn save_mod(&mut self, mod_path: &Path, manifest: &ModManifest) -> Result<()> {
+        self.profile
+            .mods
+            .insert(mod_path.to_owned(), manifest.clone());
+        self.write()
+    }
+
+    fn remove_mod(&mut self, mod_path: &Path) -> Result<()> {
+        self.profile.mods.remove(mod_path);
+        self.write()
+    }
+
+    fn save_settings(&mut self, p: &Profile) -> Result<()> {
+        let mods = std::mem::take(&mut self.profile.mods);
+        self.profile = Profile { mods, ..p.clone() };
+        self.write()
+    }
+
+    fn defer_writes(&mut self) -> Result<()> {
+        self.deferred = true;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if !self.deferred {
+            return Ok(());
+        }
+        self.deferred = false;
+        self.write()
+    }
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::*;
+
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+
+    use crate::encoding::*;
+    use crate::file_utils::no_write;
+
+    /// Keeps the profile in a SQLite database.
+    ///
+    /// To keep up with new fields in the profile without schema migrations,
+    /// each row holds JSON of everything besides its children:
+    /// the profile's settings, each mod's manifest (sans files),
+    /// and each file's metadata.
+    /// Paths are encoded with encode_path() so they round-trip
+    /// even when they aren't valid Unicode.
+    pub struct SqliteProfileStore {
+        conn: Connection,
+    }
+
+    static SCHEMA: &str = r#"
+        CREATE TABLE settings (
+            id INTEGER PRIMARY KEY CHECK (id = 0),
+            json TEXT NOT NULL
+        );
+        CREATE TABLE mods (
+            path TEXT PRIMARY KEY,
+            json TEXT NOT NULL
+        );
+        CREATE TABLE files (
+            mod_path TEXT NOT NULL REFERENCES mods(path) ON DELETE CASCADE,
+            path TEXT NOT NULL,
+            json TEXT NOT NULL,
+            PRIMARY KEY (mod_path, path)
+        );
+    "#;
+
+    impl SqliteProfileStore {
+        pub fn open() -> Result<Self> {
+            // With --no-write, SQLite itself will refuse any changes.
+            let access = if no_write() {
+                OpenFlags::SQLITE_OPEN_READ_ONLY
+            } else {
+                OpenFlags::SQLITE_OPEN_READ_WRITE
+            };
+            let conn = Connection::open_with_flags(
+                in_profile_dir(PROFILE_DB_PATH),
+                access | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )
+            .with_context(|| format!("Couldn't open profile database ({})", PROFILE_DB_PATH))?;
+            conn.pragma_update(None, "foreign_keys", true)?;
+            Ok(Self { conn })
+        }
+
+        pub fn create(p: &Profile) -> Result<()> {
+            let conn = Connection::open(in_profile_dir(PROFILE_DB_PATH)).with_context(|| {
+                format!("Couldn't create profile database ({})", PROFILE_DB_PATH)
+            })?;
+            conn.execute_batch(SCHEMA)
+                .context("Couldn't set up profile database")?;
+            drop(conn);
+
+            let mut store = Self::open()?;
+            store.save_settings(p)?;
+            for (mod_path, manifest) in &p.mods {
+                store.save_mod(mod_path, manifest)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// The profile without its mods, which have their own table.
+    fn settings_json(p: &Profile) -> Result<String> {
+        let settings = Profile {
+            mods: BTreeMap::new(),
+            ..p.clone()
+        };
+        Ok(serde_json::to_string(&settings)?)
+    }
+
+    /// The manifest without its files, which have their own table.
+    fn manifest_json(m: &ModManifest) -> Result<String> {
+        let header = ModManifest {
+            files: BTreeMap::new(),
+            ..m.clone()
+        };
+        Ok(serde_json::to_string(&header)?)
+    }
+
+    impl ProfileStore for SqliteProfileStore {
+        fn load(&mut self) -> Result<Profile> {
+            let mut p = self.load_settings()?;
+
+            let mut mods = self.conn.prepare("SELECT path, json FROM mods")?;
+            let mut rows = mods.query([])?;
+            while let Some(row) = rows.next()? {
+                let path: String = row.get(0)?;
+                let json: String = row.get(1)?;
+                let manifest: ModManifest = serde_json::from_str(&json)
+                    .with_context(|| format!("Couldn't parse the manifest for {}", path))?;
+                p.mods.insert(decode_path(&path)?, manifest);
+            }
+
+            let mut files = self
+                .conn
+                .prepare("SELECT mod_path, path, json FROM files")?;
+            let mut rows = files.query([])?;
+            while let Some(row) = rows.next()? {
+                let mod_path: String = row.get(0)?;
+                let path: String = row.get(1)?;
+                let json: String = row.get(2)?;
+                let meta: ModFileMetadata = serde_json::from_str(&json)
+                    .with_context(|| format!("Couldn't parse metadata for {}", path))?;
+                p.mods
+                    .get_mut(&decode_path(&mod_path)?)
+                    .ok_or_else(|| format_err!("{} belongs to unknown mod {}", path, mod_path))?
+                    .files
+                    .insert(decode_path(&path)?, meta);
+            }
+
+            Ok(p)
+        }
+
+        fn load_settings(&mut self) -> Result<Profile> {
+            let settings: Option<String> = self
+                .conn
+                .query_row("SELECT json FROM settings WHERE id = 0", [], |row| {
+                    row.get(0)
+                })
+                .optional()?;
+            let settings = settings.ok_or_else(|| {
+                format_err!("The profile database ({}) is empty", PROFILE_DB_PATH)
+            })?;
+            serde_json::from_str(&settings).context("Couldn't parse profile settings")
+        }
+
+        fn save_mod(&mut self, mod_path: &Path, manifest: &ModManifest) -> Result<()> {
+            debug!("Saving {} to the profile database...", mod_path.display());
+            let mod_key = encode_path(mod_path);
+            // (A savepoint instead of a transaction, in case writes are deferred.)
+            let tx = self.conn.savepoint()?;
+            tx.execute(
+                "INSERT INTO mods (path, json) VALUES (?1, ?2) \
+                 ON CONFLICT(path) DO UPDATE SET json = excluded.json",
+                params![mod_key, manifest_json(manifest)?],
+            )?;
+            {
+                let mut existing =
+                    tx.prepare("SELECT path, json FROM files WHERE mod_path = ?1")?;
+                let existing: BTreeMap<String, String> = existing
+                    .query_map(params![mod_key], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<rusqlite::Result<_>>()?;
+
+                let mut upsert = tx.prepare(
+                    "INSERT INTO files (mod_path, path, json) VALUES (?1, ?2, ?3) \
+                     ON CONFLICT(mod_path, path) DO UPDATE SET json = excluded.json",
+                )?;
+                let mut keep = BTreeSet::new();
+                for (file, meta) in &manifest.files {
+                    let file_key = encode_path(file);
+                    let json = serde_json::to_string(meta)?;
+                    // Only write what changed.
+                    if existing.get(&file_key) != Some(&json) {
+                        trace!("Saving {} to the profile database", file.display());
+                        upsert.execute(params![mod_key, file_key, json])?;
+                    }
+                    keep.insert(file_key);
+                }
+
+                let mut delete =
+                    tx.prepare("DELETE FROM files WHERE mod_path = ?1 AND path = ?2")?;
+                for stale in existing.keys().filter(|k| !keep.contains(*k)) {
+                    delete.execute(params![mod_key, stale])?;
+                }
+            }
+            tx.commit()
+                .context("Couldn't commit changes to the profile database")?;
+            Ok(())
+        }
+
+        fn remove_mod(&mut self, mod_path: &Path) -> Result<()> {
+            debug!(
+                "Removing {} from the profile database...",
+                mod_path.display()
+            );
+            self.conn
+                .execute(
+                    "DELETE FROM mods WHERE path = ?1",
+                    params![encode_path(mod_path)],
+                )
+                .context("Couldn't remove mod from the profile database")?;
+            Ok(())
+        }
+
+        fn save_settings(&mut self, p: &Profile) -> Result<()> {
+            self.conn
+                .execute(
+                    "INSERT INTO settings (id, json) VALUES (0, ?1) \
+                     ON CONFLICT(id) DO UPDATE SET json = excluded.json",
+                    params![settings_json(p)?],
+                )
+                .context("Couldn't save profile settings")?;
+            Ok(())
+        }
+
+        fn defer_writes(&mut self) -> Result<()> {
+            self.conn
+                .execute_batch("BEGIN")
+                .context("Couldn't start a profile database transaction")
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            if self.conn.is_autocommit() {
+                return Ok(());
+            }
+            self.conn
+                .execute_batch("COMMIT")
+                .context("Couldn't commit changes to the profile database")
+        }
+    }
+}
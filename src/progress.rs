@@ -0,0 +1,214 @@
+//! Machine-readable progress for GUIs and other wrappers (`--progress json`).
+//!
+//! Each event is a JSON object on its own line, written to stderr
+//! or wherever `--progress-to` says (e.g., /dev/fd/3):
+//!
+//! ```text
+//! {"event":"started","operation":"add","mod":"mod1.zip","files":4}
+//! {"event":"started","operation":"verify-files","files":12}
+//! {"event":"backed-up","file":"A.txt"}
+//! {"event":"installed","file":"A.txt"}
+//! {"event":"progress","done":1,"total":4,"percent":25}
+//! {"event":"warning","message":"..."}
+//! {"event":"finished","result":"ok","exit-code":0}
+//! ```
+//!
+//! Operations that work on every mod's files at once (like `check`) have no "mod".
+//!
+//! Like the history log, this is global state that commands report to
+//! as they go; it does nothing unless `--progress` was given.
+
+use std::fs;
+use std::io::{self, prelude::*};
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::*;
+use serde_derive::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FileEvent {
+    /// A game file was backed up before a mod file replaced it.
+    BackedUp,
+    /// A mod file was installed.
+    Installed,
+    /// A game file was restored from its backup.
+    Restored,
+    /// A mod file (that replaced nothing) was removed.
+    Removed,
+    /// A file was hashed and compared to what the profile expects.
+    Checked,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+enum Event<'a> {
+    Started {
+        operation: &'a str,
+        #[serde(rename = "mod", skip_serializing_if = "Option::is_none")]
+        mod_path: Option<&'a Path>,
+        files: usize,
+    },
+    Progress {
+        done: usize,
+        total: usize,
+        percent: usize,
+    },
+    Warning {
+        message: &'a str,
+    },
+    #[serde(rename_all = "kebab-case")]
+    Finished {
+        result: &'a str,
+        exit_code: i32,
+    },
+}
+
+/// File events are named by what happened, so they're tagged by hand.
+#[derive(Serialize)]
+struct FileLine<'a> {
+    event: FileEvent,
+    file: &'a Path,
+}
+
+struct Sink {
+    out: Box<dyn Write + Send>,
+    /// Files done of the current operation's total
+    done: usize,
+    total: usize,
+}
+
+static SINK: Mutex<Option<Sink>> = Mutex::new(None);
+
+/// Starts writing progress events to the given file, or stderr if there isn't one.
+pub fn begin(to: Option<&Path>) -> Result<()> {
+    let out: Box<dyn Write + Send> = match to {
+        Some(path) => Box::new(
+            fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .with_context(|| format!("Couldn't open {} for progress", path.display()))?,
+        ),
+        None => Box::new(io::stderr()),
+    };
+    begin_with(out);
+    Ok(())
+}
+
+/// Starts writing progress events to the given writer,
+/// one `write()` per event.
+pub fn begin_with(out: Box<dyn Write + Send>) {
+    *SINK.lock().unwrap() = Some(Sink {
+        out,
+        done: 0,
+        total: 0,
+    });
+}
+
+/// Stops writing progress events.
+pub fn end() {
+    *SINK.lock().unwrap() = None;
+}
+
+fn emit<E: serde::Serialize>(sink: &mut Sink, event: &E) {
+    let mut line = serde_json::to_string(event).expect("Couldn't serialize progress event");
+    line.push('\n');
+    // Progress is best-effort; a GUI that stopped listening shouldn't stop us.
+    let _ = sink.out.write(line.as_bytes());
+    let _ = sink.out.flush();
+}
+
+/// Notes that we're starting to work on a mod's files.
+pub fn start(operation: &str, mod_path: &Path, files: usize) {
+    start_with(operation, Some(mod_path), files);
+}
+
+/// Notes that we're starting to work on the files of all the mods at once.
+pub fn start_all(operation: &str, files: usize) {
+    start_with(operation, None, files);
+}
+
+fn start_with(operation: &str, mod_path: Option<&Path>, files: usize) {
+    if let Some(sink) = &mut *SINK.lock().unwrap() {
+        sink.done = 0;
+        sink.total = files;
+        emit(
+            sink,
+            &Event::Started {
+                operation,
+                mod_path,
+                files,
+            },
+        );
+    }
+}
+
+/// Notes something that happened to a file, which isn't done yet.
+pub fn file(event: FileEvent, file: &Path) {
+    if let Some(sink) = &mut *SINK.lock().unwrap() {
+        emit(sink, &FileLine { event, file });
+    }
+}
+
+/// Notes the last thing that happened to a file, which is done.
+pub fn file_done(event: FileEvent, file: &Path) {
+    if let Some(sink) = &mut *SINK.lock().unwrap() {
+        emit(sink, &FileLine { event, file });
+        sink.done += 1;
+        let (done, total) = (sink.done, sink.total.max(sink.done));
+        emit(
+            sink,
+            &Event::Progress {
+                done,
+                total,
+                percent: done * 100 / total,
+            },
+        );
+    }
+}
+
+pub fn warning(message: &str) {
+    if let Some(sink) = &mut *SINK.lock().unwrap() {
+        emit(sink, &Event::Warning { message });
+    }
+}
+
+/// Notes how the command ended.
+pub fn finish<T>(result: &Result<T>, exit_code: i32) {
+    if let Some(sink) = &mut *SINK.lock().unwrap() {
+        let message;
+        let result = match result {
+            Ok(_) => "ok",
+            Err(e) => {
+                message = format!("{:#}", e);
+                &message
+            }
+        };
+        emit(sink, &Event::Finished { result, exit_code });
+    }
+}
+
+/// Passes log messages on to another logger,
+/// and sends warnings and errors along as progress events.
+pub struct Logger<L> {
+    pub inner: L,
+}
+
+impl<L: log::Log> log::Log for Logger<L> {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if record.level() <= log::Level::Warn {
+            warning(&record.args().to_string());
+        }
+        self.inner.log(record)
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use log::*;
+use structopt::*;
+
+use crate::failure::*;
+use crate::file_utils::*;
+use crate::history;
+use crate::profile::*;
+use crate::update;
+
+/// Rebuilds missing or damaged backups from the game's original files
+///
+/// Run this after having the game put its original files back
+/// (e.g., with Steam's "Verify integrity of game files").
+/// Each backup that's missing or doesn't match the profile is copied again
+/// from the game file, if that matches what the profile says the original was.
+/// Mod files the game replaced with its originals are then listed
+/// (or with --reinstall, reinstalled like `modman update` would).
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    #[structopt(short = "n", long)]
+    dry_run: bool,
+
+    /// Reinstall mod files the game put its originals back over.
+    #[structopt(long)]
+    reinstall: bool,
+}
+
+impl Args {
+    /// Makes this a dry run, for `--no-write`.
+    pub fn make_dry_run(&mut self) {
+        self.dry_run = true;
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let p = load_and_check_profile()?;
+    let dry_run = args.dry_run;
+    if !dry_run {
+        check_backup_dirs()?;
+    }
+
+    // Files we still don't have a good backup of
+    let mut unrecoverable = String::new();
+    // Mods with files the game replaced with its originals
+    let mut to_reinstall = BTreeSet::new();
+
+    for (mod_path, manifest) in &p.mods {
+        for (file, meta) in &manifest.files {
+            let original_hash = match &meta.original_hash {
+                Some(h) => h,
+                None => continue,
+            };
+            let game_path = p.game_path(file);
+            let backup_path = mod_path_to_backup_path(file);
+            let backup_ok = backup_path.is_file() && hash_file(&backup_path)? == *original_hash;
+            let game_hash = if game_path.is_file() && !is_symlink(&game_path) {
+                Some(hash_file(&game_path)?)
+            } else {
+                None
+            };
+            let game_is_original = game_hash.as_ref() == Some(original_hash);
+
+            if !backup_ok {
+                if game_is_original {
+                    rebuild(file, &game_path, &backup_path, dry_run)?;
+                } else {
+                    unrecoverable += &format!(
+                        "\n\t{} ({} {})",
+                        backup_path.display(),
+                        game_path.display(),
+                        if game_hash.is_none() {
+                            "is missing"
+                        } else if game_hash.as_ref() == Some(&meta.mod_hash) {
+                            "still has the mod file"
+                        } else {
+                            "doesn't match the original either"
+                        }
+                    );
+                    continue;
+                }
+            }
+            if game_is_original {
+                debug!("{} needs to be reinstalled", file.display());
+                to_reinstall.insert(mod_path.clone());
+            }
+        }
+    }
+
+    if !to_reinstall.is_empty() {
+        if args.reinstall {
+            let mods: Vec<PathBuf> = to_reinstall.into_iter().collect();
+            update::update_mods(mods, true, false, dry_run)?;
+        } else {
+            let mut message =
+                "These mods have files the game put its originals back over:".to_owned();
+            for mod_path in &to_reinstall {
+                message += &format!("\n\t{}", mod_path.display());
+            }
+            message += "\nRun `modman rebuild-backups --reinstall` \
+                        (or `modman update`) to reinstall them.";
+            println!("{}", message);
+        }
+    }
+
+    if !unrecoverable.is_empty() {
+        fail!(
+            Failure::VerificationFailed,
+            "Couldn't rebuild these backups, since the game doesn't have \
+             its original files for them:{}\n\
+             Have the game restore its files (e.g., Steam's \"Verify integrity of game files\"), \
+             then try again.",
+            unrecoverable
+        );
+    }
+    Ok(())
+}
+
+/// Copies the game's original file (which we've checked) to its backup.
+fn rebuild(file: &Path, game_path: &Path, backup_path: &Path, dry_run: bool) -> Result<()> {
+    if dry_run {
+        println!(
+            "Would rebuild {} from {}",
+            backup_path.display(),
+            game_path.display()
+        );
+        return Ok(());
+    }
+    info!(
+        "Rebuilding {} from {}",
+        backup_path.display(),
+        game_path.display()
+    );
+    history::note_file(file);
+    // (This keeps the original's permissions and modification time.)
+    update::backup_file(game_path, file)
+}
@@ -0,0 +1,370 @@
+//! Salvaging what we can from a damaged JSON profile (`modman profile recover`).
+//!
+//! serde_json gives up at the first problem, so we split the profile into
+//! its top-level members (and "mods" into each mod's entry) ourselves,
+//! parse each on its own, and keep whatever parses.
+//! A truncated profile is cut off partway through a member,
+//! so everything before the cut is kept.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use log::*;
+use serde_json::{Map, Value};
+use structopt::*;
+
+use crate::failure::*;
+use crate::file_utils::*;
+use crate::profile::*;
+use crate::profile_store::*;
+
+/// Salvages what it can from a damaged profile
+///
+/// If modman.profile was truncated (say, by a crash) or badly hand-edited,
+/// this keeps every setting and mod entry that can still be read,
+/// reports the rest, and checks the recovered mods against the backups
+/// in the backup directory. The damaged profile is kept as
+/// modman.profile.broken.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    /// Report what could be recovered without writing anything.
+    #[structopt(short = "n", long)]
+    dry_run: bool,
+
+    /// The root directory to use if the profile's can't be recovered
+    #[structopt(long, name = "DIR")]
+    root: Option<PathBuf>,
+}
+
+impl Args {
+    /// Makes this a dry run, for `--no-write`.
+    pub fn make_dry_run(&mut self) {
+        self.dry_run = true;
+    }
+
+    pub fn resolve_paths(&mut self, base: &Path) {
+        if let Some(root) = &mut self.root {
+            *root = base.join(&root);
+        }
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    match existing_backend() {
+        Some(Backend::Json) => (),
+        Some(Backend::Sqlite) => fail!(
+            Failure::Usage,
+            "Only JSON profiles ({}) can be recovered",
+            PROFILE_PATH
+        ),
+        None => fail!(
+            Failure::NoProfile,
+            "There's no profile ({}) here to recover.",
+            PROFILE_PATH
+        ),
+    }
+    let text = fs::read(PROFILE_PATH)
+        .with_context(|| format!("Couldn't read profile file ({})", PROFILE_PATH))?;
+    let text = String::from_utf8_lossy(&text);
+    if serde_json::from_str::<Profile>(&text).is_ok() {
+        println!("The profile isn't damaged; there's nothing to recover.");
+        return Ok(());
+    }
+
+    let (members, intact) = split_object(&text);
+    let mut lost = Vec::new();
+    // (A member that was cut off is reported on its own below.)
+    if !intact && members.last().is_none_or(|m| m.complete) {
+        lost.push(match members.last() {
+            Some(m) => format!("everything after {}", m.key),
+            None => "the whole profile".to_owned(),
+        });
+    }
+
+    // Settings first, each on its own, since a bad one shouldn't cost the rest.
+    let mut settings = Map::new();
+    settings.insert("mods".to_owned(), Value::Object(Map::new()));
+    match members.iter().find(|m| m.key == "root_directory") {
+        Some(m) if m.complete && serde_json::from_str::<PathBuf>(m.value).is_ok() => {
+            settings.insert(m.key.clone(), serde_json::from_str(m.value)?);
+        }
+        _ => match &args.root {
+            Some(root) => {
+                lost.push("the root directory (using --root instead)".to_owned());
+                settings.insert("root_directory".to_owned(), serde_json::to_value(root)?);
+            }
+            None => fail!(
+                Failure::BadProfile,
+                "Couldn't recover the profile's root directory. \
+                 Pass --root <DIR> to say where it is."
+            ),
+        },
+    }
+    for m in members
+        .iter()
+        .filter(|m| m.key != "root_directory" && m.key != "mods")
+    {
+        let value = match serde_json::from_str::<Value>(m.value) {
+            Ok(v) if m.complete => v,
+            _ => {
+                lost.push(format!("the {} setting", m.key));
+                continue;
+            }
+        };
+        let mut with = settings.clone();
+        with.insert(m.key.clone(), value);
+        if serde_json::from_value::<Profile>(Value::Object(with.clone())).is_ok() {
+            settings = with;
+        } else {
+            lost.push(format!("the {} setting", m.key));
+        }
+    }
+    let mut p: Profile = serde_json::from_value(Value::Object(settings))?;
+    set_storage_dir(p.storage_directory.as_deref());
+
+    // Then each mod.
+    let mut mods_seen = 0;
+    match members.iter().find(|m| m.key == "mods") {
+        Some(mods) => {
+            let (entries, mods_intact) = split_object(mods.value);
+            if !mods_intact && entries.last().is_none_or(|m| m.complete) {
+                lost.push(match entries.last() {
+                    Some(m) => format!("every mod after {}", m.key),
+                    None => "every mod".to_owned(),
+                });
+            }
+            for entry in entries {
+                mods_seen += 1;
+                if !entry.complete {
+                    lost.push(format!("the mod {} (it was cut off)", entry.key));
+                    continue;
+                }
+                match serde_json::from_str::<ModManifest>(entry.value) {
+                    Ok(manifest) => {
+                        p.mods.insert(PathBuf::from(&entry.key), manifest);
+                    }
+                    Err(e) => lost.push(format!(
+                        "the mod {} ({})",
+                        entry.key,
+                        describe_error(&e, &text, entry.value)
+                    )),
+                }
+            }
+        }
+        None => lost.push("the list of installed mods".to_owned()),
+    }
+
+    println!(
+        "Recovered {} of {} mod{}",
+        p.mods.len(),
+        mods_seen,
+        if mods_seen == 1 { "" } else { "s" }
+    );
+    if !lost.is_empty() {
+        println!("Couldn't recover:");
+        for l in &lost {
+            println!("\t{}", l);
+        }
+    }
+    cross_check_backups(&p)?;
+
+    if args.dry_run {
+        return Ok(());
+    }
+    let broken_path = format!("{}.broken", PROFILE_PATH);
+    if Path::new(&broken_path).exists() {
+        bail!(
+            "{} already exists. Move it somewhere else to recover the profile again.",
+            broken_path
+        );
+    }
+    rename_file(Path::new(PROFILE_PATH), Path::new(&broken_path))
+        .with_context(|| format!("Couldn't rename {} to {}", PROFILE_PATH, broken_path))?;
+    create_new_profile_file(&p)?;
+    println!(
+        "Wrote the recovered profile to {} (the damaged one is at {})",
+        PROFILE_PATH, broken_path
+    );
+    Ok(())
+}
+
+/// Reports recovered mods' backups that are missing,
+/// and backups that no recovered mod claims (whose mods were probably lost).
+fn cross_check_backups(p: &Profile) -> Result<()> {
+    let backups = backup_dir();
+    let mut claimed = BTreeSet::new();
+    for (mod_path, manifest) in &p.mods {
+        for (file, meta) in &manifest.files {
+            if meta.original_hash.is_none() {
+                continue;
+            }
+            let backup = mod_path_to_backup_path(file);
+            if !backup.exists() {
+                println!(
+                    "The backup of {} (from {}) is missing",
+                    file.display(),
+                    mod_path.display()
+                );
+            }
+            claimed.insert(backup);
+        }
+    }
+    if !backups.exists() {
+        return Ok(());
+    }
+    let unclaimed: Vec<PathBuf> = collect_file_paths_in_dir(&backups)?
+        .into_iter()
+        .map(|f| backups.join(f))
+        .filter(|b| !claimed.contains(b))
+        .collect();
+    if !unclaimed.is_empty() {
+        println!("No recovered mod installed these files, but they have backups:");
+        for b in &unclaimed {
+            println!("\t{}", b.strip_prefix(&backups).unwrap_or(b).display());
+        }
+        println!(
+            "Add the mods that installed them again with \
+             `modman add --adopt-existing-backup` to keep using those backups."
+        );
+    }
+    debug!("{} backups checked", claimed.len() + unclaimed.len());
+    Ok(())
+}
+
+/// Describes an error parsing `value` (part of `text`),
+/// with its line in the whole of `text`, not just `value`.
+fn describe_error(e: &serde_json::Error, text: &str, value: &str) -> String {
+    let message = e.to_string();
+    let message = match message.rfind(" at line ") {
+        Some(i) => &message[..i],
+        None => &message,
+    };
+    let offset = value.as_ptr() as usize - text.as_ptr() as usize;
+    let line = text[..offset].matches('\n').count() + e.line();
+    format!("{} on line {}", message, line)
+}
+
+/// A member of a JSON object: its key and the raw text of its value
+struct Member<'a> {
+    key: String,
+    value: &'a str,
+    /// False if the text ran out before the value ended
+    complete: bool,
+}
+
+/// Splits the JSON object at the start of `text` into its members,
+/// stopping at the first thing that doesn't look like one.
+/// Returns them, and whether the whole object was there.
+fn split_object(text: &str) -> (Vec<Member<'_>>, bool) {
+    let bytes = text.as_bytes();
+    let mut members = Vec::new();
+    let mut i = skip_whitespace(bytes, 0);
+    if bytes.get(i) != Some(&b'{') {
+        return (members, false);
+    }
+    i = skip_whitespace(bytes, i + 1);
+    if bytes.get(i) == Some(&b'}') {
+        return (members, true);
+    }
+    loop {
+        let key_end = match scan_string(bytes, i) {
+            Some(end) => end,
+            None => return (members, false),
+        };
+        let key: String = match serde_json::from_str(&text[i..key_end]) {
+            Ok(k) => k,
+            Err(_) => return (members, false),
+        };
+        i = skip_whitespace(bytes, key_end);
+        if bytes.get(i) != Some(&b':') {
+            return (members, false);
+        }
+        let start = skip_whitespace(bytes, i + 1);
+        match scan_value(bytes, start) {
+            Some(end) => members.push(Member {
+                key,
+                value: &text[start..end],
+                complete: true,
+            }),
+            None => {
+                members.push(Member {
+                    key,
+                    value: &text[start..],
+                    complete: false,
+                });
+                return (members, false);
+            }
+        }
+        let end = members.last().unwrap().value.len() + start;
+        i = skip_whitespace(bytes, end);
+        match bytes.get(i) {
+            Some(b',') => i = skip_whitespace(bytes, i + 1),
+            Some(b'}') => return (members, true),
+            _ => return (members, false),
+        }
+    }
+}
+
+fn skip_whitespace(bytes: &[u8], mut i: usize) -> usize {
+    while bytes.get(i).is_some_and(u8::is_ascii_whitespace) {
+        i += 1;
+    }
+    i
+}
+
+/// Given the index of a string's opening quote, returns the index after its closing one.
+fn scan_string(bytes: &[u8], start: usize) -> Option<usize> {
+    if bytes.get(start) != Some(&b'"') {
+        return None;
+    }
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return Some(i + 1),
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Returns the index just past the value starting at `start`,
+/// or None if the text ends first.
+/// (This only finds where it ends; parsing it says whether it's any good.)
+fn scan_value(bytes: &[u8], start: usize) -> Option<usize> {
+    match bytes.get(start)? {
+        b'"' => scan_string(bytes, start),
+        b'{' | b'[' => {
+            let mut depth = 0;
+            let mut i = start;
+            while i < bytes.len() {
+                match bytes[i] {
+                    b'"' => {
+                        i = scan_string(bytes, i)?;
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(i + 1);
+                        }
+                    }
+                    _ => (),
+                }
+                i += 1;
+            }
+            None
+        }
+        _ => {
+            // Numbers, true, false, null (or garbage)
+            let len = bytes[start..]
+                .iter()
+                .position(|b| matches!(b, b',' | b'}' | b']') || b.is_ascii_whitespace())?;
+            Some(start + len)
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use log::*;
+use rayon::prelude::*;
+use structopt::*;
+
+use crate::file_utils::*;
+use crate::profile::*;
+
+/// Points the profile at a moved or reinstalled game directory
+///
+/// Every installed mod file is checked at <NEW_DIR>.
+/// Files that are missing mean their mod should be removed and added again;
+/// files that have changed (e.g., after a reinstall) need `modman update`.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    #[structopt(short = "n", long)]
+    dry_run: bool,
+
+    /// The new root directory
+    #[structopt(name = "NEW_DIR")]
+    new_root: PathBuf,
+}
+
+impl Args {
+    /// Makes this a dry run, for `--no-write`.
+    pub fn make_dry_run(&mut self) {
+        self.dry_run = true;
+    }
+
+    pub fn resolve_paths(&mut self, base: &Path) {
+        self.new_root = base.join(&self.new_root);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum FileState {
+    Intact,
+    Changed,
+    Missing,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    if !args.new_root.is_dir() {
+        bail!("{} is not an existing directory!", args.new_root.display());
+    }
+
+    let (mut store, mut p) = open_profile()?;
+    info!(
+        "Checking installed mod files in {}...",
+        args.new_root.display()
+    );
+
+    let new_dirs = GameDirs {
+        root_directory: &args.new_root,
+        ..p.game_dirs()
+    };
+    let mut all_intact = true;
+    for (mod_path, manifest) in &p.mods {
+        let states = manifest
+            .files
+            .par_iter()
+            .map(|(file, meta)| {
+                let game_path = new_dirs.game_path(file);
+                // (Covered files aren't in the root directory.)
+                let state = if p.is_covered(mod_path, file) {
+                    FileState::Intact
+                } else if !game_path.exists() {
+                    FileState::Missing
+                } else if hash_file(&game_path)? != meta.mod_hash {
+                    FileState::Changed
+                } else {
+                    FileState::Intact
+                };
+                Ok((file, state))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let missing: Vec<_> = states
+            .iter()
+            .filter(|(_, s)| *s == FileState::Missing)
+            .collect();
+        let changed: Vec<_> = states
+            .iter()
+            .filter(|(_, s)| *s == FileState::Changed)
+            .collect();
+
+        if !missing.is_empty() {
+            let mut warning = format!(
+                "{} is missing files in the new root directory \
+                 (remove it, then add it again):",
+                mod_path.display()
+            );
+            for (file, _) in &missing {
+                warning += &format!("\n\t{}", file.display());
+            }
+            warn!("{}", warning);
+        }
+        if !changed.is_empty() {
+            let mut warning = format!(
+                "{} has files that changed in the new root directory \
+                 (run `modman update`):",
+                mod_path.display()
+            );
+            for (file, _) in &changed {
+                warning += &format!("\n\t{}", file.display());
+            }
+            warn!("{}", warning);
+        }
+        if missing.is_empty() && changed.is_empty() {
+            info!("\tAll files from {} are intact", mod_path.display());
+        } else {
+            all_intact = false;
+        }
+    }
+
+    if args.dry_run {
+        return Ok(());
+    }
+
+    info!(
+        "Moving root directory from {} to {}",
+        p.root_directory.display(),
+        args.new_root.display()
+    );
+    p.root_directory = args.new_root;
+    store.save_settings(&p)?;
+
+    if all_intact {
+        info!("All installed mod files are intact!");
+    }
+    Ok(())
+}
@@ -1,66 +1,397 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Write};
 use std::fs;
 use std::path::{Path, PathBuf};
 
 use anyhow::*;
 use log::*;
+use serde_derive::Serialize;
 use structopt::*;
 
+use crate::auto_check::auto_check;
+use crate::deps::*;
 use crate::file_utils::*;
+use crate::games;
+use crate::history;
+use crate::hooks::*;
+use crate::output::print_changes;
+use crate::plan::{self, Operation, PlanCommand};
 use crate::profile::*;
+use crate::profile_store::*;
+use crate::progress::{self, FileEvent};
+use crate::stats::format_size;
 use rayon::prelude::*;
 
 /// Uninstalls a mod
 ///
 /// Mod files from <MOD> are removed from the root directory
 /// and any files they replaced are restored from backups.
+/// <MOD> can be the path the mod was installed from, its alias,
+/// its name (sans extension), or just part of its path.
+/// With --all, every mod is removed (each before the mods it requires),
+/// and with --tag, every mod with the given tag (see `modman tag`).
 #[derive(Debug, StructOpt)]
 #[structopt(verbatim_doc_comment)]
 pub struct Args {
     #[structopt(short = "n", long)]
     dry_run: bool,
 
-    #[structopt(name = "MOD", required(true))]
+    /// Don't change anything; write a plan of every file operation
+    /// to <PLAN_FILE> (or stdout, if it's -) for `modman apply`.
+    #[structopt(long, name = "PLAN_FILE")]
+    plan: Option<PathBuf>,
+
+    /// Restore backups even if installed mod files changed
+    /// or are missing (e.g., after a game update),
+    /// and go ahead even if the automatic check (see `modman auto-check`)
+    /// finds problems.
+    #[structopt(short, long, conflicts_with = "PLAN_FILE")]
+    force: bool,
+
+    /// Keep going past files that can't be restored or removed,
+    /// leaving them in the profile to try again later.
+    #[structopt(short, long, conflicts_with = "PLAN_FILE")]
+    keep_going: bool,
+
+    /// If installed mod files changed, also print which ones (and how)
+    /// to stdout as JSON.
+    #[structopt(long, conflicts_with = "PLAN_FILE")]
+    json: bool,
+
+    /// Remove every installed mod.
+    #[structopt(long, conflicts_with = "MOD")]
+    all: bool,
+
+    /// Remove every installed mod with the given tag.
+    #[structopt(long, name = "TAG", conflicts_with_all = &["MOD", "all"])]
+    tag: Option<String>,
+
+    #[structopt(name = "MOD", required_unless_one = &["all", "TAG"])]
     mod_names: Vec<PathBuf>,
 }
 
+impl Args {
+    pub fn new(mod_names: Vec<PathBuf>, dry_run: bool) -> Self {
+        Self {
+            dry_run,
+            plan: None,
+            force: false,
+            keep_going: false,
+            json: false,
+            all: false,
+            tag: None,
+            mod_names,
+        }
+    }
+
+    /// Makes this a dry run, for `--no-write`.
+    /// Returns false if it would still write something (a plan file).
+    pub fn make_dry_run(&mut self) -> bool {
+        self.dry_run = true;
+        self.plan.as_deref().is_none_or(plan::is_stdout)
+    }
+
+    pub fn resolve_paths(&mut self, base: &Path) {
+        for name in &mut self.mod_names {
+            resolve_mod_name(base, name);
+        }
+        if let Some(plan) = self.plan.as_mut().filter(|p| !plan::is_stdout(p)) {
+            *plan = base.join(&plan);
+        }
+    }
+}
+
 pub fn run(args: Args) -> Result<()> {
-    let mut p = load_and_check_profile()?;
+    let mod_names = if args.all {
+        let p = load_and_check_profile()?;
+        if p.mods.is_empty() {
+            info!("No mods are installed.");
+            return Ok(());
+        }
+        order_mods_to_remove(&p)
+    } else if let Some(tag) = &args.tag {
+        let p = load_and_check_profile()?;
+        let tagged: Vec<PathBuf> = order_mods_to_remove(&p)
+            .into_iter()
+            .filter(|m| p.mods[m].tags.contains(tag))
+            .collect();
+        if tagged.is_empty() {
+            bail!("No installed mods are tagged {}", tag);
+        }
+        tagged
+    } else {
+        args.mod_names
+    };
+    if let Some(plan_path) = args.plan {
+        let mods = mod_names.clone();
+        return plan::write_plan(&plan_path, PlanCommand::Remove, mods, || {
+            remove_mods(mod_names, false, false, true)
+        });
+    }
+    if !args.dry_run {
+        auto_check(args.force, true)?;
+    }
+    let result = remove_mods(mod_names, args.force, args.keep_going, args.dry_run);
+    if args.json {
+        if let Some(changed) = result
+            .as_ref()
+            .err()
+            .and_then(|e| e.downcast_ref::<ChangedFiles>())
+        {
+            println!("{}", serde_json::to_string_pretty(changed)?);
+        }
+    }
+    result
+}
+
+/// Why an installed mod file doesn't match what the mod installed
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Problem {
+    Changed,
+    Missing,
+    /// We couldn't hash it (only with --force; otherwise that's an error).
+    Unreadable,
+}
+
+/// An installed mod file that isn't what the mod installed
+#[derive(Debug, Serialize)]
+pub struct ChangedFile {
+    pub path: PathBuf,
+    pub problem: Problem,
+    pub expected_hash: FileHash,
+    pub actual_hash: Option<FileHash>,
+    pub expected_size: Option<u64>,
+    pub actual_size: Option<u64>,
+    /// When the file was last modified, in RFC 3339
+    pub modified: Option<String>,
+    /// The game's copy of the file from before the mod was installed,
+    /// which removing the mod would restore
+    pub backup: Option<PathBuf>,
+}
+
+/// The error `remove` gives when installed mod files changed,
+/// listing all of them at once.
+#[derive(Debug, Serialize)]
+pub struct ChangedFiles {
+    #[serde(rename = "mod")]
+    pub mod_path: PathBuf,
+    pub files: Vec<ChangedFile>,
+}
+
+impl ChangedFiles {
+    /// Lists each file, what we expected, and what we found.
+    pub fn report(&self) -> String {
+        let mut r = String::new();
+        let size = |s: Option<u64>| s.map(format_size).unwrap_or_else(|| "?".to_owned());
+        let short = |h: &FileHash| hex::encode(h.bytes)[..12].to_owned();
+        let _ = writeln!(
+            r,
+            "{} installed file{} from {} changed:",
+            self.files.len(),
+            if self.files.len() == 1 { "" } else { "s" },
+            self.mod_path.display()
+        );
+        for f in &self.files {
+            let _ = write!(r, "  {}: ", f.path.display());
+            let _ = match (f.problem, &f.actual_hash) {
+                (Problem::Changed, Some(actual)) => writeln!(
+                    r,
+                    "expected {} ({}), found {} ({}, modified {})",
+                    short(&f.expected_hash),
+                    size(f.expected_size),
+                    short(actual),
+                    size(f.actual_size),
+                    f.modified.as_deref().unwrap_or("?")
+                ),
+                (Problem::Missing, _) => writeln!(
+                    r,
+                    "missing (expected {}, {})",
+                    short(&f.expected_hash),
+                    size(f.expected_size)
+                ),
+                _ => writeln!(r, "couldn't be read"),
+            };
+            if let Some(backup) = &f.backup {
+                let _ = writeln!(r, "    pre-install copy: {}", backup.display());
+            }
+        }
+        r
+    }
+}
+
+impl fmt::Display for ChangedFiles {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}Did the game update?\n\
+             Run `modman update` first, or `modman remove --force` to restore backups anyways.",
+            self.report()
+        )
+    }
+}
+
+impl std::error::Error for ChangedFiles {}
+
+pub fn remove_mods(
+    mod_names: Vec<PathBuf>,
+    force: bool,
+    keep_going: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let (mut store, mut p) = open_and_check_profile()?;
+    if !dry_run {
+        games::warn_if_running(&p);
+    }
+    let mod_names = mod_names
+        .iter()
+        .map(|n| p.find_mod(n))
+        .collect::<Result<Vec<_>>>()?;
 
-    for mod_name in args.mod_names {
+    for mod_name in &mod_names {
+        for (dependent, req) in dependents(mod_name, &p, &mod_names) {
+            warn!(
+                "{} requires {}, which is being removed",
+                dependent.display(),
+                req
+            );
+        }
+    }
+
+    // Dry runs show what they'd do by comparing the profile before and after.
+    let before = (dry_run && !plan::is_planning()).then(|| p.clone());
+
+    // When removing a bunch of mods, write the profile once at the end
+    // instead of after each one.
+    let batched = mod_names.len() > 1 && !dry_run;
+    if batched {
+        store.defer_writes()?;
+    }
+
+    let mut stuck = String::new();
+    let mut removed = 0;
+    let mut result = Ok(());
+    for mod_name in &mod_names {
         info!("Removing {}...", mod_name.display());
 
-        let mod_path = Path::new(&mod_name);
-        remove_mod(&mod_path, &mut p, args.dry_run)?;
+        let mod_path = Path::new(mod_name);
+        match remove_mod(mod_path, &mut p, &mut *store, force, keep_going, dry_run) {
+            Ok(failed) if failed.is_empty() => removed += 1,
+            Ok(failed) => {
+                for file in failed {
+                    stuck += &format!("\n\t{}", p.game_path(&file).display());
+                }
+            }
+            Err(e) if keep_going => {
+                warn!("{:#}", e);
+                stuck += &format!("\n\t{} (all of it)", mod_name.display());
+            }
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    // Save whatever we managed to remove, even if we hit an error.
+    if batched {
+        store.flush()?;
+    }
+    result?;
+    if batched {
+        info!("Removed {} of {} mods", removed, mod_names.len());
     }
 
-    if args.dry_run {
-        print_profile(&p)?;
+    if let Some(before) = before {
+        print_changes(&before, &p)?;
     }
 
+    if !stuck.is_empty() {
+        bail!(
+            "Couldn't finish removing:{}\n\
+             These are still in the profile. Fix the problems above, then remove them again.",
+            stuck
+        );
+    }
     Ok(())
 }
 
-fn remove_mod(mod_path: &Path, p: &mut Profile, dry_run: bool) -> Result<()> {
+/// Removes the mod, returning the files that couldn't be restored or removed
+/// (if keep_going is set; otherwise we stop at the first error).
+fn remove_mod(
+    mod_path: &Path,
+    p: &mut Profile,
+    store: &mut dyn ProfileStore,
+    force: bool,
+    keep_going: bool,
+    dry_run: bool,
+) -> Result<Vec<PathBuf>> {
     // First sanity check: this mod is in the profile
-    let removed_mod: ModManifest = p.mods.remove(mod_path).ok_or_else(|| {
-        return format_err!("{} hasn't been added.", mod_path.display());
-    })?;
+    let removed_mod: ModManifest = p
+        .mods
+        .remove(mod_path)
+        .ok_or_else(|| format_err!("{} hasn't been added.", mod_path.display()))?;
+    let others = others_with(&removed_mod, p);
 
     // Everything after this is filesystem work.
     if dry_run {
-        return Ok(());
+        let version = &removed_mod.version;
+        run_hooks(
+            Event::PreRemove,
+            mod_path,
+            version,
+            &removed_mod.hooks,
+            p,
+            true,
+        )?;
+        run_hooks(
+            Event::PostRemove,
+            mod_path,
+            version,
+            &removed_mod.hooks,
+            p,
+            true,
+        )?;
+        for (file, meta) in &removed_mod.files {
+            // Only the last mod layered over a file has it installed.
+            if let Some(layers) = p.layers_of(file) {
+                if layers.last().is_some_and(|top| top == mod_path) {
+                    let path = file.clone();
+                    plan::note(mod_path, Operation::Restore { path });
+                }
+                continue;
+            }
+            if meta.identical || others.contains_key(&path_key(file, p.case_insensitive)) {
+                continue;
+            }
+            let path = file.clone();
+            plan::note(
+                mod_path,
+                if meta.original_hash.is_some() {
+                    Operation::Restore { path }
+                } else {
+                    Operation::Delete { path }
+                },
+            );
+        }
+        return Ok(Vec::new());
     }
+    history::note_mod(mod_path);
 
     // We'll do this in a few steps to minimize the chance that data
     // is lost:
-    // 1. Verify that all the files we installed are unmodified (add flag to skip?)
-    // 2. Restore all files from backups.
-    // 3. Remove mod files that needed no backup.
+    // 1. Verify that all the files we installed are unmodified
+    //    (unless we're forced to go on anyways).
+    // 2. Put back the files of mods layered under this one,
+    //    then restore all files from backups.
+    // 3. Remove mod files that needed no backup,
+    //    then the directories installing the mod created.
     // 4. Remove the mod from the profile.
     // 5. Remove the backups.
     //
-    // Unlike activation, we don't need to keep a journal since we don't
-    // do anything destructive until we've restored all backups.
+    // Unlike activation, we don't keep a journal. The only game files we
+    // overwrite or delete are the mod's own, to put back what they replaced
+    // (from backups, or the files of mods layered under it),
+    // and the profile keeps the mod and its layers until step 4.
     // (TODO: Is applying mods in one pass worth a journal and rescue command?)
     // If we run into issues, tell the user what we've done so far and bail.
 
@@ -68,97 +399,584 @@ fn remove_mod(mod_path: &Path, p: &mut Profile, dry_run: bool) -> Result<()> {
         "Checking that all mod files installed by {} are unmodified...",
         mod_path.display()
     );
-    let all_intact = removed_mod
+    let mut changed = removed_mod
         .files
         .par_iter()
+        // Covered files aren't in the game directory.
+        .filter(|(file, _)| !p.is_covered(mod_path, file))
         .map(|(file, meta)| {
-            let hash_matches =
-                meta.mod_hash == hash_file(&mod_path_to_game_path(file, &p.root_directory))?;
-            if !hash_matches {
-                warn!(
-                    "Mod file {} has changed from when it was installed by mod {}",
-                    file.display(),
-                    mod_path.display()
-                );
-            }
-            Ok(hash_matches)
+            let game_path = p.game_path(file);
+            let (problem, actual_hash) = match hash_installed_file(&game_path, meta) {
+                Ok(h) if h == meta.mod_hash => return Ok(None),
+                Ok(h) => (Problem::Changed, Some(h)),
+                Err(e) if is_not_found(&e) => (Problem::Missing, None),
+                Err(e) if force => {
+                    warn!("{:#}", e);
+                    (Problem::Unreadable, None)
+                }
+                Err(e) => return Err(e),
+            };
+            let stat = fs::symlink_metadata(&game_path).ok();
+            Ok(Some(ChangedFile {
+                path: file.clone(),
+                problem,
+                expected_hash: meta.mod_hash.clone(),
+                actual_hash,
+                expected_size: meta.size,
+                actual_size: stat.as_ref().map(|s| s.len()),
+                modified: stat
+                    .and_then(|s| s.modified().ok())
+                    .map(|t| humantime::format_rfc3339_seconds(t).to_string()),
+                backup: meta
+                    .original_hash
+                    .as_ref()
+                    .map(|_| mod_path_to_backup_path(file)),
+            }))
         })
-        .reduce(
-            || -> Result<bool> { Ok(true) },
-            |left, right| Ok(left? && right?),
-        )?;
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<ChangedFile>>>()?;
 
-    if !all_intact {
-        bail!("Some installed mod files were changed. Did the game update?");
+    if changed.is_empty() {
+        info!("All mod files from {} are intact!", mod_path.display());
+    } else {
+        changed.sort_by(|a, b| a.path.cmp(&b.path));
+        let changed = ChangedFiles {
+            mod_path: mod_path.to_owned(),
+            files: changed,
+        };
+        if force {
+            warn!(
+                "{}\nRestoring backups anyways.",
+                changed.report().trim_end()
+            );
+        } else {
+            p.mods.insert(mod_path.to_owned(), removed_mod);
+            return Err(changed.into());
+        }
     }
-    info!("All mod files from {} are intact!", mod_path.display());
+
+    run_hooks(
+        Event::PreRemove,
+        mod_path,
+        &removed_mod.version,
+        &removed_mod.hooks,
+        p,
+        false,
+    )?;
 
     // We could split files that need backups and ones that don't
     // using Iterator::partition() for steps 2 and 3,
     // but it seems simpler to iterate twice instead of allocating storage
     // for partitioned references.
 
-    // Step 2:
-    removed_mod
+    // Files layered with other mods' go back to the mod before this one
+    // (if this one's is installed), or stay put.
+    let (layered, own): (Vec<_>, Vec<_>) = removed_mod
         .files
-        .par_iter()
-        .filter(|(_f, m)| m.original_hash.is_some())
-        .try_for_each(|(file, meta)| {
-            info!("Restoring {}", file.display());
-            restore_file_from_backup(file, meta, &p.root_directory)
-        })?;
+        .iter()
+        .partition(|(f, _)| p.layers_of(f).is_some());
+    let Unlayered {
+        mut failed,
+        heirs,
+        uncovered,
+    } = unlayer(mod_path, &layered, &others, p, keep_going)?;
+
+    // Files other mods share stay put.
+    let (still_shared, own): (Vec<_>, Vec<_>) = own
+        .into_iter()
+        .partition(|(f, _)| others.contains_key(&path_key(f, p.case_insensitive)));
+    hand_off(&still_shared, &others, p, store)?;
+
+    // Files that were identical to the game's are still the game's.
+    let (own, identical): (Vec<_>, Vec<_>) = own.into_iter().partition(|(_f, m)| !m.identical);
+    for (file, _) in identical {
+        debug!(
+            "Leaving {}, which was the game's to begin with",
+            file.display()
+        );
+    }
+
+    let (backed_up, added): (Vec<_>, Vec<_>) = own
+        .into_iter()
+        .partition(|(_f, m)| m.original_hash.is_some());
+
+    progress::start("remove", mod_path, backed_up.len() + added.len());
+
+    // Step 2:
+    failed.extend(for_each_file(&backed_up, keep_going, |file, meta| {
+        debug!("Restoring {}", file.display());
+        history::note_file(file);
+        restore_file_from_backup(file, meta, &p.game_dirs())?;
+        progress::file_done(FileEvent::Restored, file);
+        Ok(())
+    })?);
 
     // Step 3:
-    removed_mod
-        .files
-        .par_iter()
-        .filter(|(_f, m)| m.original_hash.is_none())
-        .try_for_each(|(file, _)| {
-            info!("Removing {}", file.display());
-            let game_path = mod_path_to_game_path(file, &p.root_directory);
-            // Keep moving if it's already gone. This gets us to subsequent steps
-            // if a previous run of `remove` was interrupted.
-            fs::remove_file(&game_path)
-                .or_else(|e| {
-                    if e.kind() == std::io::ErrorKind::NotFound {
-                        warn!("{} was already removed!", game_path.display());
-                        Ok(())
-                    } else {
-                        Err(e)
-                    }
-                })
-                .with_context(|| format!("Couldn't remove {}", game_path.display()))?;
-            remove_empty_parents(&game_path, &p.root_directory)
-        })?;
+    failed.extend(for_each_file(&added, keep_going, |file, _| {
+        debug!("Removing {}", file.display());
+        history::note_file(file);
+        let game_path = p.game_path(file);
+        // Keep moving if it's already gone. This gets us to subsequent steps
+        // if a previous run of `remove` was interrupted.
+        discard_file(&game_path)
+            .or_else(|e| {
+                if e.kind() == std::io::ErrorKind::NotFound {
+                    strict_warn!(MissingFile, "{} was already removed!", game_path.display());
+                    Ok(())
+                } else {
+                    Err(e)
+                }
+            })
+            .with_context(|| format!("Couldn't remove {}", game_path.display()))?;
+        progress::file_done(FileEvent::Removed, file);
+        Ok(())
+    })?);
+    // They were restored and removed in parallel, so say what we did in order.
+    let mut done: Vec<_> = backed_up
+        .iter()
+        .chain(&added)
+        .filter(|(f, _)| !failed.contains(f))
+        .collect();
+    done.sort_by_key(|(f, _)| *f);
+    for (file, meta) in done {
+        if meta.original_hash.is_some() {
+            info!("Restored {}", file.display());
+        } else {
+            info!("Removed {}", file.display());
+        }
+    }
+
+    // (If some files couldn't be removed, we'll get these next time.)
+    if failed.is_empty() {
+        remove_directories(&removed_mod.directories, p, store)?;
+    }
 
     // Step 4:
-    update_profile_file(&p)?;
+    if failed.is_empty() {
+        store.remove_mod(mod_path)?;
+    } else {
+        // Keep what we couldn't restore or remove so we can try again.
+        let remaining = ModManifest {
+            files: removed_mod
+                .files
+                .iter()
+                .filter(|(f, _)| failed.contains(f))
+                .map(|(f, meta)| (f.clone(), meta.clone()))
+                .collect(),
+            ..removed_mod.clone()
+        };
+        store.save_mod(mod_path, &remaining)?;
+        p.mods.insert(mod_path.to_owned(), remaining);
+    }
+    // Save the layers without the mod only now, along with the mod itself.
+    // If we stopped before here, the profile still has the mod and its files,
+    // so its layers have to as well.
+    if !layered.is_empty() {
+        for heir in &heirs {
+            store.save_mod(heir, &p.mods[heir])?;
+        }
+        store.save_settings(p)?;
+        remove_uncovered(&uncovered, p)?;
+    }
 
     // Step 5:
-    removed_mod
+    let restored: Vec<_> = backed_up
+        .into_iter()
+        .filter(|(f, _)| !failed.contains(f))
+        .collect();
+    let stale_backups = for_each_file(&restored, keep_going, |file, _| {
+        let backup_path = mod_path_to_backup_path(file);
+        debug!("Removing {}", backup_path.display());
+        discard_file(&backup_path)
+            .with_context(|| format!("Couldn't remove {}", backup_path.display()))?;
+        remove_empty_parents(&backup_path, &backup_dir())
+    })?;
+    // These were restored and aren't in the profile anymore,
+    // so just let the user know they can be cleaned up.
+    if !stale_backups.is_empty() {
+        let mut warning =
+            "Couldn't remove these backups (you can delete them yourself):".to_owned();
+        for file in &stale_backups {
+            warning += &format!("\n\t{}", mod_path_to_backup_path(file).display());
+        }
+        warn!("{}", warning);
+    }
+
+    if !failed.is_empty() {
+        return Ok(failed);
+    }
+    run_hooks(
+        Event::PostRemove,
+        mod_path,
+        &removed_mod.version,
+        &removed_mod.hooks,
+        p,
+        false,
+    )?;
+    Ok(failed)
+}
+
+/// The other mods with each of a mod's files (by path_key()),
+/// and what they call it
+type Others = BTreeMap<PathBuf, Vec<(PathBuf, PathBuf)>>;
+
+/// Finds the other mods in the profile with the given mod's files,
+/// indexing the profile once instead of searching every mod for each file.
+/// (The paths are copied, since handing files off changes the profile.)
+fn others_with(manifest: &ModManifest, p: &Profile) -> Others {
+    let installed = p.installed_by_key();
+    manifest
         .files
-        .par_iter()
-        .filter(|(_f, m)| m.original_hash.is_some())
-        .try_for_each(|(file, _)| {
-            let backup_path = mod_path_to_backup_path(file);
-            debug!("Removing {}", backup_path.display());
-            fs::remove_file(&backup_path)
-                .with_context(|| format!("Couldn't remove {}", backup_path.display()))?;
-            remove_empty_parents(&backup_path, &Path::new(BACKUP_PATH))
+        .keys()
+        .filter_map(|file| {
+            let key = path_key(file, p.case_insensitive);
+            let mods = installed
+                .get(&key)?
+                .iter()
+                .map(|(mod_path, f, _)| ((*mod_path).clone(), (*f).clone()))
+                .collect();
+            Some((key, mods))
+        })
+        .collect()
+}
+
+/// Returns what the given mod calls the file with the given key,
+/// if it's one of the others with it.
+fn name_in<'a>(others: &'a Others, key: &Path, mod_path: &Path) -> Option<&'a PathBuf> {
+    others
+        .get(key)?
+        .iter()
+        .find(|(m, _)| m == mod_path)
+        .map(|(_, f)| f)
+}
+
+/// For files the mod being removed installed first but other mods share,
+/// makes one of those mods their owner (and so, of their backups),
+/// so that the originals are restored once the last of them is removed.
+fn hand_off(
+    files: &[(&PathBuf, &ModFileMetadata)],
+    others: &Others,
+    p: &mut Profile,
+    store: &mut dyn ProfileStore,
+) -> Result<()> {
+    let case_insensitive = p.case_insensitive;
+    let mut heirs = BTreeSet::new();
+    for (file, meta) in files {
+        if meta.shared {
+            debug!("Leaving {}, which other mods share", file.display());
+            continue;
+        }
+        // Every mod sharing it has the same contents, so any will do.
+        let (heir, heir_file) = &others[&path_key(file, case_insensitive)][0];
+        let heir_meta = p
+            .mods
+            .get_mut(heir)
+            .and_then(|manifest| manifest.files.get_mut(heir_file))
+            .expect("No other mod shares the file");
+        info!(
+            "Leaving {} installed for {}",
+            file.display(),
+            heir.display()
+        );
+        heir_meta.shared = false;
+        heir_meta.identical = meta.identical;
+        heir_meta.original_hash = meta.original_hash.clone();
+        heir_meta.original_mode = meta.original_mode;
+        heir_meta.original_size = meta.original_size;
+        heir_meta.backup_mtime = meta.backup_mtime;
+        heirs.insert(heir.clone());
+    }
+    for heir in &heirs {
+        store.save_mod(heir, &p.mods[heir])?;
+    }
+    Ok(())
+}
+
+/// What unlayer() did
+struct Unlayered {
+    /// Files whose covered file couldn't be put back
+    /// (if keep_going is set; otherwise we stop at the first error)
+    failed: Vec<PathBuf>,
+    /// Mods that were handed the game's original of a file
+    heirs: BTreeSet<PathBuf>,
+    /// Covered files that might not be needed anymore
+    uncovered: Vec<FileHash>,
+}
+
+/// For files other mods were layered with (see `Profile::layers`):
+/// if the mod's file is the one installed, puts back the one it covered.
+/// If it was the first, the game's original goes to the mod after it.
+/// Either way, the mod leaves the file's layers.
+///
+/// This only changes the profile in memory;
+/// remove_mod() saves it along with the mod's removal.
+fn unlayer(
+    mod_path: &Path,
+    files: &[(&PathBuf, &ModFileMetadata)],
+    others: &Others,
+    p: &mut Profile,
+    keep_going: bool,
+) -> Result<Unlayered> {
+    let case_insensitive = p.case_insensitive;
+    let mut failed = Vec::new();
+    let mut heirs = BTreeSet::new();
+    let mut uncovered = Vec::new();
+    for (file, meta) in files {
+        let key = path_key(file, case_insensitive);
+        let layers = &p.layers[&key];
+        let position = layers
+            .iter()
+            .position(|m| m == mod_path)
+            .expect("The mod isn't in the file's layers");
+        let last = layers.len() - 1;
+        if position == last {
+            let below = layers[last - 1].clone();
+            let below_meta = name_in(others, &key, &below)
+                .and_then(|below_file| p.mods[&below].files.get(below_file))
+                .expect("The mod below has no such file")
+                .clone();
+            debug!("Putting back {}'s {}", below.display(), file.display());
+            history::note_file(file);
+            match uncover(file, meta, &below_meta, &p.game_dirs()) {
+                Ok(()) => info!("Put back {}'s {}", below.display(), file.display()),
+                Err(e) if keep_going => {
+                    warn!("{:#}", e);
+                    failed.push((*file).clone());
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+            uncovered.push(below_meta.mod_hash);
+        } else {
+            debug!(
+                "Leaving {}, which {} is installed over",
+                file.display(),
+                layers[last].display()
+            );
+            if position == 0 {
+                let heir = layers[1].clone();
+                let heir_file =
+                    name_in(others, &key, &heir).expect("The next mod has no such file");
+                hand_down_original(file, meta, &heir, heir_file, p)?;
+                heirs.insert(heir);
+            }
+            uncovered.push(meta.mod_hash.clone());
+        }
+
+        let layers = p.layers.get_mut(&key).unwrap();
+        layers.remove(position);
+        // One mod left is just a mod with a file.
+        if layers.len() == 1 {
+            p.layers.remove(&key);
+        }
+    }
+    Ok(Unlayered {
+        failed,
+        heirs,
+        uncovered,
+    })
+}
+
+/// Removes covered files nothing's layered over anymore.
+fn remove_uncovered(uncovered: &[FileHash], p: &Profile) -> Result<()> {
+    for hash in uncovered {
+        let covered = covered_path(hash);
+        if !p.needs_covered(hash) && covered.exists() {
+            debug!("Removing {}", covered.display());
+            discard_file(&covered)
+                .with_context(|| format!("Couldn't remove {}", covered.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// When the first mod layered over a file is removed, makes the next
+/// the owner of the game's original (and so, of its backup).
+fn hand_down_original(
+    file: &Path,
+    meta: &ModFileMetadata,
+    heir: &Path,
+    heir_file: &Path,
+    p: &mut Profile,
+) -> Result<()> {
+    let quick_check = p.quick_check;
+    let heir_meta = p
+        .mods
+        .get_mut(heir)
+        .and_then(|manifest| manifest.files.get_mut(heir_file))
+        .expect("The next mod has no such file");
+    if meta.identical {
+        // The game's file was left as it was, so it's what covered/ kept.
+        let covered = covered_path(&meta.mod_hash);
+        let backup_path = mod_path_to_backup_path(file);
+        debug!("Keeping {} as {}", covered.display(), backup_path.display());
+        if let Some(parent) = backup_path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Couldn't create directory {}", parent.display()))?;
+        }
+        copy_file(&covered, &backup_path).with_context(|| {
+            format!(
+                "Couldn't copy {} to {}",
+                covered.display(),
+                backup_path.display()
+            )
         })?;
+        heir_meta.original_hash = Some(meta.mod_hash.clone());
+        heir_meta.original_mode = meta.mode;
+        heir_meta.original_size = meta.size;
+        heir_meta.backup_mtime = match quick_check {
+            true => Some(file_mtime(&backup_path)?),
+            false => None,
+        };
+    } else {
+        heir_meta.original_hash = meta.original_hash.clone();
+        heir_meta.original_mode = meta.original_mode;
+        heir_meta.original_size = meta.original_size;
+        heir_meta.backup_mtime = meta.backup_mtime;
+    }
+    Ok(())
+}
+
+/// Puts back the file of the mod below the one being removed
+/// from covered/, where `modman add --overwrite` kept it.
+fn uncover(
+    file: &Path,
+    meta: &ModFileMetadata,
+    below: &ModFileMetadata,
+    dirs: &GameDirs,
+) -> Result<()> {
+    let covered = covered_path(&below.mod_hash);
+    let game_path = dirs.game_path(file);
+    debug!("Copying {} to {}", covered.display(), game_path.display());
 
+    let mut reader = fs::File::open(&covered).with_context(|| {
+        format!(
+            "Couldn't open {} to put it back at {}",
+            covered.display(),
+            game_path.display()
+        )
+    })?;
+    // (If the game deleted the mod file, it might have deleted its directory too.)
+    if let Some(parent) = game_path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Couldn't create directory {}", parent.display()))?;
+    }
+    let mut game_file = create_file(&game_path)
+        .with_context(|| format!("Couldn't open {} to overwrite it", game_path.display()))?;
+    let hash = hash_and_write(&mut reader, &mut game_file)?;
+    drop(game_file);
+    copy_mtime(&covered, &game_path)?;
+    match below.mode {
+        Some(mode) => set_mode(&game_path, mode)?,
+        None if meta.mode.is_some() => clear_executable(&game_path)?,
+        None => {}
+    }
+
+    if hash != below.mod_hash {
+        strict_warn!(
+            RestoreMismatch,
+            "{}'s contents didn't match the hash stored in the profile file \
+             when it was put back at {}",
+            covered.display(),
+            game_path.display()
+        );
+    }
     Ok(())
 }
 
+/// Removes the directories installing the mod created, once they're empty.
+/// Ones with other mods' files in them are handed off to those mods,
+/// to remove along with them.
+fn remove_directories(
+    directories: &BTreeSet<PathBuf>,
+    p: &mut Profile,
+    store: &mut dyn ProfileStore,
+) -> Result<()> {
+    let case_insensitive = p.case_insensitive;
+    let mut heirs = BTreeSet::new();
+    // Subdirectories sort after their parents, so go backwards.
+    for dir in directories.iter().rev() {
+        let game_dir = p.game_path(dir);
+        if !game_dir.is_dir() {
+            debug!("{} was already removed", game_dir.display());
+            continue;
+        }
+        let empty = fs::read_dir(&game_dir)
+            .with_context(|| format!("Couldn't read directory {}", game_dir.display()))?
+            .next()
+            .is_none();
+        if empty {
+            debug!("Removing empty directory {}", game_dir.display());
+            remove_dir_if_empty(&game_dir)?;
+            continue;
+        }
+        let key = path_key(dir, case_insensitive);
+        let heir = p.mods.iter_mut().find(|(_, manifest)| {
+            manifest
+                .files
+                .keys()
+                .any(|f| path_key(f, case_insensitive).starts_with(&key))
+        });
+        match heir {
+            Some((heir, manifest)) => {
+                debug!(
+                    "Leaving {}, which has {}'s files in it",
+                    game_dir.display(),
+                    heir.display()
+                );
+                manifest.directories.insert(dir.clone());
+                heirs.insert(heir.clone());
+            }
+            None => info!("Leaving {}, which isn't empty", game_dir.display()),
+        }
+    }
+    for heir in &heirs {
+        store.save_mod(heir, &p.mods[heir])?;
+    }
+    Ok(())
+}
+
+/// Runs f on each of the mod's files, in parallel.
+///
+/// If keep_going is set, errors are logged and we carry on,
+/// returning the files that failed. Otherwise we stop at the first one.
+fn for_each_file<F>(
+    files: &[(&PathBuf, &ModFileMetadata)],
+    keep_going: bool,
+    f: F,
+) -> Result<Vec<PathBuf>>
+where
+    F: Fn(&Path, &ModFileMetadata) -> Result<()> + Sync,
+{
+    if !keep_going {
+        files
+            .par_iter()
+            .try_for_each(|(file, meta)| f(file, meta))?;
+        return Ok(Vec::new());
+    }
+    let mut failed: Vec<PathBuf> = files
+        .par_iter()
+        .filter_map(|(file, meta)| match f(file, meta) {
+            Ok(()) => None,
+            Err(e) => {
+                warn!("{:#}", e);
+                Some((*file).clone())
+            }
+        })
+        .collect();
+    failed.sort();
+    Ok(failed)
+}
+
 fn restore_file_from_backup(
     mod_path: &Path,
     mod_meta: &ModFileMetadata,
-    root_directory: &Path,
+    dirs: &GameDirs,
 ) -> Result<()> {
     assert!(mod_meta.original_hash.is_some());
 
     let backup_path = mod_path_to_backup_path(mod_path);
-    let game_path = mod_path_to_game_path(mod_path, root_directory);
+    let game_path = dirs.game_path(mod_path);
     debug!(
         "Restoring {} to {}",
         backup_path.display(),
@@ -175,8 +993,18 @@ fn restore_file_from_backup(
             game_path.display()
         )
     })?;
+    // Don't write the original through a link we installed!
+    if mod_meta.symlink.is_some() {
+        remove_file(&game_path)
+            .with_context(|| format!("Couldn't remove {}", game_path.display()))?;
+    }
+    // (If the game deleted the mod file, it might have deleted its directory too.)
+    if let Some(parent) = game_path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Couldn't create directory {}", parent.display()))?;
+    }
     // Because we're restoring contents, this will truncate an existing file.
-    let mut game_file = fs::File::create(&game_path)
+    let mut game_file = create_file(&game_path)
         .with_context(|| format!("Couldn't open {} to overwrite it", game_path.display()))?;
 
     let hash = hash_and_write(&mut reader, &mut game_file)?;
@@ -185,8 +1013,18 @@ fn restore_file_from_backup(
         backup_path.display(),
         hash.bytes
     );
+    drop(game_file);
+    copy_mtime(&backup_path, &game_path)?;
+    // Writing the file kept the mod file's permissions, so put the original's back.
+    match mod_meta.original_mode {
+        Some(mode) => set_mode(&game_path, mode)?,
+        None if mod_meta.mode.is_some() => clear_executable(&game_path)?,
+        None => {}
+    }
+
     if hash != *mod_meta.original_hash.as_ref().unwrap() {
-        warn!(
+        strict_warn!(
+            RestoreMismatch,
             "{}'s contents didn't match the hash stored in the profile file
                            when it was restored to {}",
             backup_path.display(),
@@ -0,0 +1,53 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use structopt::*;
+
+use crate::add::check_alias;
+use crate::profile::*;
+
+/// Gives an installed mod a short name to refer to it by
+///
+/// <MOD> can be the path the mod was installed from, its current alias,
+/// its name (sans extension), or just part of its path.
+/// Only the profile changes; the mod's files are left as they are.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    /// Remove the mod's alias instead.
+    #[structopt(long, conflicts_with = "NEW_ALIAS")]
+    clear: bool,
+
+    #[structopt(name = "MOD")]
+    mod_name: PathBuf,
+
+    #[structopt(name = "NEW_ALIAS", required_unless = "clear")]
+    alias: Option<String>,
+}
+
+impl Args {
+    pub fn resolve_paths(&mut self, base: &Path) {
+        resolve_mod_name(base, &mut self.mod_name);
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let (mut store, mut p) = open_and_check_profile()?;
+    let mod_path = p.find_mod(&args.mod_name)?;
+
+    // Check it against every other mod's name.
+    let mut manifest = p.mods.remove(&mod_path).unwrap();
+    let new = if args.clear { None } else { args.alias };
+    if let Some(alias) = &new {
+        check_alias(alias, &p)?;
+    }
+    let old = std::mem::replace(&mut manifest.alias, new);
+    store.save_mod(&mod_path, &manifest)?;
+
+    match (old, &manifest.alias) {
+        (_, Some(new)) => println!("{} is now called {}", mod_path.display(), new),
+        (Some(old), None) => println!("{} is no longer called {}", mod_path.display(), old),
+        (None, None) => println!("{} didn't have an alias", mod_path.display()),
+    }
+    Ok(())
+}
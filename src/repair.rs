@@ -1,10 +1,13 @@
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use anyhow::*;
 use log::*;
 use structopt::*;
 
+use crate::file_utils::*;
+use crate::games;
+use crate::history;
 use crate::journal::*;
 use crate::profile::*;
 
@@ -14,6 +17,8 @@ use crate::profile::*;
 /// and replacing in the game directory. If it's interrupted before it can finish,
 /// we can use the journal to try to undo the partial installation, restoring the
 /// game files to their previous state.
+/// Backups that don't match the hashes the journal recorded for the files
+/// they replaced aren't restored (or deleted).
 ///
 /// There's no similar repair functionality if `modman update` is interrupted
 /// since its job is to replace outdated backups with new, unknown contents from
@@ -26,8 +31,18 @@ pub struct Args {
     dry_run: bool,
 }
 
+impl Args {
+    /// Makes this a dry run, for `--no-write`.
+    pub fn make_dry_run(&mut self) {
+        self.dry_run = true;
+    }
+}
+
 pub fn run(args: Args) -> Result<()> {
     let p = load_and_check_profile()?;
+    if !args.dry_run {
+        games::warn_if_running(&p);
+    }
 
     let journal_map = read_journal()?;
 
@@ -38,19 +53,45 @@ pub fn run(args: Args) -> Result<()> {
     // We'll make most messages INFO level here, since
     // someone is having a bad time if they're running this.
     // We'd like to be verbose to help them figure out what the situation is.
-    info!("Found a journal from an interrupted `modman add`.");
+    match read_journal_header()? {
+        Some(h) => info!(
+            "Found a journal from an interrupted `modman add {}` (run {}).",
+            h.mod_path.display(),
+            h.run_id
+        ),
+        None => info!("Found a journal from an interrupted `modman add`."),
+    }
     info!("Restoring what files we can find...");
 
+    let installed = p.installed_by_key();
     let mut clean_run = true;
-    for (path, action) in &journal_map {
-        match try_to_undo(path, *action, &p, args.dry_run) {
-            Ok(()) => (),
+    let mut untrusted = Vec::new();
+    for (path, entry) in &journal_map {
+        match try_to_undo(path, entry, &p, &installed, args.dry_run) {
+            Ok(None) => (),
+            Ok(Some(u)) => untrusted.push(u),
             Err(e) => {
                 error!("{:#}", e);
                 clean_run = false;
             }
         }
     }
+    if !untrusted.is_empty() {
+        let mut message = "These backups don't match the game files \
+                           the interrupted `modman add` replaced, \
+                           so they were left where they are:"
+            .to_owned();
+        for u in &untrusted {
+            message += &format!(
+                "\n\t{} (expected {:x}, but it's {:x})",
+                mod_path_to_backup_path(&u.path).display(),
+                u.expected.bytes,
+                u.actual.bytes
+            );
+        }
+        error!("{}", message);
+        clean_run = false;
+    }
 
     if clean_run {
         if !args.dry_run {
@@ -70,11 +111,27 @@ pub fn run(args: Args) -> Result<()> {
     Ok(())
 }
 
-fn try_to_undo(path: &Path, action: JournalAction, p: &Profile, dry_run: bool) -> Result<()> {
-    if p.mods
-        .values()
-        .any(|manifest| manifest.files.keys().any(|file| file == path))
-    {
+/// A backup that doesn't match the journal's hash of the file it replaced
+struct Untrusted {
+    path: PathBuf,
+    expected: FileHash,
+    actual: FileHash,
+}
+
+/// Undoes what the journal says was done to the file.
+/// Backups that don't match the journal aren't restored, but returned.
+fn try_to_undo(
+    path: &Path,
+    entry: &JournalEntry,
+    p: &Profile,
+    installed: &InstalledFiles,
+    dry_run: bool,
+) -> Result<Option<Untrusted>> {
+    // Files it covered still belong to the mods that installed them.
+    if entry.action == JournalAction::Covered {
+        return try_to_uncover(path, p, installed, dry_run).map(|()| None);
+    }
+    if installed.contains_key(&path_key(path, p.case_insensitive)) {
         bail!(
             "{} is referenced in both the activation jurnal and the profile. \
         Something is wrong - journals should be deleted before their mod is added to the profile.",
@@ -82,40 +139,138 @@ fn try_to_undo(path: &Path, action: JournalAction, p: &Profile, dry_run: bool) -
         );
     }
 
-    match action {
-        JournalAction::Added => try_to_remove(path, &p, dry_run),
-        JournalAction::Replaced => try_to_restore(path, &p, dry_run),
+    match entry.action {
+        JournalAction::Added => try_to_remove(path, p, dry_run).map(|()| None),
+        JournalAction::Replaced => try_to_restore(path, entry.original_hash.as_ref(), p, dry_run),
+        JournalAction::Covered => unreachable!(),
     }
 }
 
 fn try_to_remove(path: &Path, p: &Profile, dry_run: bool) -> Result<()> {
     info!("Remove {}", path.display());
     if !dry_run {
-        let game_path = mod_path_to_game_path(path, &p.root_directory);
-        fs::remove_file(&game_path)
+        history::note_file(path);
+        let game_path = p.game_path(path);
+        discard_file(&game_path)
             .with_context(|| format!("Couldn't remove {}", game_path.display()))?;
+        remove_empty_parents(&game_path, &p.game_dirs().root_of(path))?;
     }
 
     Ok(())
 }
 
-fn try_to_restore(path: &Path, p: &Profile, dry_run: bool) -> Result<()> {
+/// Puts back the file of the mod that the interrupted `modman add --overwrite`
+/// was installing over, from covered/.
+fn try_to_uncover(
+    path: &Path,
+    p: &Profile,
+    installed: &InstalledFiles,
+    dry_run: bool,
+) -> Result<()> {
+    // It's the last mod layered over the file, or the only one with it.
+    let key = path_key(path, p.case_insensitive);
+    let top = p.layers.get(&key).and_then(|layers| layers.last());
+    let meta = installed
+        .get(&key)
+        .and_then(|mods| {
+            mods.iter()
+                .find(|(mod_path, _, _)| top.is_none() || top == Some(*mod_path))
+        })
+        .map(|(_, _, meta)| *meta)
+        .ok_or_else(|| {
+            format_err!(
+                "The journal says {} was installed over another mod's file, \
+                 but no mod in the profile has it.",
+                path.display()
+            )
+        })?;
+    info!("Put back {}", path.display());
+    let covered = covered_path(&meta.mod_hash);
+    let game_path = p.game_path(path);
+    // It might have been interrupted before it got that far.
+    if !covered.exists() {
+        if game_path.is_file() && hash_file(&game_path)? == meta.mod_hash {
+            return Ok(());
+        }
+        bail!(
+            "{} is missing, so {} can't be put back",
+            covered.display(),
+            game_path.display()
+        );
+    }
+    if !dry_run {
+        history::note_file(path);
+        copy_file(&covered, &game_path).with_context(|| {
+            format!(
+                "Couldn't copy {} to {}",
+                covered.display(),
+                game_path.display()
+            )
+        })?;
+        copy_mtime(&covered, &game_path)?;
+        match meta.mode {
+            Some(mode) => set_mode(&game_path, mode)?,
+            None => clear_executable(&game_path)?,
+        }
+        // Unless a mod still there covers the same contents, it's done.
+        if !p.needs_covered(&meta.mod_hash) {
+            discard_file(&covered)
+                .with_context(|| format!("Couldn't remove {}", covered.display()))?;
+        }
+    }
+    Ok(())
+}
+
+fn try_to_restore(
+    path: &Path,
+    expected: Option<&FileHash>,
+    p: &Profile,
+    dry_run: bool,
+) -> Result<Option<Untrusted>> {
     info!("Restore {}", path.display());
+    let backup_path = mod_path_to_backup_path(path);
+    // Don't put back something besides what we replaced.
+    if let Some(expected) = expected {
+        let actual = hash_file(&backup_path)?;
+        if actual != *expected {
+            return Ok(Some(Untrusted {
+                path: path.to_owned(),
+                expected: expected.clone(),
+                actual,
+            }));
+        }
+    }
     if !dry_run {
-        let backup_path = mod_path_to_backup_path(path);
-        let game_path = mod_path_to_game_path(path, &p.root_directory);
+        history::note_file(path);
+        let game_path = p.game_path(path);
+        // Don't copy the original through a link we installed!
+        if is_symlink(&game_path) {
+            remove_file(&game_path)
+                .with_context(|| format!("Couldn't remove {}", game_path.display()))?;
+        }
         // Let copy fail if the backup doesn't exist.
-        fs::copy(&backup_path, &game_path).with_context(|| {
+        copy_file(&backup_path, &game_path).with_context(|| {
             format!(
                 "Couldn't copy {} to {}",
                 backup_path.display(),
                 game_path.display()
             )
         })?;
+        copy_mtime(&backup_path, &game_path)?;
+        if let Some(expected) = expected {
+            if hash_file(&game_path)? != *expected {
+                bail!(
+                    "{} doesn't match its backup after restoring it; keeping {}",
+                    game_path.display(),
+                    backup_path.display()
+                );
+            }
+        }
         // If restoration succeeds, let's remove the backup.
-        fs::remove_file(&backup_path)
+        discard_file(&backup_path)
             .with_context(|| format!("Couldn't remove {}", backup_path.display()))?;
+        remove_empty_parents(&backup_path, &backup_dir())?;
     }
 
-    Ok(())
+    Ok(None)
 }
@@ -0,0 +1,94 @@
+use std::fs;
+
+use anyhow::*;
+use structopt::*;
+
+use crate::failure::*;
+use crate::profile::*;
+use crate::profile_store::*;
+
+/// Lists or restores earlier versions of the profile
+///
+/// Each command that changes the profile (including ones sent to
+/// `modman serve`) first keeps the one it's replacing
+/// (modman.profile.1 is the newest, up to modman.profile.5).
+/// Without <GENERATION>, lists them. With it, restores that version,
+/// keeping the current one as generation 1 so this can be undone too.
+/// Only the profile changes, not the game's files;
+/// run `modman check` afterwards to make sure they agree.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    #[structopt(name = "GENERATION")]
+    generation: Option<usize>,
+}
+
+impl Args {
+    /// Returns true if we'll change the profile (not just list versions).
+    pub fn is_changing(&self) -> bool {
+        self.generation.is_some()
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    if existing_backend() == Some(Backend::Sqlite) {
+        fail!(
+            Failure::Usage,
+            "Only JSON profiles ({}) keep earlier versions",
+            PROFILE_PATH
+        );
+    }
+
+    let generation = match args.generation {
+        Some(g) => g,
+        None => return list_generations(),
+    };
+    let path = profile_generation_path(generation);
+    if generation == 0 || !path.exists() {
+        fail!(
+            Failure::Usage,
+            "There's no generation {} of the profile (see `modman profile rollback`)",
+            generation
+        );
+    }
+    let p = read_profile_from(&path)?;
+    update_profile_file(&p)?;
+    println!(
+        "Restored generation {} of the profile ({} mod{} installed).\n\
+         The game's files weren't changed; run `modman check` to make sure they match it.",
+        generation,
+        p.mods.len(),
+        if p.mods.len() == 1 { "" } else { "s" }
+    );
+    Ok(())
+}
+
+fn list_generations() -> Result<()> {
+    let mut any = false;
+    for generation in 1..=PROFILE_GENERATIONS {
+        let path = profile_generation_path(generation);
+        let modified = match fs::metadata(&path).and_then(|m| m.modified()) {
+            Ok(m) => m,
+            Err(_) => continue,
+        };
+        any = true;
+        let mods = match read_profile_from(&path) {
+            Ok(p) => format!(
+                "{} mod{}",
+                p.mods.len(),
+                if p.mods.len() == 1 { "" } else { "s" }
+            ),
+            Err(_) => "damaged".to_owned(),
+        };
+        println!(
+            "{}: {} ({})",
+            generation,
+            humantime::format_rfc3339_seconds(modified),
+            mods
+        );
+    }
+    if !any {
+        println!("No earlier versions of the profile have been kept.");
+    }
+    Ok(())
+}
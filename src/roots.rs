@@ -0,0 +1,99 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use structopt::*;
+
+use crate::profile::*;
+
+/// Lists or changes the profile's named roots
+///
+/// Besides the root directory (named `game`), mods can install files
+/// to other directories, like a game's folder in your documents.
+/// Mods pick a root with `root = "<NAME>"` in their mod.toml
+/// (or `[roots]` for certain files), or with `modman add --root-name`.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    #[structopt(subcommand)]
+    action: Option<Action>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Action {
+    /// Names <DIR> as a root mods can install to,
+    /// or points the existing root <NAME> at it.
+    Add {
+        #[structopt(name = "NAME")]
+        name: String,
+
+        #[structopt(name = "DIR")]
+        dir: PathBuf,
+    },
+    /// Removes the root <NAME>. No installed mods can use it.
+    Remove {
+        #[structopt(name = "NAME")]
+        name: String,
+    },
+}
+
+impl Args {
+    /// Returns true if we'll change the profile (not just show it).
+    pub fn is_changing(&self) -> bool {
+        self.action.is_some()
+    }
+
+    pub fn resolve_paths(&mut self, base: &Path) {
+        if let Some(Action::Add { dir, .. }) = &mut self.action {
+            *dir = base.join(&dir);
+        }
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let (mut store, mut p) = open_profile()?;
+
+    match args.action {
+        None => {
+            println!("{}: {}", DEFAULT_ROOT_NAME, p.root_directory.display());
+            for (name, dir) in &p.roots {
+                println!("{}: {}", name, dir.display());
+            }
+            return Ok(());
+        }
+        Some(Action::Add { name, dir }) => {
+            if name == DEFAULT_ROOT_NAME {
+                bail!(
+                    "The root directory is always named {} (use `modman relocate-root` to move it)",
+                    DEFAULT_ROOT_NAME
+                );
+            }
+            if name.is_empty() || name.contains(['/', '\\']) {
+                bail!("{} isn't a valid root name", name);
+            }
+            if !dir.is_dir() {
+                bail!("{} is not an existing directory!", dir.display());
+            }
+            p.roots.insert(name, dir);
+        }
+        Some(Action::Remove { name }) => {
+            if p.roots.remove(&name).is_none() {
+                bail!("The profile has no root named {}", name);
+            }
+            let users: Vec<_> = p
+                .mods
+                .iter()
+                .filter(|(_, manifest)| {
+                    manifest
+                        .files
+                        .keys()
+                        .any(|f| split_root(f).0 == Some(name.as_str()))
+                })
+                .map(|(mod_path, _)| mod_path.display().to_string())
+                .collect();
+            if !users.is_empty() {
+                bail!("Installed mods use the {} root: {}", name, users.join(", "));
+            }
+        }
+    }
+    store.save_settings(&p)
+}
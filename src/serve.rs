@@ -0,0 +1,319 @@
+//! `modman serve`: a JSON-RPC 2.0 server on a Unix domain socket,
+//! so long-running frontends can drive modman without spawning it for every action.
+//!
+//! Requests and responses are one JSON object per line:
+//!
+//! ```text
+//! --> {"jsonrpc": "2.0", "id": 1, "method": "add", "params": {"mods": ["mod1.zip"]}}
+//! <-- {"jsonrpc": "2.0", "id": 1, "result": null}
+//! ```
+//!
+//! Methods:
+//!
+//! - `list`: the installed mods, as `[{"path", "version", "alias", "tags", "files"}]`
+//! - `add`, `remove`, and `update`: take `{"mods": [...], "dry-run": bool}`
+//!   like the commands do (`update`'s mods are optional)
+//! - `check`: returns true if the checks passed, or false if they found problems
+//! - `subscribe`: sends this connection progress events as `progress` notifications
+//!   (see `--progress json`), including warnings
+//! - `shutdown`: stops the server
+//!
+//! Errors' codes are modman's exit codes (see `modman --help`), besides
+//! JSON-RPC's own for malformed requests.
+//!
+//! Clients can connect at the same time, but operations run one at a time.
+//! A JSON profile is kept in memory between them, and only read again when
+//! the file changes, so changes made with the command line in the meantime
+//! aren't lost. (SQLite profiles are read from their database each time.)
+
+use std::io::{prelude::*, BufReader};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use anyhow::*;
+use log::*;
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use structopt::*;
+
+use crate::failure::*;
+use crate::history;
+use crate::profile::*;
+use crate::progress;
+use crate::{add, check, list, remove, update};
+
+/// Serves modman's commands over a local socket
+///
+/// Frontends (GUIs, web dashboards, etc.) can connect to <SOCKET>
+/// and send JSON-RPC 2.0 requests, one per line, to list, add, remove,
+/// check, and update mods, and to subscribe to progress events.
+/// Mod paths are relative to the profile's directory.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    /// Where to put the socket
+    /// (by default, serve.sock in the backup directory)
+    #[structopt(long, name = "SOCKET")]
+    socket: Option<PathBuf>,
+}
+
+impl Args {
+    pub fn resolve_paths(&mut self, base: &Path) {
+        if let Some(socket) = &mut self.socket {
+            *socket = base.join(&socket);
+        }
+    }
+}
+
+// JSON-RPC's error codes
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    jsonrpc: String,
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+struct ModParams {
+    mods: Vec<PathBuf>,
+    dry_run: bool,
+}
+
+/// A connection's socket, shared between its responses
+/// and progress notifications from other connections' operations
+type Client = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// Everyone who's subscribed to progress events
+static SUBSCRIBERS: Mutex<Vec<Client>> = Mutex::new(Vec::new());
+
+/// Only one operation runs at a time.
+static OPERATION: Mutex<()> = Mutex::new(());
+
+/// Waits for any other operation to finish.
+fn lock_operation() -> MutexGuard<'static, ()> {
+    // Operations' panics are caught and sent back as errors,
+    // but don't let one poison every request after it if that fails.
+    OPERATION
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Writes each progress event to every subscriber as a JSON-RPC notification.
+struct Broadcast;
+
+impl Write for Broadcast {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // The progress module writes each event, newline and all, at once.
+        let event = String::from_utf8_lossy(buf);
+        let notification = format!(
+            "{{\"jsonrpc\":\"2.0\",\"method\":\"progress\",\"params\":{}}}\n",
+            event.trim_end()
+        );
+        // Drop anyone who hung up.
+        SUBSCRIBERS.lock().unwrap().retain(|client| {
+            client
+                .lock()
+                .unwrap()
+                .write_all(notification.as_bytes())
+                .is_ok()
+        });
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+pub fn run(args: Args) -> Result<()> {
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    // Make sure there's a profile to serve.
+    cache_profile();
+    load_and_check_profile()?;
+    let socket = args
+        .socket
+        .unwrap_or_else(|| storage_dir().join("serve.sock"));
+
+    if socket.exists() {
+        if UnixStream::connect(&socket).is_ok() {
+            bail!("Another modman is already serving at {}", socket.display());
+        }
+        // Left over from a server that didn't shut down cleanly
+        std::fs::remove_file(&socket)
+            .with_context(|| format!("Couldn't remove {}", socket.display()))?;
+    }
+    let listener = UnixListener::bind(&socket)
+        .with_context(|| format!("Couldn't listen on {}", socket.display()))?;
+    info!("Serving on {}", socket.display());
+
+    progress::begin_with(Box::new(Broadcast));
+
+    // Connections get their own threads,
+    // but operations should use as many workers as we were told to.
+    let pool = Arc::new(
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(rayon::current_num_threads())
+            .build()
+            .context("Couldn't start worker threads")?,
+    );
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Couldn't accept a connection: {}", e);
+                continue;
+            }
+        };
+        let pool = pool.clone();
+        let socket = socket.clone();
+        std::thread::spawn(move || {
+            let reader = match stream.try_clone() {
+                Ok(r) => r,
+                Err(e) => {
+                    warn!("Couldn't read from a connection: {}", e);
+                    return;
+                }
+            };
+            let client: Client = Arc::new(Mutex::new(Box::new(stream)));
+            // Hold onto the operation lock until we exit
+            // so we don't cut another client's operation short.
+            if let Some(_operation) = serve_client(reader, &client, &pool) {
+                info!("Shutting down");
+                let _ = std::fs::remove_file(&socket);
+                std::process::exit(0);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn run(_args: Args) -> Result<()> {
+    bail!("`modman serve` needs Unix domain sockets, which this platform doesn't have (yet)")
+}
+
+/// Handles requests from a client until it disconnects.
+/// If it asked us to shut down, returns the operation lock,
+/// taken once any running operation finished.
+fn serve_client<R: Read>(
+    reader: R,
+    client: &Client,
+    pool: &rayon::ThreadPool,
+) -> Option<MutexGuard<'static, ()>> {
+    for line in BufReader::new(reader).lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(_) => break,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (response, shut_down) = match serde_json::from_str::<Request>(&line) {
+            Ok(req) if req.jsonrpc != "2.0" => (
+                error_response(&req.id, INVALID_REQUEST, "Expected JSON-RPC 2.0"),
+                None,
+            ),
+            Ok(req) if req.method == "shutdown" => (
+                json!({"jsonrpc": "2.0", "id": req.id, "result": null}),
+                Some(lock_operation()),
+            ),
+            Ok(req) => (handle(&req, client, pool), None),
+            Err(e) => (
+                error_response(&Value::Null, PARSE_ERROR, &e.to_string()),
+                None,
+            ),
+        };
+        let mut line = response.to_string();
+        line.push('\n');
+        if client.lock().unwrap().write_all(line.as_bytes()).is_err() {
+            break;
+        }
+        if shut_down.is_some() {
+            return shut_down;
+        }
+    }
+    None
+}
+
+fn handle(req: &Request, client: &Client, pool: &rayon::ThreadPool) -> Value {
+    debug!("Got request {:?}", req);
+    let method = req.method.as_str();
+    let params = match method {
+        "add" | "remove" | "update" if !req.params.is_null() => {
+            match serde_json::from_value(req.params.clone()) {
+                Ok(p) => p,
+                Err(e) => return error_response(&req.id, INVALID_PARAMS, &e.to_string()),
+            }
+        }
+        "list" | "check" | "add" | "remove" | "update" | "subscribe" => ModParams::default(),
+        other => {
+            return error_response(
+                &req.id,
+                METHOD_NOT_FOUND,
+                &format!("There's no method {}", other),
+            )
+        }
+    };
+
+    let _operation = lock_operation();
+    let operation = || match method {
+        "list" => Ok(serde_json::to_value(list::listed_mods()?)?),
+        "check" => match check::run(check::Args::new(false)) {
+            Ok(()) => Ok(json!(true)),
+            Err(e) if exit_code(&e) == Failure::VerificationFailed as i32 => Ok(json!(false)),
+            Err(e) => Err(e),
+        },
+        "add" => logged(method, &params, || {
+            add::run(add::Args::new(params.mods.clone(), params.dry_run))
+        }),
+        "remove" => logged(method, &params, || {
+            remove::remove_mods(params.mods.clone(), false, false, params.dry_run)
+        }),
+        "update" => logged(method, &params, || {
+            update::update_mods(params.mods.clone(), false, false, params.dry_run)
+        }),
+        "subscribe" => {
+            SUBSCRIBERS.lock().unwrap().push(client.clone());
+            Ok(Value::Null)
+        }
+        _ => unreachable!(),
+    };
+    let result = catch_unwind(AssertUnwindSafe(|| pool.install(operation)))
+        .unwrap_or_else(|_| Err(format_err!("modman panicked (this is a bug)")));
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": req.id, "result": result}),
+        Err(e) => error_response(&req.id, exit_code(&e), &format!("{:#}", e)),
+    }
+}
+
+/// Runs a command that changes the game directory, recording it in the history log
+/// like it would be from the command line.
+fn logged<F: FnOnce() -> Result<()>>(method: &str, params: &ModParams, f: F) -> Result<Value> {
+    let mut command = vec!["serve".to_owned(), method.to_owned()];
+    if params.dry_run {
+        command.push("--dry-run".to_owned());
+    }
+    command.extend(params.mods.iter().map(|m| m.display().to_string()));
+    history::record(command, f).map(|()| Value::Null)
+}
+
+fn error_response(id: &Value, code: i32, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {"code": code, "message": message},
+    })
+}
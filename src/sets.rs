@@ -0,0 +1,182 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use structopt::*;
+
+use crate::download::is_url;
+use crate::failure::*;
+use crate::pack::*;
+use crate::profile::*;
+
+/// Lists, saves, or switches between named sets of mods
+///
+/// A set (say, "PvP" or "Campaign") is a list of mods kept in the profile.
+/// Applying one removes the installed mods that aren't in it
+/// and adds the ones that aren't installed, leaving the rest alone.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    #[structopt(subcommand)]
+    action: Option<Action>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Action {
+    /// Saves <MOD>s (or, if none are given, the installed mods) as the set <NAME>,
+    /// replacing any set by that name.
+    Save {
+        #[structopt(name = "NAME")]
+        name: String,
+
+        /// Paths or URLs of mods, or names of installed ones
+        #[structopt(name = "MOD")]
+        mod_names: Vec<PathBuf>,
+    },
+    /// Removes and adds mods so that exactly the set <NAME> is installed.
+    Apply {
+        #[structopt(short = "n", long)]
+        dry_run: bool,
+
+        #[structopt(name = "NAME")]
+        name: String,
+    },
+    /// Forgets the set <NAME>. Its mods are left installed.
+    Remove {
+        #[structopt(name = "NAME")]
+        name: String,
+    },
+}
+
+impl Args {
+    /// Returns true if we'll add or remove mods.
+    pub fn is_applying(&self) -> bool {
+        matches!(self.action, Some(Action::Apply { .. }))
+    }
+
+    /// Makes this a dry run, for `--no-write`.
+    /// Returns false if it would still write something (a saved set).
+    pub fn make_dry_run(&mut self) -> bool {
+        match &mut self.action {
+            None => true,
+            Some(Action::Apply { dry_run, .. }) => {
+                *dry_run = true;
+                true
+            }
+            Some(_) => false,
+        }
+    }
+
+    pub fn resolve_paths(&mut self, base: &Path) {
+        if let Some(Action::Save { mod_names, .. }) = &mut self.action {
+            for name in mod_names {
+                if !name.to_str().map(is_url).unwrap_or(false) {
+                    resolve_mod_name(base, name);
+                }
+            }
+        }
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    match args.action {
+        None => list_sets(),
+        Some(Action::Save { name, mod_names }) => save_set(name, mod_names),
+        Some(Action::Apply { dry_run, name }) => {
+            let p = load_and_check_profile()?;
+            let set = ModPack {
+                mods: get_set(&p, &name)?.clone(),
+            };
+            sync_to(Path::new(""), &set, &name, dry_run)
+        }
+        Some(Action::Remove { name }) => {
+            let (mut store, mut p) = open_and_check_profile()?;
+            get_set(&p, &name)?;
+            p.sets.remove(&name);
+            store.save_settings(&p)?;
+            println!("Removed the set {}", name);
+            Ok(())
+        }
+    }
+}
+
+fn get_set<'a>(p: &'a Profile, name: &str) -> Result<&'a Vec<PackEntry>> {
+    match p.sets.get(name) {
+        Some(s) => Ok(s),
+        None => fail!(Failure::Usage, "The profile has no set named {}", name),
+    }
+}
+
+fn list_sets() -> Result<()> {
+    let p = load_and_check_profile()?;
+    if p.sets.is_empty() {
+        println!("No sets have been saved.");
+        return Ok(());
+    }
+    for (name, entries) in &p.sets {
+        let set = ModPack {
+            mods: entries.clone(),
+        };
+        let applied = diff_pack(Path::new(""), &set, &p)?.is_empty();
+        println!("{}{}", name, if applied { " (applied)" } else { "" });
+        for entry in entries {
+            println!("\t{}", entry.source);
+        }
+    }
+    Ok(())
+}
+
+fn save_set(name: String, mod_names: Vec<PathBuf>) -> Result<()> {
+    let (mut store, mut p) = open_and_check_profile()?;
+    if name.is_empty() {
+        bail!("A set's name can't be empty");
+    }
+
+    // Installed mods are saved by where they came from,
+    // so applying the set can add them again later.
+    let source_of = |path: &Path, manifest: &ModManifest| match &manifest.source {
+        Some(url) => url.clone(),
+        None => path.to_string_lossy().into_owned(),
+    };
+    let mut sources = Vec::new();
+    if mod_names.is_empty() {
+        for (path, manifest) in install_order(&p) {
+            sources.push(source_of(path, manifest));
+        }
+    } else {
+        for name in &mod_names {
+            let as_str = name.to_string_lossy();
+            let source = if is_url(&as_str) || name.exists() {
+                as_str.into_owned()
+            } else {
+                let path = p.find_mod(name)?;
+                source_of(&path, &p.mods[&path])
+            };
+            if sources.contains(&source) {
+                bail!("{} is in the set more than once", source);
+            }
+            sources.push(source);
+        }
+    }
+    if sources.is_empty() {
+        fail!(Failure::Usage, "No mods are installed to save as a set");
+    }
+
+    let count = sources.len();
+    let entries = sources
+        .into_iter()
+        .map(|source| PackEntry {
+            source,
+            version: None,
+            sha224: None,
+        })
+        .collect();
+    p.sets.insert(name.clone(), entries);
+    store.save_settings(&p)?;
+    println!(
+        "Saved {} mod{} as the set {}",
+        count,
+        if count == 1 { "" } else { "s" },
+        name
+    );
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use log::*;
+use structopt::*;
+
+use crate::modification::*;
+use crate::pager;
+use crate::profile::*;
+use crate::tag::format_tags;
+
+/// Shows what's recorded about an installed mod
+///
+/// <MOD> can be the path the mod was installed from, its alias,
+/// its name (sans extension), or just part of its path.
+///
+/// Long READMEs and changelogs are shown with $PAGER.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    /// List the files the mod installed.
+    #[structopt(short, long)]
+    files: bool,
+
+    /// Show the mod's README (from the mod itself, so it needs to still be there).
+    #[structopt(short, long)]
+    readme: bool,
+
+    /// Show the mod's CHANGELOG.txt, if it has one (also from the mod itself).
+    #[structopt(short, long)]
+    changelog: bool,
+
+    #[structopt(name = "MOD")]
+    mod_name: PathBuf,
+}
+
+impl Args {
+    pub fn resolve_paths(&mut self, base: &Path) {
+        resolve_mod_name(base, &mut self.mod_name);
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let p = load_and_check_profile()?;
+    let mod_path = p.find_mod(&args.mod_name)?;
+    let manifest = &p.mods[&mod_path];
+
+    println!("{} (v{})", mod_path.display(), manifest.version);
+    if let Some(raw) = &manifest.version_string {
+        println!("Version as the mod wrote it: {}", raw);
+    }
+    if let Some(alias) = &manifest.alias {
+        println!("Alias: {}", alias);
+    }
+    if !manifest.tags.is_empty() {
+        println!("Tags: {}", format_tags(&manifest.tags));
+    }
+    if let Some(source) = &manifest.source {
+        println!("Downloaded from: {}", source);
+    }
+    if let Some(root) = &manifest.root {
+        println!("Installed to root: {}", root);
+    }
+    for req in &manifest.requires {
+        println!("Requires: {}", req);
+    }
+    for conflict in &manifest.conflicts {
+        println!("Conflicts with: {}", conflict);
+    }
+    let replaced = manifest
+        .files
+        .values()
+        .filter(|meta| meta.original_hash.is_some())
+        .count();
+    println!(
+        "{} files ({} replaced game files)",
+        manifest.files.len(),
+        replaced
+    );
+    if args.files {
+        for f in manifest.files.keys() {
+            println!("\t{}", f.display());
+        }
+    }
+
+    if args.readme || args.changelog {
+        // We don't store these in the manifest, so go get the mod itself.
+        let m = open_mod(&mod_path, p.unicode_form, manifest.root.as_deref())
+            .with_context(|| format!("Couldn't open mod {}", mod_path.display()))?;
+        let mut text = String::new();
+        if args.readme {
+            text += m.readme();
+        }
+        if args.changelog {
+            match m.changelog() {
+                Some(changelog) => {
+                    if !text.is_empty() && !text.ends_with('\n') {
+                        text.push('\n');
+                    }
+                    text += changelog;
+                }
+                None => warn!("{} doesn't have a CHANGELOG.txt", mod_path.display()),
+            }
+        }
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        pager::page(&text)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,93 @@
+//! Making sure there's room for what we're about to copy
+//! before we start, instead of running out halfway through
+//! (and leaving a journal to clean up).
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use log::*;
+
+use crate::failure::*;
+use crate::stats::format_size;
+
+/// How many bytes we need on each filesystem we're writing to
+#[derive(Debug, Default)]
+pub struct SpaceNeeded {
+    /// A directory on each filesystem (for messages and checking free space),
+    /// and how much we'll write there
+    filesystems: BTreeMap<FilesystemId, (PathBuf, u64)>,
+}
+
+impl SpaceNeeded {
+    /// Notes that we'll write the given number of bytes to the given path.
+    pub fn add(&mut self, path: &Path, bytes: u64) -> Result<()> {
+        let dir = existing_ancestor(path);
+        let id = filesystem_id(&dir)?;
+        self.filesystems.entry(id).or_insert((dir, 0)).1 += bytes;
+        Ok(())
+    }
+
+    /// Fails if any filesystem doesn't have room for what we'll write to it.
+    pub fn check(&self) -> Result<()> {
+        for (dir, needed) in self.filesystems.values() {
+            let available = fs2::available_space(dir).with_context(|| {
+                format!("Couldn't find how much space is free on {}", dir.display())
+            })?;
+            debug!(
+                "Need {} on the filesystem with {}; {} is free",
+                format_size(*needed),
+                dir.display(),
+                format_size(available)
+            );
+            if *needed > available {
+                fail!(
+                    Failure::NoSpace,
+                    "This needs {} on the drive with {}, but only {} is free. \
+                     Free up some space, or pass --skip-space-check if you're sure.",
+                    format_size(*needed),
+                    dir.display(),
+                    format_size(available)
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The closest directory at or above the path that exists
+/// (since we often want to know about a file we haven't made yet).
+fn existing_ancestor(path: &Path) -> PathBuf {
+    let mut dir = path;
+    loop {
+        if dir.is_dir() {
+            return dir.to_owned();
+        }
+        match dir.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => dir = parent,
+            _ => return PathBuf::from("."),
+        }
+    }
+}
+
+#[cfg(unix)]
+type FilesystemId = u64;
+
+#[cfg(unix)]
+fn filesystem_id(path: &Path) -> Result<FilesystemId> {
+    use std::os::unix::fs::MetadataExt;
+    let meta =
+        std::fs::metadata(path).with_context(|| format!("Couldn't stat {}", path.display()))?;
+    Ok(meta.dev())
+}
+
+/// Without device numbers, go by drive (or the share on a network path).
+#[cfg(not(unix))]
+type FilesystemId = PathBuf;
+
+#[cfg(not(unix))]
+fn filesystem_id(path: &Path) -> Result<FilesystemId> {
+    let full = std::fs::canonicalize(path)
+        .with_context(|| format!("Couldn't find the full path of {}", path.display()))?;
+    Ok(full.components().take(1).collect())
+}
@@ -0,0 +1,72 @@
+//! How much disk space installed mods (and the backups they need) take up
+
+use anyhow::*;
+
+use crate::file_utils::*;
+use crate::profile::*;
+
+/// Bytes a mod takes up
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ModSize {
+    /// Its files in the game directory
+    pub installed: u64,
+    /// Backups of the game files it replaced
+    pub backups: u64,
+}
+
+/// Adds up the sizes of a mod's files.
+///
+/// Profiles from before we recorded sizes don't have them,
+/// so look at what's on disk for those files.
+pub fn mod_size(p: &Profile, manifest: &ModManifest) -> ModSize {
+    let mut size = ModSize::default();
+    for (path, meta) in &manifest.files {
+        size.installed += meta
+            .size
+            .unwrap_or_else(|| file_size(&p.game_path(path)).unwrap_or(0));
+        if meta.original_hash.is_some() {
+            size.backups += meta
+                .original_size
+                .unwrap_or_else(|| file_size(&mod_path_to_backup_path(path)).unwrap_or(0));
+        }
+    }
+    size
+}
+
+/// Formats a number of bytes for people, e.g., 1.5 MiB.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+pub fn run() -> Result<()> {
+    let p = load_and_check_profile()?;
+
+    let mut total = ModSize::default();
+    for (mod_path, manifest) in &p.mods {
+        let size = mod_size(&p, manifest);
+        println!(
+            "{}: {} installed, {} of backups",
+            mod_path.display(),
+            format_size(size.installed),
+            format_size(size.backups)
+        );
+        total.installed += size.installed;
+        total.backups += size.backups;
+    }
+    println!(
+        "Total: {} installed, {} of backups",
+        format_size(total.installed),
+        format_size(total.backups)
+    );
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use anyhow::*;
+use structopt::*;
+
+use crate::games;
+use crate::profile::*;
+
+/// Shows or sets the game's Steam app ID
+///
+/// With an app ID, `modman check` can tell when Steam updates the game
+/// (by comparing its build to the one at the last `modman update`)
+/// without hashing any files.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    /// Forget the app ID.
+    #[structopt(long, conflicts_with = "APP_ID")]
+    clear: bool,
+
+    #[structopt(name = "APP_ID")]
+    app_id: Option<u32>,
+}
+
+impl Args {
+    /// Returns true if we'll change the profile (not just show it).
+    pub fn is_changing(&self) -> bool {
+        self.clear || self.app_id.is_some()
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let (mut store, mut p) = open_profile()?;
+
+    if args.clear {
+        p.steam_app_id = None;
+        p.steam_build_id = None;
+    } else if let Some(id) = args.app_id {
+        if games::app_manifest_path(id).is_none() {
+            bail!("Couldn't find Steam app {} in any Steam library", id);
+        }
+        p.steam_app_id = Some(id);
+        // Start with the current build; we can't know if anything changed before now.
+        p.steam_build_id = None;
+        games::note_steam_build(&mut p, true)?;
+    } else {
+        match p.steam_app_id {
+            Some(id) => {
+                println!("Steam app {}", id);
+                if let Some(b) = &p.steam_build_id {
+                    println!("Build at the last update: {}", b);
+                }
+                if let Some(b) = games::steam_build_id(id)? {
+                    println!("Current build: {}", b);
+                }
+            }
+            None => println!("No Steam app ID set"),
+        }
+        return Ok(());
+    }
+    store.save_settings(&p)
+}
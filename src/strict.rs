@@ -0,0 +1,126 @@
+//! Strict mode (`--strict`, or `modman strict on`), for scripts that would
+//! rather fail than carry on past certain warnings.
+//!
+//! Warnings that strict mode cares about are given with `strict_warn!`,
+//! which logs them like `warn!` and notes their class.
+//! The command still finishes what it was doing (so it doesn't leave things
+//! half done), but then fails with `Failure::StrictWarnings`.
+//!
+//! Like the history log and progress events, this is global state.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use anyhow::*;
+use structopt::*;
+
+use crate::failure::*;
+use crate::profile::*;
+use crate::profile_store::open_profile_store;
+
+/// Kinds of warnings that are errors in strict mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Warning {
+    /// A backup restored by `remove` didn't hash to what the profile recorded.
+    RestoreMismatch,
+    /// A mod's file is a different version than the one that was installed.
+    VersionDrift,
+    /// A mod's file changed since it was installed.
+    ModChanged,
+    /// An installed file (or backup) was already gone.
+    MissingFile,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Warning::RestoreMismatch => "restore-mismatch",
+            Warning::VersionDrift => "version-drift",
+            Warning::ModChanged => "mod-changed",
+            Warning::MissingFile => "missing-file",
+        })
+    }
+}
+
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+static GIVEN: Mutex<BTreeSet<Warning>> = Mutex::new(BTreeSet::new());
+
+/// Turns strict mode on (or off).
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
+}
+
+/// Returns true if the profile has strict mode on.
+/// If there's no profile (or it can't be read), it doesn't.
+pub fn profile_is_strict() -> bool {
+    open_profile_store()
+        .and_then(|mut s| s.load_settings())
+        .is_ok_and(|p| p.strict)
+}
+
+/// Notes that a warning of the given class was given.
+pub fn note(warning: Warning) {
+    GIVEN.lock().unwrap().insert(warning);
+}
+
+/// Like `warn!`, but notes the warning's class for strict mode.
+#[macro_export]
+macro_rules! strict_warn {
+    ($warning:ident, $($arg:tt)*) => {{
+        $crate::strict::note($crate::strict::Warning::$warning);
+        log::warn!($($arg)*);
+    }};
+}
+
+/// Fails if we're in strict mode and any warnings it cares about were given.
+pub fn finish() -> Result<()> {
+    let given = GIVEN.lock().unwrap();
+    if !STRICT.load(Ordering::Relaxed) || given.is_empty() {
+        return Ok(());
+    }
+    let classes: Vec<String> = given.iter().map(Warning::to_string).collect();
+    fail!(
+        Failure::StrictWarnings,
+        "Failing because of warnings in strict mode ({})",
+        classes.join(", ")
+    );
+}
+
+/// Shows or sets whether the profile is in strict mode
+///
+/// In strict mode (also turned on with `modman --strict`), these warnings
+/// make a command fail (with exit code 10) once it's done:
+///     restore-mismatch  A restored backup didn't match the profile's hash of it.
+///     version-drift     A mod's file is a different version than the one installed.
+///     mod-changed       A mod's file changed since it was installed.
+///     missing-file      An installed file or backup was already gone.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    #[structopt(name = "SETTING", possible_values = &["on", "off"])]
+    setting: Option<String>,
+}
+
+impl Args {
+    /// Returns true if we'll change the profile (not just show it).
+    pub fn is_changing(&self) -> bool {
+        self.setting.is_some()
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let (mut store, mut p) = open_profile()?;
+    match args.setting.as_deref() {
+        Some(s) => {
+            p.strict = s == "on";
+            store.save_settings(&p)
+        }
+        None => {
+            println!("{}", if p.strict { "on" } else { "off" });
+            Ok(())
+        }
+    }
+}
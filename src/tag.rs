@@ -0,0 +1,75 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use structopt::*;
+
+use crate::profile::*;
+
+/// Lists or changes an installed mod's tags
+///
+/// Tags group mods (say, `graphics` or `multiplayer-safe`) so that
+/// `modman list --tag` and `modman remove --tag` can work on them together.
+/// Each change is +<TAG> to add a tag or -<TAG> to remove one.
+/// Mods can tag themselves with `tags = [...]` in their mod.toml.
+/// Only the profile changes; the mod's files are left as they are.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment, setting = clap::AppSettings::AllowLeadingHyphen)]
+pub struct Args {
+    #[structopt(name = "MOD")]
+    mod_name: PathBuf,
+
+    #[structopt(name = "CHANGES")]
+    changes: Vec<String>,
+}
+
+impl Args {
+    /// Returns true if we'll change the profile (not just show tags).
+    pub fn is_changing(&self) -> bool {
+        !self.changes.is_empty()
+    }
+
+    pub fn resolve_paths(&mut self, base: &Path) {
+        resolve_mod_name(base, &mut self.mod_name);
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let (mut store, mut p) = open_and_check_profile()?;
+    let mod_path = p.find_mod(&args.mod_name)?;
+    let manifest = p.mods.get_mut(&mod_path).unwrap();
+
+    if args.is_changing() {
+        for change in &args.changes {
+            if let Some(tag) = change.strip_prefix('-') {
+                if !manifest.tags.remove(tag) {
+                    bail!("{} isn't tagged {}", mod_path.display(), tag);
+                }
+            } else {
+                let tag = change.strip_prefix('+').unwrap_or(change);
+                check_tag(tag)?;
+                manifest.tags.insert(tag.to_owned());
+            }
+        }
+        store.save_mod(&mod_path, manifest)?;
+    }
+
+    if manifest.tags.is_empty() {
+        println!("{} has no tags", mod_path.display());
+    } else {
+        println!("{}: {}", mod_path.display(), format_tags(&manifest.tags));
+    }
+    Ok(())
+}
+
+/// Makes sure the tag is something we can parse back out of `modman tag`.
+pub fn check_tag(tag: &str) -> Result<()> {
+    if tag.is_empty() || tag.starts_with(['+', '-']) || tag.contains(char::is_whitespace) {
+        bail!("{:?} isn't a valid tag", tag);
+    }
+    Ok(())
+}
+
+pub fn format_tags(tags: &BTreeSet<String>) -> String {
+    tags.iter().cloned().collect::<Vec<_>>().join(", ")
+}
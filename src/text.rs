@@ -0,0 +1,189 @@
+//! Reading text files (READMEs, changelogs) that mods ship in who knows
+//! what encoding
+
+use encoding_rs::{Encoding, UTF_8};
+use log::*;
+
+/// Decodes a text file's contents as best we can.
+///
+/// UTF-8 (with or without a BOM) and UTF-16 with a BOM are easy.
+/// Lots of older mods have READMEs in a Windows code page (1251 for Cyrillic,
+/// Shift-JIS, etc.) though, so for anything else, guess the encoding
+/// from the contents. If even that can't decode it cleanly, we get what we can
+/// (with replacement characters), since a garbled README shouldn't keep
+/// a mod from installing.
+pub fn decode_text(bytes: &[u8], name: &str) -> String {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(bytes) {
+        let (text, had_errors) = encoding.decode_without_bom_handling(&bytes[bom_length..]);
+        if had_errors {
+            warn!(
+                "{} isn't valid {}; some characters couldn't be read",
+                name,
+                encoding.name()
+            );
+        }
+        return text.into_owned();
+    }
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return text.to_owned();
+    }
+
+    let mut detector = chardetng::EncodingDetector::new();
+    detector.feed(bytes, true);
+    let encoding = detector.guess(None, false);
+    info!("{} isn't UTF-8; guessing it's {}", name, encoding.name());
+    let (text, had_errors) = encoding.decode_without_bom_handling(bytes);
+    if !had_errors {
+        return text.into_owned();
+    }
+    warn!(
+        "Couldn't tell what encoding {} is in; some characters couldn't be read",
+        name
+    );
+    UTF_8.decode_without_bom_handling(bytes).0.into_owned()
+}
+
+/// Files bigger than this aren't diffed by dry runs.
+pub const MAX_DIFF_SIZE: u64 = 64 * 1024;
+
+/// How many lines of a diff we print before giving up
+const MAX_DIFF_LINES: usize = 200;
+
+/// Lines of unchanged context around each change
+const DIFF_CONTEXT: usize = 3;
+
+/// Returns the file's contents as text, if it looks like text
+/// (UTF-8 without any NULs).
+pub fn as_text(bytes: &[u8]) -> Option<&str> {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    std::str::from_utf8(bytes)
+        .ok()
+        .filter(|text| !text.contains('\0'))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DiffLine<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Finds the fewest lines to remove from old and add to get new
+/// (by way of their longest common subsequence).
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffLine<'a>> {
+    // Mods usually change a few lines here and there,
+    // so skip the common start and end before doing the quadratic part.
+    let prefix = old.iter().zip(new).take_while(|(o, n)| o == n).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(o, n)| o == n)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    // lcs[i][j] is the length of the LCS of old_middle[i..] and new_middle[j..].
+    let width = new_middle.len() + 1;
+    let mut lcs = vec![0u32; (old_middle.len() + 1) * width];
+    for i in (0..old_middle.len()).rev() {
+        for j in (0..new_middle.len()).rev() {
+            lcs[i * width + j] = if old_middle[i] == new_middle[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut lines: Vec<DiffLine> = old[..prefix].iter().map(|l| DiffLine::Same(l)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < old_middle.len() || j < new_middle.len() {
+        if i < old_middle.len() && j < new_middle.len() && old_middle[i] == new_middle[j] {
+            lines.push(DiffLine::Same(old_middle[i]));
+            i += 1;
+            j += 1;
+        } else if j == new_middle.len()
+            || (i < old_middle.len() && lcs[(i + 1) * width + j] >= lcs[i * width + j + 1])
+        {
+            lines.push(DiffLine::Removed(old_middle[i]));
+            i += 1;
+        } else {
+            lines.push(DiffLine::Added(new_middle[j]));
+            j += 1;
+        }
+    }
+    lines.extend(old[old.len() - suffix..].iter().map(|l| DiffLine::Same(l)));
+    lines
+}
+
+/// Makes a unified diff (like `diff -u`) of two texts,
+/// cut short if it's very long.
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let lines = diff_lines(&old_lines, &new_lines);
+
+    // Group changes (and the context around them) into hunks.
+    let changed: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, l)| !matches!(l, DiffLine::Same(_)))
+        .map(|(i, _)| i)
+        .collect();
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for i in changed {
+        let start = i.saturating_sub(DIFF_CONTEXT);
+        let end = (i + DIFF_CONTEXT + 1).min(lines.len());
+        match hunks.last_mut() {
+            Some((_, last_end)) if start <= *last_end => *last_end = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut out = vec![format!("--- {}", old_name), format!("+++ {}", new_name)];
+    for (start, end) in hunks {
+        // Line numbers (from 1) where the hunk starts in each file
+        let old_start = 1 + lines[..start]
+            .iter()
+            .filter(|l| !matches!(l, DiffLine::Added(_)))
+            .count();
+        let new_start = 1 + lines[..start]
+            .iter()
+            .filter(|l| !matches!(l, DiffLine::Removed(_)))
+            .count();
+        let hunk = &lines[start..end];
+        let old_count = hunk
+            .iter()
+            .filter(|l| !matches!(l, DiffLine::Added(_)))
+            .count();
+        let new_count = hunk
+            .iter()
+            .filter(|l| !matches!(l, DiffLine::Removed(_)))
+            .count();
+        // (Like diff, empty ranges start at the line before.)
+        out.push(format!(
+            "@@ -{},{} +{},{} @@",
+            old_start - (old_count == 0) as usize,
+            old_count,
+            new_start - (new_count == 0) as usize,
+            new_count
+        ));
+        for l in hunk {
+            out.push(match l {
+                DiffLine::Same(s) => format!(" {}", s),
+                DiffLine::Removed(s) => format!("-{}", s),
+                DiffLine::Added(s) => format!("+{}", s),
+            });
+        }
+    }
+
+    if out.len() > MAX_DIFF_LINES {
+        let cut = out.len() - MAX_DIFF_LINES;
+        out.truncate(MAX_DIFF_LINES);
+        out.push(format!("... ({} more lines)", cut));
+    }
+    let mut diff = out.join("\n");
+    diff.push('\n');
+    diff
+}
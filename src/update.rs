@@ -1,39 +1,264 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
+use std::io::{self, prelude::*};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 use anyhow::*;
 use log::*;
+use rayon::prelude::*;
 use semver::Version;
 use structopt::*;
 
+use crate::auto_check::auto_check;
+use crate::delta::*;
+use crate::deps::ModBox;
+use crate::failure::*;
 use crate::file_utils::*;
+use crate::games;
+use crate::history;
+use crate::merge::*;
 use crate::modification::*;
+use crate::older_originals::keep_older_original;
+use crate::plan::{self, Operation, PlanCommand};
 use crate::profile::*;
+use crate::profile_store::*;
+use crate::progress::{self, FileEvent};
 
 /// Checks if installed mod files have been overwritten by an update.
 ///
 /// If they have, updates the backups and reinstalls the mod files.
+/// If the update deleted a file a mod replaced, it's dropped from the profile
+/// (there's nothing left to restore). If the update moved it, its backup
+/// and the mod file follow it to its new location.
+/// Given one or more <MOD>s (named like `modman remove` takes them),
+/// only their files are checked.
 #[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
 pub struct Args {
     #[structopt(short = "n", long)]
     dry_run: bool,
+
+    /// Only back up and reinstall changed files.
+    /// Missing files are just reported.
+    #[structopt(long, conflicts_with = "PLAN_FILE")]
+    changed_only: bool,
+
+    /// If a mod's files aren't what they were when it was installed
+    /// (say, its author re-uploaded the same version with fixes),
+    /// install the new ones and record them in the profile.
+    #[structopt(long)]
+    accept_mod_changes: bool,
+
+    /// Go ahead even if the automatic check (see `modman auto-check`)
+    /// finds an interrupted `modman add`.
+    #[structopt(long)]
+    force: bool,
+
+    /// Don't change anything; write a plan of every file operation
+    /// to <PLAN_FILE> (or stdout, if it's -) for `modman apply`.
+    #[structopt(long, name = "PLAN_FILE")]
+    plan: Option<PathBuf>,
+
+    #[structopt(name = "MOD")]
+    mod_names: Vec<PathBuf>,
+}
+
+impl Args {
+    pub fn new(mod_names: Vec<PathBuf>, dry_run: bool) -> Self {
+        Self {
+            dry_run,
+            changed_only: false,
+            accept_mod_changes: false,
+            force: false,
+            plan: None,
+            mod_names,
+        }
+    }
+
+    /// Makes this a dry run, for `--no-write`.
+    /// Returns false if it would still write something (a plan file).
+    pub fn make_dry_run(&mut self) -> bool {
+        self.dry_run = true;
+        self.plan.as_deref().is_none_or(plan::is_stdout)
+    }
+
+    pub fn resolve_paths(&mut self, base: &Path) {
+        for name in &mut self.mod_names {
+            resolve_mod_name(base, name);
+        }
+        if let Some(plan) = self.plan.as_mut().filter(|p| !plan::is_stdout(p)) {
+            *plan = base.join(&plan);
+        }
+    }
 }
 
 pub fn run(args: Args) -> Result<()> {
-    let mut p = load_and_check_profile()?;
-    update_installed_mods(&mut p, args.dry_run)?;
+    let mod_names = args.mod_names;
+    let accept_mod_changes = args.accept_mod_changes;
+    if let Some(plan_path) = &args.plan {
+        let mods = mod_names.clone();
+        return plan::write_plan(plan_path, PlanCommand::Update, mods, || {
+            update_mods(mod_names, false, accept_mod_changes, true)
+        });
+    }
+    // Changed files are what updating fixes, so they're no reason to stop.
+    if !args.dry_run {
+        auto_check(args.force, false)?;
+    }
+    update_mods(
+        mod_names,
+        args.changed_only,
+        args.accept_mod_changes,
+        args.dry_run,
+    )
+}
+
+pub fn update_mods(
+    mod_names: Vec<PathBuf>,
+    changed_only: bool,
+    accept_mod_changes: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let (mut store, mut p) = open_and_check_profile()?;
+    if !dry_run {
+        games::warn_if_running(&p);
+        check_backup_dirs()?;
+    }
+    let only = mod_names
+        .iter()
+        .map(|n| p.find_mod(n))
+        .collect::<Result<BTreeSet<_>>>()?;
+    update_installed_mods(
+        &mut p,
+        &mut *store,
+        &only,
+        changed_only,
+        accept_mod_changes,
+        dry_run,
+    )?;
+    // If we only checked some mods, we can't say the game's files are all
+    // up to date for this build.
+    if !dry_run && only.is_empty() && games::note_steam_build(&mut p, true)? {
+        store.save_settings(&p)?;
+    }
     Ok(())
 }
 
-fn update_installed_mods(p: &mut Profile, dry_run: bool) -> Result<()> {
+/// What update_file() found (and did) for one installed file
+enum FileUpdate {
+    Unchanged,
+    Updated(ModFileMetadata, UpdateReason),
+    /// The game file's gone; see reconcile_missing_files().
+    Missing,
+}
+
+/// Why a file was updated
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum UpdateReason {
+    /// The game changed it, so it was backed up and the mod file reinstalled.
+    GameChanged,
+    /// The mod's file changed, so it was reinstalled (`--accept-mod-changes`).
+    ModChanged,
+}
+
+/// Where an installed file is among the mods layered over it
+/// (see `Profile::layers`)
+enum Layer {
+    /// No other mod installed it (or they all share it).
+    Only,
+    /// It's installed over other mods' files; the given mod's was the first,
+    /// so it has the game's original (as the given file, which might differ
+    /// in case from ours).
+    Top(PathBuf, PathBuf),
+    /// Another mod's file is installed over it, so it's not ours to update.
+    Covered,
+}
+
+/// Files we're still updating for a mod, and what we've found so far
+#[derive(Default)]
+struct PendingMod {
+    remaining: usize,
+    updated: Vec<(PathBuf, ModFileMetadata)>,
+    missing: Vec<PathBuf>,
+}
+
+/// What update_installed_mods() keeps track of as files are updated
+/// (out of order, on several threads), so that it can save each mod
+/// as soon as all its files are done. That way a crash partway through
+/// only loses what we were doing to the mods we hadn't finished.
+struct UpdateState<'a> {
+    store: &'a mut dyn ProfileStore,
+    manifests: &'a mut BTreeMap<PathBuf, ModManifest>,
+    pending: BTreeMap<PathBuf, PendingMod>,
+    /// Moved files can't go anywhere another mod already has.
+    tracked: BTreeSet<PathBuf>,
+    updated_mods: usize,
+    /// Files that were updated (by mod, then path), to report once we're done
+    /// so they're in order instead of however the threads finished.
+    updated_files: BTreeSet<(PathBuf, PathBuf, UpdateReason)>,
+    /// Record when new backups were made (see `modman quick-check`).
+    mark_backups: bool,
+}
+
+impl UpdateState<'_> {
+    /// Gives the game's original of a file mods are layered over
+    /// to the first of them, which it belongs to,
+    /// and returns the updated metadata of the one installed without it.
+    fn hand_down(
+        &mut self,
+        first: &Path,
+        first_file: &Path,
+        metadata: ModFileMetadata,
+        dry_run: bool,
+    ) -> Result<ModFileMetadata> {
+        let manifest = self.manifests.get_mut(first).unwrap();
+        let first_meta = manifest.files.get_mut(first_file).unwrap();
+        if first_meta.original_hash != metadata.original_hash {
+            first_meta.original_hash = metadata.original_hash.clone();
+            first_meta.original_mode = metadata.original_mode;
+            first_meta.original_size = metadata.original_size;
+            first_meta.backup_mtime = metadata.backup_mtime;
+            if !dry_run {
+                self.store.save_mod(first, manifest)?;
+            }
+        }
+        Ok(ModFileMetadata {
+            original_hash: None,
+            original_mode: None,
+            original_size: None,
+            backup_mtime: None,
+            ..metadata
+        })
+    }
+}
+
+/// Updates the files of the installed mods in `only`, or every mod if it's empty.
+fn update_installed_mods(
+    p: &mut Profile,
+    store: &mut dyn ProfileStore,
+    only: &BTreeSet<PathBuf>,
+    changed_only: bool,
+    accept_mod_changes: bool,
+    dry_run: bool,
+) -> Result<()> {
     info!("Checking installed mod files...");
 
-    let mut updates_made = false;
+    let tracked: BTreeSet<PathBuf> = p
+        .mods
+        .values()
+        .flat_map(|manifest| manifest.files.keys())
+        .map(|f| path_key(f, p.case_insensitive))
+        .collect();
 
-    for (mod_path, manifest) in &mut p.mods {
-        // First, open up the mod.
-        // (If we can't find it, we can't reinstall the mod files.)
-        let m = open_mod(mod_path)?;
+    // First, open up the mods.
+    // (If we can't find one, we can't reinstall its files.)
+    let mut mods: BTreeMap<PathBuf, ModBox> = BTreeMap::new();
+    for (mod_path, manifest) in &p.mods {
+        if !only.is_empty() && !only.contains(mod_path) {
+            continue;
+        }
+        let m = open_mod(mod_path, p.unicode_form, manifest.root.as_deref())?;
 
         let current_version: &Version = m.version();
         let activated_version: &Version = &manifest.version;
@@ -45,47 +270,318 @@ fn update_installed_mods(p: &mut Profile, dry_run: bool) -> Result<()> {
                 activated_version
             );
         }
+        mods.insert(mod_path.clone(), m);
+    }
 
-        for (mod_file_path, metadata) in &mut manifest.files {
-            if let Some(new_metadata) = update_file(
-                mod_path,
-                mod_file_path,
-                metadata,
-                &*m,
-                &p.root_directory,
-                dry_run,
-            )? {
-                updates_made = true;
-                *metadata = new_metadata;
-            }
+    // Don't reinstall files from an archive that isn't the one we installed,
+    // unless we're told to take what's in it now.
+    let mut replaced_archives = Vec::new();
+    for mod_path in mods.keys() {
+        if !archive_changed(mod_path, &p.mods[mod_path])? {
+            continue;
+        }
+        if !accept_mod_changes {
+            fail!(
+                Failure::VerificationFailed,
+                "{} isn't the archive it was installed from (it's changed since).\n\
+                 Pass --accept-mod-changes to install its files as they are now.",
+                mod_path.display()
+            );
+        }
+        warn!(
+            "{} isn't the archive it was installed from; reinstalling its changed files",
+            mod_path.display()
+        );
+        replaced_archives.push(mod_path.clone());
+    }
+
+    // The files of mods that others are layered over, by path_key(),
+    // so we can find the originals they have without searching each mod.
+    let ci = p.case_insensitive;
+    let firsts: BTreeSet<&PathBuf> = p.layers.values().map(|layers| &layers[0]).collect();
+    let layered_files: BTreeMap<(&PathBuf, PathBuf), &PathBuf> = firsts
+        .into_iter()
+        .flat_map(|first| {
+            p.mods[first]
+                .files
+                .keys()
+                .map(move |f| ((first, path_key(f, ci)), f))
+        })
+        .collect();
+
+    // Then check every mod's files at once, instead of a mod at a time,
+    // so that lots of small mods don't leave threads idle.
+    let mut files = Vec::new();
+    let mut pending = BTreeMap::new();
+    for mod_path in mods.keys() {
+        let manifest = &p.mods[mod_path];
+        for (file, meta) in &manifest.files {
+            let (layer, meta) = match p.layers_of(file) {
+                None => (Layer::Only, meta.clone()),
+                Some(layers) if layers.last() == Some(mod_path) => {
+                    // Update it against the game's original,
+                    // which the first mod has.
+                    let first = &layers[0];
+                    let first_file = layered_files[&(first, path_key(file, ci))];
+                    let original = &p.mods[first].files[first_file];
+                    let meta = ModFileMetadata {
+                        original_hash: original.original_hash.clone(),
+                        original_mode: original.original_mode,
+                        original_size: original.original_size,
+                        backup_mtime: original.backup_mtime,
+                        ..meta.clone()
+                    };
+                    (Layer::Top(first.clone(), first_file.clone()), meta)
+                }
+                Some(_) => (Layer::Covered, meta.clone()),
+            };
+            files.push((mod_path, file.clone(), meta, manifest.keep_mtimes, layer));
         }
-        // Ideally we'd like to write out the profile file here,
-        // once after each mod we've visited.
-        // However, we'd need to borrow p, which has a mutable borrow on it
-        // from this loop. What do?
+        let remaining = manifest.files.len();
+        pending.insert(
+            mod_path.clone(),
+            PendingMod {
+                remaining,
+                ..Default::default()
+            },
+        );
     }
+    progress::start_all("update", files.len());
+
+    let keep_originals = p.keep_originals.unwrap_or(0);
+    let quick_check = p.quick_check;
+    // Borrow the fields we need separately from the mods we're updating.
+    let dirs = GameDirs {
+        root_directory: &p.root_directory,
+        roots: &p.roots,
+        case_insensitive: p.case_insensitive,
+    };
+    let state = Mutex::new(UpdateState {
+        store,
+        manifests: &mut p.mods,
+        pending,
+        tracked,
+        updated_mods: 0,
+        updated_files: BTreeSet::new(),
+        mark_backups: quick_check,
+    });
+
+    // Mods that can only read a file at a time get their files in turn.
+    let streaming: BTreeMap<&PathBuf, Mutex<()>> = mods
+        .iter()
+        .filter(|(_, m)| m.streaming_only())
+        .map(|(mod_path, _)| (mod_path, Mutex::new(())))
+        .collect();
+
+    files.into_par_iter().try_for_each(
+        |(mod_path, mod_file_path, metadata, keep_mtimes, layer)| -> Result<()> {
+            let m = &*mods[mod_path];
+            let turn = streaming.get(mod_path).map(|t| t.lock().unwrap());
+            let game_path = dirs.game_path(&mod_file_path);
+            // Shared files are the owning mod's to update,
+            // and covered ones the mod installed over them's.
+            let outcome = if metadata.shared || matches!(layer, Layer::Covered) {
+                FileUpdate::Unchanged
+            } else if !game_path.exists() && !is_symlink(&game_path) {
+                FileUpdate::Missing
+            } else {
+                match update_file(
+                    mod_path,
+                    &mod_file_path,
+                    &metadata,
+                    m,
+                    keep_mtimes,
+                    &dirs,
+                    keep_originals,
+                    quick_check,
+                    dry_run,
+                )? {
+                    Some(new_metadata) => {
+                        FileUpdate::Updated(new_metadata, UpdateReason::GameChanged)
+                    }
+                    None if accept_mod_changes => match reinstall_if_mod_changed(
+                        mod_path,
+                        &mod_file_path,
+                        &metadata,
+                        m,
+                        keep_mtimes,
+                        &dirs,
+                        dry_run,
+                    )? {
+                        Some(new_metadata) => {
+                            FileUpdate::Updated(new_metadata, UpdateReason::ModChanged)
+                        }
+                        None => FileUpdate::Unchanged,
+                    },
+                    None => FileUpdate::Unchanged,
+                }
+            };
+            drop(turn);
+            progress::file_done(
+                match outcome {
+                    FileUpdate::Updated(..) => FileEvent::Installed,
+                    _ => FileEvent::Checked,
+                },
+                &mod_file_path,
+            );
+
+            let mut state = state.lock().unwrap();
+            let outcome = match (outcome, &layer) {
+                (FileUpdate::Updated(new_metadata, reason), Layer::Top(first, first_file)) => {
+                    let new_metadata = state.hand_down(first, first_file, new_metadata, dry_run)?;
+                    FileUpdate::Updated(new_metadata, reason)
+                }
+                (outcome, _) => outcome,
+            };
+            if let FileUpdate::Updated(_, reason) = outcome {
+                state
+                    .updated_files
+                    .insert((mod_path.clone(), mod_file_path.clone(), reason));
+            }
+            let done = {
+                let pending = state.pending.get_mut(mod_path).unwrap();
+                match outcome {
+                    FileUpdate::Unchanged => (),
+                    FileUpdate::Updated(new_metadata, _) => {
+                        pending.updated.push((mod_file_path, new_metadata))
+                    }
+                    FileUpdate::Missing => pending.missing.push(mod_file_path),
+                }
+                pending.remaining -= 1;
+                pending.remaining == 0
+            };
+            if done {
+                finish_mod(&mut state, mod_path, m, &dirs, changed_only, dry_run)?;
+            }
+            Ok(())
+        },
+    )?;
 
     if !dry_run {
-        remove_empty_tree(Path::new(TEMPDIR_PATH), RemoveRoot(false))
+        remove_empty_tree(&tempdir(), RemoveRoot(false))
             .context("Couldn't clean up temp directory")?;
     }
 
-    if updates_made {
+    let UpdateState {
+        updated_mods,
+        updated_files,
+        ..
+    } = state.into_inner().unwrap();
+
+    // Now that we've installed what's in them, remember the new archives.
+    for mod_path in &replaced_archives {
+        let manifest = p.mods.get_mut(mod_path).unwrap();
+        record_mod_archive(mod_path, manifest)?;
         if !dry_run {
-            update_profile_file(&p)?;
+            store.save_mod(mod_path, manifest)?;
         }
-    } else {
+    }
+    for (mod_path, mod_file_path, reason) in &updated_files {
+        let source_path = p.mods[mod_path].files[mod_file_path].mod_file(mod_file_path);
+        report_update(
+            mod_path,
+            mod_file_path,
+            source_path,
+            *reason,
+            &dirs,
+            dry_run,
+        );
+    }
+    if updated_mods == 0 {
         info!("Game files haven't changed, no updates needed.");
     }
 
     Ok(())
 }
 
+/// Says what we did (or would do) to a file we updated.
+fn report_update(
+    mod_path: &Path,
+    mod_file_path: &Path,
+    source_path: &Path,
+    reason: UpdateReason,
+    dirs: &GameDirs,
+    dry_run: bool,
+) {
+    match (reason, dry_run) {
+        // Plans say what we'd do themselves.
+        (_, true) if plan::is_planning() => (),
+        (UpdateReason::GameChanged, true) => println!(
+            "{} was changed and needs its backup updated",
+            mod_file_path.display()
+        ),
+        (UpdateReason::GameChanged, false) => info!(
+            "{} changed. Backed up new version and reinstalled mod file.",
+            dirs.game_path(mod_file_path).display()
+        ),
+        (UpdateReason::ModChanged, true) => println!(
+            "{} changed in {} and needs to be reinstalled",
+            source_path.display(),
+            mod_path.display()
+        ),
+        (UpdateReason::ModChanged, false) => info!(
+            "{} changed in {}. Reinstalled it.",
+            source_path.display(),
+            mod_path.display()
+        ),
+    }
+}
+
+/// Once all a mod's files have been checked, records what changed
+/// (and deals with any that are missing), then saves the mod to the profile.
+fn finish_mod(
+    state: &mut UpdateState,
+    mod_path: &Path,
+    m: &dyn Mod,
+    dirs: &GameDirs,
+    changed_only: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let pending = state.pending.remove(mod_path).unwrap();
+    let manifest = state.manifests.get_mut(mod_path).unwrap();
+
+    let mut changed = !pending.updated.is_empty();
+    manifest.files.extend(pending.updated);
+
+    let missing = pending.missing;
+    if !missing.is_empty() {
+        if changed_only {
+            let mut warning = format!("{} has files the game no longer has:", mod_path.display());
+            for file in &missing {
+                warning += &format!("\n\t{}", file.display());
+            }
+            warn!("{}", warning);
+        } else {
+            changed |= reconcile_missing_files(
+                mod_path,
+                manifest,
+                &missing,
+                m,
+                dirs,
+                &mut state.tracked,
+                state.mark_backups,
+                dry_run,
+            )?;
+        }
+    }
+
+    if changed {
+        state.updated_mods += 1;
+        if !dry_run {
+            history::note_mod(mod_path);
+            state.store.save_mod(mod_path, manifest)?;
+        }
+    }
+    Ok(())
+}
+
 /// The core of update_installed_mods's loop.
 /// Given the path of the mod (for tracing purposes),
 /// the path of the file to update, that file's metadata,
 /// the mod itself (for reinstalling the mod file),
-/// the game's root directory, and a dry run flag,
+/// whether to give the file the mod's modification time,
+/// where the profile installs files, how many older originals to keep,
+/// and a dry run flag,
 ///
 /// 1. See if the game file's been changed by an update.
 /// 2. If it has,
@@ -98,21 +594,35 @@ fn update_installed_mods(p: &mut Profile, dry_run: bool) -> Result<()> {
 /// This function could be broken down even more, but it's hard to do that
 /// without passing lots of args everywhere.
 /// For anything we do, we want a handful of paths for debug and trace statements.
+#[allow(clippy::too_many_arguments)]
 fn update_file(
     mod_path: &Path,
     mod_file_path: &Path,
     old_metadata: &ModFileMetadata,
     m: &dyn Mod,
-    root_directory: &Path,
+    keep_mtimes: bool,
+    dirs: &GameDirs,
+    keep_originals: usize,
+    mark_backups: bool,
     dry_run: bool,
 ) -> Result<Option<ModFileMetadata>> {
-    let game_path = mod_path_to_game_path(mod_file_path, root_directory);
-    let game_hash = hash_file(&game_path)?;
+    let game_path = dirs.game_path(mod_file_path);
+    let game_hash = hash_installed_file(&game_path, old_metadata)?;
     if game_hash == old_metadata.mod_hash {
         // Cool, nothing changed
         return Ok(None);
     }
 
+    if old_metadata.symlink.is_some() {
+        warn!(
+            "{} was installed as a symbolic link, but something replaced it. \
+             Remove {} and add it again to reinstall it.",
+            game_path.display(),
+            mod_path.display()
+        );
+        return Ok(None);
+    }
+
     trace!(
         "{} hashed to\n{:x},\nexpected {:x}",
         game_path.display(),
@@ -120,23 +630,76 @@ fn update_file(
         old_metadata.mod_hash.bytes
     );
 
+    // If the game moved the file the mod replaced, the mod file followed it.
+    let source_path = old_metadata.mod_file(mod_file_path);
+
+    // A binary patch only applies to the file it was made for.
+    let patch = m.config().patch.get(source_path);
+    if let Some(patch) = patch {
+        if game_hash != patch.original_hash {
+            warn!(
+                "{} changed, but {}'s patch for it ({}) only applies to the version \
+                 it was made for. Look for a version of the mod made for this version of the game.",
+                game_path.display(),
+                mod_path.display(),
+                patch.file.display()
+            );
+            return Ok(None);
+        }
+    }
+
     if dry_run {
-        println!(
-            "{} was changed and needs its backup updated",
-            mod_file_path.display()
+        plan::note(
+            mod_path,
+            Operation::Backup {
+                path: mod_file_path.to_owned(),
+            },
+        );
+        plan::note(
+            mod_path,
+            Operation::Install {
+                path: mod_file_path.to_owned(),
+            },
         );
         return Ok(Some(ModFileMetadata {
             mod_hash: old_metadata.mod_hash.clone(),
             original_hash: Some(game_hash),
+            merge: old_metadata.merge,
+            symlink: None,
+            mode: old_metadata.mode,
+            original_mode: executable_mode(&game_path)?,
+            mod_file: old_metadata.mod_file.clone(),
+            size: old_metadata.size,
+            original_size: Some(file_size(&game_path)?),
+            backup_mtime: None,
+            shared: false,
+            identical: false,
         }));
     }
 
-    info!(
+    debug!(
         "{} changed. Backing up new version and reinstalling mod file.",
         game_path.display()
     );
 
+    history::note_file(mod_file_path);
+    // (Copying the file keeps its permissions, so the backup has them too.)
+    let original_mode = executable_mode(&game_path)?;
+    let original_size = file_size(&game_path)?;
+    if let Some(old) = old_metadata
+        .original_hash
+        .as_ref()
+        .filter(|_| keep_originals > 0)
+    {
+        keep_older_original(mod_file_path, old, keep_originals)?;
+    }
     backup_file(&game_path, mod_file_path)?;
+    // (See `modman quick-check`.)
+    let backup_mtime = match mark_backups {
+        true => Some(file_mtime(&mod_path_to_backup_path(mod_file_path))?),
+        false => None,
+    };
+    progress::file(FileEvent::BackedUp, mod_file_path);
 
     // This is very simimlar to what `modman add` is doing
     // to initially install mods, but it has a few differences
@@ -144,13 +707,39 @@ fn update_file(
     // we don't have to create directories, etc.)
     // But should we factor them into a common function to get their traces
     // and behavior in sync anyways?
-    let mut mod_file_reader = m.read_file(&mod_file_path)?;
-    let mut game_file = fs::File::create(&game_path)
+    let mut mod_file_reader: Box<dyn Read + Send> = match patch {
+        Some(patch) => {
+            let mut delta = m.read_file(&patch.file)?;
+            Box::new(io::Cursor::new(apply_patch(patch, &game_path, &mut delta)?))
+        }
+        None => m.read_file(source_path)?,
+    };
+    // Merge the mod file into the game's new version of the file.
+    if let Some(mode) = old_metadata.merge {
+        let merged = merge_into_game_file(mode, &game_path, &mut mod_file_reader)?;
+        mod_file_reader = Box::new(io::Cursor::new(merged));
+    }
+    let mut game_file = create_file(&game_path)
         .with_context(|| format!("Couldn't overwrite {}", game_path.display()))?;
 
-    let mod_hash = hash_and_write(&mut mod_file_reader, &mut game_file)?;
+    let mod_hash = hash_and_write(&mut mod_file_reader, &mut game_file).with_context(|| {
+        format!(
+            "Couldn't reinstall {} from {}",
+            source_path.display(),
+            mod_path.display()
+        )
+    })?;
+    drop(game_file);
+    if let Some(mode) = old_metadata.mode {
+        set_mode(&game_path, mode)?;
+    }
+    if keep_mtimes && old_metadata.merge.is_none() && patch.is_none() {
+        if let Some(mtime) = m.mtime(source_path)? {
+            set_mtime(&game_path, mtime)?;
+        }
+    }
 
-    let full_mod_path = mod_path.join(mod_file_path);
+    let full_mod_path = mod_path.join(source_path);
     trace!(
         "Mod file {} hashed to\n{:x}",
         full_mod_path.display(),
@@ -160,12 +749,25 @@ fn update_file(
     let new_metadata = ModFileMetadata {
         mod_hash,
         original_hash: Some(game_hash),
+        merge: old_metadata.merge,
+        symlink: None,
+        mode: old_metadata.mode,
+        original_mode,
+        mod_file: old_metadata.mod_file.clone(),
+        size: Some(file_size(&game_path)?),
+        original_size: Some(original_size),
+        backup_mtime,
+        shared: false,
+        identical: false,
     };
 
     // TODO Update metadata and write it out
-    if old_metadata.mod_hash != new_metadata.mod_hash {
-        warn!(
-            "The mod file {} doesn't hash to what it did last time it was installed!",
+    // (Merged files are expected to change along with the game's.)
+    if old_metadata.mod_hash != new_metadata.mod_hash && old_metadata.merge.is_none() {
+        strict_warn!(
+            ModChanged,
+            "The mod file {} doesn't hash to what it did last time it was installed! \
+             (Run `modman update --accept-mod-changes` if that's expected.)",
             full_mod_path.display()
         );
     }
@@ -173,13 +775,314 @@ fn update_file(
     Ok(Some(new_metadata))
 }
 
+/// For `--accept-mod-changes`: if the game's file is still what the mod
+/// installed, but the mod's file isn't what it was when it was installed
+/// (e.g., its author re-uploaded the same version), install the new one.
+///
+/// Returns the file's new metadata if it changed, or None if it didn't.
+#[allow(clippy::too_many_arguments)]
+fn reinstall_if_mod_changed(
+    mod_path: &Path,
+    mod_file_path: &Path,
+    old_metadata: &ModFileMetadata,
+    m: &dyn Mod,
+    keep_mtimes: bool,
+    dirs: &GameDirs,
+    dry_run: bool,
+) -> Result<Option<ModFileMetadata>> {
+    // There's nothing to hash for links, and they're reinstalled by path anyways.
+    if old_metadata.symlink.is_some() {
+        return Ok(None);
+    }
+    let game_path = dirs.game_path(mod_file_path);
+    let source_path = old_metadata.mod_file(mod_file_path);
+
+    // Patched and merged files are made from the game's original,
+    // which is in the backup (if there was one) now that the game file is modded.
+    let original_path = mod_path_to_backup_path(mod_file_path);
+    let patch = m.config().patch.get(source_path);
+    let read_contents = || -> Result<Box<dyn Read + Send>> {
+        let mut contents: Box<dyn Read + Send> = match patch {
+            Some(patch) => {
+                let mut delta = m.read_file(&patch.file)?;
+                Box::new(io::Cursor::new(apply_patch(
+                    patch,
+                    &original_path,
+                    &mut delta,
+                )?))
+            }
+            None => m.read_file(source_path)?,
+        };
+        if let Some(mode) = old_metadata.merge {
+            let merged = merge_into_game_file(mode, &original_path, &mut contents)?;
+            contents = Box::new(io::Cursor::new(merged));
+        }
+        Ok(contents)
+    };
+
+    // Hash it first so we don't rewrite files that haven't changed.
+    let new_hash = hash_contents(&mut read_contents()?)?;
+    if new_hash == old_metadata.mod_hash {
+        return Ok(None);
+    }
+
+    if dry_run {
+        plan::note(
+            mod_path,
+            Operation::Install {
+                path: mod_file_path.to_owned(),
+            },
+        );
+        return Ok(Some(ModFileMetadata {
+            mod_hash: new_hash,
+            ..old_metadata.clone()
+        }));
+    }
+
+    debug!(
+        "{} changed in {}. Reinstalling it.",
+        source_path.display(),
+        mod_path.display()
+    );
+    history::note_file(mod_file_path);
+    let mut game_file = create_file(&game_path)
+        .with_context(|| format!("Couldn't overwrite {}", game_path.display()))?;
+    let mod_hash = hash_and_write(&mut read_contents()?, &mut game_file)
+        .with_context(|| format!("Couldn't reinstall {}", game_path.display()))?;
+    drop(game_file);
+
+    let mode = if patch.is_none() && old_metadata.merge.is_none() {
+        m.mode(source_path)?
+    } else {
+        None
+    };
+    if let Some(mode) = mode {
+        set_mode(&game_path, mode)?;
+    }
+    if keep_mtimes && old_metadata.merge.is_none() && patch.is_none() {
+        if let Some(mtime) = m.mtime(source_path)? {
+            set_mtime(&game_path, mtime)?;
+        }
+    }
+
+    Ok(Some(ModFileMetadata {
+        mod_hash,
+        mode,
+        size: Some(file_size(&game_path)?),
+        ..old_metadata.clone()
+    }))
+}
+
+/// Handles installed mod files that are gone from the game directory.
+///
+/// If a game update deleted a file the mod replaced, there's nothing left
+/// to restore, so we drop it from the manifest along with its backup.
+/// If the update moved it (i.e., we find a file with the backup's contents
+/// that no mod knows about), the mod file follows it to its new location:
+/// we back up the game's file there and reinstall the mod file over it.
+///
+/// Files the mod added (which didn't replace anything) are left alone,
+/// since the mod needs them.
+///
+/// Returns true if the manifest changed.
+#[allow(clippy::too_many_arguments)]
+fn reconcile_missing_files(
+    mod_path: &Path,
+    manifest: &mut ModManifest,
+    missing: &[PathBuf],
+    m: &dyn Mod,
+    dirs: &GameDirs,
+    tracked: &mut BTreeSet<PathBuf>,
+    mark_backups: bool,
+    dry_run: bool,
+) -> Result<bool> {
+    let replaced: Vec<(&Path, &FileHash)> = missing
+        .iter()
+        .filter_map(|f| {
+            let original = manifest.files[f].original_hash.as_ref();
+            original.map(|h| (f.as_path(), h))
+        })
+        .collect();
+    let moved = find_moved_files(&replaced, dirs, tracked)?;
+
+    let mut changed = false;
+    for file in missing {
+        let game_path = dirs.game_path(file);
+        if manifest.files[file].original_hash.is_none() {
+            strict_warn!(
+                MissingFile,
+                "{} is missing! Remove {} and add it again to reinstall it.",
+                game_path.display(),
+                mod_path.display()
+            );
+            continue;
+        }
+        let meta = manifest.files.remove(file).unwrap();
+        changed = true;
+
+        match moved.get(file) {
+            Some(new_path) => {
+                note(
+                    dry_run,
+                    format!(
+                        "{} moved to {}. Moving its backup and mod file along with it.",
+                        game_path.display(),
+                        dirs.game_path(new_path).display()
+                    ),
+                );
+                let moved_meta = ModFileMetadata {
+                    mod_file: Some(meta.mod_file(file).to_owned()),
+                    ..meta
+                };
+                // The game's file at its new location looks like it changed,
+                // so this backs it up and reinstalls the mod file over it.
+                let new_meta = update_file(
+                    mod_path,
+                    new_path,
+                    &moved_meta,
+                    m,
+                    manifest.keep_mtimes,
+                    dirs,
+                    // (It has no backup at its new path to keep.)
+                    0,
+                    mark_backups,
+                    dry_run,
+                )?;
+                tracked.insert(path_key(new_path, dirs.case_insensitive));
+                manifest
+                    .files
+                    .insert(new_path.clone(), new_meta.unwrap_or(moved_meta));
+            }
+            None => note(
+                dry_run,
+                format!(
+                    "{} is gone (deleted by a game update?), so {} no longer replaces it.",
+                    game_path.display(),
+                    mod_path.display()
+                ),
+            ),
+        }
+
+        // Either way, the old backup is stale.
+        if !dry_run {
+            history::note_file(file);
+            let backup_path = mod_path_to_backup_path(file);
+            debug!("Removing {}", backup_path.display());
+            match discard_file(&backup_path) {
+                Ok(()) => remove_empty_parents(&backup_path, &backup_dir())?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    strict_warn!(
+                        MissingFile,
+                        "{} was already removed!",
+                        backup_path.display()
+                    )
+                }
+                Err(e) => {
+                    return Err(Error::from(e)
+                        .context(format!("Couldn't remove {}", backup_path.display())))
+                }
+            }
+        }
+    }
+    Ok(changed)
+}
+
+/// Prints what we'd do on a dry run, or logs what we're doing otherwise.
+fn note(dry_run: bool, message: String) {
+    if !dry_run {
+        info!("{}", message);
+    } else if !plan::is_planning() {
+        println!("{}", message);
+    }
+}
+
+/// Looks for the game files that replaced files were moved to,
+/// by looking for untracked files with the same contents as their backups.
+///
+/// Returns a map of the missing files' paths to their new ones.
+fn find_moved_files(
+    replaced: &[(&Path, &FileHash)],
+    dirs: &GameDirs,
+    tracked: &BTreeSet<PathBuf>,
+) -> Result<BTreeMap<PathBuf, PathBuf>> {
+    let mut moved = BTreeMap::new();
+    if replaced.is_empty() {
+        return Ok(moved);
+    }
+
+    // Only hash files that are the same size as a backup.
+    let mut by_size: BTreeMap<u64, Vec<(&Path, &FileHash)>> = BTreeMap::new();
+    for (file, hash) in replaced {
+        if let Ok(meta) = fs::metadata(mod_path_to_backup_path(file)) {
+            by_size.entry(meta.len()).or_default().push((file, hash));
+        }
+    }
+
+    // Don't mistake the backups themselves for moved files
+    // if they're somewhere in the game directory.
+    let storage = fs::canonicalize(storage_dir()).ok();
+
+    let root_names: BTreeSet<Option<&str>> = replaced
+        .iter()
+        .map(|(f, _)| split_root(f).0.filter(|r| dirs.roots.contains_key(*r)))
+        .collect();
+    for root_name in root_names {
+        let root = in_profile_dir(match root_name {
+            Some(name) => dirs.roots[name].as_path(),
+            None => dirs.root_directory,
+        });
+        debug!("Looking for moved files in {}", root.display());
+
+        let mut to_visit = vec![root.clone()];
+        while let Some(dir) = to_visit.pop() {
+            if storage.is_some() && fs::canonicalize(&dir).ok() == storage {
+                continue;
+            }
+            let entries =
+                fs::read_dir(&dir).with_context(|| format!("Couldn't read {}", dir.display()))?;
+            for entry in entries {
+                let entry = entry?;
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    to_visit.push(entry.path());
+                    continue;
+                } else if !file_type.is_file() {
+                    continue;
+                }
+                let candidates = match by_size.get(&entry.metadata()?.len()) {
+                    Some(c) => c,
+                    None => continue,
+                };
+
+                let path = entry.path();
+                let relative = path.strip_prefix(&root).unwrap();
+                let key = in_root(root_name.unwrap_or(DEFAULT_ROOT_NAME), relative);
+                if tracked.contains(&path_key(&key, dirs.case_insensitive)) {
+                    continue;
+                }
+                let hash = hash_file(&path)?;
+                let found = candidates.iter().find(|(file, h)| {
+                    **h == hash
+                        && !moved.contains_key(*file)
+                        && split_root(file).0.filter(|r| dirs.roots.contains_key(*r)) == root_name
+                });
+                if let Some((file, _)) = found {
+                    debug!("{} moved to {}", file.display(), path.display());
+                    moved.insert(file.to_path_buf(), key);
+                }
+            }
+        }
+    }
+    Ok(moved)
+}
+
 /// Given a mod path, hash and backup the corresponding game file.
 ///
 /// Like `hash_and_backup()` from `modman add`, but with some simplifications
 /// since we _know_ `game_file_path` should be there.
 ///
 /// TODO: The duplication between this and `hadh_and_backup()` makes me sad.
-fn backup_file(game_file_path: &Path, mod_file_path: &Path) -> Result<()> {
+pub fn backup_file(game_file_path: &Path, mod_file_path: &Path) -> Result<()> {
     debug!("Backing up {}", game_file_path.display());
 
     // First, copy the file to a temporary location, hashing it as we go.
@@ -191,20 +1094,21 @@ fn backup_file(game_file_path: &Path, mod_file_path: &Path) -> Result<()> {
     );
     // Create temporary subdirectories as needed
     if let Some(parent) = temp_file_path.parent() {
-        fs::create_dir_all(&parent)
+        fs::create_dir_all(parent)
             .with_context(|| format!("Couldn't create temp directory {}", parent.display()))?;
     }
 
-    fs::copy(game_file_path, &temp_file_path).with_context(|| {
+    copy_file(game_file_path, &temp_file_path).with_context(|| {
         format!(
             "Couldn't copy {} to {}",
             game_file_path.display(),
             temp_file_path.display()
         )
     })?;
+    copy_mtime(game_file_path, &temp_file_path)?;
 
     // Next, create any needed directory structure.
-    let mut backup_file_dir = PathBuf::from(BACKUP_PATH);
+    let mut backup_file_dir = backup_dir();
     if let Some(parent) = mod_file_path.parent() {
         backup_file_dir.push(parent);
     }
@@ -222,7 +1126,7 @@ fn backup_file(game_file_path: &Path, mod_file_path: &Path) -> Result<()> {
 
     // Move the backup from the temporary location to its final spot
     // in the backup directory.
-    fs::rename(&temp_file_path, &backup_path).with_context(|| {
+    replace_file(&temp_file_path, &backup_path).with_context(|| {
         format!(
             "Couldn't rename {} to {}",
             temp_file_path.display(),
@@ -0,0 +1,159 @@
+//! A tiny parser for Valve's KeyValues ("VDF") text format,
+//! which Steam uses for its library list and app manifests:
+//!
+//! ```text
+//! "AppState"
+//! {
+//!     "appid"      "223750"
+//!     "installdir" "DCSWorld"
+//! }
+//! ```
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Looks up a key in an object. (Keys are case-insensitive.)
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(pairs) => pairs
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| v),
+            Value::String(_) => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            Value::Object(_) => None,
+        }
+    }
+
+    /// The object's key-value pairs, or nothing if it's a string.
+    pub fn pairs(&self) -> &[(String, Value)] {
+        match self {
+            Value::Object(pairs) => pairs,
+            Value::String(_) => &[],
+        }
+    }
+}
+
+/// Parses a VDF file, returning its top-level keys as an object.
+pub fn read_file(path: &Path) -> Result<Value> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("Couldn't read {}", path.display()))?;
+    parse(&text).with_context(|| format!("Couldn't parse {}", path.display()))
+}
+
+pub fn parse(text: &str) -> Result<Value> {
+    let mut tokens = Tokens {
+        chars: text.chars().peekable(),
+        line: 1,
+    };
+    let pairs = parse_pairs(&mut tokens, false)?;
+    Ok(Value::Object(pairs))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    String(String),
+}
+
+struct Tokens<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+}
+
+impl Tokens<'_> {
+    fn next(&mut self) -> Result<Option<Token>> {
+        loop {
+            match self.chars.next() {
+                None => return Ok(None),
+                Some('\n') => self.line += 1,
+                Some(c) if c.is_whitespace() => {}
+                Some('/') if self.chars.peek() == Some(&'/') => {
+                    // Comments run to the end of the line.
+                    for c in &mut self.chars {
+                        if c == '\n' {
+                            self.line += 1;
+                            break;
+                        }
+                    }
+                }
+                Some('{') => return Ok(Some(Token::Open)),
+                Some('}') => return Ok(Some(Token::Close)),
+                Some('"') => return self.quoted().map(Some),
+                Some(c) => {
+                    let mut s = c.to_string();
+                    while let Some(&c) = self.chars.peek() {
+                        if c.is_whitespace() || c == '{' || c == '}' || c == '"' {
+                            break;
+                        }
+                        s.push(c);
+                        self.chars.next();
+                    }
+                    return Ok(Some(Token::String(s)));
+                }
+            }
+        }
+    }
+
+    fn quoted(&mut self) -> Result<Token> {
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                None => bail!("Unterminated string on line {}", self.line),
+                Some('"') => return Ok(Token::String(s)),
+                Some('\\') => match self.chars.next() {
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some(c) => s.push(c),
+                    None => bail!("Unterminated string on line {}", self.line),
+                },
+                Some(c) => {
+                    if c == '\n' {
+                        self.line += 1;
+                    }
+                    s.push(c);
+                }
+            }
+        }
+    }
+}
+
+fn parse_pairs(tokens: &mut Tokens, nested: bool) -> Result<Vec<(String, Value)>> {
+    let mut pairs = Vec::new();
+    loop {
+        let key = match tokens.next()? {
+            Some(Token::String(k)) => k,
+            Some(Token::Close) if nested => return Ok(pairs),
+            None if !nested => return Ok(pairs),
+            None => bail!("Missing a closing brace"),
+            Some(t) => bail!("Expected a key on line {}, found {:?}", tokens.line, t),
+        };
+        let value = match tokens.next()? {
+            Some(Token::String(v)) => Value::String(v),
+            Some(Token::Open) => Value::Object(parse_pairs(tokens, true)?),
+            Some(t) => bail!(
+                "Expected a value for {} on line {}, found {:?}",
+                key,
+                tokens.line,
+                t
+            ),
+            None => bail!("{} has no value", key),
+        };
+        pairs.push((key, value));
+    }
+}
@@ -16,3 +16,37 @@ where
     let s = String::deserialize(deserializer)?;
     semver::Version::parse(&s).map_err(|err| serde::de::Error::custom(format!("{}", err)))
 }
+
+/// Parses a mod's VERSION.txt, forgiving the ways people tend to write
+/// versions that aren't quite semantic versions: surrounding whitespace
+/// (like Windows line endings) or a BOM, a leading `v`,
+/// and missing minor or patch numbers (`1.2` is `1.2.0`).
+///
+/// Returns the parsed version and the trimmed string it came from.
+pub fn parse_mod_version(contents: &str) -> anyhow::Result<(Version, String)> {
+    let raw = contents.trim_start_matches('\u{feff}').trim();
+    let unprefixed = raw
+        .strip_prefix('v')
+        .or_else(|| raw.strip_prefix('V'))
+        .unwrap_or(raw);
+
+    let version = match Version::parse(unprefixed) {
+        Ok(v) => v,
+        Err(e) => {
+            // Pad 1 or 1.2 (maybe followed by a -prerelease or +build).
+            let core_end = unprefixed.find(['-', '+']).unwrap_or(unprefixed.len());
+            let (core, rest) = unprefixed.split_at(core_end);
+            let components = core.split('.').count();
+            let numeric = core
+                .split('.')
+                .all(|c| !c.is_empty() && c.bytes().all(|b| b.is_ascii_digit()));
+            if !numeric || components > 2 {
+                anyhow::bail!("{:?} isn't a version number ({})", raw, e);
+            }
+            let padded = format!("{}{}{}", core, ".0".repeat(3 - components), rest);
+            Version::parse(&padded)
+                .map_err(|e| anyhow::format_err!("{:?} isn't a version number ({})", raw, e))?
+        }
+    };
+    Ok((version, raw.to_owned()))
+}
@@ -0,0 +1,197 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::{Duration, SystemTime};
+
+use anyhow::*;
+use log::*;
+use notify::{RecursiveMode, Watcher};
+use structopt::*;
+
+use crate::file_utils::*;
+use crate::profile::*;
+
+/// Watch installed mod files and backups for changes by other programs
+///
+/// Prints a line whenever one stops matching what modman recorded
+/// (or goes back to matching it), until interrupted with Ctrl-C.
+/// Handy for catching game updaters and other tools fighting over files.
+/// Restart it after adding or removing mods.
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {}
+
+/// How long to wait for things to settle after a change before we look,
+/// so a file being written doesn't get reported at every chunk.
+const SETTLE_TIME: Duration = Duration::from_millis(250);
+
+/// Something we're watching
+struct Watched<'a> {
+    /// The file's path in the profile
+    file: &'a Path,
+    /// Where it actually is
+    path: PathBuf,
+    meta: &'a ModFileMetadata,
+    kind: Kind<'a>,
+    /// Whether it matched the last time we looked
+    ok: bool,
+}
+
+enum Kind<'a> {
+    /// A file installed by the given mod
+    Installed(&'a Path),
+    /// The backup of a file a mod replaced
+    Backup,
+}
+
+pub fn run(_args: Args) -> Result<()> {
+    let p = load_and_check_profile()?;
+
+    // Keyed by their full paths, which is what we get notified about.
+    let mut watched = BTreeMap::new();
+    for (mod_path, manifest) in &p.mods {
+        for (file, meta) in &manifest.files {
+            let game_path = p.game_path(file);
+            watched.insert(
+                absolute(&game_path)?,
+                Watched {
+                    file,
+                    path: game_path,
+                    meta,
+                    kind: Kind::Installed(mod_path),
+                    ok: true,
+                },
+            );
+            if meta.original_hash.is_some() {
+                let backup_path = mod_path_to_backup_path(file);
+                watched.insert(
+                    absolute(&backup_path)?,
+                    Watched {
+                        file,
+                        path: backup_path,
+                        meta,
+                        kind: Kind::Backup,
+                        ok: true,
+                    },
+                );
+            }
+        }
+    }
+    // Start from how things are now, so we only report what changes.
+    for w in watched.values_mut() {
+        w.ok = matches(w)?;
+        if !w.ok {
+            debug!("{} already doesn't match", w.path.display());
+        }
+    }
+
+    let (tx, rx) = channel();
+    let mut watcher = notify::recommended_watcher(tx).context("Couldn't start watching files")?;
+    let dirs = std::iter::once(&p.root_directory)
+        .chain(p.roots.values())
+        .cloned()
+        .chain(std::iter::once(backup_dir()));
+    for dir in dirs {
+        if !dir.is_dir() {
+            continue;
+        }
+        watcher
+            .watch(&dir, RecursiveMode::Recursive)
+            .with_context(|| format!("Couldn't watch {}", dir.display()))?;
+        debug!("Watching {}", dir.display());
+    }
+    info!(
+        "Watching {} files for changes (press Ctrl-C to stop)...",
+        watched.len()
+    );
+
+    loop {
+        let first = rx.recv().context("Stopped getting file notifications")?;
+        let mut changed = BTreeSet::new();
+        let mut event = first;
+        loop {
+            match event {
+                Ok(e) => changed.extend(e.paths),
+                Err(e) => warn!("Trouble watching files: {}", e),
+            }
+            event = match rx.recv_timeout(SETTLE_TIME) {
+                Ok(e) => e,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => bail!("Stopped getting file notifications"),
+            };
+        }
+
+        for path in changed {
+            let w = match watched.get_mut(&absolute(&path)?) {
+                Some(w) => w,
+                None => continue,
+            };
+            // Don't stop watching just because something's in the way for a moment.
+            let ok = match matches(w) {
+                Ok(ok) => ok,
+                Err(e) => {
+                    warn!("Couldn't check {}: {:#}", w.path.display(), e);
+                    continue;
+                }
+            };
+            if ok != w.ok {
+                w.ok = ok;
+                report(w);
+            }
+        }
+    }
+}
+
+/// Returns true if the watched file has the contents modman recorded.
+fn matches(w: &Watched) -> Result<bool> {
+    let hash = match &w.kind {
+        Kind::Installed(_) => hash_installed_file(&w.path, w.meta),
+        Kind::Backup => hash_file(&w.path),
+    };
+    let expected = match &w.kind {
+        Kind::Installed(_) => &w.meta.mod_hash,
+        Kind::Backup => w.meta.original_hash.as_ref().unwrap(),
+    };
+    match hash {
+        Ok(h) => Ok(h == *expected),
+        Err(e) if is_not_found(&e) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn report(w: &Watched) {
+    let now = humantime::format_rfc3339_seconds(SystemTime::now());
+    let what = match &w.kind {
+        Kind::Installed(mod_path) => {
+            format!("{} (installed by {})", w.path.display(), mod_path.display())
+        }
+        Kind::Backup => format!("The backup of {}", w.file.display()),
+    };
+    if w.ok {
+        println!("{} {} is back to what modman recorded", now, what);
+    } else if w.path.exists() || is_symlink(&w.path) {
+        println!("{} {} was changed by another program", now, what);
+    } else {
+        println!("{} {} was deleted by another program", now, what);
+    }
+}
+
+/// Returns the full path to the given one, without resolving it
+/// if it's a symbolic link (like an installed one might be).
+fn absolute(path: &Path) -> Result<PathBuf> {
+    let name = match path.file_name() {
+        Some(n) => n,
+        None => return Ok(path.to_owned()),
+    };
+    let parent = path
+        .parent()
+        .filter(|d| !d.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    match fs::canonicalize(parent) {
+        Ok(dir) => Ok(dir.join(name)),
+        // Its directory doesn't exist (yet), so we can't resolve any links in it.
+        Err(_) => Ok(env::current_dir()?.join(path)),
+    }
+}
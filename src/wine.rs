@@ -0,0 +1,149 @@
+//! Managing Windows games from Linux (and macOS), under Wine or Proton.
+//!
+//! A Wine prefix is a fake Windows install: drive_c/ holds the game's
+//! "Program Files" and the user's documents (in drive_c/users/<USER>/).
+//! Given one, we look for a game's directories there instead of in the
+//! real home directory, and treat paths as case-insensitive like Windows
+//! (and Wine, which emulates it) does.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use log::*;
+use structopt::*;
+
+use crate::games;
+use crate::profile::*;
+
+/// Shows or sets the Wine (or Proton) prefix the game runs in
+///
+/// For Windows games run on Linux with Wine or Proton, this is the directory
+/// holding drive_c/ (for Proton, <STEAM LIBRARY>/steamapps/compatdata/<APP ID>/pfx).
+/// `modman init --game` finds Proton's prefix by itself, or takes --wine-prefix.
+/// With one, game presets' directories in your documents are looked for
+/// in the prefix, and paths are treated as case-insensitive
+/// (which can only be turned on before any mods are added).
+#[derive(Debug, StructOpt)]
+#[structopt(verbatim_doc_comment)]
+pub struct Args {
+    /// Forget the prefix.
+    #[structopt(long, conflicts_with = "PREFIX")]
+    clear: bool,
+
+    #[structopt(name = "PREFIX")]
+    prefix: Option<PathBuf>,
+}
+
+impl Args {
+    /// Returns true if we'll change the profile (not just show it).
+    pub fn is_changing(&self) -> bool {
+        self.clear || self.prefix.is_some()
+    }
+
+    pub fn resolve_paths(&mut self, base: &Path) {
+        if let Some(prefix) = &mut self.prefix {
+            *prefix = base.join(&prefix);
+        }
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let (mut store, mut p) = open_profile()?;
+
+    if args.clear {
+        p.wine_prefix = None;
+    } else if let Some(prefix) = args.prefix {
+        check_prefix(&prefix)?;
+        if !p.case_insensitive {
+            if p.mods.is_empty() {
+                info!("Treating paths in the game's directories as case-insensitive");
+                p.case_insensitive = true;
+            } else {
+                warn!(
+                    "Wine treats paths as case-insensitive, but this profile doesn't, \
+                     and that can't change once mods are added."
+                );
+            }
+        }
+        p.wine_prefix = Some(prefix);
+    } else {
+        match &p.wine_prefix {
+            Some(prefix) => {
+                println!("{}", prefix.display());
+                if let Some(user) = user_dir(prefix) {
+                    println!("User directory: {}", user.display());
+                }
+            }
+            None => println!("No Wine prefix set"),
+        }
+        return Ok(());
+    }
+    store.save_settings(&p)
+}
+
+/// Makes sure the given directory looks like a Wine prefix.
+pub fn check_prefix(prefix: &Path) -> Result<()> {
+    if !prefix.join("drive_c").is_dir() {
+        bail!(
+            "{} doesn't look like a Wine prefix (it has no drive_c/)",
+            prefix.display()
+        );
+    }
+    Ok(())
+}
+
+/// Finds the prefix Proton runs the given Steam app in, if it has one.
+pub fn find_proton_prefix(app_id: u32) -> Option<PathBuf> {
+    // The manifest is in <library>/steamapps/, next to compatdata/
+    let manifest = games::app_manifest_path(app_id)?;
+    let prefix = manifest
+        .parent()?
+        .join("compatdata")
+        .join(app_id.to_string())
+        .join("pfx");
+    debug!("Looking for Proton's prefix in {}", prefix.display());
+    Some(prefix).filter(|p| p.join("drive_c").is_dir())
+}
+
+/// Returns the Windows user's directory in the prefix (what `~` is there).
+///
+/// Proton's user is always steamuser; Wine's is named after you.
+pub fn user_dir(prefix: &Path) -> Option<PathBuf> {
+    let users = prefix.join("drive_c").join("users");
+    let mut candidates = vec!["steamuser".to_owned()];
+    if let Some(user) = env::var_os("USER").or_else(|| env::var_os("USERNAME")) {
+        candidates.push(user.to_string_lossy().into_owned());
+    }
+    if let Some(found) = candidates
+        .iter()
+        .map(|c| users.join(c))
+        .find(|d| d.is_dir())
+    {
+        return Some(found);
+    }
+    // Otherwise, if there's just one user (besides Public), it's them.
+    let mut others: Vec<PathBuf> = fs::read_dir(&users)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name() != "Public" && e.path().is_dir())
+        .map(|e| e.path())
+        .collect();
+    if others.len() == 1 {
+        others.pop()
+    } else {
+        None
+    }
+}
+
+/// Turns a Windows path (`C:\Program Files\...`) into one in the prefix.
+/// Returns None for paths on other drives.
+pub fn in_prefix(prefix: &Path, windows_path: &str) -> Option<PathBuf> {
+    let rest = windows_path
+        .strip_prefix("C:")
+        .or_else(|| windows_path.strip_prefix("c:"))?;
+    let mut path = prefix.join("drive_c");
+    path.extend(rest.split(['\\', '/']).filter(|c| !c.is_empty()));
+    Some(path)
+}
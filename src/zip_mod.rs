@@ -1,154 +1,606 @@
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, prelude::*, BufReader, SeekFrom};
 use std::path::*;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use self::piz::FileTree;
 use anyhow::*;
-use memmap::Mmap;
-use owning_ref::OwningHandle;
-use piz::read as piz;
+use flate2::read::DeflateDecoder;
+use log::*;
 use semver::Version;
+use zip::{CompressionMethod, ZipArchive};
 
+use crate::checksums::*;
+use crate::file_utils::executable;
+use crate::mod_config::*;
 use crate::modification::Mod;
+use crate::text::decode_text;
+use crate::version_serde::parse_mod_version;
 
-type ZipArchiveHandle = OwningHandle<Box<Mmap>, Box<piz::ZipArchive<'static>>>;
-type FileTreeHandle = OwningHandle<ZipArchiveHandle, Box<piz::DirectoryContents<'static>>>;
+/// Where a mod file lives in the archive.
+///
+/// We read files straight from the archive with plain file I/O
+/// (instead of mapping the whole thing into memory),
+/// so all we need to hang onto is where each one starts and how to decode it.
+#[derive(Clone)]
+struct ZipEntry {
+    /// The entry's full name in the archive, for error messages
+    name: String,
+    data_start: u64,
+    compressed_size: u64,
+    size: u64,
+    compression: CompressionMethod,
+    crc32: u32,
+    /// Unix permissions, if it's executable (and the archive has them)
+    mode: Option<u32>,
+    mtime: SystemTime,
+}
 
 pub struct ZipMod {
-    tree: FileTreeHandle,
+    zip_path: PathBuf,
 
-    /// The base mod directory name, which we need to strip off of all paths.
-    base_dir: &'static piz::Directory<'static>,
+    /// Mod files, with the base mod directory stripped off of their paths
+    files: BTreeMap<PathBuf, ZipEntry>,
 
     v: Version,
 
+    /// VERSION.txt, as written
+    raw_v: String,
+
     r: String,
+
+    changelog: Option<String>,
+
+    c: ModConfig,
+
+    checksums: Option<Checksums>,
+
+    /// Entries decompressed ahead of time (see prefetch())
+    prefetched: Arc<Prefetched>,
+}
+
+// Installing a file from a zip is decompressing it, then writing it out.
+// Each worker does both for one file at a time, so while it's writing,
+// its share of the CPU sits idle (and while it's decompressing, the disk does).
+// So a background thread decompresses the next few files to be installed
+// while workers write out the last ones.
+
+/// How many entries to decompress ahead of when they're read (see `--prefetch`)
+static PREFETCH_WINDOW: AtomicUsize = AtomicUsize::new(8);
+
+/// Entries bigger than this aren't worth holding in memory;
+/// they keep a worker busy long enough on their own.
+const MAX_PREFETCH_SIZE: u64 = 32 << 20;
+
+/// Sets how many entries to decompress ahead of when they're read.
+/// (0 turns prefetching off.)
+pub fn set_prefetch_window(window: usize) {
+    PREFETCH_WINDOW.store(window, Ordering::Relaxed);
+}
+
+#[derive(Default)]
+struct Prefetched {
+    slots: Mutex<PrefetchSlots>,
+    /// Signaled when a slot changes
+    changed: Condvar,
+    /// Set when the mod is dropped, so the prefetching thread stops.
+    stop: AtomicBool,
+}
+
+#[derive(Default)]
+struct PrefetchSlots {
+    entries: BTreeMap<PathBuf, Slot>,
+    /// How many entries are Ready
+    ready: usize,
+}
+
+enum Slot {
+    /// Being decompressed; wait for it.
+    Reading,
+    /// Decompressed, and waiting to be read
+    Ready(Vec<u8>),
+    /// Read (or being read) without prefetching, so don't bother.
+    Taken,
+}
+
+impl Drop for ZipMod {
+    fn drop(&mut self) {
+        self.prefetched.stop.store(true, Ordering::Relaxed);
+        self.prefetched.changed.notify_all();
+    }
+}
+
+/// Decompresses the given entries in order, staying at most `window` ahead
+/// of whoever's reading them.
+fn prefetch_entries(
+    zip_path: PathBuf,
+    entries: Vec<(PathBuf, ZipEntry)>,
+    window: usize,
+    prefetched: Arc<Prefetched>,
+) {
+    for (path, entry) in entries {
+        let mut slots = prefetched.slots.lock().unwrap();
+        while slots.ready >= window && !prefetched.stop.load(Ordering::Relaxed) {
+            slots = prefetched.changed.wait(slots).unwrap();
+        }
+        if prefetched.stop.load(Ordering::Relaxed) {
+            return;
+        }
+        if slots.entries.contains_key(&path) {
+            continue;
+        }
+        slots.entries.insert(path.clone(), Slot::Reading);
+        drop(slots);
+
+        let contents = open_entry(&zip_path, &entry).and_then(|mut r| {
+            let mut contents = Vec::with_capacity(entry.size as usize);
+            r.read_to_end(&mut contents)?;
+            Ok(contents)
+        });
+
+        let mut slots = prefetched.slots.lock().unwrap();
+        match contents {
+            Ok(c) => {
+                slots.entries.insert(path, Slot::Ready(c));
+                slots.ready += 1;
+            }
+            // Let whoever reads it find the problem.
+            Err(e) => {
+                trace!("Couldn't prefetch {}: {:#}", entry.name, e);
+                slots.entries.insert(path, Slot::Taken);
+            }
+        }
+        drop(slots);
+        prefetched.changed.notify_all();
+    }
+}
+
+/// Returns a reader of the entry's decompressed contents.
+/// (Its size and CRC aren't checked; see CheckedReader.)
+fn open_entry(zip_path: &Path, entry: &ZipEntry) -> Result<Box<dyn Read + Send>> {
+    // Each reader gets its own handle to the archive
+    // so that files can be read in parallel.
+    let mut f =
+        File::open(zip_path).with_context(|| format!("Couldn't open {}", zip_path.display()))?;
+    f.seek(SeekFrom::Start(entry.data_start))?;
+    let compressed = f.take(entry.compressed_size);
+
+    Ok(match entry.compression {
+        CompressionMethod::Deflated => Box::new(DeflateDecoder::new(compressed)),
+        _ => Box::new(compressed),
+    })
+}
+
+/// Finds where an archive's mods are: the empty path if the archive is a mod
+/// (it has a VERSION.txt in its root), or otherwise each top-level directory
+/// with a VERSION.txt, if it bundles several.
+/// (Mods can install files named VERSION.txt; those don't count.)
+pub fn mod_roots(zip_path: &Path) -> Result<Vec<PathBuf>> {
+    let file = File::open(zip_path)?;
+    let mut archive = ZipArchive::new(BufReader::new(file))
+        .context("Couldn't read the archive's central directory (is it a zip file?)")?;
+    let mut roots = Vec::new();
+    for i in 0..archive.len() {
+        let entry = archive.by_index_raw(i)?;
+        let path = match entry.enclosed_name() {
+            Some(p) if !entry.is_dir() => p,
+            _ => continue,
+        };
+        if path.file_name() != Some("VERSION.txt".as_ref()) {
+            continue;
+        }
+        let dir = path.parent().unwrap();
+        match dir.components().count() {
+            0 => return Ok(vec![PathBuf::new()]),
+            1 => roots.push(dir.to_owned()),
+            _ => {}
+        }
+    }
+    roots.sort();
+    roots.dedup();
+    Ok(roots)
 }
 
 impl ZipMod {
     pub fn new(zip_path: &Path) -> Result<Self> {
+        Self::within(zip_path, Path::new(""))
+    }
+
+    /// Opens the mod in the given directory of the archive
+    /// (for archives that bundle several; see mod_roots()).
+    pub fn within(zip_path: &Path, inner: &Path) -> Result<Self> {
         let file = File::open(zip_path)?;
-        let mmap = Box::new(unsafe { Mmap::map(&file)? });
+        let archive_len = file.metadata()?.len();
+        // (The zip crate handles Zip64 archives for us -
+        // those over 4 GB or with more than 65,535 entries.)
+        let mut archive = ZipArchive::new(BufReader::new(file))
+            .context("Couldn't read the archive's central directory (is it a zip file?)")?;
+        let entry_count = archive.len();
 
-        let archive = OwningHandle::try_new(mmap, unsafe {
-            |map| piz::ZipArchive::new(map.as_ref().unwrap()).map(Box::new)
-        })?;
-        let tree = OwningHandle::try_new(archive, unsafe {
-            |ar| piz::as_tree(ar.as_ref().unwrap().entries()).map(Box::new)
-        })?;
+        let mut version_index: Option<usize> = None;
 
-        let mut version_info: Option<Version> = None;
+        let mut readme_index: Option<usize> = None;
 
-        let mut readme: Option<String> = None;
+        let mut changelog_index: Option<usize> = None;
 
-        let mut base_dir: *const piz::Directory = std::ptr::null();
+        let mut config_index: Option<usize> = None;
 
-        for (path, entry) in tree.iter() {
-            // TODO: Parcel out into functions
-            match &*path.to_string_lossy() {
-                // Carve out special exception for .git in case people build
-                // mods with Git.
-                // TODO: Other exceptions?
-                ".git" => {
-                    continue;
-                }
-                "VERSION.txt" => {
-                    assert!(version_info.is_none());
-                    let z = tree.as_owner();
-                    let mut vf = z
-                        .read(entry.metadata())
-                        .context("Couldn't open VERSION.txt")?;
-                    let mut version_string = String::new();
-                    vf.read_to_string(&mut version_string)?;
-                    version_info = Some(
-                        Version::parse(&version_string).context("Couldn't parse version string")?,
-                    );
-                }
-                "README.txt" => {
-                    assert!(readme.is_none());
-                    let z = tree.as_owner();
-                    let mut rf = z
-                        .read(entry.metadata())
-                        .context("Couldn't open README.txt")?;
-                    let mut readme_string = String::new();
-                    rf.read_to_string(&mut readme_string)?;
-                    readme = Some(readme_string);
-                }
-                _ => {
-                    if let piz::DirectoryEntry::Directory(dir) = entry {
-                        if base_dir.is_null() {
-                            base_dir = &*dir;
-                        } else {
-                            bail!(
-                                "{} contains more than one base directory.",
-                                zip_path.display()
-                            );
-                        }
-                    } else {
-                        bail!(
-                            "{} contains files root besides README.txt and VERSION.txt.",
-                            zip_path.display()
-                        );
-                    }
-                }
+        let mut checksums_index: Option<usize> = None;
+
+        let mut base_dir: Option<PathBuf> = None;
+
+        let mut files = BTreeMap::new();
+
+        for i in 0..entry_count {
+            // Ask for the raw entry so that we just read its header
+            // instead of decompressing the whole thing.
+            let entry = archive
+                .by_index_raw(i)
+                .with_context(|| format!("Couldn't read entry {} of {}", i + 1, entry_count))?;
+            let path = entry
+                .enclosed_name()
+                .ok_or_else(|| format_err!("{} has an unsafe path", entry.name()))?;
+            // Other mods bundled in the archive aren't ours.
+            let path = match path.strip_prefix(inner) {
+                Ok(p) => p.to_owned(),
+                Err(_) => continue,
             };
-        }
 
-        if version_info.is_none() {
-            bail!("Couldn't find VERSION.txt");
-        }
-        if readme.is_none() {
-            bail!("Couldn't find README.txt");
+            let mut components = path.components();
+            let first = match components.next() {
+                Some(c) => PathBuf::from(c.as_os_str()),
+                None => continue,
+            };
+            let rest = components.as_path();
+
+            // Carve out special exception for .git in case people build
+            // mods with Git.
+            // TODO: Other exceptions?
+            if first == Path::new(".git") {
+                continue;
+            }
+
+            if rest.as_os_str().is_empty() && !entry.is_dir() {
+                let index = match &*first.to_string_lossy() {
+                    "VERSION.txt" => &mut version_index,
+                    "README.txt" => &mut readme_index,
+                    "CHANGELOG.txt" => &mut changelog_index,
+                    "mod.toml" => &mut config_index,
+                    "CHECKSUMS.txt" => &mut checksums_index,
+                    _ => bail!(
+                        "{} contains files in its root besides README.txt, VERSION.txt, \
+                         CHANGELOG.txt, mod.toml, and CHECKSUMS.txt.",
+                        zip_path.display()
+                    ),
+                };
+                assert!(index.is_none());
+                *index = Some(i);
+                continue;
+            }
+
+            match &base_dir {
+                Some(b) if *b != first => bail!(
+                    "{} contains more than one base directory.",
+                    zip_path.display()
+                ),
+                Some(_) => {}
+                None => base_dir = Some(first),
+            }
+
+            if entry.is_dir() {
+                continue;
+            }
+            match entry.compression() {
+                CompressionMethod::Stored | CompressionMethod::Deflated => {}
+                other => bail!(
+                    "{} is compressed with {:?}, which modman can't read \
+                     (only stored and deflated files are supported)",
+                    entry.name(),
+                    other
+                ),
+            }
+            if entry.data_start().saturating_add(entry.compressed_size()) > archive_len {
+                bail!(
+                    "{} runs past the end of the archive (was it only partly downloaded?)",
+                    entry.name()
+                );
+            }
+            files.insert(
+                rest.to_owned(),
+                ZipEntry {
+                    name: entry.name().to_owned(),
+                    data_start: entry.data_start(),
+                    compressed_size: entry.compressed_size(),
+                    size: entry.size(),
+                    compression: entry.compression(),
+                    crc32: entry.crc32(),
+                    mode: entry.unix_mode().and_then(executable),
+                    mtime: entry_mtime(&entry),
+                },
+            );
         }
-        if base_dir.is_null() {
+
+        let (version_info, version_string) = match version_index {
+            Some(i) => {
+                let version_string =
+                    read_entry_to_text(&mut archive, i).context("Couldn't open VERSION.txt")?;
+                parse_mod_version(&version_string).context("Couldn't parse version string")?
+            }
+            None => bail!("Couldn't find VERSION.txt"),
+        };
+        let readme = match readme_index {
+            Some(i) => read_entry_to_text(&mut archive, i).context("Couldn't open README.txt")?,
+            None => bail!("Couldn't find README.txt"),
+        };
+        let changelog = match changelog_index {
+            Some(i) => {
+                Some(read_entry_to_text(&mut archive, i).context("Couldn't open CHANGELOG.txt")?)
+            }
+            None => None,
+        };
+        let config = match config_index {
+            Some(i) => {
+                let config_string = read_entry_to_string(&mut archive, i)
+                    .with_context(|| format!("Couldn't open {}", MOD_CONFIG_NAME))?;
+                ModConfig::parse(&config_string)?
+            }
+            None => ModConfig::default(),
+        };
+        if base_dir.is_none() {
             bail!("Couldn't find a base directory");
         }
+        let checksums = match checksums_index {
+            Some(i) => {
+                let text = read_entry_to_string(&mut archive, i)
+                    .with_context(|| format!("Couldn't open {}", CHECKSUMS_NAME))?;
+                let checksums = parse_checksums(&text)?;
+                check_listed(&checksums, &files.keys().cloned().collect::<Vec<_>>())?;
+                Some(checksums)
+            }
+            None => None,
+        };
 
         Ok(Self {
-            tree,
-            // the pointee doesn't move. It lives in a Box -
-            // see [`owning_ref::StableAddress`](http://kimundi.github.io/owning-ref-rs/owning_ref/trait.StableAddress.html)
-            // - so moving Self won't invalidate the address.
-            // We make the lifetime of this reference `&'static` because there's
-            // no lifetime to tag it with, so handing that reference to other
-            // code would be quite unsafe... but we have no reason to.
-            base_dir: unsafe { &base_dir.as_ref().unwrap() },
-            v: version_info.unwrap(),
-            r: readme.unwrap(),
+            zip_path: zip_path.to_owned(),
+            files,
+            v: version_info,
+            raw_v: version_string,
+            r: readme,
+            changelog,
+            c: config,
+            checksums,
+            prefetched: Default::default(),
         })
     }
 
-    fn zip_archive(&self) -> &piz::ZipArchive {
-        self.tree.as_owner()
+    /// Takes the given file's prefetched contents, if it has any.
+    /// If it's being prefetched, waits for it.
+    fn take_prefetched(&self, p: &Path) -> Option<Vec<u8>> {
+        let mut slots = self.prefetched.slots.lock().unwrap();
+        loop {
+            match slots.entries.get(p) {
+                Some(Slot::Reading) => {
+                    slots = self.prefetched.changed.wait(slots).unwrap();
+                }
+                Some(Slot::Ready(_)) => {
+                    let ready = slots.entries.insert(p.to_owned(), Slot::Taken);
+                    slots.ready -= 1;
+                    drop(slots);
+                    // There's room for another.
+                    self.prefetched.changed.notify_all();
+                    match ready {
+                        Some(Slot::Ready(contents)) => return Some(contents),
+                        _ => unreachable!(),
+                    }
+                }
+                Some(Slot::Taken) => return None,
+                None => {
+                    slots.entries.insert(p.to_owned(), Slot::Taken);
+                    return None;
+                }
+            }
+        }
     }
 }
 
+fn read_entry_to_string<R: Read + Seek>(archive: &mut ZipArchive<R>, i: usize) -> Result<String> {
+    let mut entry = archive.by_index(i)?;
+    let mut s = String::new();
+    entry
+        .read_to_string(&mut s)
+        .with_context(|| format!("Couldn't decode {}", entry.name()))?;
+    Ok(s)
+}
+
+/// Reads a text file (like the README) that might not be UTF-8.
+fn read_entry_to_text<R: Read + Seek>(archive: &mut ZipArchive<R>, i: usize) -> Result<String> {
+    let mut entry = archive.by_index(i)?;
+    let mut bytes = Vec::new();
+    entry
+        .read_to_end(&mut bytes)
+        .with_context(|| format!("Couldn't read {}", entry.name()))?;
+    Ok(decode_text(&bytes, entry.name()))
+}
+
+/// Returns when the entry was last modified.
+///
+/// Zip files store times in local time (with no time zone, thanks MS-DOS),
+/// so prefer the UTC timestamp most zip tools also add if it's there.
+fn entry_mtime(entry: &zip::read::ZipFile) -> SystemTime {
+    if let Some(secs) = extended_timestamp(entry.extra_data()) {
+        return UNIX_EPOCH + Duration::from_secs(secs.into());
+    }
+    // Otherwise we'll just have to pretend it's UTC.
+    let t = entry.last_modified();
+    let days = days_from_civil(t.year().into(), t.month().into(), t.day().into());
+    let secs = days * 86400
+        + i64::from(t.hour()) * 3600
+        + i64::from(t.minute()) * 60
+        + i64::from(t.second());
+    UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)
+}
+
+/// Finds the modification time in an "extended timestamp" (0x5455) extra field.
+fn extended_timestamp(mut extra: &[u8]) -> Option<u32> {
+    while extra.len() >= 4 {
+        let id = u16::from_le_bytes([extra[0], extra[1]]);
+        let len = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        let data = extra.get(4..4 + len)?;
+        // The first byte says which times follow; bit 0 is the modification time.
+        if id == 0x5455 && data.len() >= 5 && data[0] & 1 != 0 {
+            return Some(u32::from_le_bytes([data[1], data[2], data[3], data[4]]));
+        }
+        extra = &extra[4 + len..];
+    }
+    None
+}
+
+/// Days since 1970-01-01, from http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
 impl Mod for ZipMod {
     fn paths(&self) -> Result<Vec<PathBuf>> {
-        Ok(self
-            .base_dir
-            .children
-            .files()
-            .map(|d| {
-                let whole_path = d.path.as_ref();
-                let base_dir_path = self.base_dir.metadata.path.as_ref();
-                let sans_base_dir = whole_path.strip_prefix(base_dir_path).unwrap();
-                PathBuf::from(sans_base_dir)
-            })
-            .collect())
+        Ok(self.files.keys().cloned().collect())
     }
 
     fn read_file<'a>(&'a self, p: &Path) -> Result<Box<dyn Read + Send + 'a>> {
-        let metadata = self.base_dir.children.lookup(p)?;
-        let reader = self.zip_archive().read(metadata)?;
-        Ok(reader)
+        let entry = self.files.get(p).ok_or_else(|| {
+            format_err!("{} has no file {}", self.zip_path.display(), p.display())
+        })?;
+
+        let decompressed: Box<dyn Read + Send> = match self.take_prefetched(p) {
+            Some(contents) => Box::new(io::Cursor::new(contents)),
+            None => open_entry(&self.zip_path, entry)?,
+        };
+        let crc_checked = Box::new(CheckedReader {
+            inner: decompressed,
+            entry,
+            hasher: crc32fast::Hasher::new(),
+            bytes_read: 0,
+        });
+        checked_reader(crc_checked, self.checksums.as_ref(), p)
+    }
+
+    fn prefetch(&self, paths: &[PathBuf]) {
+        let window = PREFETCH_WINDOW.load(Ordering::Relaxed);
+        if window == 0 {
+            return;
+        }
+        let entries: Vec<(PathBuf, ZipEntry)> = paths
+            .iter()
+            .filter_map(|p| self.files.get(p).map(|e| (p.clone(), e.clone())))
+            .filter(|(_, e)| e.size <= MAX_PREFETCH_SIZE)
+            .collect();
+        if entries.is_empty() {
+            return;
+        }
+        let zip_path = self.zip_path.clone();
+        let prefetched = self.prefetched.clone();
+        thread::spawn(move || prefetch_entries(zip_path, entries, window, prefetched));
+    }
+
+    fn symlink(&self, _p: &Path) -> Result<Option<PathBuf>> {
+        Ok(None)
+    }
+
+    fn mode(&self, p: &Path) -> Result<Option<u32>> {
+        match self.files.get(p) {
+            Some(entry) => Ok(entry.mode),
+            None => bail!("{} has no file {}", self.zip_path.display(), p.display()),
+        }
+    }
+
+    fn mtime(&self, p: &Path) -> Result<Option<SystemTime>> {
+        match self.files.get(p) {
+            Some(entry) => Ok(Some(entry.mtime)),
+            None => bail!("{} has no file {}", self.zip_path.display(), p.display()),
+        }
+    }
+
+    fn size(&self, p: &Path) -> Result<u64> {
+        match self.files.get(p) {
+            Some(entry) => Ok(entry.size),
+            None => bail!("{} has no file {}", self.zip_path.display(), p.display()),
+        }
     }
 
     fn version(&self) -> &Version {
         &self.v
     }
 
+    fn version_string(&self) -> &str {
+        &self.raw_v
+    }
+
     fn readme(&self) -> &str {
         &self.r
     }
+
+    fn changelog(&self) -> Option<&str> {
+        self.changelog.as_deref()
+    }
+
+    fn config(&self) -> &ModConfig {
+        &self.c
+    }
+}
+
+/// Checks a file's size and CRC once we've read all of it,
+/// and names the file in any errors we hit along the way
+/// (so that a bad entry in a big archive is easy to find).
+struct CheckedReader<'a> {
+    inner: Box<dyn Read + Send>,
+    entry: &'a ZipEntry,
+    hasher: crc32fast::Hasher,
+    bytes_read: u64,
+}
+
+impl CheckedReader<'_> {
+    fn corrupt(&self, problem: String) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} in the mod archive is corrupt: {}. \
+                 Try downloading the mod again.",
+                self.entry.name, problem
+            ),
+        )
+    }
+}
+
+impl Read for CheckedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self
+            .inner
+            .read(buf)
+            .map_err(|e| self.corrupt(format!("couldn't decompress it ({})", e)))?;
+        if n == 0 && !buf.is_empty() {
+            if self.bytes_read != self.entry.size {
+                return Err(self.corrupt(format!(
+                    "it should be {} bytes, but was {}",
+                    self.entry.size, self.bytes_read
+                )));
+            }
+            let crc = self.hasher.clone().finalize();
+            if crc != self.entry.crc32 {
+                return Err(self.corrupt(format!(
+                    "its CRC is {:08x}, but should be {:08x}",
+                    crc, self.entry.crc32
+                )));
+            }
+        }
+        self.hasher.update(&buf[..n]);
+        self.bytes_read += n as u64;
+        Ok(n)
+    }
 }
@@ -17,7 +17,27 @@ rootsums()
 
 backupsums()
 {
-   find modman-backup -type f | LC_ALL=C sort | tr '\n' '\0' | xargs -0 sha224sum
+   find modman-backup -type f -not -name history.log | LC_ALL=C sort | tr '\n' '\0' | xargs -0 sha224sum
+}
+
+# The profile, minus what's recorded about zip mods' archives
+# (which depends on when the zips were made)
+profile()
+{
+    grep -vE '"archive_(hash|size|mtime)": ' modman.profile
+}
+
+# Runs modman, expecting it to exit with the given code.
+expect_code()
+{
+    local expected=$1
+    shift
+    local code=0
+    $quietrun "$@" > /dev/null 2>&1 || code=$?
+    if [ "$code" -ne "$expected" ]; then
+        echo "Expected \`modman $*\` to exit with $expected, but it exited with $code"
+        exit 1
+    fi
 }
 
 echo "Building..."
@@ -25,7 +45,7 @@ cargo build
 
 # Make sure that everything's starting the way we expect.
 echo "Cleaning up test environment..."
-rm -f modman.profile
+rm -f modman.profile modman.profile.[0-9]*
 rm -rf modman-backup
 
 # Make a zip version of mod1
@@ -44,14 +64,19 @@ diff -u <(backupsums) expected/empty.backup
 # That's pretty fragile, but we should be running these tests often enough
 # to notice if they get out of sync.
 
+echo "Testing exit codes for bad arguments and missing profiles"
+expect_code 2 --not-a-flag list
+expect_code 2 add
+expect_code 3 -C rootdir list
+
 echo "Testing init failure on existing profile"
 out=$(! $run init --root rootdir 2>&1)
-echo "$out" | grep -q 'A profile already exists.'
+grep -q 'A profile already exists.' <<< "$out"
 
 echo "Testing init failure on existing backup directory"
 mv modman.profile modman.profile.tmp
 out=$(! $run init --root rootdir 2>&1)
-echo "$out" | grep -q "Please move or remove it."
+grep -q "Please move or remove it." <<< "$out"
 mv modman.profile.tmp modman.profile
 
 echo "Testing repair"
@@ -69,16 +94,53 @@ echo "Replace A.txt" > modman-backup/temp/activate.journal
 echo "Replace B.txt" >> modman-backup/temp/activate.journal
 echo "Add C.txt" >> modman-backup/temp/activate.journal
 
+expect_code 8 add mod1.zip
 $run repair
 diff -u <(rootsums) expected/starting.root
 diff -u <(backupsums) expected/empty.backup
 
+echo "Testing resuming an interrupted add"
+# As if `add mod1.zip` was interrupted while adding C.txt,
+# after replacing A.txt but before backing up B.txt.
+mv rootdir/A.txt modman-backup/originals/A.txt
+cp mod1/modroot/A.txt rootdir
+echo "Half of C" > rootdir/C.txt
+echo "Replace A.txt" > modman-backup/temp/activate.journal
+echo "Replace B.txt" >> modman-backup/temp/activate.journal
+echo "Add C.txt" >> modman-backup/temp/activate.journal
+$quietrun add --resume mod1.zip
+diff -u <(profile) expected/mod1.profile
+diff -u expected/mod1.backup <(backupsums)
+diff -u expected/mod1.root <(rootsums)
+$quietrun check
+out=$(! $quietrun add --resume mod2 2>&1)
+grep -q "There's no interrupted \`modman add\` to resume." <<< "$out"
+$quietrun remove mod1
+rm modman-backup/history.log
+diff -u <(rootsums) expected/starting.root
+diff -u <(backupsums) expected/empty.backup
+
+echo "Testing leftover backups"
+# As if `add mod1.zip` was interrupted and its journal was lost
+cp rootdir/A.txt modman-backup/originals/A.txt
+echo "Not B.txt" > modman-backup/originals/B.txt
+out=$(! $quietrun add mod1.zip 2>&1)
+grep -q "modman-backup/originals/A.txt (matches the game's file)" <<< "$out"
+grep -q "modman-backup/originals/B.txt (doesn't match the game's file)" <<< "$out"
+$quietrun add --adopt-existing-backup mod1.zip
+diff -u <(echo "Not B.txt") modman-backup/quarantine/B.txt
+$quietrun check
+$quietrun remove mod1
+rm -r modman-backup/quarantine modman-backup/history.log
+diff -u <(rootsums) expected/starting.root
+diff -u <(backupsums) expected/empty.backup
+
 echo "Activating a ZIP mod (mod1)"
 $run add mod1.zip
 #cp modman.profile expected/mod1.profile
 #backupsums > expected/mod1.backup
 #rootsums > expected/mod1.root
-diff -u modman.profile expected/mod1.profile
+diff -u <(profile) expected/mod1.profile
 diff -u expected/mod1.backup <(backupsums)
 diff -u expected/mod1.root <(rootsums)
 
@@ -87,21 +149,43 @@ $run add mod2
 #cp modman.profile expected/mod2.profile
 #backupsums > expected/mod2.backup
 #rootsums > expected/mod2.root
-diff -u modman.profile expected/mod2.profile
+diff -u <(profile) expected/mod2.profile
 diff -u expected/mod2.backup <(backupsums)
 diff -u expected/mod2.root <(rootsums)
 
 echo "Testing activation failure when adding the same mod twice"
 out=$(! $run add mod1.zip 2>&1)
-echo "$out" | grep -q "mod1.zip has already been added!"
+grep -q "mod1.zip has already been added!" <<< "$out"
 
 echo "Testing activation conflict detection"
 out=$(! $run add mod-conflicting.zip 2>&1)
-echo "$out" | grep -q "A.txt from mod-conflicting.zip would overwrite the same file from mod1"
+grep -q "A.txt from mod-conflicting.zip would overwrite the same file from mod1" <<< "$out"
+expect_code 5 add mod-conflicting.zip
+expect_code 5 add mod1.zip
+
+echo "Testing exit codes for unknown mods"
+expect_code 6 remove not-a-mod
+expect_code 6 show mod
 
 echo "Testing list"
 #$run list -f -r > expected/list.txt
 diff -u expected/list.txt <($run list --files --readme)
+out=$($quietrun list --size)
+grep -q "^mod1.zip (v1.2.3) - 165 B installed, 64 B of backups$" <<< "$out"
+out=$($quietrun stats)
+grep -q "^Total: .* installed, .* of backups$" <<< "$out"
+out=$($quietrun show mod2 --changelog)
+grep -q "^0.0.1-pre-lol: The first version, with a new directory of new files.$" <<< "$out"
+
+echo "Testing mods with non-UTF-8 READMEs and sloppy versions (v1.0, CRLF)"
+out=$($quietrun add --dry-run mod-cp1251)
+grep -q '^+ mod-cp1251 (v1.0.0)$' <<< "$out"
+grep -q '^  + cp1251.txt' <<< "$out"
+$quietrun add mod-cp1251
+out=$($quietrun show mod-cp1251)
+grep -q '^Version as the mod wrote it: v1.0$' <<< "$out"
+$quietrun remove mod-cp1251
+rm modman-backup/history.log
 
 echo "Testing check"
 $run check
@@ -114,6 +198,7 @@ echo "Changed game contents" > rootdir/A.txt
 #! $quietrun -- check > expected/check.warns 2>&1
 out=$(! $quietrun check 2>&1)
 diff -u expected/check.warns <(echo "$out")
+expect_code 7 check
 # Undo those changes.
 rm modman-backup/temp/activate.journal
 mv modman-backup/originals/wut.txt modman-backup/originals/A.txt
@@ -123,15 +208,27 @@ $run check
 echo "Testing update with version mismatch"
 echo "1.2.3" > mod2/VERSION.txt
 out=$(! $run update 2>&1)
-echo "$out" | grep -q "mod2's version ([1-9.]\+) doesn't match what it was"
+grep -q "mod2's version ([1-9.]\+) doesn't match what it was" <<< "$out"
 git checkout -- mod2/VERSION.txt
 
 echo "Testing no-op update"
 $run update
-diff -u modman.profile expected/mod2.profile
+diff -u <(profile) expected/mod2.profile
 diff -u expected/mod2.backup <(backupsums)
 diff -u expected/mod2.root <(rootsums)
 
+echo "Testing update --accept-mod-changes"
+echo "A re-uploaded A" > mod2/mod2/newdir/newsubdir/A.txt
+$run update
+$quietrun update --accept-mod-changes
+$quietrun check
+if diff -q <(profile) expected/mod2.profile > /dev/null; then exit 1; fi
+diff -u <(echo "A re-uploaded A") rootdir/newdir/newsubdir/A.txt
+git checkout -- mod2/mod2/newdir/newsubdir/A.txt
+$quietrun update --accept-mod-changes
+diff -u <(profile) expected/mod2.profile
+diff -u expected/mod2.root <(rootsums)
+
 echo "Testing update"
 echo "I am the latest and greatest version of B." > rootdir/B.txt
 echo "I am a new game file replacing the mod file, C." > rootdir/C.txt
@@ -152,4 +249,986 @@ diff -u <(echo "I am a new game file replacing the mod file, C.") rootdir/C.txt
 git checkout -- rootdir/B.txt
 rm rootdir/C.txt
 
+echo "Testing history"
+$run history | grep -q "remove mod1.zip mod2 (6 files): ok"
+
+echo "Testing plans"
+$run add --plan plan.json mod2
+diff -u modman.profile expected/empty.profile
+grep -q '"op": "install"' plan.json
+$run apply plan.json
+$quietrun list | grep -q "^mod2"
+out=$(! $run apply plan.json 2>&1)
+grep -q "is out of date" <<< "$out"
+$run remove mod2
+rm plan.json
+diff -u modman.profile expected/empty.profile
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing check --fix for interrupted removes"
+$quietrun add mod1.zip
+# As if `remove mod1.zip` was interrupted after restoring A.txt and removing C.txt
+cp modman-backup/originals/A.txt rootdir/A.txt
+rm rootdir/C.txt
+out=$(! $quietrun check 2>&1)
+grep -q "run \`modman remove --force mod1.zip\`" <<< "$out"
+$quietrun check --fix
+diff -u modman.profile expected/empty.profile
+diff -u <(rootsums) expected/starting.root
+# As if it was interrupted before removing backups it restored
+cp rootdir/B.txt modman-backup/originals/B.txt
+out=$(! $quietrun check 2>&1)
+grep -q "interrupted after restoring them" <<< "$out"
+$quietrun check --fix
+$quietrun check
+rm modman-backup/history.log
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing rebuild-backups"
+$quietrun add mod1.zip
+cp modman-backup/originals/A.txt A.orig
+rm modman-backup/originals/A.txt
+expect_code 7 rebuild-backups
+# As if the game's files were verified (and the original put back)
+cp A.orig rootdir/A.txt
+rm A.orig
+out=$($quietrun rebuild-backups)
+grep -q "^	mod1.zip$" <<< "$out"
+diff -u expected/mod1.backup <(backupsums)
+$quietrun rebuild-backups --reinstall
+diff -u <(profile) expected/mod1.profile
+diff -u expected/mod1.root <(rootsums)
+$quietrun remove mod1
+rm modman-backup/history.log
+diff -u <(rootsums) expected/starting.root
+
+echo "Testing backup export and import"
+$quietrun add mod1.zip
+# Older originals (see keep-originals) come along too.
+mkdir modman-backup/older-originals
+echo "An older A.txt" > older.txt
+older_hash=$(sha224sum older.txt | cut -d ' ' -f 1)
+mv older.txt "modman-backup/older-originals/$older_hash"
+echo "{\"A.txt\": [\"$older_hash\"]}" > modman-backup/older-originals/index.json
+$quietrun backup export backup-test.zip
+mkdir imported
+$quietrun -C imported backup import --root ../rootdir ../backup-test.zip
+diff -u <(backupsums) <(cd imported && backupsums)
+test -f "imported/modman-backup/older-originals/$older_hash"
+$quietrun -C imported check
+rm -r imported modman-backup/older-originals
+# Swap a backup for something else; import should catch it.
+mkdir -p tampered/modman-backup/originals tampered/modman-backup/older-originals
+echo "Not A.txt" > tampered/modman-backup/originals/A.txt
+echo "Not an older A.txt" > "tampered/modman-backup/older-originals/$older_hash"
+sh -c 'cd tampered && zip -r ../backup-test.zip modman-backup' > /dev/null
+mkdir imported
+out=$(! $quietrun -C imported backup import ../backup-test.zip 2>&1)
+grep -q "modman-backup/originals/A.txt has changed" <<< "$out"
+grep -q "modman-backup/older-originals/$older_hash has changed" <<< "$out"
+test ! -e imported/modman-backup
+rm -r imported tampered backup-test.zip
+$quietrun remove mod1
+rm modman-backup/history.log
+diff -u <(rootsums) expected/starting.root
+
+echo "Testing --trash"
+$quietrun add mod1.zip
+XDG_DATA_HOME="$PWD/trash-test" $quietrun --trash remove mod1
+test -f trash-test/Trash/files/C.txt
+test -f trash-test/Trash/files/N.txt
+ls trash-test/Trash/info | grep -q "A.txt.trashinfo"
+rm -r trash-test
+diff -u <(rootsums) expected/starting.root
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing add --verify-archive"
+# Damage a file in a copy of the archive; its CRC won't match any more.
+cp mod1.zip corrupt.zip
+offset=$(grep -obUa "modified version of file A" corrupt.zip | cut -d: -f1)
+printf 'MODIFIED' | dd of=corrupt.zip bs=1 seek="$offset" conv=notrunc 2> /dev/null
+out=$(! $quietrun add --verify-archive corrupt.zip 2>&1)
+grep -q "A.txt in the mod archive is corrupt" <<< "$out"
+expect_code 7 add --verify-archive corrupt.zip
+diff -u <(rootsums) expected/starting.root
+diff -u expected/empty.backup <(backupsums)
+rm corrupt.zip
+$quietrun add --verify-archive mod1.zip
+$quietrun remove mod1
+rm modman-backup/history.log
+diff -u <(rootsums) expected/starting.root
+
+echo "Testing CHECKSUMS.txt"
+cp -r mod1 checksummed
+$quietrun make-checksums checksummed
+(cd checksummed/modroot && sha224sum --quiet -c ../CHECKSUMS.txt)
+echo "Tampered" >> checksummed/modroot/B.txt
+out=$(! $quietrun add --verify-archive checksummed 2>&1)
+grep -q "B.txt doesn't match its checksum in CHECKSUMS.txt" <<< "$out"
+diff -u <(rootsums) expected/starting.root
+cp mod1/modroot/B.txt checksummed/modroot/B.txt
+echo "Extra" > checksummed/modroot/extra.txt
+out=$(! $quietrun add checksummed 2>&1)
+grep -q "extra.txt isn't listed in CHECKSUMS.txt" <<< "$out"
+rm checksummed/modroot/extra.txt
+sh -c 'cd checksummed && zip -qr ../checksummed.zip .'
+$quietrun add checksummed.zip
+diff -u expected/mod1.root <(rootsums)
+$quietrun remove checksummed
+rm -r checksummed checksummed.zip modman-backup/history.log
+diff -u <(rootsums) expected/starting.root
+
+echo "Testing outdated"
+cp -r mod1 upgradable
+$quietrun add --alias upgradable-alias upgradable
+out=$($quietrun outdated)
+grep -q "All installed mods are up to date." <<< "$out"
+echo "2.0.0" > upgradable/VERSION.txt
+out=$($quietrun outdated)
+grep -q "upgradable: v1.2.3 -> v2.0.0" <<< "$out"
+out=$($quietrun outdated -n --upgrade-all)
+grep -q "Would upgrade" <<< "$out"
+out=$($quietrun show upgradable)
+grep -q "v1.2.3" <<< "$out"
+$quietrun outdated --upgrade-all > /dev/null
+out=$($quietrun show upgradable-alias)
+grep -q "v2.0.0" <<< "$out"
+diff -u expected/mod1.root <(rootsums)
+$quietrun remove upgradable
+rm -r upgradable modman-backup/history.log
+diff -u <(rootsums) expected/starting.root
+
+echo "Testing watch"
+$quietrun add mod1.zip
+# Run the binary directly so we can stop it.
+../target/debug/modman watch > watch.log 2>&1 &
+watcher=$!
+sleep 1
+echo "Clobbered" > rootdir/A.txt
+rm rootdir/newdir/N.txt
+sleep 1
+kill $watcher
+wait $watcher || true
+grep -q "rootdir/A.txt (installed by mod1.zip) was changed by another program" watch.log
+grep -q "rootdir/newdir/N.txt (installed by mod1.zip) was deleted by another program" watch.log
+rm watch.log
+$quietrun remove --force mod1
+rm modman-backup/history.log
+diff -u <(rootsums) expected/starting.root
+
+echo "Testing auto-check"
+$quietrun auto-check on
+out=$($quietrun auto-check)
+grep -q "on" <<< "$out"
+$quietrun add mod1.zip
+echo "Something a lot longer than what the mod installed" > rootdir/newdir/N.txt
+expect_code 7 add mod2
+expect_code 7 remove mod1
+touch modman-backup/temp/activate.journal
+expect_code 8 update
+rm modman-backup/temp/activate.journal
+# Changed files are what update is for, so it just warns about them.
+out=$($quietrun update 2>&1)
+grep -q "rootdir/newdir/N.txt" <<< "$out"
+$quietrun remove mod1
+# (Update took our N.txt for a new game file, so removing the mod restored it.)
+rm -r rootdir/newdir
+$quietrun auto-check off
+rm modman-backup/history.log
+diff -u <(rootsums) expected/starting.root
+
+echo "Testing mods sharing identical files"
+cp -r mod1 twin
+$quietrun add mod1.zip
+$quietrun add twin
+test "$(grep -c '"shared": true' modman.profile)" -eq 4
+$quietrun check
+# Removing the mod that installed them first leaves them for the other.
+$quietrun remove mod1
+diff -u expected/mod1.root <(rootsums)
+$quietrun check
+$quietrun remove twin
+rm -r twin modman-backup/history.log
+diff -u <(rootsums) expected/starting.root
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing mod files identical to the game's"
+cp -r mod1 same
+cp rootdir/A.txt same/modroot/A.txt
+out=$($run add same 2>&1)
+grep -q "identical to the game files they replaced" <<< "$out"
+$quietrun remove same
+$quietrun add --skip-identical same
+test "$(grep -c '"identical": true' modman.profile)" -eq 1
+test ! -e modman-backup/A.txt
+$quietrun check
+$quietrun remove same
+rm -r same modman-backup/history.log
+diff -u <(rootsums) expected/starting.root
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing --no-write"
+profile_sum=$(sha224sum modman.profile)
+$quietrun --no-write add mod1 > /dev/null
+$quietrun add mod2
+$quietrun --no-write remove mod2 > /dev/null
+$quietrun --no-write update
+$quietrun --no-write check
+test "$(sha224sum modman.profile)" != "$profile_sum"
+profile_sum=$(sha224sum modman.profile)
+expect_code 2 --no-write check --fix
+expect_code 2 --no-write add --plan plan.json mod1
+test ! -e plan.json
+$quietrun --no-write remove mod2 > /dev/null
+test "$(sha224sum modman.profile)" = "$profile_sum"
+$quietrun remove mod2
+diff -u <(rootsums) expected/starting.root
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing the log file"
+$quietrun --log-file add mod1
+grep -q "DEBUG modman::add: Backing up" modman-backup/modman.log
+$quietrun --log-file=other.log remove mod1
+grep -q "INFO  modman: Finished" other.log
+# Big logs are rotated.
+head -c 1100000 /dev/zero > modman-backup/modman.log
+$quietrun --log-file list
+test -s modman-backup/modman.log.1
+grep -q "Running \`modman --log-file list\`" modman-backup/modman.log
+rm modman-backup/modman.log* other.log modman-backup/history.log
+diff -u <(rootsums) expected/starting.root
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing dry run summaries"
+$quietrun add mod2
+out=$($quietrun --color never add -n mod1)
+grep -q '^  ~ A.txt (36 B)$' <<< "$out"
+grep -q '^  + C.txt (19 B)$' <<< "$out"
+grep -q '^4 files: 2 added, 2 replaced; 165 B to install; 64 B of game files to back up$' <<< "$out"
+out=$($quietrun --color always remove -n mod2)
+grep -q $'\e\[31m  - newdir/newsubdir/A.txt' <<< "$out"
+grep -q '^2 files: 2 deleted$' <<< "$out"
+# Only errors get through --quiet.
+cp -r mod1 same
+cp rootdir/A.txt same/modroot/A.txt
+err=$($quietrun add -n same 2>&1 > /dev/null)
+grep -q "identical to the game files" <<< "$err"
+err=$($quietrun --quiet add -n same 2>&1 > /dev/null)
+test -z "$err"
+rm -r same
+$quietrun remove mod2
+rm modman-backup/history.log
+diff -u <(rootsums) expected/starting.root
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing leftover temporary files"
+mkdir -p modman-backup/temp/sub modman-backup/downloads
+echo "half a backup" > modman-backup/temp/sub/A.txt
+echo "half a download" > modman-backup/downloads/1234abcd-mod.zip.part
+echo "a whole download" > modman-backup/downloads/1234abcd-mod.zip
+out=$($quietrun check 2>&1 || true)
+grep -q "modman-backup/temp/sub/A.txt (14 B, .* old)" <<< "$out"
+grep -q "modman-backup/downloads/1234abcd-mod.zip.part (16 B" <<< "$out"
+test -z "$(echo "$out" | grep "1234abcd-mod.zip (")"
+expect_code 7 check
+$quietrun check --fix
+$quietrun check
+test ! -e modman-backup/temp/sub
+test ! -e modman-backup/downloads/1234abcd-mod.zip.part
+test -e modman-backup/downloads/1234abcd-mod.zip
+rm -r modman-backup/downloads
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing clean"
+out=$($quietrun clean)
+test "$out" = "Nothing to clean up."
+mkdir -p modman-backup/temp/sub modman-backup/downloads
+echo "half a backup" > modman-backup/temp/sub/A.txt
+echo "a whole download" > modman-backup/downloads/1234abcd-mod.zip
+echo "{}" > modman.profile.new
+out=$($quietrun clean -n)
+grep -q "^Would remove 3 files, freeing 34 B$" <<< "$out"
+test -e modman-backup/temp/sub/A.txt
+# Not while `modman add` was interrupted
+touch modman-backup/temp/activate.journal
+expect_code 8 clean
+rm modman-backup/temp/activate.journal
+out=$($quietrun clean)
+grep -q "^Removed 3 files, freeing 34 B$" <<< "$out"
+test ! -e modman-backup/temp/sub
+test ! -e modman.profile.new
+test ! -e modman-backup/downloads/1234abcd-mod.zip
+rm -r modman-backup/downloads
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing init --adopt"
+$quietrun add mod1.zip mod2
+mv modman.profile adopt.profile.orig
+expect_code 1 init --root rootdir
+out=$($quietrun init --adopt --root rootdir mod1.zip mod2 mod-conflicting.zip 2>&1)
+grep -q "^Adopted mod1.zip (4 files, 2 backed up)$" <<< "$out"
+grep -q "mod-conflicting.zip (1 of 1 files aren't installed)" <<< "$out"
+diff -u adopt.profile.orig modman.profile
+rm adopt.profile.orig
+$quietrun check
+$quietrun remove mod1.zip mod2
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing move-storage"
+$quietrun add mod1.zip
+out=$($quietrun move-storage -n ../test-storage/backups)
+grep -q "^Would move modman-backup/ " <<< "$out"
+test -d modman-backup
+$quietrun move-storage ../test-storage/backups
+test ! -e modman-backup
+grep -q '"storage_directory": "../test-storage/backups"' modman.profile
+$quietrun check
+$quietrun remove mod1.zip
+test -e ../test-storage/backups/history.log
+expect_code 1 move-storage rootdir
+$quietrun move-storage modman-backup
+test ! -e ../test-storage/backups
+test -z "$(grep storage_directory modman.profile)"
+rm -r ../test-storage
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing backups of game files named like the journal"
+mkdir -p journaled/files
+echo "1.0.0" > journaled/VERSION.txt
+echo "Replaces a file named like our journal" > journaled/README.txt
+echo "Modded" > journaled/files/activate.journal
+echo "Original" > rootdir/activate.journal
+$quietrun add journaled
+grep -q "^Modded$" rootdir/activate.journal
+grep -q "^Original$" modman-backup/originals/activate.journal
+$quietrun check
+$quietrun remove journaled
+grep -q "^Original$" rootdir/activate.journal
+rm -r journaled rootdir/activate.journal modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing backups of files with the same name in different directories"
+mkdir -p same-names/files/a same-names/files/b rootdir/a rootdir/b
+echo "1.0.0" > same-names/VERSION.txt
+echo "Replaces two files with the same name" > same-names/README.txt
+for d in a b; do
+    echo "Modded $d" > same-names/files/$d/config.ini
+    echo "Original $d" > rootdir/$d/config.ini
+done
+$quietrun add same-names
+for d in a b; do
+    grep -q "^Modded $d$" rootdir/$d/config.ini
+    grep -q "^Original $d$" modman-backup/originals/$d/config.ini
+done
+test -z "$(find modman-backup/temp -type f)"
+$quietrun remove same-names
+for d in a b; do
+    grep -q "^Original $d$" rootdir/$d/config.ini
+done
+rm -r same-names rootdir/a rootdir/b modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing removing nested new directories"
+mkdir -p nested/files/deep/er/est
+echo "1.0.0" > nested/VERSION.txt
+echo "Adds files a few directories down" > nested/README.txt
+echo "Deep" > nested/files/deep/er/est/D.txt
+echo "Shallow" > nested/files/deep/S.txt
+$quietrun add nested
+grep -q "^Deep$" rootdir/deep/er/est/D.txt
+$quietrun remove nested
+test ! -e rootdir/deep
+test -d rootdir
+# As if `add nested` was interrupted before updating the profile
+mkdir -p rootdir/deep/er/est
+cp nested/files/deep/er/est/D.txt rootdir/deep/er/est
+echo "Add deep/er/est/D.txt" > modman-backup/temp/activate.journal
+$quietrun repair
+test ! -e rootdir/deep
+rm -r nested modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing removing directories mods created"
+# The game's own (empty) directories stay put...
+mkdir -p rootdir/empty nested/files/empty/new
+echo "1.0.0" > nested/VERSION.txt
+echo "Adds a file to an empty directory of the game's" > nested/README.txt
+echo "New" > nested/files/empty/new/N.txt
+$quietrun add nested
+grep -q '"empty/new"' modman.profile
+test -z "$(grep '"empty"' modman.profile)"
+$quietrun remove nested
+test -d rootdir/empty
+test ! -e rootdir/empty/new
+rm -r nested rootdir/empty
+# ...and ones with other mods' files in them are left for those mods.
+$quietrun add mod1.zip mod2
+$quietrun remove mod1.zip
+test -d rootdir/newdir
+grep -q '"newdir"' modman.profile
+$quietrun remove mod2
+test ! -e rootdir/newdir
+rm modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing journals with hashes"
+# As if `add mod1.zip` was interrupted after replacing A.txt
+mv rootdir/A.txt modman-backup/originals/A.txt
+cp mod1/modroot/A.txt rootdir
+echo "Journal 2 0badf00d mod1.zip" > modman-backup/temp/activate.journal
+echo "Replace A.txt" >> modman-backup/temp/activate.journal
+echo "Backed-up $(sha224sum < modman-backup/originals/A.txt | cut -d' ' -f1) A.txt" \
+    >> modman-backup/temp/activate.journal
+out=$(! $quietrun check 2>&1)
+grep -q "\`modman add mod1.zip\` (run 0badf00d) was interrupted after changing 1 files" <<< "$out"
+out=$(! $quietrun add --resume mod2 2>&1)
+grep -q "was adding mod1.zip, not mod2" <<< "$out"
+# Backups that don't match what was backed up stay put.
+mv modman-backup/originals/A.txt A.orig
+echo "Tampered" > modman-backup/originals/A.txt
+out=$(! $quietrun repair 2>&1)
+grep -q "modman-backup/originals/A.txt (expected [0-9a-f]*, but it's [0-9a-f]*)" <<< "$out"
+grep -q "^Tampered$" modman-backup/originals/A.txt
+cmp mod1/modroot/A.txt rootdir/A.txt
+mv A.orig modman-backup/originals/A.txt
+$quietrun repair
+rm modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+# Journals start with which mod they're for.
+out=$($quietrun -vv add --dry-run mod1.zip 2>&1)
+grep -q $'^\tJournal 2 [0-9a-f]* mod1.zip' <<< "$out"
+
+echo "Testing printing plans"
+out=$($quietrun add --plan - mod1.zip mod2)
+test ! -e ./-
+grep -q '"mod2": \[' <<< "$out"
+# Operations are grouped by mod, then sorted by path.
+test "$(echo "$out" | grep '"path"' | head -n 3 | tr -d ' ')" = '"path":"A.txt"
+"path":"A.txt"
+"path":"B.txt"'
+# Plans can't carry options that change what's installed.
+expect_code 2 add --plan - --overwrite mod1.zip
+expect_code 2 add --plan - --tag foo mod1.zip
+
+echo "Testing that output is in order"
+out=$($quietrun -v add mod1.zip 2>&1)
+diff -u <(echo "$out" | grep -E "INFO - (Adding|Replacing) ") - <<EOF
+INFO - Replacing A.txt
+INFO - Replacing B.txt
+INFO - Adding C.txt
+INFO - Adding newdir/N.txt
+EOF
+out=$($quietrun -v remove mod1.zip 2>&1)
+diff -u <(echo "$out" | grep -E "INFO - (Restored|Removed) ") - <<EOF
+INFO - Restored A.txt
+INFO - Restored B.txt
+INFO - Removed C.txt
+INFO - Removed newdir/N.txt
+EOF
+rm modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing adding mods from a list"
+printf "# Some mods\nmod1.zip\n\n  mod2  \n" > mods.txt
+out=$($quietrun add --dry-run --manifest mods.txt)
+grep -q "^+ mod1.zip" <<< "$out"
+grep -q "^+ mod2" <<< "$out"
+$quietrun add --from-stdin < mods.txt
+diff -u <(profile) expected/mod2.profile
+$quietrun remove mod1.zip mod2
+out=$(! $quietrun add --from-stdin < /dev/null 2>&1)
+grep -q "The mod list is empty" <<< "$out"
+rm mods.txt modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing rename"
+$quietrun add mod1.zip mod2
+out=$($quietrun rename mod2 m2)
+grep -q "mod2 is now called m2" <<< "$out"
+out=$($quietrun list)
+grep -q "^mod2 (v.*, alias m2)" <<< "$out"
+out=$($quietrun show m2)
+grep -q "mod2" <<< "$out"
+out=$(! $quietrun rename mod1.zip m2 2>&1)
+grep -q "m2" <<< "$out"
+out=$($quietrun rename m2 --clear)
+grep -q "mod2 is no longer called m2" <<< "$out"
+diff -u <(profile) expected/mod2.profile
+$quietrun remove mod1.zip mod2
+rm modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing tags"
+cp -r mod2 tagged
+echo 'tags = ["graphics"]' > tagged/mod.toml
+$quietrun add --tag multiplayer-safe mod1.zip tagged
+out=$($quietrun list)
+grep -q "^tagged (v.*, tagged graphics, multiplayer-safe)" <<< "$out"
+out=$($quietrun tag mod1.zip +graphics -multiplayer-safe)
+grep -q "mod1.zip: graphics" <<< "$out"
+out=$($quietrun show mod1.zip)
+grep -q "Tags: graphics" <<< "$out"
+diff -u <($quietrun list --tag multiplayer-safe | cut -d' ' -f1) - <<EOF
+tagged
+EOF
+out=$(! $quietrun tag tagged -nonexistent 2>&1)
+grep -q "isn't tagged nonexistent" <<< "$out"
+out=$(! $quietrun remove --tag nonexistent 2>&1)
+grep -q "No installed mods are tagged nonexistent" <<< "$out"
+$quietrun remove --tag graphics
+[ -z "$($quietrun list)" ]
+rm -r tagged modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing mod sets"
+$quietrun add mod1.zip
+out=$($quietrun set save Campaign)
+grep -q "Saved 1 mod as the set Campaign" <<< "$out"
+$quietrun set save PvP mod2 > /dev/null
+out=$($quietrun set apply -n PvP)
+diff -u <(echo "$out") - <<EOF
+Would remove mod1.zip
+Would add mod2
+EOF
+diff -u expected/mod1.root <(rootsums)
+$quietrun set apply PvP
+diff -u <($quietrun list | cut -d' ' -f1) - <<EOF
+mod2
+EOF
+out=$($quietrun set)
+grep -q "^PvP (applied)" <<< "$out"
+$quietrun set apply Campaign
+diff -u expected/mod1.root <(rootsums)
+$quietrun set remove PvP > /dev/null
+out=$(! $quietrun set apply PvP 2>&1)
+grep -q "The profile has no set named PvP" <<< "$out"
+$quietrun set remove Campaign > /dev/null
+out=$($quietrun set)
+grep -q "No sets have been saved." <<< "$out"
+$quietrun remove mod1.zip
+rm modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing staging"
+out=$($quietrun add --stage staged mod1)
+grep -q "modman apply staged" <<< "$out"
+diff -r mod1/modroot staged/files
+diff -r rootdir staged/originals
+grep -q '"op": "backup"' staged/plan.json
+diff -u expected/starting.root <(rootsums)
+test ! -e modman-backup/history.log
+out=$(! $quietrun add --stage staged mod1 2>&1)
+grep -q "isn't empty" <<< "$out"
+expect_code 2 add --stage staged2 --keep-mtimes mod1
+expect_code 2 add --stage staged2 --alias one mod1
+test ! -e staged2
+cp -r staged tampered
+echo "Tampered" > tampered/files/C.txt
+out=$(! $quietrun apply tampered 2>&1)
+grep -q "tampered/files/C.txt isn't what would be installed anymore" <<< "$out"
+diff -u expected/starting.root <(rootsums)
+$quietrun apply staged
+diff -u expected/mod1.root <(rootsums)
+$quietrun remove mod1
+rm -r staged tampered modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing dry run diffs"
+seq 1 10 > rootdir/config.ini
+mkdir -p tweak/modroot
+echo "1.0.0" > tweak/VERSION.txt
+touch tweak/README.txt
+seq 1 10 | sed 's/^5$/five/' > tweak/modroot/config.ini
+out=$($quietrun --color never add -n tweak)
+diff -u <(echo "$out" | sed -n '/^---/,/^ 8$/p') - <<EOF
+--- a/config.ini
++++ b/config.ini
+@@ -2,7 +2,7 @@
+ 2
+ 3
+ 4
+-5
++five
+ 6
+ 7
+ 8
+EOF
+# Binary files and plans don't get diffs.
+printf '\0\1\2' > tweak/modroot/config.ini
+out=$($quietrun add -n tweak)
+! grep -q "^--- a/config.ini" <<< "$out"
+out=$($quietrun add --plan - tweak)
+! grep -q "^--- a/config.ini" <<< "$out"
+rm -r tweak rootdir/config.ini
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing older originals"
+$quietrun keep-originals 2
+test "$($quietrun keep-originals)" = 2
+$quietrun add mod1.zip
+echo "B, version 2" > rootdir/B.txt
+$quietrun update
+echo "B, version 3" > rootdir/B.txt
+$quietrun update
+echo "B, version 4" > rootdir/B.txt
+$quietrun update
+out=$($quietrun restore-original B.txt)
+test "$(echo "$out" | wc -l)" = 2
+out=$(! $quietrun restore-original B.txt --generation 1 2>&1)
+grep -q "mod1.zip installed B.txt" <<< "$out"
+$quietrun restore-original B.txt --generation 2 --output older-B.txt > /dev/null
+diff -u <(echo "B, version 2") older-B.txt
+out=$(! $quietrun restore-original B.txt --generation 3 2>&1)
+grep -q "B.txt has 2 older originals kept, not 3" <<< "$out"
+$quietrun keep-originals 1
+out=$($quietrun restore-original B.txt)
+test "$(echo "$out" | wc -l)" = 1
+$quietrun remove mod1.zip
+out=$(! $quietrun restore-original B.txt --generation 1 2>&1)
+grep -q "Pass --force to replace it" <<< "$out"
+$quietrun restore-original B.txt --generation 1 --force > /dev/null
+diff -u <(echo "B, version 3") rootdir/B.txt
+$quietrun keep-originals 0
+test ! -e modman-backup/older-originals
+git checkout -- rootdir/B.txt
+rm older-B.txt modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing remove's report of changed files"
+$quietrun add mod1.zip
+echo "Clobbered" > rootdir/A.txt
+rm rootdir/newdir/N.txt
+out=$(! $quietrun remove mod1 2>&1)
+grep -q "2 installed files from mod1.zip changed:" <<< "$out"
+grep -q "A.txt: expected 8d7ca13a65c9 (36 B), found 05877b949430 (10 B, modified " <<< "$out"
+grep -q "pre-install copy: modman-backup/originals/A.txt" <<< "$out"
+grep -q "newdir/N.txt: missing (expected 3918e7158a52, 74 B)" <<< "$out"
+out=$(! $quietrun remove --json mod1 2>/dev/null)
+# (The JSON is long enough that echo could get SIGPIPE once grep finds a match.)
+grep -q '"problem": "changed"' <<< "$out"
+grep -q '"actual_hash": "05877b949430f3838795a2158312ff6916a286be34f1429c1cc8dbde"' <<< "$out"
+grep -q '"problem": "missing"' <<< "$out"
+$quietrun remove --force mod1
+rm modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing strict mode"
+$quietrun add mod1.zip
+rm rootdir/newdir/N.txt
+# The mod is still removed; the command just fails afterwards.
+expect_code 10 --strict remove --force mod1
+diff -u expected/starting.root <(rootsums)
+$quietrun strict on
+out=$($quietrun strict)
+grep -q "on" <<< "$out"
+$quietrun add mod1.zip
+rm rootdir/newdir/N.txt
+out=$(! $quietrun remove --force mod1 2>&1)
+grep -q "Failing because of warnings in strict mode (missing-file)" <<< "$out"
+$quietrun strict off
+$quietrun add mod1.zip
+$quietrun remove mod1
+rm modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+diff -u modman.profile expected/empty.profile
+
+echo "Testing profile recover"
+$quietrun add mod1.zip mod2
+cp modman.profile good.profile
+# Cut the profile off partway through the last mod.
+head -c -20 good.profile > modman.profile
+expect_code 4 list
+out=$($quietrun profile recover)
+grep -q "Recovered 1 of 2 mods" <<< "$out"
+grep -q "the mod mod2 (it was cut off)" <<< "$out"
+cmp modman.profile.broken <(head -c -20 good.profile)
+out=$($quietrun list)
+grep -q "mod1.zip" <<< "$out"
+rm modman.profile.broken
+# A bad hand-edit only costs the mod it's in, and its backups are reported.
+sed 's/"mod_hash": "8d7c/"mod_hash": "zz7c/' good.profile > modman.profile
+out=$($quietrun profile recover -n)
+grep -q "Recovered 1 of 2 mods" <<< "$out"
+grep -q "the mod mod1.zip (invalid value: .* on line " <<< "$out"
+grep -q "A.txt" <<< "$out"
+mv good.profile modman.profile
+$quietrun remove mod1.zip mod2
+rm modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing profile rollback"
+$quietrun add mod1.zip
+out=$($quietrun profile rollback)
+grep -q "^1: .* (0 mods)" <<< "$out"
+out=$($quietrun profile rollback 1)
+grep -q "Restored generation 1 of the profile (0 mods installed)" <<< "$out"
+out=$($quietrun list)
+[ -z "$out" ]
+# Rolling back keeps what it replaced, so it can be undone.
+$quietrun profile rollback 1
+out=$($quietrun list)
+grep -q "mod1.zip" <<< "$out"
+expect_code 2 profile rollback 9
+$quietrun remove mod1
+rm modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing read-only game files"
+chmod a-w rootdir/A.txt
+$quietrun add mod1.zip
+diff -u <(sha224sum < mod1/modroot/A.txt) <(sha224sum < rootdir/A.txt)
+[ "$(stat -c %a rootdir/A.txt)" = 444 ]
+$quietrun remove mod1
+[ "$(stat -c %a rootdir/A.txt)" = 444 ]
+chmod u+w rootdir/A.txt
+rm modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+
+echo "Testing elevate"
+out=$($quietrun elevate)
+grep -q "off" <<< "$out"
+$quietrun elevate on
+out=$($quietrun elevate)
+grep -q "on" <<< "$out"
+# Everything here is writable, so there's nothing to ask for.
+$quietrun add mod1.zip
+$quietrun --elevate remove mod1
+$quietrun elevate off
+rm modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+diff -u modman.profile expected/empty.profile
+
+echo "Testing wine-prefix"
+mkdir -p wine/prefix/drive_c/users/steamuser/Documents/My\ Games/Foo
+mkdir -p wine/prefix/drive_c/Games/Foo wine/config/modman
+cat > wine/config/modman/games.toml <<'EOF'
+[foo]
+name = "Foo"
+paths = ['C:\Games\Foo']
+roots = { saved = "~/Documents/My Games/Foo" }
+EOF
+XDG_CONFIG_HOME="$PWD/wine/config" $quietrun -C wine init --game foo --wine-prefix prefix
+grep -q '"case_insensitive": true' wine/modman.profile
+grep -q 'drive_c/Games/Foo' wine/modman.profile
+grep -q 'drive_c/users/steamuser/Documents/My Games/Foo' wine/modman.profile
+out=$($quietrun -C wine wine-prefix)
+grep -q "User directory: .*prefix/drive_c/users/steamuser" <<< "$out"
+expect_code 1 wine-prefix rootdir
+$quietrun add mod1.zip
+$quietrun wine-prefix wine/prefix
+grep -q '"wine_prefix"' modman.profile
+$quietrun wine-prefix --clear
+out=$($quietrun wine-prefix)
+grep -q "No Wine prefix set" <<< "$out"
+$quietrun remove mod1
+rm -r wine
+rm modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+diff -u modman.profile expected/empty.profile
+
+echo "Testing --overwrite"
+$quietrun add mod1.zip
+$quietrun add --overwrite mod-conflicting.zip
+diff -u mod-conflicting/rootdir/A.txt rootdir/A.txt
+grep -q '"layers"' modman.profile
+$quietrun check
+# Removing the mod installed over the other puts the other's file back.
+$quietrun remove mod-conflicting.zip
+diff -u mod1/modroot/A.txt rootdir/A.txt
+! grep -q '"layers"' modman.profile
+[ -z "$(ls -A modman-backup/covered)" ]
+# Removing the first mod leaves the one installed over it,
+# which then restores the game's original.
+$quietrun add --overwrite mod-conflicting.zip
+$quietrun remove mod1.zip
+diff -u mod-conflicting/rootdir/A.txt rootdir/A.txt
+$quietrun check
+$quietrun remove mod-conflicting.zip
+# A mod in the middle just leaves.
+$quietrun add mod1.zip
+$quietrun add --overwrite mod-conflicting.zip
+$quietrun add --overwrite mod-conflicting
+$quietrun remove mod-conflicting.zip
+diff -u mod-conflicting/rootdir/A.txt rootdir/A.txt
+$quietrun remove mod-conflicting
+diff -u mod1/modroot/A.txt rootdir/A.txt
+$quietrun remove mod1.zip
+# If the rest of the first mod can't be restored, the profile keeps it
+# in the file's layers, and removing it again hands the original down.
+$quietrun add mod1.zip
+$quietrun add --overwrite mod-conflicting.zip
+mv modman-backup/originals/B.txt B.txt.bak
+expect_code 1 remove mod1.zip
+grep -q '"layers"' modman.profile
+grep -q 'mod1.zip' <<< "$(profile | grep -A4 '"layers"')"
+mv B.txt.bak modman-backup/originals/B.txt
+$quietrun remove --force mod1.zip
+diff -u mod-conflicting/rootdir/A.txt rootdir/A.txt
+$quietrun check
+$quietrun remove mod-conflicting.zip
+rm -r modman-backup/covered
+rm modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+diff -u modman.profile expected/empty.profile
+
+echo "Testing quick-check"
+$quietrun add mod1.zip
+$quietrun quick-check on
+out=$($quietrun quick-check)
+grep -q "on" <<< "$out"
+$quietrun check
+# Damage a backup without changing its size or modification time.
+# A quick check can't tell, but a deep one can.
+cp -p modman-backup/originals/A.txt pristine-A.txt
+printf 'X' | dd of=modman-backup/originals/A.txt bs=1 conv=notrunc status=none
+touch -r pristine-A.txt modman-backup/originals/A.txt
+$quietrun check
+expect_code 7 check --deep
+# Anything that does change them gets the backup hashed.
+touch modman-backup/originals/A.txt
+expect_code 7 check
+mv pristine-A.txt modman-backup/originals/A.txt
+$quietrun check --deep
+$quietrun quick-check off
+$quietrun remove mod1.zip
+rm modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+diff -u modman.profile expected/empty.profile
+
+echo "Testing hashing modes"
+$quietrun add mod1.zip
+for mode in buffered mmap stream; do
+    $quietrun --hashing $mode check
+done
+MODMAN_HASHING=mmap $quietrun check
+expect_code 2 --hashing sideways check
+$quietrun remove mod1.zip
+rm modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+diff -u modman.profile expected/empty.profile
+
+echo "Testing --io-profile"
+$quietrun --io-profile hdd --io-limit 1 add mod1.zip
+MODMAN_IO_PROFILE=hdd $quietrun check
+expect_code 2 --io-profile floppy check
+expect_code 2 --io-limit 0 check
+$quietrun --io-profile hdd remove mod1.zip
+rm modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+diff -u modman.profile expected/empty.profile
+
+echo "Testing --prefetch"
+for window in 0 1 64; do
+    $quietrun --prefetch $window add mod1.zip
+    $quietrun check
+    $quietrun remove mod1.zip
+done
+MODMAN_PREFETCH=2 $quietrun add mod1.zip
+$quietrun remove mod1.zip
+expect_code 2 --prefetch lots check
+rm modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+diff -u modman.profile expected/empty.profile
+
+echo "Testing archives that bundle several mods"
+rm -f bundle.zip && zip -r9 bundle.zip mod1 mod2 > /dev/null
+out=$(! $quietrun add bundle.zip 2>&1 < /dev/null)
+grep -q "bundle.zip holds several mods" <<< "$out"
+grep -q "Pick which to install with --select" <<< "$out"
+expect_code 2 add bundle.zip --select nope
+$quietrun add bundle.zip --select mod1 --select mod2/
+out=$($quietrun list)
+grep -q "^bundle.zip/mod1 (v1.2.3)" <<< "$out"
+grep -q "^bundle.zip/mod2" <<< "$out"
+$quietrun check
+$quietrun update
+$quietrun remove bundle.zip/mod1 bundle.zip/mod2
+# --select picks from bundles, and leaves archives that are one mod alone.
+$quietrun add mod-conflicting.zip bundle.zip --select mod2
+out=$($quietrun list)
+grep -q "^mod-conflicting.zip" <<< "$out"
+grep -q "^bundle.zip/mod2" <<< "$out"
+! grep -q "^bundle.zip/mod1" <<< "$out"
+$quietrun remove mod-conflicting.zip bundle.zip/mod2
+# A mod can install its own VERSION.txt without looking like a bundle.
+rm -rf mod-versioned mod-versioned.zip
+mkdir -p mod-versioned/modroot
+cp mod1/VERSION.txt mod1/README.txt mod-versioned/
+echo "The mod's own version file" > mod-versioned/modroot/VERSION.txt
+sh -c 'cd mod-versioned && zip -r9 ../mod-versioned.zip *' > /dev/null
+$quietrun add mod-versioned.zip
+grep -q "own version file" rootdir/VERSION.txt
+$quietrun remove mod-versioned.zip
+rm -rf modman-backup/history.log bundle.zip mod-versioned mod-versioned.zip
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+diff -u modman.profile expected/empty.profile
+
+echo "Testing extract"
+$quietrun add mod1.zip
+rm -rf extracted
+$quietrun extract mod1 --to extracted
+diff -r mod1/modroot extracted
+expect_code 5 extract mod1.zip --to extracted
+$quietrun extract --force mod1.zip --to extracted A.txt
+expect_code 2 extract mod1.zip --to extracted nope.txt
+rm -rf extracted
+$quietrun extract mod2 --to extracted
+diff -r mod2/mod2 extracted
+$quietrun remove mod1.zip
+rm -rf extracted modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+diff -u modman.profile expected/empty.profile
+
+echo "Testing archive hashes"
+$quietrun add mod1.zip
+grep -q '"archive_hash": ' modman.profile
+grep -q '"archive_mtime": ' modman.profile
+# A touched archive with the same contents is still the same one.
+touch mod1.zip
+out=$($quietrun list 2>&1)
+test -z "$(grep "isn't the archive it was installed from" <<< "$out")"
+# Same files, different archive (as if it were downloaded again)
+echo "Downloaded again" | zip -z mod1.zip > /dev/null
+out=$($quietrun list 2>&1)
+grep -q "mod1.zip isn't the archive it was installed from" <<< "$out"
+expect_code 7 check
+expect_code 7 update
+$quietrun update --accept-mod-changes
+$quietrun check
+$quietrun remove mod1.zip
+rm -f mod1.zip && sh -c 'cd mod1 && zip -r9 ../mod1.zip *' > /dev/null
+rm modman-backup/history.log
+diff -u expected/starting.root <(rootsums)
+diff -u expected/empty.backup <(backupsums)
+diff -u modman.profile expected/empty.profile
+
 echo "All tests passed!"
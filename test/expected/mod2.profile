@@ -3,35 +3,49 @@
   "mods": {
     "mod1.zip": {
       "version": "1.2.3",
+      "directories": [
+        "newdir"
+      ],
       "files": {
         "A.txt": {
           "mod_hash": "8d7ca13a65c98098f7733fa95231d5698d9bb66c7bdf8e1cd086b403",
-          "original_hash": "36ff4c95f706b203a843b42a17bed28e471d092379f167c71b1849f2"
+          "original_hash": "36ff4c95f706b203a843b42a17bed28e471d092379f167c71b1849f2",
+          "size": 36,
+          "original_size": 32
         },
         "B.txt": {
           "mod_hash": "81de48251f17cc72ab659de73701fe93aae1f78fa00739ea01faaecc",
-          "original_hash": "bf37a3133247df82a11a1c0f594e5b097189ece6cfa23f8f0c5d657e"
+          "original_hash": "bf37a3133247df82a11a1c0f594e5b097189ece6cfa23f8f0c5d657e",
+          "size": 36,
+          "original_size": 32
         },
         "C.txt": {
           "mod_hash": "4791fd7d53541c90c1b438f15d90f3590e300ef57caff29ec4a08eed",
-          "original_hash": null
+          "original_hash": null,
+          "size": 19
         },
         "newdir/N.txt": {
           "mod_hash": "3918e7158a52d0b15de752da20621516e69454f06022b312dced1bb2",
-          "original_hash": null
+          "original_hash": null,
+          "size": 74
         }
       }
     },
     "mod2": {
       "version": "0.0.1-pre-lol",
+      "directories": [
+        "newdir/newsubdir"
+      ],
       "files": {
         "newdir/newsubdir/A.txt": {
           "mod_hash": "f50bab83150629c45bbd779b7b90dbf227cd3cdaa15733c3986a62b8",
-          "original_hash": null
+          "original_hash": null,
+          "size": 46
         },
         "newdir/newsubdir/B.txt": {
           "mod_hash": "edd654728370f8b404d7afc259e1b07b8a338d979301ab0b5f1fa03c",
-          "original_hash": null
+          "original_hash": null,
+          "size": 46
         }
       }
     }
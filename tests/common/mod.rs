@@ -0,0 +1,190 @@
+//! A throwaway game directory and profile for integration tests,
+//! so features can be tested end-to-end without a real game.
+//!
+//! Like `modman` itself, everything works relative to the working directory,
+//! which is global. A `TestEnv` changes to its own temporary directory
+//! and holds a lock until it's dropped, so tests using them run one at a time.
+//!
+//! ```ignore
+//! let env = TestEnv::new()?;
+//! env.write_game_file("A.txt", b"The game's A")?;
+//! let m = env.zip_mod("mod1", "1.0", &[("A.txt", b"The mod's A")])?;
+//! env.add(&[&m])?;
+//! assert_eq!(env.read_game_file("A.txt")?, b"The mod's A");
+//! env.remove(&[&m])?;
+//! assert_eq!(env.read_game_file("A.txt")?, b"The game's A");
+//! ```
+
+use std::env;
+use std::fs;
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use anyhow::*;
+use zip::write::{FileOptions, ZipWriter};
+
+use modman::file_utils::run_id;
+use modman::profile::*;
+use modman::{add, check, init, remove, update};
+
+/// The game's root directory, in each test environment
+pub const GAME_DIR: &str = "game";
+
+/// Test environments change the working directory, so only one exists at a time.
+static ENV: Mutex<()> = Mutex::new(());
+
+/// Numbers each environment's directory, so no two share one.
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A temporary directory with a game root (game/) and a profile for it,
+/// which is the working directory until it's dropped.
+pub struct TestEnv {
+    dir: PathBuf,
+    previous: PathBuf,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl TestEnv {
+    /// Makes a new directory with an empty game root and runs `modman init` in it.
+    pub fn new() -> Result<Self> {
+        // A test that panicked while holding the lock still cleaned up
+        // when its TestEnv was dropped, so carry on.
+        let lock = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let dir = env::temp_dir().join(format!(
+            "modman-test-{}-{}",
+            run_id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        if dir.exists() {
+            fs::remove_dir_all(&dir)
+                .with_context(|| format!("Couldn't clear out {}", dir.display()))?;
+        }
+        fs::create_dir_all(dir.join(GAME_DIR))
+            .with_context(|| format!("Couldn't create {}", dir.display()))?;
+        let previous = env::current_dir().context("Couldn't get the working directory")?;
+        env::set_current_dir(&dir)
+            .with_context(|| format!("Couldn't change to {}", dir.display()))?;
+
+        // Set this up before anything can fail so Drop puts things back.
+        let test_env = Self {
+            dir,
+            previous,
+            _lock: lock,
+        };
+        set_storage_dir(None);
+        init::run(init::Args::new(PathBuf::from(GAME_DIR)))?;
+        Ok(test_env)
+    }
+
+    /// Where the given file is in the game's root directory
+    pub fn game_path<P: AsRef<Path>>(&self, file: P) -> PathBuf {
+        self.dir.join(GAME_DIR).join(file)
+    }
+
+    /// Writes a game file (and any directories it's in).
+    pub fn write_game_file<P: AsRef<Path>>(&self, file: P, contents: &[u8]) -> Result<()> {
+        write_file(&self.game_path(file), contents)
+    }
+
+    pub fn read_game_file<P: AsRef<Path>>(&self, file: P) -> Result<Vec<u8>> {
+        let path = self.game_path(file);
+        fs::read(&path).with_context(|| format!("Couldn't read {}", path.display()))
+    }
+
+    /// Makes a directory mod named <name> with the given version and files
+    /// (paths relative to the game's root, and their contents).
+    /// Returns its path, to pass to `add()`, etc.
+    pub fn dir_mod(&self, name: &str, version: &str, files: &[(&str, &[u8])]) -> Result<PathBuf> {
+        let mod_path = self.dir.join(name);
+        if mod_path.exists() {
+            fs::remove_dir_all(&mod_path)
+                .with_context(|| format!("Couldn't clear out {}", mod_path.display()))?;
+        }
+        for (name, contents) in mod_entries(version, files) {
+            write_file(&mod_path.join(name), &contents)?;
+        }
+        Ok(PathBuf::from(name))
+    }
+
+    /// Like `dir_mod()`, but makes <name>.zip.
+    pub fn zip_mod(&self, name: &str, version: &str, files: &[(&str, &[u8])]) -> Result<PathBuf> {
+        let file_name = format!("{}.zip", name);
+        let zip_path = self.dir.join(&file_name);
+        let zip_file = fs::File::create(&zip_path)
+            .with_context(|| format!("Couldn't create {}", zip_path.display()))?;
+        let mut zip = ZipWriter::new(io::BufWriter::new(zip_file));
+        let options = FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        for (name, contents) in mod_entries(version, files) {
+            zip.start_file(name, options)?;
+            zip.write_all(&contents)?;
+        }
+        zip.finish()?.flush()?;
+        Ok(PathBuf::from(file_name))
+    }
+
+    /// Runs `modman add` with the given mods.
+    pub fn add<P: AsRef<Path>>(&self, mods: &[P]) -> Result<()> {
+        add::run(add::Args::new(to_paths(mods), false))
+    }
+
+    /// Runs `modman remove` with the given mods.
+    pub fn remove<P: AsRef<Path>>(&self, mods: &[P]) -> Result<()> {
+        remove::remove_mods(to_paths(mods), false, false, false)
+    }
+
+    /// Runs `modman update` on every installed mod.
+    pub fn update(&self) -> Result<()> {
+        update::run(update::Args::new(Vec::new(), false))
+    }
+
+    /// Runs `modman check` (without --fix).
+    pub fn check(&self) -> Result<()> {
+        check::run(check::Args::new(false))
+    }
+
+    /// Loads the profile, to see what modman thinks is installed.
+    pub fn profile(&self) -> Result<Profile> {
+        load_and_check_profile()
+    }
+}
+
+impl Drop for TestEnv {
+    fn drop(&mut self) {
+        // Don't let the next environment find this one's settings.
+        set_storage_dir(None);
+        let _ = env::set_current_dir(&self.previous);
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// The files in a mod with the given version and files,
+/// which go in its base directory (files/).
+fn mod_entries(version: &str, files: &[(&str, &[u8])]) -> Vec<(String, Vec<u8>)> {
+    let mut entries = vec![
+        (
+            "VERSION.txt".to_owned(),
+            format!("{}\n", version).into_bytes(),
+        ),
+        ("README.txt".to_owned(), b"A mod for testing\n".to_vec()),
+    ];
+    entries.extend(
+        files
+            .iter()
+            .map(|(path, contents)| (format!("files/{}", path), contents.to_vec())),
+    );
+    entries
+}
+
+fn to_paths<P: AsRef<Path>>(paths: &[P]) -> Vec<PathBuf> {
+    paths.iter().map(|p| p.as_ref().to_owned()).collect()
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Couldn't create directory {}", parent.display()))?;
+    }
+    fs::write(path, contents).with_context(|| format!("Couldn't write {}", path.display()))
+}
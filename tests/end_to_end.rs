@@ -0,0 +1,187 @@
+//! Drives modman's commands against throwaway game directories.
+//! (See also test.sh, which runs the binary against test/.)
+
+use std::path::Path;
+
+use anyhow::*;
+
+use modman::composite_mod::CompositeMod;
+use modman::failure::{exit_code, Failure};
+use modman::file_utils::{hash_contents, hash_file, set_hash_io, HashIo};
+use modman::modification::{open_mod, register_opener, Mod};
+use modman::profile::UnicodeForm;
+use modman::remove::ChangedFiles;
+
+mod common;
+
+use common::TestEnv;
+
+#[test]
+fn add_and_remove() -> Result<()> {
+    let env = TestEnv::new()?;
+    env.write_game_file("A.txt", b"The game's A")?;
+    let m = env.zip_mod(
+        "mod1",
+        "1.0",
+        &[("A.txt", b"The mod's A"), ("new/B.txt", b"The mod's B")],
+    )?;
+
+    env.add(&[&m])?;
+    assert_eq!(env.read_game_file("A.txt")?, b"The mod's A");
+    assert_eq!(env.read_game_file("new/B.txt")?, b"The mod's B");
+    assert_eq!(env.profile()?.mods.len(), 1);
+    env.check()?;
+
+    env.remove(&[&m])?;
+    assert_eq!(env.read_game_file("A.txt")?, b"The game's A");
+    assert!(!env.game_path("new").exists());
+    assert!(env.profile()?.mods.is_empty());
+    Ok(())
+}
+
+#[test]
+fn directory_mods() -> Result<()> {
+    let env = TestEnv::new()?;
+    let m = env.dir_mod("mod1", "1.2.3", &[("A.txt", b"The mod's A")])?;
+    env.add(&[&m])?;
+    let p = env.profile()?;
+    assert_eq!(p.mods[&m].version.to_string(), "1.2.3");
+    env.remove(&[&m])?;
+    assert!(!env.game_path("A.txt").exists());
+    Ok(())
+}
+
+#[test]
+fn changed_files_stop_remove() -> Result<()> {
+    let env = TestEnv::new()?;
+    env.write_game_file("A.txt", b"The game's A")?;
+    let m = env.zip_mod("mod1", "1.0", &[("A.txt", b"The mod's A")])?;
+    env.add(&[&m])?;
+    env.write_game_file("A.txt", b"Something else")?;
+
+    let e = env.check().unwrap_err();
+    assert_eq!(exit_code(&e), Failure::VerificationFailed as i32);
+
+    let e = env.remove(&[&m]).unwrap_err();
+    let changed = e.downcast_ref::<ChangedFiles>().unwrap();
+    assert_eq!(changed.files.len(), 1);
+    assert_eq!(changed.files[0].path.to_str(), Some("A.txt"));
+    assert_eq!(changed.files[0].actual_size, Some(14));
+    // Nothing was removed.
+    assert_eq!(env.profile()?.mods.len(), 1);
+    assert_eq!(env.read_game_file("A.txt")?, b"Something else");
+    Ok(())
+}
+
+#[test]
+fn update_after_the_game_does() -> Result<()> {
+    let env = TestEnv::new()?;
+    env.write_game_file("A.txt", b"The game's A")?;
+    let m = env.zip_mod("mod1", "1.0", &[("A.txt", b"The mod's A")])?;
+    env.add(&[&m])?;
+
+    // The game updates, clobbering the mod's file.
+    env.write_game_file("A.txt", b"The game's new A")?;
+    env.update()?;
+    assert_eq!(env.read_game_file("A.txt")?, b"The mod's A");
+    env.check()?;
+
+    // Removing the mod puts back the game's new version.
+    env.remove(&[&m])?;
+    assert_eq!(env.read_game_file("A.txt")?, b"The game's new A");
+    Ok(())
+}
+
+#[test]
+fn adding_twice() -> Result<()> {
+    let env = TestEnv::new()?;
+    let m = env.zip_mod("mod1", "1.0", &[("A.txt", b"The mod's A")])?;
+    env.add(&[&m])?;
+    let e = env.add(&[&m]).unwrap_err();
+    assert!(
+        format!("{:#}", e).contains("has already been added"),
+        "{:#}",
+        e
+    );
+    // The first install is untouched.
+    assert_eq!(env.read_game_file("A.txt")?, b"The mod's A");
+    Ok(())
+}
+
+#[test]
+fn hashing_modes_agree() -> Result<()> {
+    let env = TestEnv::new()?;
+    // (Big enough to be mapped with HashIo::Mmap)
+    let contents: Vec<u8> = (0..20u32 << 20).map(|i| (i % 251) as u8).collect();
+    env.write_game_file("big.bin", &contents)?;
+    let path = env.game_path("big.bin");
+
+    let mut hashes = Vec::new();
+    for mode in [HashIo::Stream, HashIo::Buffered, HashIo::Mmap] {
+        set_hash_io(mode);
+        hashes.push(hash_file(&path)?);
+        hashes.push(hash_contents(&mut &contents[..])?);
+    }
+    set_hash_io(HashIo::Buffered);
+    assert!(hashes.iter().all(|h| *h == hashes[0]));
+    Ok(())
+}
+
+/// Opens <name>.both as <name>-base.zip and <name>-extra.zip combined.
+fn open_both(p: &Path) -> Option<Result<Box<dyn Mod>>> {
+    if p.extension()? != "both" {
+        return None;
+    }
+    let stem = p.file_stem()?.to_str()?;
+    let open = |part: &str| {
+        open_mod(
+            Path::new(&format!("{}-{}.zip", stem, part)),
+            UnicodeForm::None,
+            None,
+        )
+    };
+    Some((|| {
+        let parts = vec![open("base")?, open("extra")?];
+        let m: Box<dyn Mod> = Box::new(CompositeMod::new(parts)?);
+        Ok(m)
+    })())
+}
+
+#[test]
+fn registered_composite_mods() -> Result<()> {
+    register_opener(Box::new(open_both));
+    let env = TestEnv::new()?;
+    env.write_game_file("A.txt", b"The game's A")?;
+    env.zip_mod("mod1-base", "2.0.0", &[("A.txt", b"The mod's A")])?;
+    env.zip_mod("mod1-extra", "1.0", &[("B.txt", b"The extra B")])?;
+
+    env.add(&["mod1.both"])?;
+    assert_eq!(env.read_game_file("A.txt")?, b"The mod's A");
+    assert_eq!(env.read_game_file("B.txt")?, b"The extra B");
+    // The first mod speaks for the whole.
+    assert_eq!(
+        env.profile()?.mods[Path::new("mod1.both")]
+            .version
+            .to_string(),
+        "2.0.0"
+    );
+    env.write_game_file("A.txt", b"The game's new A")?;
+    env.update()?;
+    assert_eq!(env.read_game_file("A.txt")?, b"The mod's A");
+    env.check()?;
+
+    env.remove(&["mod1.both"])?;
+    assert_eq!(env.read_game_file("A.txt")?, b"The game's new A");
+    assert!(!env.game_path("B.txt").exists());
+
+    // Mods can't be combined if they have the same files.
+    env.zip_mod("mod2-base", "1.0", &[("A.txt", b"One A")])?;
+    env.zip_mod("mod2-extra", "1.0", &[("A.txt", b"Another A")])?;
+    let e = env.add(&["mod2.both"]).unwrap_err();
+    assert!(
+        format!("{:#}", e).contains("more than one of the mods"),
+        "{:#}",
+        e
+    );
+    Ok(())
+}